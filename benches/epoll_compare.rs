@@ -0,0 +1,396 @@
+//! benchmarks dpoll on loopback connections, and optionally kernel epoll with kernel TCP sockets
+//! running the same scenarios, as a sanity baseline rather than a fair comparison
+//!
+//! - events: connections that all have a byte waiting and are never read from, so every pwait
+//!   reports all of them again, measured in events delivered per second for a growing item count
+//! - throughput: a single connection with one side writing messages of a fixed size as fast as it
+//!   gets EPOLLOUT and the other reading them, measured in bytes per second for growing sizes
+//!
+//! usage: `cargo bench --bench epoll_compare -- [millis per scenario] [dpoll|kernel|both]`, 1000
+//! and dpoll by default, the backend is selected with DPOLL_LIBOS like for any other app, dpoll
+//! listens on DPOLL_BENCH_PORT, 20000 if it is not set, and the kernel on the port after it
+
+use std::{
+    env,
+    io::Error,
+    mem,
+    process::ExitCode,
+    ptr,
+    time::{Duration, Instant},
+};
+
+use demi_epoll::bindings::*;
+use libc::{
+    AF_INET, EAGAIN, EINPROGRESS, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLLERR, EPOLLHUP, EPOLLIN,
+    EPOLLOUT, INADDR_LOOPBACK, SO_REUSEADDR, SOCK_NONBLOCK, SOCK_STREAM, SOL_SOCKET, c_int, c_void,
+    epoll_event, sigset_t, size_t, sockaddr, sockaddr_in, socklen_t, ssize_t,
+};
+
+const LISTENER: u64 = u64::MAX;
+/// the connections of the events scenario, kept under the default limit of 1024 fds with both
+/// ends of each in the process
+const ITEMS: &[usize] = &[1, 16, 64, 256];
+/// the message sizes of the throughput scenario
+const SIZES: &[usize] = &[64, 1024, 16 * 1024, 64 * 1024];
+/// how long setting connections up may go without any of them progressing
+const STALL: Duration = Duration::from_secs(10);
+
+/// the calls the scenarios make, either the dpoll ones or their kernel counterparts
+struct Api {
+    name: &'static str,
+    create: unsafe extern "C" fn(c_int) -> c_int,
+    ctl: unsafe extern "C" fn(c_int, c_int, c_int, *mut epoll_event) -> c_int,
+    pwait: unsafe extern "C" fn(c_int, *mut epoll_event, c_int, c_int, *const sigset_t) -> c_int,
+    socket: unsafe extern "C" fn(c_int, c_int, c_int) -> c_int,
+    bind: unsafe extern "C" fn(c_int, *const sockaddr, socklen_t) -> c_int,
+    listen: unsafe extern "C" fn(c_int, c_int) -> c_int,
+    accept: unsafe extern "C" fn(c_int, *mut sockaddr, *mut socklen_t) -> c_int,
+    connect: unsafe extern "C" fn(c_int, *const sockaddr, socklen_t) -> c_int,
+    read: unsafe extern "C" fn(c_int, *mut c_void, size_t) -> ssize_t,
+    write: unsafe extern "C" fn(c_int, *const c_void, size_t) -> ssize_t,
+    close: unsafe extern "C" fn(c_int) -> c_int,
+}
+
+static DPOLL: Api = Api {
+    name: "dpoll",
+    create: dpoll_create,
+    ctl: dpoll_ctl,
+    pwait: dpoll_pwait,
+    socket: dpoll_socket,
+    bind: dpoll_bind,
+    listen: dpoll_listen,
+    accept: dpoll_accept,
+    connect: dpoll_connect,
+    read: dpoll_read,
+    write: dpoll_write,
+    close: dpoll_close,
+};
+
+static KERNEL: Api = Api {
+    name: "kernel",
+    create: libc::epoll_create1,
+    ctl: libc::epoll_ctl,
+    pwait: libc::epoll_pwait,
+    socket: kernel_socket,
+    bind: libc::bind,
+    listen: libc::listen,
+    accept: kernel_accept,
+    connect: libc::connect,
+    read: libc::read,
+    write: libc::write,
+    close: libc::close,
+};
+
+/// a nonblocking socket, reusing addresses so a rerun can bind while the connections of the last
+/// one are still in TIME_WAIT
+extern "C" fn kernel_socket(domain: c_int, r#type: c_int, proto: c_int) -> c_int {
+    let fd = unsafe { libc::socket(domain, r#type | SOCK_NONBLOCK, proto) };
+    if fd >= 0 {
+        let on: c_int = 1;
+        let len = mem::size_of::<c_int>() as socklen_t;
+        let on = &on as *const c_int as *const c_void;
+        unsafe { libc::setsockopt(fd, SOL_SOCKET, SO_REUSEADDR, on, len) };
+    }
+    return fd;
+}
+
+extern "C" fn kernel_accept(fd: c_int, addr: *mut sockaddr, len: *mut socklen_t) -> c_int {
+    return unsafe { libc::accept4(fd, addr, len, SOCK_NONBLOCK) };
+}
+
+fn fail(what: &str) -> String {
+    return format!("{what} failed: {}", Error::last_os_error());
+}
+
+fn would_block() -> bool {
+    return Error::last_os_error().raw_os_error() == Some(EAGAIN);
+}
+
+fn loopback(port: u16) -> sockaddr_in {
+    let mut addr: sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_family = AF_INET as _;
+    addr.sin_port = port.to_be();
+    addr.sin_addr.s_addr = INADDR_LOOPBACK.to_be();
+    return addr;
+}
+
+struct Bench {
+    api: &'static Api,
+    pol: c_int,
+    listener: c_int,
+    addr: sockaddr_in,
+    /// how long each scenario is measured for
+    length: Duration,
+    events: Vec<epoll_event>,
+}
+
+impl Bench {
+    fn new(api: &'static Api, port: u16, length: Duration) -> Result<Self, String> {
+        let pol = unsafe { (api.create)(0) };
+        if pol < 0 {
+            return Err(fail("create"));
+        }
+
+        let addr = loopback(port);
+        let addr_ptr = &addr as *const sockaddr_in as *const sockaddr;
+        let addr_len = mem::size_of::<sockaddr_in>() as socklen_t;
+
+        let listener = unsafe { (api.socket)(AF_INET, SOCK_STREAM, 0) };
+        if listener < 0 {
+            return Err(fail("socket"));
+        }
+        if unsafe { (api.bind)(listener, addr_ptr, addr_len) } != 0 {
+            return Err(fail("bind"));
+        }
+        if unsafe { (api.listen)(listener, 512) } != 0 {
+            return Err(fail("listen"));
+        }
+
+        let bench = Self {
+            api,
+            pol,
+            listener,
+            addr,
+            length,
+            events: vec![epoll_event { events: 0, u64: 0 }; 256],
+        };
+        bench.register(listener, EPOLLIN, LISTENER)?;
+        return Ok(bench);
+    }
+
+    fn register(&self, fd: c_int, events: c_int, data: u64) -> Result<(), String> {
+        let mut ev = epoll_event {
+            events: events as u32,
+            u64: data,
+        };
+        if unsafe { (self.api.ctl)(self.pol, EPOLL_CTL_ADD, fd, &mut ev) } != 0 {
+            return Err(fail("ctl"));
+        }
+        return Ok(());
+    }
+
+    fn unregister(&self, fd: c_int) {
+        let mut ev = epoll_event { events: 0, u64: 0 };
+        unsafe { (self.api.ctl)(self.pol, EPOLL_CTL_DEL, fd, &mut ev) };
+    }
+
+    fn pwait(&mut self, timeout: c_int) -> Result<usize, String> {
+        let len = self.events.len() as c_int;
+        let events = self.events.as_mut_ptr();
+        let ret = unsafe { (self.api.pwait)(self.pol, events, len, timeout, ptr::null()) };
+        if ret < 0 {
+            return Err(fail("pwait"));
+        }
+        return Ok(ret as usize);
+    }
+
+    /// connects `count` clients to the listener, returning the clients and the accepted ends, none
+    /// of them registered
+    fn connect(&mut self, count: usize) -> Result<(Vec<c_int>, Vec<c_int>), String> {
+        let addr_ptr = &self.addr as *const sockaddr_in as *const sockaddr;
+        let addr_len = mem::size_of::<sockaddr_in>() as socklen_t;
+
+        let mut clients = Vec::with_capacity(count);
+        for idx in 0..count {
+            let fd = unsafe { (self.api.socket)(AF_INET, SOCK_STREAM, 0) };
+            if fd < 0 {
+                return Err(fail("socket"));
+            }
+            let ret = unsafe { (self.api.connect)(fd, addr_ptr, addr_len) };
+            if ret != 0 && Error::last_os_error().raw_os_error() != Some(EINPROGRESS) {
+                return Err(fail("connect"));
+            }
+            self.register(fd, EPOLLOUT, idx as u64)?;
+            clients.push(fd);
+        }
+
+        let mut servers = Vec::with_capacity(count);
+        let mut connected = 0;
+        let mut progress = Instant::now();
+        while servers.len() < count || connected < count {
+            if progress.elapsed() > STALL {
+                return Err(format!("no connection progressed for {STALL:?}"));
+            }
+
+            for idx in 0..self.pwait(100)? {
+                let (data, events) = (self.events[idx].u64, self.events[idx].events);
+                if data != LISTENER {
+                    if events & (EPOLLERR | EPOLLHUP) as u32 != 0 {
+                        return Err(format!("client {data} failed to connect"));
+                    }
+                    self.unregister(clients[data as usize]);
+                    connected += 1;
+                    progress = Instant::now();
+                    continue;
+                }
+
+                let accept = self.api.accept;
+                loop {
+                    let fd = unsafe { accept(self.listener, ptr::null_mut(), ptr::null_mut()) };
+                    if fd < 0 {
+                        if would_block() {
+                            break;
+                        }
+                        return Err(fail("accept"));
+                    }
+                    servers.push(fd);
+                    progress = Instant::now();
+                }
+            }
+        }
+
+        return Ok((clients, servers));
+    }
+
+    fn close(&self, fds: &[c_int]) {
+        for &fd in fds {
+            self.unregister(fd);
+            unsafe { (self.api.close)(fd) };
+        }
+    }
+
+    /// events per second with `count` items always ready
+    fn events(&mut self, count: usize) -> Result<f64, String> {
+        let (clients, servers) = self.connect(count)?;
+        for &fd in &servers {
+            let byte = [0u8];
+            if unsafe { (self.api.write)(fd, byte.as_ptr() as *const _, 1) } != 1 {
+                return Err(fail("write"));
+            }
+        }
+        for (idx, &fd) in clients.iter().enumerate() {
+            self.register(fd, EPOLLIN, idx as u64)?;
+        }
+
+        // waits for every byte to arrive, so all the items are ready from then on
+        let mut seen = vec![false; count];
+        let start = Instant::now();
+        while seen.iter().any(|s| !s) {
+            if start.elapsed() > STALL {
+                return Err(format!("the bytes did not all arrive within {STALL:?}"));
+            }
+            for idx in 0..self.pwait(100)? {
+                seen[self.events[idx].u64 as usize] = true;
+            }
+        }
+
+        let mut delivered = 0;
+        let start = Instant::now();
+        while start.elapsed() < self.length {
+            delivered += self.pwait(0)?;
+        }
+        let took = start.elapsed();
+
+        self.close(&clients);
+        self.close(&servers);
+        return Ok(delivered as f64 / took.as_secs_f64());
+    }
+
+    /// bytes per second written with messages of `size` bytes, read back on the other end
+    fn throughput(&mut self, size: usize) -> Result<f64, String> {
+        const WRITER: u64 = 0;
+        const READER: u64 = 1;
+
+        let (clients, servers) = self.connect(1)?;
+        let (writer, reader) = (clients[0], servers[0]);
+        self.register(writer, EPOLLOUT, WRITER)?;
+        self.register(reader, EPOLLIN, READER)?;
+
+        let msg = vec![0xa5u8; size];
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut received = 0;
+        let start = Instant::now();
+        while start.elapsed() < self.length {
+            for idx in 0..self.pwait(100)? {
+                let (data, events) = (self.events[idx].u64, self.events[idx].events);
+                if data == WRITER && events & EPOLLOUT as u32 != 0 {
+                    let ret = unsafe { (self.api.write)(writer, msg.as_ptr() as *const _, size) };
+                    if ret < 0 && !would_block() {
+                        return Err(fail("write"));
+                    }
+                }
+                if data == READER && events & EPOLLIN as u32 != 0 {
+                    let len = buf.len();
+                    let ret = unsafe { (self.api.read)(reader, buf.as_mut_ptr() as *mut _, len) };
+                    if ret == 0 {
+                        return Err("the reader got closed".to_owned());
+                    }
+                    if ret < 0 && !would_block() {
+                        return Err(fail("read"));
+                    }
+                    received += ret.max(0) as usize;
+                }
+            }
+        }
+        let took = start.elapsed();
+
+        self.close(&clients);
+        self.close(&servers);
+        return Ok(received as f64 / took.as_secs_f64());
+    }
+}
+
+impl Drop for Bench {
+    fn drop(&mut self) {
+        self.close(&[self.listener]);
+        unsafe { (self.api.close)(self.pol) };
+    }
+}
+
+fn run(api: &'static Api, port: u16, length: Duration) -> Result<(), String> {
+    let mut bench = Bench::new(api, port, length)?;
+
+    println!("{}, events delivered with every item ready:", api.name);
+    for &count in ITEMS {
+        let rate = bench.events(count)?;
+        println!("  {count:>6} items: {rate:>14.0} events/s");
+    }
+
+    println!("{}, write throughput:", api.name);
+    for &size in SIZES {
+        let rate = bench.throughput(size)?;
+        println!(
+            "  {size:>6} bytes: {:>10.1} MB/s, {:>10.0} messages/s",
+            rate / 1e6,
+            rate / size as f64
+        );
+    }
+    return Ok(());
+}
+
+fn main() -> ExitCode {
+    // cargo bench passes --bench
+    let mut args = env::args().skip(1).filter(|a| a != "--bench");
+    let millis = args.next().map_or(Ok(1000), |a| a.parse());
+    let which = args.next().unwrap_or_else(|| "dpoll".to_owned());
+    let port = env::var("DPOLL_BENCH_PORT").map_or(Ok(20000u16), |p| p.parse());
+
+    let apis: &[&'static Api] = match which.as_str() {
+        "dpoll" => &[&DPOLL],
+        "kernel" => &[&KERNEL],
+        "both" => &[&DPOLL, &KERNEL],
+        _ => &[],
+    };
+    let (Ok(millis), Ok(port), false) = (millis, port, apis.is_empty()) else {
+        eprintln!("usage: epoll_compare [millis per scenario] [dpoll|kernel|both]");
+        return ExitCode::FAILURE;
+    };
+
+    if apis.iter().any(|api| ptr::eq(*api, &DPOLL)) && dpoll_init() != 0 {
+        eprintln!("{}", fail("dpoll_init"));
+        return ExitCode::FAILURE;
+    }
+
+    for api in apis {
+        let port = if ptr::eq(*api, &KERNEL) {
+            port.wrapping_add(1)
+        } else {
+            port
+        };
+        if let Err(e) = run(api, port, Duration::from_millis(millis)) {
+            eprintln!("{}: {e}", api.name);
+            return ExitCode::FAILURE;
+        }
+    }
+    return ExitCode::SUCCESS;
+}
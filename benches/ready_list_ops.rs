@@ -0,0 +1,31 @@
+//! a microbenchmark of the ready list, pushing, removing and draining the items of a dpoll like a
+//! pwait with many sockets becoming ready does
+//!
+//! usage: `cargo bench --features fuzzing --bench ready_list_ops -- [items] [rounds]`, 10000 items
+//! and 1000 rounds by default, the fuzzing feature exposes the internals
+
+use std::{env, process::ExitCode};
+
+use demi_epoll::fuzzing::ready_list_ops;
+
+fn main() -> ExitCode {
+    // cargo bench passes --bench
+    let mut args = env::args().skip(1).filter(|a| a != "--bench");
+    let items = args.next().map_or(Ok(10_000), |a| a.parse());
+    let rounds = args.next().map_or(Ok(1_000), |a| a.parse());
+
+    let (Ok(items), Ok(rounds)) = (items, rounds) else {
+        eprintln!("usage: ready_list_ops [items] [rounds]");
+        return ExitCode::FAILURE;
+    };
+
+    let took = ready_list_ops(items, rounds);
+    // every item is pushed and then either removed or drained
+    let ops = 2 * items * rounds;
+    println!("{rounds} rounds over {items} items took {took:?}");
+    println!(
+        "{:.1} ns per operation",
+        took.as_nanos() as f64 / ops.max(1) as f64
+    );
+    return ExitCode::SUCCESS;
+}
@@ -1,3 +1,86 @@
+use std::{env, fs};
+
 fn main() {
-    println!("cargo:rustc-link-lib=demikernel");
+    println!("cargo:rerun-if-env-changed=DEMIKERNEL_LIB_DIR");
+    if let Ok(dir) = env::var("DEMIKERNEL_LIB_DIR") {
+        println!("cargo:rustc-link-search=native={dir}");
+    }
+
+    // the mock feature defines the demikernel symbols itself
+    if env::var_os("CARGO_FEATURE_MOCK").is_none() {
+        println!("cargo:rustc-link-lib=demikernel");
+    }
+
+    // the soname changes with the ABI, so binaries linked against an older one fail to load
+    // instead of misbehaving
+    let soname = format!("libdemi_epoll.so.{}", abi_version());
+    println!("cargo:rustc-cdylib-link-arg=-Wl,-soname,{soname}");
+
+    generate_header();
+    generate_bindings();
+}
+
+/// DPOLL_ABI_VERSION, read from src/bindings/mod.rs so it is only defined once
+fn abi_version() -> u32 {
+    const PREFIX: &str = "pub const DPOLL_ABI_VERSION: u32 = ";
+
+    let dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let bindings = fs::read_to_string(format!("{dir}/src/bindings/mod.rs")).unwrap();
+    let line = bindings
+        .lines()
+        .find_map(|l| l.strip_prefix(PREFIX))
+        .expect("DPOLL_ABI_VERSION not found in src/bindings/mod.rs");
+
+    return line.trim_end_matches(';').parse().unwrap();
+}
+
+/// with the bindgen feature, generates the raw demikernel bindings from the headers in
+/// DEMIKERNEL_INCLUDE_DIR into OUT_DIR and sets the `dpoll_bindgen` cfg for src/wrappers/mod.rs to
+/// include them, without the feature or the directory the checked-in src/wrappers/raw.rs is used
+fn generate_bindings() {
+    println!("cargo::rustc-check-cfg=cfg(dpoll_bindgen)");
+    println!("cargo:rerun-if-env-changed=DEMIKERNEL_INCLUDE_DIR");
+
+    #[cfg(feature = "bindgen")]
+    {
+        let Ok(include) = env::var("DEMIKERNEL_INCLUDE_DIR") else {
+            println!("cargo:warning=DEMIKERNEL_INCLUDE_DIR is not set, using src/wrappers/raw.rs");
+            return;
+        };
+        println!("cargo:rerun-if-changed={include}/demi");
+
+        let bindings = bindgen::Builder::default()
+            .header_contents(
+                "demi.h",
+                "#include <demi/libos.h>\n#include <demi/sga.h>\n#include <demi/wait.h>\n",
+            )
+            .clang_arg(format!("-I{include}"))
+            .generate()
+            .expect("generating the demikernel bindings failed");
+
+        let out = env::var("OUT_DIR").unwrap();
+        bindings
+            .write_to_file(format!("{out}/raw.rs"))
+            .expect("writing the demikernel bindings failed");
+        println!("cargo:rustc-cfg=dpoll_bindgen");
+    }
+}
+
+/// with the cbindgen feature, regenerates c/dpoll.h from the C ABI in src/bindings/mod.rs,
+/// configured by cbindgen.toml, without it the checked-in header is left alone
+fn generate_header() {
+    #[cfg(feature = "cbindgen")]
+    {
+        println!("cargo:rerun-if-changed=src/bindings/mod.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+
+        let dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+        let config = cbindgen::Config::from_file(format!("{dir}/cbindgen.toml")).unwrap();
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{dir}/src/bindings/mod.rs"))
+            .generate()
+            .expect("generating c/dpoll.h failed")
+            .write_to_file(format!("{dir}/c/dpoll.h"));
+    }
 }
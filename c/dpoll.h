@@ -1,44 +1,429 @@
 #pragma once
 
+// generated from src/bindings/mod.rs by `make update_c_header`, do not edit
+
 #include <stdarg.h>
 #include <stdbool.h>
 #include <stdint.h>
 #include <stdlib.h>
+#include <netinet/in.h>
 #include <sys/epoll.h>
 #include <sys/socket.h>
+#include <demi/types.h>
+
+/// the version of the ABI described by dpoll.h, bumped whenever a function or struct changes
+/// incompatibly
+#define DPOLL_ABI_VERSION 1
+
+/// dpoll sockets can connect, see `dpoll_connect`, if the libOS supports it
+#define DPOLL_CAP_CONNECT (1 << 0)
+
+/// dpoll sockets can be SOCK_DGRAM, not supported yet
+#define DPOLL_CAP_UDP (1 << 1)
+
+/// dpoll sockets can be registered with EPOLLET, not supported yet
+#define DPOLL_CAP_EDGE_TRIGGERED (1 << 2)
+
+/// dpoll sockets can be registered with EPOLLONESHOT, not supported yet
+#define DPOLL_CAP_ONESHOT (1 << 3)
+
+/// reads and writes can hand their buffers to demikernel without copying, not supported yet
+#define DPOLL_CAP_ZERO_COPY (1 << 4)
+
+/// sockets and dpolls are locked instead of borrowed, built with the thread-safe feature. fds are
+/// still per thread, only valid on the thread that created them or adopted them with `dpoll_adopt`
+#define DPOLL_CAP_MULTITHREAD (1 << 5)
+
+/// a background thread harvests the completions, built with the reactor feature, see
+/// `dpoll_get_wakeup_fd`
+#define DPOLL_CAP_REACTOR (1 << 6)
+
+/// the libOS bypasses the kernel network stack
+#define DPOLL_CAP_KERNEL_BYPASS (1 << 7)
+
+/// listeners can share an address, see `dpoll_listen_sharded`, no libOS supports it yet
+#define DPOLL_CAP_REUSEPORT (1 << 8)
+
+/// or-ed into the `op` of a `dpoll_ctl_op`, an add or modify then overwrites the data of its event
+/// with `fd` in `data.fd`, zeroing the rest, for code written for epoll that expects to get its fds
+/// back from pwait without ever setting the data itself
+#define DPOLL_CTL_DATA_FD 256
+
+/// or-ed into the `op` of a `dpoll_ctl_op`, an add or modify of a dpoll socket then keeps the data
+/// of its event and pwait reports a cookie of the registration instead, which `dpoll_cookie_data`
+/// turns back into the data as long as the registration was not deleted or modified since
+///
+/// this catches events still held for a fd that was closed or re-registered meanwhile, the fd the
+/// slot of a closed one is reused for never yields the same cookie. fails with EINVAL for kernel
+/// fds and together with `DPOLL_CTL_DATA_FD`
+#define DPOLL_CTL_COOKIE 512
+
+/// the option level of the dpoll specific socket options
+#define SOL_DPOLL 17488
+
+/// whether a pop is started as soon as EPOLLIN is requested, on by default and inherited by
+/// accepted sockets
+///
+/// when off, pops are only started by reads that find no data, so demikernel only buffers what
+/// the application asked for
+#define DPOLL_SO_AUTOPOP 1
+
+/// scanning the registered sockets for the operations to wait on and the ready ones
+#define DPOLL_PHASE_SCHEDULE 0
+
+/// waiting for and processing demikernel completions
+#define DPOLL_PHASE_DEMI_WAIT 1
+
+/// draining the ready sockets into the events
+#define DPOLL_PHASE_DRAIN 2
+
+/// waiting on the kernel fds
+#define DPOLL_PHASE_EPOLL 3
+
+enum dpoll_fd_kind {
+    DPOLL_FD_KERNEL = 0,
+    DPOLL_FD_SOCKET = 1,
+    DPOLL_FD_INSTANCE = 2,
+};
 
+enum dpoll_op_state {
+    DPOLL_OP_NONE = 0,
+    DPOLL_OP_RUNNING = 1,
+    DPOLL_OP_COMPLETED = 2,
+};
+
+struct dpoll_registration {
+    /// -1 for a socket or nested dpoll whose fd was closed already
+    int fd;
+    enum dpoll_fd_kind kind;
+    /// the events and flags of the last add or modify
+    uint32_t events;
+    /// the registered events that are ready right now
+    uint32_t ready;
+    /// the data of the last add or modify
+    uint64_t data;
+};
+
+struct dpoll_ctl_op {
+    int op;
+    int fd;
+    struct epoll_event event;
+};
+
+struct dpoll_connect_req {
+    int fd;
+    struct sockaddr_in addr;
+    /// the epoll data reported with the completion
+    uint64_t data;
+    /// set to the errno of a failed submission, 0 otherwise
+    int err;
+};
+
+struct dpoll_fd_info {
+    enum dpoll_fd_kind kind;
+    /// the slot of the fd, only meaningful for dpoll fds
+    uint32_t index;
+    uint8_t generation;
+    /// false if the slot is free or was reused by a newer generation
+    bool live;
+    /// the demikernel qd of a live socket
+    uint32_t qd;
+    /// whether a live socket was not closed yet
+    bool open;
+    /// the number of sockets registered in a live dpoll instance
+    uint32_t items;
+    enum dpoll_op_state accept;
+    enum dpoll_op_state connect;
+    enum dpoll_op_state read;
+    enum dpoll_op_state write;
+};
+
+struct dpoll_stats {
+    uint64_t pwait_calls;
+    /// demikernel completions processed over all waits
+    uint64_t completions;
+    /// events returned by pwait, including kernel ones
+    uint64_t events;
+    /// the ready list length at the end of the last pwait
+    uint64_t ready_list_depth;
+    /// the number of registered dpoll sockets
+    uint64_t items;
+    /// demikernel operations of the registered sockets that did not complete yet
+    uint64_t running_operations;
+    /// received bytes the registered sockets hold that were not read yet
+    uint64_t recv_buffered;
+    /// registered sockets that stopped popping until their received bytes are read
+    uint64_t recv_stopped;
+    /// demikernel waits that returned at least one completion
+    uint64_t demi_waits;
+    /// the most completions processed by a single demikernel wait
+    uint64_t max_completions_per_wait;
+    /// listeners not waited on for the rest of a pwait as it completed its maximum of accepts
+    uint64_t deferred_accepts;
+    /// the most demikernel tokens a single pwait waited on
+    uint64_t max_qtoks;
+    /// pwaits that had to grow the buffer of the tokens they wait on
+    uint64_t qtoks_grows;
+    /// the most sockets registered at once
+    uint64_t max_items;
+    /// adds that failed with ENOSPC as the dpoll had its maximum of sockets registered, see
+    /// `dpoll_set_max_items`
+    uint64_t refused_adds;
+};
+
+/// the time the pwaits of a dpoll spent in a phase, in nanoseconds, the percentiles are exact to
+/// within 12.5%
+struct dpoll_phase_latency {
+    /// the pwaits recorded
+    uint64_t count;
+    uint64_t min_ns;
+    uint64_t max_ns;
+    uint64_t mean_ns;
+    uint64_t p50_ns;
+    uint64_t p90_ns;
+    uint64_t p99_ns;
+    uint64_t p999_ns;
+};
+
+struct dpoll_sga_pool_stats {
+    /// write allocations served by a cached sga
+    uint64_t hits;
+    /// write allocations that went to demikernel
+    uint64_t misses;
+    /// sgas cached right now
+    uint64_t cached;
+    /// sgas freed as their size class was full
+    uint64_t freed;
+    /// sgas allocated to prewarm the pool
+    uint64_t prewarmed;
+};
+
+/// invoked for every event `dpoll_pwait` returns, in order and before it returns
+typedef void (*dpoll_event_callback)(void *ctx, const struct epoll_event *event);
+
+/// dpoll sockets are always non-blocking and never survive an exec, so SOCK_NONBLOCK and
+/// SOCK_CLOEXEC are accepted and change nothing
+///
+/// fails with EAFNOSUPPORT for any domain but AF_INET and with EPROTONOSUPPORT for any type but
+/// SOCK_STREAM
 int dpoll_socket(int domain, int type, int proto);
 
 int dpoll_bind(int socket_fd, const struct sockaddr *addr, socklen_t addr_len);
 
 int dpoll_listen(int socket_fd, int backlog);
 
+/// SHUT_RD or SHUT_RDWR on a listener stops it accepting until it listens again, so a server can
+/// drain the connections it accepted before closing it: the connections not accepted yet are
+/// closed and the listener is not reported as readable anymore
+///
+/// SHUT_WR leaves a listener as it is, connected dpoll sockets fail with EOPNOTSUPP as demikernel
+/// cannot half close a connection and unconnected ones with ENOTCONN, kernel fds are passed
+/// through to shutdown
+int dpoll_shutdown(int socket_fd, int how);
+
+/// creates `n` sockets listening on `addr` with `backlog`, e.g. one per worker thread, and writes
+/// their fds to `fds`, which has to have room for `n`
+///
+/// without SO_REUSEPORT in demikernel the listeners cannot share the address, so they are bound to
+/// `n` consecutive ports starting at the one of `addr`, or to an ephemeral port each if it is 0
+///
+/// returns 0, or -1 and sets errno with none of the listeners left open, fails with EINVAL if `n`
+/// <= 0 or the ports would run past 65535
+int dpoll_listen_sharded(const struct sockaddr *addr,
+                         socklen_t addr_len,
+                         int n,
+                         int backlog,
+                         int *fds);
+
+/// writes the address of the peer to `addr` if it is not NULL, truncated to `*addr_len` bytes, and
+/// sets `*addr_len` to its full length
 int dpoll_accept(int socket_fd, struct sockaddr *addr, socklen_t *addr_len);
 
+/// closes any kind of fd, like close(2) the fd is released even if closing what is behind it fails,
+/// closing it again fails with EBADF
+///
+/// kernel fds are deleted from the dpolls first, dpoll sockets are unregistered from them and
+/// dpolls are no longer waited on by the dpolls they are nested in, so none of them is reported
+/// by a pwait starting after its close returned, events a pwait collected before are still
+/// delivered
 int dpoll_close(int fd);
 
+/// once a write or read failed with an error that ended the connection, e.g. ECONNRESET, the next
+/// write fails with that error unless SO_ERROR took it already and every later one with EPIPE,
+/// like write(2). no SIGPIPE is ever raised, as if every write was sent with MSG_NOSIGNAL
 ssize_t dpoll_write(int socket_fd, const void *buf, size_t len);
 
+/// blocks until the writes queued on `socket_fd` completed or `timeout_ms` passed, a negative
+/// timeout blocks until they completed, returns the number of bytes left unflushed
+///
+/// kernel fds have nothing queued in dpoll and return 0 right away, a write that failed meanwhile
+/// fails the flush with its error, like the next write would
+ssize_t dpoll_flush(int socket_fd, int timeout_ms);
+
+/// like read(2), it returns 0 once the peer shut its side down and everything it sent before was
+/// read, and fails with EWOULDBLOCK only while nothing arrived on a connection still open
 ssize_t dpoll_read(int socket_fd, void *buf, size_t len);
 
+/// registers `n` buffers of this thread for `dpoll_read_fixed`, the memory has to stay valid until
+/// `dpoll_unregister_buffers`
+///
+/// none of the demikernel libOSes can pop into the memory of the application, so the data is
+/// still copied once, like by `dpoll_read`
+///
+/// fails with EBUSY if buffers are registered already and with EINVAL if `n` is not within 1 and
+/// 16384 or one of the buffers is NULL or empty
+int dpoll_register_buffers(const struct iovec *bufs, int n);
+
+/// fails with ENXIO if no buffers are registered
+int dpoll_unregister_buffers(void);
+
+/// like `dpoll_read`, into `len` bytes of the registered buffer `buf_index` starting `offset`
+/// bytes in
+///
+/// fails with ENXIO if no buffers are registered and with EINVAL if the range does not fit into
+/// the buffer
+ssize_t dpoll_read_fixed(int socket_fd, int buf_index, size_t offset, size_t len);
+
+/// like `dpoll_read`, but only returns data that already arrived, it never polls demikernel nor
+/// starts a new read, which is left to the next `dpoll_read` or `dpoll_pwait`
+ssize_t dpoll_try_read(int socket_fd, void *buf, size_t len);
+
+/// like `dpoll_write`, but fails with EWOULDBLOCK instead of polling demikernel when the send
+/// queue is full, only completions seen by `dpoll_pwait` make room
+ssize_t dpoll_try_write(int socket_fd, const void *buf, size_t len);
+
+/// like `dpoll_write`, the iovecs of up to 64 KiB go out in a single push however many there
+/// are, longer writes are split into pushes of 64 KiB, unlike writev(2) more than IOV_MAX iovecs
+/// are taken
 ssize_t dpoll_writev(int socket_fd, const struct iovec *vecs, int iovec_count);
 
+/// fills the iovecs with all the data received so far, not only with that of one demikernel pop
+/// like `dpoll_read`, unlike readv(2) more than IOV_MAX iovecs are taken
 ssize_t dpoll_readv(int socket_fd, struct iovec *vecs, int iovec_count);
 
+/// initializes demikernel and registers the fork handlers, dpoll fds are not usable in a forked
+/// child
 int dpoll_init(void);
 
+/// closes every demikernel queue of the process, of all its threads, to be called right before
+/// an exec, returns how many were closed
+///
+/// the exec does not keep any demikernel state, so queues left open would only leak what is
+/// behind them, e.g. DPDK queues and hugepages. from then on every dpoll fd fails with EBADF, as
+/// in a forked child, except for `dpoll_close`, which is left to release the fds if the exec fails
+int dpoll_prepare_exec(void);
+
+/// `dpoll_prepare_exec` followed by execve(2), only returns if the exec failed
+int dpoll_execve(const char *path, const char *const *argv, const char *const *envp);
+
+/// the DPOLL_ABI_VERSION the library was built with, a program compiled against another version
+/// should refuse to run
+uint32_t dpoll_abi_version(void);
+
+/// the `DPOLL_CAP_*` flags of what the library supports, for applications and interposition
+/// layers that detect features at runtime instead of assuming them from the header
+///
+/// the libOS ones are of the libOS in use, before `dpoll_init` of the one the library was built
+/// for. bits not known to the caller are for features added after it was compiled and must be
+/// ignored
+uint64_t dpoll_capabilities(void);
+
+/// the version of the library, `major << 16 | minor << 8 | patch`
+uint32_t dpoll_version(void);
+
+/// the errno set by the last dpoll call of this thread that failed, for runtimes that cannot read
+/// errno itself reliably across their FFI layers
+///
+/// like errno, it is not cleared by successful calls, and calls on kernel fds that are passed
+/// through to libc do not set it
+int dpoll_errno(void);
+
+/// the message of the errno `code`, a static string that must not be freed
+const char *dpoll_strerror(int code);
+
 int dpoll_create(int flags);
 
+/// fails exactly like epoll_ctl would, e.g. with EEXIST for an add of a registered fd and with
+/// ENOENT for a modify or delete of one that is not, also if it is registered in another dpoll,
+/// fds that are not dpoll sockets or dpolls are passed through to the kernel epoll of `dpollfd`
+///
+/// for dpoll sockets EPOLLWAKEUP, EPOLLEXCLUSIVE and EPOLLPRI are accepted without any effect,
+/// EPOLLEXCLUSIVE only on an add like the kernel, any other bit but EPOLLIN, EPOLLOUT, EPOLLERR and
+/// EPOLLHUP fails with EINVAL
 int dpoll_ctl(int dpollfd, int op, int fd, struct epoll_event *event);
 
+/// fills `event` with the events and data `fd` is registered with in `dpollfd`, as the last
+/// `dpoll_ctl` add or modify left them, for debugging
+///
+/// fails with ENOENT if `fd` is not registered and with EOPNOTSUPP for kernel fds, the kernel
+/// epoll keeps those
+int dpoll_get_registration(int dpollfd, int fd, struct epoll_event *event);
+
+/// fills `out` with up to `cap` registrations of `dpollfd`, like the fdinfo of an epoll fd lists
+/// them, meant for debuggers and leak checkers
+///
+/// the sockets come first, then the kernel fds and nested dpolls sorted by fd, the kernel keeps
+/// the events and data of those and they are read from /proc, 0 if it is not mounted, with ERR and
+/// HUP always set in the events like the kernel does
+///
+/// returns the number of registrations, which is more than `cap` if `out` is too short, `out` can
+/// be null with a `cap` of 0 to only count them, or -1 and sets errno to EINVAL for a negative
+/// `cap` and to EFAULT for a null `out` otherwise
+int dpoll_list(int dpollfd, struct dpoll_registration *out, int cap);
+
+/// applies `len` ctl operations on `dpollfd` in order, stopping at the first failing one, see
+/// `DPOLL_CTL_DATA_FD` for registering the fds as their data and `DPOLL_CTL_COOKIE` for reporting
+/// cookies instead
+///
+/// returns the number of applied operations, or -1 and sets errno if the first one failed. if fewer
+/// than `len` were applied, errno is set to why `ops[returned]` failed, like for a -1
+int dpoll_ctl_batch(int dpollfd, struct dpoll_ctl_op *ops, int len);
+
+/// the fd a cookie reported for a `DPOLL_CTL_COOKIE` registration was made for, which might have
+/// been closed since
+int dpoll_cookie_fd(uint64_t cookie);
+
+/// stores the data of the registration that `cookie` was reported for in `data`, see
+/// `DPOLL_CTL_COOKIE`
+///
+/// fails with ESTALE if its fd was closed or the registration deleted or modified since, with EBADF
+/// if `dpollfd` is not a dpoll and with EFAULT if `data` is NULL
+int dpoll_cookie_data(int dpollfd, uint64_t cookie, uint64_t *data);
+
+/// `sigmask`, if not NULL, replaces the signal mask only while blocked in demikernel or in the
+/// kernel, atomically for the latter like epoll_pwait
+///
+/// sockets are reported in the order they became ready, those that do not fit are reported first
+/// by the next call, and kernel fds left out of a full call go first in the next one, see
+/// src/dpoll/ready_list.rs
+///
+/// fails with EINVAL if `events_len` <= 0 and with EFAULT if `events` is NULL
 int dpoll_pwait(int dpollfd,
                 struct epoll_event *events,
                 int events_len,
                 int timeout,
                 const sigset_t *sigmask);
 
+/// SO_KEEPALIVE, TCP_KEEPIDLE, TCP_KEEPINTVL and TCP_KEEPCNT emulate keepalive on dpoll sockets, a
+/// connection idle for as long as the kernel would keep it is reported as EPOLLERR with ETIMEDOUT,
+/// DPOLL_SO_AUTOPOP at SOL_DPOLL controls when reads are started
+///
+/// SO_PRIORITY, 0 to 6, orders the sockets within their dpolls, those with a higher one are
+/// scheduled first and go ahead of the lower ones on the ready list, accepted sockets inherit it
+///
+/// SO_SNDBUF limits the bytes of the writes in flight, SO_RCVBUF the received bytes a socket
+/// buffers before it stops reading from demikernel, like with the kernel the value is doubled,
+/// raised to a minimum and reported doubled by `dpoll_getsockopt`, accepted sockets inherit both
+///
+/// other options are ignored on dpoll sockets
 int dpoll_setsockopt(int socket, int level, int optname, const void *optval, socklen_t optlen);
 
+/// writes the address of `socket` to `addr`, truncated to `*len` bytes, and sets `*len` to its full
+/// length
+///
+/// a socket neither bound nor connected has INADDR_ANY:0 as its address, like a kernel one
+///
+/// fails with EFAULT if `addr` or `len` is NULL
 int dpoll_getsockname(int socket, struct sockaddr *addr, socklen_t *len);
 
 int dpoll_sendmsg(int socket, const struct msghdr *msg, int flags);
@@ -46,3 +431,250 @@ int dpoll_sendmsg(int socket, const struct msghdr *msg, int flags);
 int dpoll_recvmsg(int socket, struct msghdr *msg, int flags);
 
 int dpoll_connect(int socket_fd, const struct sockaddr *addr, socklen_t len);
+
+/// races connects to all `len` addresses in `addrs`, keeping the first one to succeed
+///
+/// behaves like `dpoll_connect` otherwise, only AF_INET addresses are supported
+int dpoll_connect_addrs(int socket_fd, const struct sockaddr *const *addrs, int len);
+
+/// starts connecting every socket in `reqs` and registers it in `dpollfd` for EPOLLOUT
+///
+/// a failed connect is reported as EPOLLOUT, with the error available through SO_ERROR
+///
+/// returns the number of sockets connecting and registered, the `err` of every other request is
+/// set. a socket whose registration failed, e.g. with ENOSPC, keeps connecting unregistered. fails
+/// with EBADF if `dpollfd` is not valid, EINVAL for a negative `len` and EFAULT for a NULL `reqs`
+int dpoll_connect_many(int dpollfd, struct dpoll_connect_req *reqs, int len);
+
+/// registers every socket accepted on `listenfd` in `dpollfd` with `events`, before `dpoll_accept`
+/// returns it, the data of an accepted socket is `data_base + its fd`
+///
+/// a `dpollfd` of -1 stops the registration, it also stops once `dpollfd` was closed. if
+/// registering an accepted socket fails, e.g. with ENOSPC for a dpoll at its max_items, the socket
+/// is still returned, only unregistered
+int dpoll_set_accept_autoreg(int listenfd, int dpollfd, uint32_t events, uint64_t data_base);
+
+/// limits writes on `fd` to `bytes_per_sec` with bursts of up to `burst` bytes, writes over the
+/// budget fail with EWOULDBLOCK and EPOLLOUT is reported once it refills
+///
+/// a `bytes_per_sec` of 0 removes the limit
+int dpoll_set_rate(int fd, uint64_t bytes_per_sec, uint64_t burst);
+
+/// supports SO_ERROR and the options of `dpoll_setsockopt` on dpoll sockets, other
+/// options fail with ENOPROTOOPT
+int dpoll_getsockopt(int socket, int level, int optname, void *optval, socklen_t *optlen);
+
+/// fills `info` with what is known about `fd`, meant for debugging
+///
+/// returns 0, or -1 and sets errno to EFAULT if `info` is null and to EBADF for a negative `fd`. a
+/// socket or dpoll borrowed by the call running, e.g. from an event callback, is not live
+int dpoll_fd_info(int fd, struct dpoll_fd_info *info);
+
+/// has to be called in the child by processes that fork without going through pthread_atfork
+/// handlers, e.g. with a raw clone, see `dpoll_init`
+void dpoll_postfork(void);
+
+/// fills `stats` with the statistics of `dpollfd`
+///
+/// returns 0, or -1 and sets errno
+int dpoll_get_stats(int dpollfd, struct dpoll_stats *stats);
+
+/// fills `latency` with the time the pwaits of `dpollfd` spent in `phase`, one of the
+/// `DPOLL_PHASE_*` constants, summed per pwait
+///
+/// returns 0, or -1 and sets errno to EINVAL for an unknown phase, EFAULT if `latency` is NULL or
+/// EBADF
+int dpoll_get_phase_latency(int dpollfd, int phase, struct dpoll_phase_latency *latency);
+
+/// the cpu the last pwait of `dpollfd` that got completions harvested them on, the one of the
+/// reactor thread with the reactor feature and the one of the thread calling pwait without
+///
+/// returns the cpu, or -1 and sets errno to ENODATA if no pwait got completions yet, or EBADF
+int dpoll_get_harvest_cpu(int dpollfd);
+
+/// pins the reactor thread, which harvests the completions with the reactor feature, to `cpu`, or
+/// lets it run on the cpus it could when it started if `cpu` is -1, whether it started yet or not.
+/// `dpoll_init` pins it to `DPOLL_REACTOR_CPU` if that is set
+///
+/// demikernel does not expose the cores it polls its devices from, with catnip the cpu should be
+/// one local to the NIC that DPDK was not given for an application thread
+///
+/// returns 0, or -1 and sets errno to EINVAL for a cpu the process cannot run on, or to EOPNOTSUPP
+/// without the reactor feature, where the threads calling pwait harvest the completions and have
+/// to be pinned by the application, see `dpoll_get_harvest_cpu`
+int dpoll_set_reactor_cpu(int cpu);
+
+/// fills `stats` with the statistics of the sga pool of the calling thread, see the sga_pool key of
+/// `dpoll_configure`
+///
+/// returns 0, or -1 and sets errno to EFAULT if `stats` is NULL
+int dpoll_get_sga_pool_stats(struct dpoll_sga_pool_stats *stats);
+
+/// sockets of `dpollfd` that complete no operation for longer than `max_idle_ms` are reported as
+/// EPOLLHUP and their operations are not waited on anymore, until they are modified with
+/// EPOLL_CTL_MOD
+///
+/// a `max_idle_ms` <= 0 disables the sweeper
+int dpoll_set_max_idle(int dpollfd, int max_idle_ms);
+
+/// operations of the sockets of `dpollfd` that run for longer than `threshold_ms` are logged as a
+/// warning with their qd, opcode and token, and if `fail` is not 0 they are also failed with
+/// ETIMEDOUT, which the socket reports as EPOLLERR like any other failed operation
+///
+/// the operations are checked as pwait schedules them, a `threshold_ms` <= 0 disables the watchdog
+int dpoll_set_watchdog(int dpollfd, int threshold_ms, int fail);
+
+/// `dpollfd` processes up to `max` demikernel completions per wait, the first one is waited for and
+/// the rest are only polled
+///
+/// fails with EINVAL if `max` <= 0
+int dpoll_set_max_completions(int dpollfd, int max);
+
+/// moves the registrations `fd` has in the other dpolls of this thread to `dpollfd`, which waits on
+/// its running operations from then on
+///
+/// `dpollfd` keeps the events and data it registered `fd` with, otherwise it takes the ones of a
+/// dpoll `fd` is registered in, fails with ENOENT if there is none
+///
+/// fails with EOPNOTSUPP for a kernel `fd` and with EBADF for any other fd that is not a dpoll
+/// socket or a `dpollfd` that is not a dpoll
+int dpoll_migrate(int fd, int dpollfd);
+
+/// hands `fd` over to another thread, which takes it with `dpoll_adopt` and the returned ticket,
+/// `fd` is released on this thread while its running operations move with the socket
+///
+/// fails with EBUSY, leaving `fd` as it was, while `fd` is still registered in a dpoll, auto
+/// registers accepted sockets or, with the reactor feature, has running operations, with
+/// EOPNOTSUPP for a kernel `fd` and with EBADF for any other fd that is not a dpoll socket
+int dpoll_handoff(int fd);
+
+/// takes the socket handed off with `ticket` on this thread, returning its new fd
+///
+/// fails with ENOENT if no socket waits under `ticket`
+int dpoll_adopt(int ticket);
+
+/// a kernel fd that is readable whenever `dpollfd` has events to report, for event loops like
+/// asyncio or libuv to watch in place of blocking in `dpoll_pwait`, which they call with a
+/// timeout of 0 once it is readable
+///
+/// the fd belongs to the dpoll, it must not be closed nor read from
+///
+/// demikernel completions only make it readable with the reactor feature, which harvests them in
+/// the background for the operations a pwait waited on before. the completions go to the thread,
+/// so with several dpolls on one thread all of them are to be waited on once any fd is readable.
+/// without the feature only the kernel fds and the sockets changed by calls of the application
+/// make it readable, so `dpoll_pwait` has to be called periodically as well
+int dpoll_get_wakeup_fd(int dpollfd);
+
+/// makes `dpoll_pwait` on `dpollfd` call `callback` with `ctx` for each event it returns, the
+/// events are still written to the array and counted as usual
+///
+/// the callback runs on the thread of the pwait and may call any dpoll function, including on
+/// `dpollfd`, a NULL `callback` removes it
+int dpoll_set_event_callback(int dpollfd, dpoll_event_callback callback, void *ctx);
+
+/// `dpollfd` completes at most `max` accepts per pwait, the connections of the listeners beyond
+/// that are left for the next one, which keeps a burst of connections from delaying the reads and
+/// writes of the established ones
+///
+/// 0 removes the limit, fails with EINVAL if `max` < 0
+int dpoll_set_max_accepts(int dpollfd, int max);
+
+/// `dpollfd` registers at most `max` sockets, further adds fail with ENOSPC, which keeps one
+/// component of an application from taking all the tokens of demikernel, sockets already
+/// registered over a lowered limit stay registered
+///
+/// 0 removes the limit, fails with EINVAL if `max` < 0
+int dpoll_set_max_items(int dpollfd, int max);
+
+/// replaces the log filter, e.g. `demi_epoll::dpoll::ready_list=trace,warn`, NULL goes back to
+/// DPOLL_LOG, or RUST_LOG if it is not set
+///
+/// fails with EINVAL if `filter` is not valid UTF-8
+int dpoll_set_log(const char *filter);
+
+/// sets the config `key` to `value`, the keys are also read from DPOLL_<KEY> environment variables
+/// by `dpoll_init`:
+/// - send_queue_depth: pushes a socket can have in flight before writes would block, their bytes
+///   are limited by SO_SNDBUF, which is as much as the largest pushes fill by default
+/// - max_idle_ms: the idle budget new dpolls start with, see `dpoll_set_max_idle`
+/// - keepalive, keepalive_idle, keepalive_interval, keepalive_count: the keepalive settings new
+///   sockets start with, see `dpoll_setsockopt`
+/// - auto_pop: the DPOLL_SO_AUTOPOP new sockets start with
+/// - rcvbuf: the received bytes a socket buffers before it stops popping, it resumes once reads
+///   drain them below half of it, the SO_RCVBUF new sockets start with
+/// - max_completions_per_wait: the completions new dpolls process per demikernel wait, see
+///   `dpoll_set_max_completions`, 1 by default
+/// - wait_shards: the shards new dpolls split the operations they wait on into at most, so
+///   polling for more completions looks at a shard of at least 1024 operations instead of all of
+///   them, 8 by default, 1 waits on all of them at once
+/// - max_accepts_per_wait: the accepts new dpolls complete per pwait, see `dpoll_set_max_accepts`,
+///   0, the default, for no limit
+/// - max_items: the sockets new dpolls register at most, see `dpoll_set_max_items`, 0, the
+///   default, for no limit
+/// - watchdog_ms, watchdog_fail: the watchdog new dpolls start with, see `dpoll_set_watchdog`, off
+///   by default
+/// - sga_pool: the sgas per size class the writes of new sockets keep for reuse once their pushes
+///   completed, instead of allocating each from demikernel, 0, the default, for none, see
+///   `dpoll_get_sga_pool_stats`. <size>x<count>, e.g. 4096x1024, keeps count per class and
+///   prewarms the pool of each thread with count sgas for writes of up to size bytes on its first
+///   pooled write, smaller writes take them too if their own class has none cached
+/// - abort_on_panic: 1 aborts the process when a dpoll call panics, after dumping the last
+///   transitions of the dpolls of the thread to stderr, by default the panic is logged and the
+///   call fails with EFAULT, which leaves whatever it was changing in the state it panicked in
+///
+/// applies to sockets and dpolls created afterwards, fails with ENOENT for an unknown key and with
+/// EINVAL for an invalid value, leaving the config untouched
+int dpoll_configure(const char *key, const char *value);
+
+/// writes the value of the config `key` into `buf` as a NUL terminated string
+///
+/// returns the length of the value, or -1 and sets errno, to ERANGE if it does not fit in `len`
+int dpoll_config_get(const char *key, char *buf, size_t len);
+
+/// writes a snapshot of the effective config into `buf` as a NUL terminated string, for bug
+/// reports, one `key=value` line per key of `dpoll_configure`, then `backend=` with the backend
+/// in use and the environment variables read outside of the config that are set, e.g. DPOLL_LOG
+///
+/// `dpoll_init` logs it at info level
+///
+/// returns the length of the snapshot, or -1 and sets errno to ERANGE if it does not fit in
+/// `len`, a null `buf` with a `len` of 0 only returns the length
+int dpoll_dump_config(char *buf, size_t len);
+
+/// the demikernel qd behind the dpoll socket `fd`, for mixing direct demikernel calls with dpoll
+///
+/// returns the qd, or -1 and sets errno to EOPNOTSUPP for kernel fds and to EBADF for dpoll
+/// instances
+int dpoll_get_qd(int fd);
+
+/// makes `dpollfd` wait on `qt`, an operation submitted to demikernel directly, and report its
+/// completion once as EPOLLIN with `data`
+///
+/// `qt` has to be a pending operation nothing else waits on, as dpoll consumes its completion, the
+/// result is kept until it is taken with `dpoll_take_raw`, EEXIST if `qt` was already submitted
+int dpoll_submit_raw(int dpollfd, demi_qtoken_t qt, uint64_t data);
+
+/// the result of an operation passed to `dpoll_submit_raw`
+///
+/// returns 0 and fills `res`, or -1 and sets errno to EWOULDBLOCK if it did not complete yet and
+/// to ENOENT if it was not submitted or already taken, the sga of a pop is owned by the caller
+/// afterwards
+int dpoll_take_raw(int dpollfd, demi_qtoken_t qt, demi_qresult_t *res);
+
+/// hands the dpoll socket `fd` over to the application until `dpoll_attach`, returning its qd for
+/// a TLS library working on memory buffers to push and pop on directly, `dpoll_submit_raw` makes a
+/// dpoll wait on those operations
+///
+/// `fd` stays registered, but starts no pops, writes on it fail with EBUSY and only EPOLLIN for
+/// the bytes received before is reported, which are to be read with `dpoll_read` before popping.
+/// fails with EBUSY while the pushes or the pop of `fd` are still running, the call is to be
+/// repeated once a pwait or read completed them, with ENOTCONN if `fd` is not connected
+int dpoll_detach(int fd);
+
+/// takes `fd` back from the application after `dpoll_detach`, its registrations report events
+/// and its reads and writes go through dpoll again
+///
+/// the operations the application started on the qd have to be completed, fails with EINVAL if
+/// `fd` is not detached
+int dpoll_attach(int fd);
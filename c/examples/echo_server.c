@@ -0,0 +1,138 @@
+// a classic epoll echo server ported to dpoll, every epoll call is replaced by its dpoll_*
+// counterpart and nothing else changes
+//
+// usage: echo_server [port], 12345 by default, built by `make examples`
+
+#include <errno.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include <dpoll.h>
+
+#define MAX_EVENTS 64
+#define BUF_SIZE 4096
+
+struct conn {
+    int fd;
+    // the bytes read but not written back yet
+    char buf[BUF_SIZE];
+    size_t off;
+    size_t len;
+};
+
+static void die(const char *what) {
+    fprintf(stderr, "%s failed: %s\n", what, dpoll_strerror(dpoll_errno()));
+    exit(1);
+}
+
+static void close_conn(int dpfd, struct conn *c) {
+    dpoll_ctl(dpfd, EPOLL_CTL_DEL, c->fd, NULL);
+    dpoll_close(c->fd);
+    free(c);
+}
+
+// waits for EPOLLOUT only while there is something left to write back
+static int watch(int dpfd, struct conn *c) {
+    struct epoll_event ev = {
+        .events = c->len > c->off ? EPOLLOUT : EPOLLIN,
+        .data.ptr = c,
+    };
+    return dpoll_ctl(dpfd, EPOLL_CTL_MOD, c->fd, &ev);
+}
+
+static void accept_all(int dpfd, int listener) {
+    for (;;) {
+        int fd = dpoll_accept(listener, NULL, NULL);
+        if (fd < 0) {
+            if (errno != EAGAIN)
+                perror("dpoll_accept");
+            return;
+        }
+
+        struct conn *c = calloc(1, sizeof(*c));
+        c->fd = fd;
+        struct epoll_event ev = {.events = EPOLLIN, .data.ptr = c};
+        if (dpoll_ctl(dpfd, EPOLL_CTL_ADD, fd, &ev) < 0) {
+            perror("dpoll_ctl");
+            dpoll_close(fd);
+            free(c);
+        }
+    }
+}
+
+// returns -1 once the connection has to be closed
+static int serve(int dpfd, struct conn *c, uint32_t events) {
+    if (events & (EPOLLERR | EPOLLHUP))
+        return -1;
+
+    if (events & EPOLLIN && c->len == c->off) {
+        ssize_t n = dpoll_read(c->fd, c->buf, sizeof(c->buf));
+        if (n == 0 || (n < 0 && errno != EAGAIN))
+            return -1;
+        c->off = 0;
+        c->len = n > 0 ? n : 0;
+    }
+
+    while (c->off < c->len) {
+        ssize_t n = dpoll_write(c->fd, c->buf + c->off, c->len - c->off);
+        if (n < 0) {
+            if (errno != EAGAIN)
+                return -1;
+            break;
+        }
+        c->off += n;
+    }
+
+    return watch(dpfd, c);
+}
+
+int main(int argc, char **argv) {
+    int port = argc > 1 ? atoi(argv[1]) : 12345;
+
+    if (dpoll_init() < 0)
+        die("dpoll_init");
+
+    int dpfd = dpoll_create(0);
+    if (dpfd < 0)
+        die("dpoll_create");
+
+    int listener = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    if (listener < 0)
+        die("dpoll_socket");
+
+    struct sockaddr_in addr = {
+        .sin_family = AF_INET,
+        .sin_port = htons(port),
+        .sin_addr.s_addr = htonl(INADDR_ANY),
+    };
+    if (dpoll_bind(listener, (struct sockaddr *)&addr, sizeof(addr)) < 0)
+        die("dpoll_bind");
+    if (dpoll_listen(listener, 128) < 0)
+        die("dpoll_listen");
+
+    // the listener is told apart from the connections by a NULL data
+    struct epoll_event ev = {.events = EPOLLIN, .data.ptr = NULL};
+    if (dpoll_ctl(dpfd, EPOLL_CTL_ADD, listener, &ev) < 0)
+        die("dpoll_ctl");
+
+    printf("echoing on port %d\n", port);
+
+    struct epoll_event events[MAX_EVENTS];
+    for (;;) {
+        int n = dpoll_pwait(dpfd, events, MAX_EVENTS, -1, NULL);
+        if (n < 0) {
+            if (errno == EINTR)
+                continue;
+            die("dpoll_pwait");
+        }
+
+        for (int i = 0; i < n; i++) {
+            struct conn *c = events[i].data.ptr;
+            if (c == NULL)
+                accept_all(dpfd, listener);
+            else if (serve(dpfd, c, events[i].events) < 0)
+                close_conn(dpfd, c);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+// a minimal HTTP/1.1 keep-alive server on dpoll, answering every request with the same small
+// response, meant as a load target for wrk or similar
+//
+// pipelined requests are answered in order, a request with `Connection: close` closes the
+// connection once its response is written, on SIGINT the statistics of the dpoll are printed
+//
+// usage: http_server [port], 8080 by default, built by `make examples`
+
+// memmem
+#define _GNU_SOURCE
+
+#include <errno.h>
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/uio.h>
+
+#include <dpoll.h>
+
+#define MAX_EVENTS 256
+#define BUF_SIZE 8192
+
+static const char BODY[] = "Hello, world!\n";
+static const char HEADER[] = "HTTP/1.1 200 OK\r\n"
+                             "Content-Type: text/plain\r\n"
+                             "Content-Length: 14\r\n"
+                             "\r\n";
+
+struct conn {
+    int fd;
+    char req[BUF_SIZE];
+    size_t req_len;
+    // responses owed, the first one possibly partially written
+    size_t owed;
+    size_t written;
+    int closing;
+};
+
+static volatile sig_atomic_t interrupted;
+
+static void on_sigint(int sig) {
+    (void)sig;
+    interrupted = 1;
+}
+
+static void die(const char *what) {
+    fprintf(stderr, "%s failed: %s\n", what, dpoll_strerror(dpoll_errno()));
+    exit(1);
+}
+
+static void close_conn(int dpfd, struct conn *c) {
+    dpoll_ctl(dpfd, EPOLL_CTL_DEL, c->fd, NULL);
+    dpoll_close(c->fd);
+    free(c);
+}
+
+// counts the complete requests at the start of the buffer and drops them
+static void parse(struct conn *c) {
+    char *start = c->req;
+    char *end;
+    size_t left = c->req_len;
+    while ((end = memmem(start, left, "\r\n\r\n", 4)) != NULL) {
+        end += 4;
+        if (memmem(start, end - start, "Connection: close", 17) != NULL)
+            c->closing = 1;
+        c->owed++;
+        left -= end - start;
+        start = end;
+    }
+    memmove(c->req, start, left);
+    c->req_len = left;
+}
+
+// writes the owed responses, returns -1 if the connection failed
+static int respond(struct conn *c) {
+    const size_t header_len = sizeof(HEADER) - 1;
+    const size_t len = header_len + sizeof(BODY) - 1;
+    while (c->owed > 0) {
+        struct iovec vecs[2];
+        int count = 0;
+        if (c->written < header_len) {
+            vecs[count].iov_base = (char *)HEADER + c->written;
+            vecs[count++].iov_len = header_len - c->written;
+        }
+        size_t body_off = c->written > header_len ? c->written - header_len : 0;
+        vecs[count].iov_base = (char *)BODY + body_off;
+        vecs[count++].iov_len = sizeof(BODY) - 1 - body_off;
+
+        ssize_t n = dpoll_writev(c->fd, vecs, count);
+        if (n < 0)
+            return errno == EAGAIN ? 0 : -1;
+
+        c->written += n;
+        if (c->written == len) {
+            c->written = 0;
+            c->owed--;
+        }
+    }
+    return 0;
+}
+
+// returns -1 once the connection has to be closed
+static int serve(int dpfd, struct conn *c, uint32_t events) {
+    if (events & (EPOLLERR | EPOLLHUP))
+        return -1;
+
+    while (events & EPOLLIN && !c->closing && c->req_len < sizeof(c->req)) {
+        ssize_t n = dpoll_read(c->fd, c->req + c->req_len, sizeof(c->req) - c->req_len);
+        if (n == 0 || (n < 0 && errno != EAGAIN))
+            return -1;
+        if (n < 0)
+            break;
+        c->req_len += n;
+        parse(c);
+    }
+    // a request header that does not fit
+    if (c->req_len == sizeof(c->req))
+        return -1;
+
+    if (respond(c) < 0)
+        return -1;
+    if (c->owed == 0 && c->closing)
+        return -1;
+
+    struct epoll_event ev = {
+        .events = c->owed > 0 ? EPOLLOUT : EPOLLIN,
+        .data.ptr = c,
+    };
+    return dpoll_ctl(dpfd, EPOLL_CTL_MOD, c->fd, &ev);
+}
+
+static void print_stats(int dpfd) {
+    struct dpoll_stats stats;
+    if (dpoll_get_stats(dpfd, &stats) < 0)
+        die("dpoll_get_stats");
+
+    printf("pwait calls:         %lu\n", (unsigned long)stats.pwait_calls);
+    printf("completions:         %lu\n", (unsigned long)stats.completions);
+    printf("events:              %lu\n", (unsigned long)stats.events);
+    printf("connections:         %lu\n", (unsigned long)stats.items - 1);
+    printf("running operations:  %lu\n", (unsigned long)stats.running_operations);
+    printf("largest batch:       %lu\n", (unsigned long)stats.max_completions_per_wait);
+}
+
+int main(int argc, char **argv) {
+    int port = argc > 1 ? atoi(argv[1]) : 8080;
+
+    if (dpoll_init() < 0)
+        die("dpoll_init");
+
+    int dpfd = dpoll_create(0);
+    if (dpfd < 0)
+        die("dpoll_create");
+    if (dpoll_set_max_completions(dpfd, 32) < 0)
+        die("dpoll_set_max_completions");
+
+    int listener = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    if (listener < 0)
+        die("dpoll_socket");
+
+    struct sockaddr_in addr = {
+        .sin_family = AF_INET,
+        .sin_port = htons(port),
+        .sin_addr.s_addr = htonl(INADDR_ANY),
+    };
+    if (dpoll_bind(listener, (struct sockaddr *)&addr, sizeof(addr)) < 0)
+        die("dpoll_bind");
+    if (dpoll_listen(listener, 1024) < 0)
+        die("dpoll_listen");
+
+    struct epoll_event ev = {.events = EPOLLIN, .data.ptr = NULL};
+    if (dpoll_ctl(dpfd, EPOLL_CTL_ADD, listener, &ev) < 0)
+        die("dpoll_ctl");
+
+    // SIGINT is only delivered while waiting, so it never interrupts a connection half served
+    struct sigaction sa = {.sa_handler = on_sigint};
+    sigaction(SIGINT, &sa, NULL);
+    sigset_t blocked, waiting;
+    sigemptyset(&blocked);
+    sigaddset(&blocked, SIGINT);
+    sigprocmask(SIG_BLOCK, &blocked, &waiting);
+    sigdelset(&waiting, SIGINT);
+
+    printf("serving on port %d\n", port);
+
+    struct epoll_event events[MAX_EVENTS];
+    while (!interrupted) {
+        int n = dpoll_pwait(dpfd, events, MAX_EVENTS, -1, &waiting);
+        if (n < 0) {
+            if (errno == EINTR)
+                continue;
+            die("dpoll_pwait");
+        }
+
+        for (int i = 0; i < n; i++) {
+            struct conn *c = events[i].data.ptr;
+            if (c != NULL) {
+                if (serve(dpfd, c, events[i].events) < 0)
+                    close_conn(dpfd, c);
+                continue;
+            }
+
+            int fd;
+            while ((fd = dpoll_accept(listener, NULL, NULL)) >= 0) {
+                c = calloc(1, sizeof(*c));
+                c->fd = fd;
+                struct epoll_event ev = {.events = EPOLLIN, .data.ptr = c};
+                if (dpoll_ctl(dpfd, EPOLL_CTL_ADD, fd, &ev) < 0) {
+                    dpoll_close(fd);
+                    free(c);
+                }
+            }
+        }
+    }
+
+    print_stats(dpfd);
+    return 0;
+}
@@ -0,0 +1,32 @@
+//! a microbenchmark of finding the item of a completion among the ones registered to a dpoll
+//!
+//! usage: items_lookup [items] [lookups], 10000 items and a million lookups by default, needs the
+//! fuzzing feature for the internals, e.g. `cargo run --release --features fuzzing --example
+//! items_lookup`
+
+use std::{env, process::ExitCode};
+
+use demi_epoll::fuzzing::items_lookup;
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let items = args.next().map_or(Ok(10_000), |a| a.parse());
+    let lookups = args.next().map_or(Ok(1_000_000), |a| a.parse());
+
+    let (Ok(items), Ok(lookups)) = (items, lookups) else {
+        eprintln!("usage: items_lookup [items] [lookups]");
+        return ExitCode::FAILURE;
+    };
+    if items == 0 {
+        eprintln!("at least one item is needed");
+        return ExitCode::FAILURE;
+    }
+
+    let took = items_lookup(items, lookups);
+    println!("{lookups} lookups over {items} items took {took:?}");
+    println!(
+        "{:.1} ns per lookup",
+        took.as_nanos() as f64 / lookups.max(1) as f64
+    );
+    return ExitCode::SUCCESS;
+}
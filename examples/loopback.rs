@@ -0,0 +1,349 @@
+//! connects clients to a listener over loopback, all of them driven by a single dpoll, and checks
+//! every client gets back exactly the bytes it sent through the echoing server side
+//!
+//! reads and writes use random sizes, so short reads, short writes and WOULDBLOCK all happen
+//!
+//! usage: loopback [clients] [bytes per client] [seed], the backend is selected with DPOLL_LIBOS
+//! like for any other app and the listener binds to DPOLL_LOOPBACK_PORT, 12345 if it is not set
+
+use std::{
+    collections::VecDeque,
+    env,
+    io::Error,
+    mem,
+    process::ExitCode,
+    ptr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use demi_epoll::bindings::*;
+use libc::{
+    AF_INET, EAGAIN, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLOUT,
+    INADDR_LOOPBACK, SO_ERROR, SOCK_STREAM, SOL_SOCKET, c_int, epoll_event, sockaddr, sockaddr_in,
+    socklen_t,
+};
+
+const LISTENER: u64 = u64::MAX;
+/// the largest single read or write
+const MAX_CHUNK: usize = 64 * 1024;
+/// how long the run may go without any byte moving before it is considered stuck
+const STALL: Duration = Duration::from_secs(10);
+
+/// xorshift64, good enough to pick sizes
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        return self.0;
+    }
+
+    /// in 1..=max
+    fn size(&mut self, max: usize) -> usize {
+        return (self.next() % max as u64) as usize + 1;
+    }
+}
+
+/// the byte client `id` sends at `off`
+fn pattern(id: usize, off: usize) -> u8 {
+    return (off.wrapping_mul(31) ^ (off >> 8) ^ id.wrapping_mul(97)) as u8;
+}
+
+enum Role {
+    Client {
+        id: usize,
+        connected: bool,
+        sent: usize,
+        verified: usize,
+    },
+    /// the accepted end, echoes everything back
+    Server { pending: VecDeque<u8> },
+}
+
+struct Peer {
+    fd: c_int,
+    role: Role,
+    closed: bool,
+}
+
+struct Run {
+    pol: c_int,
+    peers: Vec<Peer>,
+    rng: Rng,
+    total: usize,
+    done: usize,
+    progress: Instant,
+}
+
+fn fail(what: &str) -> String {
+    return format!("{what} failed: {}", Error::last_os_error());
+}
+
+fn would_block() -> bool {
+    return Error::last_os_error().raw_os_error() == Some(EAGAIN);
+}
+
+fn loopback(port: u16) -> sockaddr_in {
+    let mut addr: sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_family = AF_INET as _;
+    addr.sin_port = port.to_be();
+    addr.sin_addr.s_addr = INADDR_LOOPBACK.to_be();
+    return addr;
+}
+
+fn register(pol: c_int, fd: c_int, data: u64) -> Result<(), String> {
+    let mut ev = epoll_event {
+        events: (EPOLLIN | EPOLLOUT) as u32,
+        u64: data,
+    };
+    if dpoll_ctl(pol, EPOLL_CTL_ADD, fd, &mut ev) != 0 {
+        return Err(fail("dpoll_ctl"));
+    }
+    return Ok(());
+}
+
+impl Run {
+    fn accept(&mut self, listener: c_int) -> Result<(), String> {
+        loop {
+            let fd = dpoll_accept(listener, ptr::null_mut(), ptr::null_mut());
+            if fd < 0 {
+                return if would_block() {
+                    Ok(())
+                } else {
+                    Err(fail("dpoll_accept"))
+                };
+            }
+
+            register(self.pol, fd, self.peers.len() as u64)?;
+            self.peers.push(Peer {
+                fd,
+                role: Role::Server {
+                    pending: VecDeque::new(),
+                },
+                closed: false,
+            });
+        }
+    }
+
+    fn handle(&mut self, idx: usize, events: u32) -> Result<(), String> {
+        let peer = &mut self.peers[idx];
+        if peer.closed {
+            return Ok(());
+        }
+        let fd = peer.fd;
+        let mut buf = vec![0u8; MAX_CHUNK];
+
+        match &mut peer.role {
+            Role::Client {
+                id,
+                connected,
+                sent,
+                verified,
+            } => {
+                if !*connected && events & (EPOLLOUT | EPOLLERR | EPOLLHUP) as u32 != 0 {
+                    let mut err: c_int = 0;
+                    let mut len = mem::size_of::<c_int>() as socklen_t;
+                    let ptr = &mut err as *mut c_int as *mut _;
+                    if dpoll_getsockopt(fd, SOL_SOCKET, SO_ERROR, ptr, &mut len) != 0 {
+                        return Err(fail("dpoll_getsockopt"));
+                    }
+                    if err != 0 {
+                        return Err(format!("connect failed: {}", Error::from_raw_os_error(err)));
+                    }
+                    *connected = true;
+                }
+                if !*connected {
+                    return Ok(());
+                }
+
+                if events & EPOLLOUT as u32 != 0 && *sent < self.total {
+                    let len = self.rng.size(MAX_CHUNK).min(self.total - *sent);
+                    for (off, b) in buf[..len].iter_mut().enumerate() {
+                        *b = pattern(*id, *sent + off);
+                    }
+                    let ret = dpoll_write(fd, buf.as_ptr() as *const _, len);
+                    if ret > 0 {
+                        *sent += ret as usize;
+                        self.progress = Instant::now();
+                    } else if !would_block() {
+                        return Err(fail("dpoll_write"));
+                    }
+                }
+
+                if events & EPOLLIN as u32 != 0 {
+                    let len = self.rng.size(MAX_CHUNK);
+                    let ret = dpoll_read(fd, buf.as_mut_ptr() as *mut _, len);
+                    if ret < 0 && !would_block() {
+                        return Err(fail("dpoll_read"));
+                    }
+                    for (off, b) in buf[..ret.max(0) as usize].iter().enumerate() {
+                        let at = *verified + off;
+                        if at >= *sent || *b != pattern(*id, at) {
+                            return Err(format!("client {id} got a corrupted byte at {at}"));
+                        }
+                    }
+                    if ret > 0 {
+                        *verified += ret as usize;
+                        self.progress = Instant::now();
+                    }
+                }
+
+                if *verified == self.total {
+                    let mut ev = epoll_event { events: 0, u64: 0 };
+                    dpoll_ctl(self.pol, EPOLL_CTL_DEL, fd, &mut ev);
+                    dpoll_close(fd);
+                    peer.closed = true;
+                    self.done += 1;
+                }
+            }
+            Role::Server { pending } => {
+                if events & EPOLLIN as u32 != 0 {
+                    let len = self.rng.size(MAX_CHUNK);
+                    let ret = dpoll_read(fd, buf.as_mut_ptr() as *mut _, len);
+                    if ret > 0 {
+                        pending.extend(&buf[..ret as usize]);
+                    } else if ret < 0 && !would_block() {
+                        return Err(fail("dpoll_read"));
+                    }
+                }
+
+                if events & EPOLLOUT as u32 != 0 && !pending.is_empty() {
+                    let len = self.rng.size(MAX_CHUNK).min(pending.len());
+                    let chunk: Vec<u8> = pending.iter().take(len).copied().collect();
+                    let ret = dpoll_write(fd, chunk.as_ptr() as *const _, len);
+                    if ret > 0 {
+                        pending.drain(..ret as usize);
+                    } else if !would_block() {
+                        return Err(fail("dpoll_write"));
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+fn run(clients: usize, total: usize, seed: u64, port: u16) -> Result<(), String> {
+    if dpoll_init() != 0 {
+        return Err(fail("dpoll_init"));
+    }
+
+    let pol = dpoll_create(0);
+    if pol < 0 {
+        return Err(fail("dpoll_create"));
+    }
+
+    let addr = loopback(port);
+    let addr_ptr = &addr as *const sockaddr_in as *const sockaddr;
+    let addr_len = mem::size_of::<sockaddr_in>() as socklen_t;
+
+    let listener = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    if listener < 0 {
+        return Err(fail("dpoll_socket"));
+    }
+    if dpoll_bind(listener, addr_ptr, addr_len) != 0 {
+        return Err(fail("dpoll_bind"));
+    }
+    if dpoll_listen(listener, clients as c_int) != 0 {
+        return Err(fail("dpoll_listen"));
+    }
+    register(pol, listener, LISTENER)?;
+
+    let mut run = Run {
+        pol,
+        peers: Vec::new(),
+        rng: Rng(seed | 1),
+        total,
+        done: 0,
+        progress: Instant::now(),
+    };
+
+    for id in 0..clients {
+        let fd = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+        if fd < 0 {
+            return Err(fail("dpoll_socket"));
+        }
+        if dpoll_connect(fd, addr_ptr, addr_len) != 0 {
+            return Err(fail("dpoll_connect"));
+        }
+        register(pol, fd, run.peers.len() as u64)?;
+        run.peers.push(Peer {
+            fd,
+            role: Role::Client {
+                id,
+                connected: false,
+                sent: 0,
+                verified: 0,
+            },
+            closed: false,
+        });
+    }
+
+    let mut events = vec![epoll_event { events: 0, u64: 0 }; 64];
+    while run.done < clients {
+        if run.progress.elapsed() > STALL {
+            return Err(format!(
+                "no progress for {STALL:?}, {} of {clients} done",
+                run.done
+            ));
+        }
+
+        let len = events.len() as c_int;
+        let ret = dpoll_pwait(pol, events.as_mut_ptr(), len, 100, ptr::null());
+        if ret < 0 {
+            return Err(fail("dpoll_pwait"));
+        }
+
+        for ev in &events[..ret as usize] {
+            let (data, evs) = (ev.u64, ev.events);
+            if data == LISTENER {
+                run.accept(listener)?;
+            } else {
+                run.handle(data as usize, evs)?;
+            }
+        }
+    }
+
+    for peer in run.peers.iter().filter(|p| !p.closed) {
+        dpoll_close(peer.fd);
+    }
+    dpoll_close(listener);
+    dpoll_close(pol);
+    return Ok(());
+}
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let clients = args.next().map_or(Ok(8), |a| a.parse());
+    let total = args.next().map_or(Ok(1 << 20), |a| a.parse());
+    let seed = args.next().map_or_else(
+        || {
+            Ok(SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64)
+        },
+        |a| a.parse(),
+    );
+    let port = env::var("DPOLL_LOOPBACK_PORT").map_or(Ok(12345), |p| p.parse());
+
+    let (Ok(clients), Ok(total), Ok(seed), Ok(port)) = (clients, total, seed, port) else {
+        eprintln!("usage: loopback [clients] [bytes per client] [seed]");
+        return ExitCode::FAILURE;
+    };
+
+    println!("{clients} clients echoing {total} bytes each, seed {seed}");
+    return match run(clients, total, seed, port) {
+        Ok(()) => {
+            println!("all clients got their bytes back");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{e}, seed {seed}");
+            ExitCode::FAILURE
+        }
+    };
+}
@@ -0,0 +1,241 @@
+//! a TLS echo server, rustls does the TLS of every connection over a `DemiStream`, all of them
+//! driven by a single dpoll
+//!
+//! usage: tls_echo <cert.pem> <key.pem> [port], the backend is selected with DPOLL_LIBOS like for
+//! any other app and the server listens on all addresses on `port`, 12345 if none is given, e.g.
+//! `openssl s_client -connect <addr>:12345` talks to it
+
+use std::{
+    env,
+    io::{self, Error, ErrorKind, Read, Write},
+    mem,
+    process::ExitCode,
+    ptr,
+    sync::Arc,
+};
+
+use demi_epoll::{bindings::*, stream::DemiStream};
+use libc::{
+    AF_INET, EAGAIN, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD, EPOLLIN, EPOLLOUT, INADDR_ANY,
+    SOCK_STREAM, c_int, epoll_event, sockaddr, sockaddr_in, socklen_t,
+};
+use rustls::{
+    ServerConfig, ServerConnection,
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+
+const LISTENER: u64 = u64::MAX;
+/// the most plaintext taken out of a connection at once
+const CHUNK: usize = 16 * 1024;
+
+struct Conn {
+    stream: DemiStream,
+    tls: ServerConnection,
+    /// whether EPOLLOUT is registered, only while rustls has ciphertext the socket did not take
+    out: bool,
+}
+
+fn fail(what: &str) -> String {
+    return format!("{what} failed: {}", Error::last_os_error());
+}
+
+fn config(cert: &str, key: &str) -> Result<Arc<ServerConfig>, String> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("{cert}: {e}"))?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| format!("{key}: {e}"))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| format!("tls config: {e}"))?;
+    return Ok(Arc::new(config));
+}
+
+fn ctl(pol: c_int, op: c_int, fd: c_int, events: c_int, data: u64) -> Result<(), String> {
+    let mut ev = epoll_event {
+        events: events as u32,
+        u64: data,
+    };
+    if dpoll_ctl(pol, op, fd, &mut ev) != 0 {
+        return Err(fail("dpoll_ctl"));
+    }
+    return Ok(());
+}
+
+/// moves ciphertext between the socket and rustls and echoes the plaintext, returns whether the
+/// connection is still open
+fn handle(conn: &mut Conn) -> io::Result<bool> {
+    let mut open = true;
+    let mut plain = [0u8; CHUNK];
+    loop {
+        match conn.tls.read_tls(&mut conn.stream) {
+            Ok(0) => open = false,
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => return Err(e),
+        }
+        conn.tls
+            .process_new_packets()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        loop {
+            match conn.tls.reader().read(&mut plain) {
+                // the peer sent close_notify
+                Ok(0) => {
+                    conn.tls.send_close_notify();
+                    open = false;
+                    break;
+                }
+                Ok(len) => conn.tls.writer().write_all(&plain[..len])?,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        if !open {
+            break;
+        }
+    }
+
+    while conn.tls.wants_write() {
+        match conn.tls.write_tls(&mut conn.stream) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => return Err(e),
+        }
+    }
+    return Ok(open || conn.tls.wants_write());
+}
+
+fn accept(
+    pol: c_int,
+    listener: c_int,
+    config: &Arc<ServerConfig>,
+    conns: &mut Vec<Option<Conn>>,
+) -> Result<(), String> {
+    loop {
+        let fd = dpoll_accept(listener, ptr::null_mut(), ptr::null_mut());
+        if fd < 0 {
+            return if Error::last_os_error().raw_os_error() == Some(EAGAIN) {
+                Ok(())
+            } else {
+                Err(fail("dpoll_accept"))
+            };
+        }
+
+        let stream = DemiStream::from_fd(fd).map_err(|e| format!("stream: {e}"))?;
+        let tls = ServerConnection::new(config.clone()).map_err(|e| format!("tls: {e}"))?;
+        let idx = conns
+            .iter()
+            .position(Option::is_none)
+            .unwrap_or(conns.len());
+        ctl(pol, EPOLL_CTL_ADD, fd, EPOLLIN, idx as u64)?;
+        let conn = Conn {
+            stream,
+            tls,
+            out: false,
+        };
+        match conns.get_mut(idx) {
+            Some(slot) => *slot = Some(conn),
+            None => conns.push(Some(conn)),
+        }
+    }
+}
+
+fn run(cert: &str, key: &str, port: u16) -> Result<(), String> {
+    let config = config(cert, key)?;
+    if dpoll_init() != 0 {
+        return Err(fail("dpoll_init"));
+    }
+
+    let pol = dpoll_create(0);
+    if pol < 0 {
+        return Err(fail("dpoll_create"));
+    }
+
+    let mut addr: sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_family = AF_INET as _;
+    addr.sin_port = port.to_be();
+    addr.sin_addr.s_addr = INADDR_ANY.to_be();
+    let addr_ptr = &addr as *const sockaddr_in as *const sockaddr;
+    let addr_len = mem::size_of::<sockaddr_in>() as socklen_t;
+
+    let listener = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    if listener < 0 {
+        return Err(fail("dpoll_socket"));
+    }
+    if dpoll_bind(listener, addr_ptr, addr_len) != 0 {
+        return Err(fail("dpoll_bind"));
+    }
+    if dpoll_listen(listener, 128) != 0 {
+        return Err(fail("dpoll_listen"));
+    }
+    ctl(pol, EPOLL_CTL_ADD, listener, EPOLLIN, LISTENER)?;
+    println!("listening on port {port}");
+
+    let mut conns: Vec<Option<Conn>> = Vec::new();
+    let mut events = vec![epoll_event { events: 0, u64: 0 }; 64];
+    loop {
+        let len = events.len() as c_int;
+        let ret = dpoll_pwait(pol, events.as_mut_ptr(), len, -1, ptr::null());
+        if ret < 0 {
+            return Err(fail("dpoll_pwait"));
+        }
+
+        for ev in &events[..ret as usize] {
+            let data = ev.u64;
+            if data == LISTENER {
+                accept(pol, listener, &config, &mut conns)?;
+                continue;
+            }
+
+            let slot = &mut conns[data as usize];
+            let Some(conn) = slot else {
+                continue;
+            };
+            let fd = conn.stream.fd();
+            let open = match handle(conn) {
+                Ok(open) => open,
+                Err(e) => {
+                    eprintln!("connection {data}: {e}");
+                    false
+                }
+            };
+            if !open {
+                ctl(pol, EPOLL_CTL_DEL, fd, 0, data)?;
+                // closes the socket
+                *slot = None;
+                continue;
+            }
+
+            // ciphertext left over is written once the socket reports EPOLLOUT
+            let out = conn.tls.wants_write();
+            if out != conn.out {
+                let events = if out { EPOLLIN | EPOLLOUT } else { EPOLLIN };
+                ctl(pol, EPOLL_CTL_MOD, fd, events, data)?;
+                conn.out = out;
+            }
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let (Some(cert), Some(key)) = (args.next(), args.next()) else {
+        eprintln!("usage: tls_echo <cert.pem> <key.pem> [port]");
+        return ExitCode::FAILURE;
+    };
+    let Ok(port) = args.next().map_or(Ok(12345), |p| p.parse()) else {
+        eprintln!("usage: tls_echo <cert.pem> <key.pem> [port]");
+        return ExitCode::FAILURE;
+    };
+
+    return match run(&cert, &key, port) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    };
+}
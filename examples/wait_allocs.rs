@@ -0,0 +1,164 @@
+//! a microbenchmark of the allocations a pwait makes, with many sockets registered to a single
+//! dpoll and none of them ever ready, so every pwait schedules the same operations again
+//!
+//! the token buffer of a dpoll is kept across pwaits, after the first one it should not allocate
+//! anymore for it, which the qtoks_grows statistic printed at the end shows
+//!
+//! usage: wait_allocs [listeners] [waits], the backend is selected with DPOLL_LIBOS like for any
+//! other app and the listeners bind to consecutive ports from DPOLL_BENCH_PORT, 20000 if it is not
+//! set
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env,
+    io::Error,
+    mem,
+    process::ExitCode,
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+use demi_epoll::bindings::*;
+use libc::{
+    AF_INET, EPOLL_CTL_ADD, EPOLLIN, INADDR_LOOPBACK, SOCK_STREAM, c_int, epoll_event, sockaddr,
+    sockaddr_in, socklen_t,
+};
+
+/// counts every allocation and reallocation of the process
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        return unsafe { System.alloc(layout) };
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        return unsafe { System.realloc(ptr, layout, new_size) };
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// pwaits before measuring, so the buffers reach their final size
+const WARMUP: usize = 16;
+
+fn fail(what: &str) -> String {
+    return format!("{what} failed: {}", Error::last_os_error());
+}
+
+fn listen(pol: c_int, port: u16) -> Result<c_int, String> {
+    let mut addr: sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_family = AF_INET as _;
+    addr.sin_port = port.to_be();
+    addr.sin_addr.s_addr = INADDR_LOOPBACK.to_be();
+    let addr_ptr = &addr as *const sockaddr_in as *const sockaddr;
+    let addr_len = mem::size_of::<sockaddr_in>() as socklen_t;
+
+    let fd = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    if fd < 0 {
+        return Err(fail("dpoll_socket"));
+    }
+    if dpoll_bind(fd, addr_ptr, addr_len) != 0 {
+        return Err(fail("dpoll_bind"));
+    }
+    if dpoll_listen(fd, 16) != 0 {
+        return Err(fail("dpoll_listen"));
+    }
+
+    let mut ev = epoll_event {
+        events: EPOLLIN as u32,
+        u64: fd as u64,
+    };
+    if dpoll_ctl(pol, EPOLL_CTL_ADD, fd, &mut ev) != 0 {
+        return Err(fail("dpoll_ctl"));
+    }
+    return Ok(fd);
+}
+
+fn run(listeners: usize, waits: usize, port: u16) -> Result<(), String> {
+    if dpoll_init() != 0 {
+        return Err(fail("dpoll_init"));
+    }
+
+    let pol = dpoll_create(0);
+    if pol < 0 {
+        return Err(fail("dpoll_create"));
+    }
+
+    let mut fds = Vec::with_capacity(listeners);
+    for off in 0..listeners {
+        let port = u16::try_from(port as usize + off).map_err(|_| "out of ports".to_owned())?;
+        fds.push(listen(pol, port)?);
+    }
+
+    let mut events = vec![epoll_event { events: 0, u64: 0 }; 64];
+    let mut pwait = || {
+        let len = events.len() as c_int;
+        if dpoll_pwait(pol, events.as_mut_ptr(), len, 0, ptr::null()) < 0 {
+            return Err(fail("dpoll_pwait"));
+        }
+        return Ok(());
+    };
+
+    for _ in 0..WARMUP {
+        pwait()?;
+    }
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..waits {
+        pwait()?;
+    }
+    let took = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    let mut stats = dpoll_stats::default();
+    if dpoll_get_stats(pol, &mut stats) != 0 {
+        return Err(fail("dpoll_get_stats"));
+    }
+
+    println!("time per wait:        {:?}", took / waits.max(1) as u32);
+    println!(
+        "allocations per wait: {:.2}",
+        allocations as f64 / waits.max(1) as f64
+    );
+    println!("most tokens per wait: {}", stats.max_qtoks);
+    println!("token buffer grown:   {} times", stats.qtoks_grows);
+
+    for fd in fds {
+        dpoll_close(fd);
+    }
+    dpoll_close(pol);
+    return Ok(());
+}
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let listeners = args.next().map_or(Ok(1024), |a| a.parse());
+    let waits = args.next().map_or(Ok(10_000), |a| a.parse());
+    let port = env::var("DPOLL_BENCH_PORT").map_or(Ok(20000), |p| p.parse());
+
+    let (Ok(listeners), Ok(waits), Ok(port)) = (listeners, waits, port) else {
+        eprintln!("usage: wait_allocs [listeners] [waits]");
+        return ExitCode::FAILURE;
+    };
+
+    println!("{waits} pwaits over {listeners} listeners");
+    return match run(listeners, waits, port) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    };
+}
@@ -0,0 +1,38 @@
+//! measures draining the completions of a pwait with the tokens of the dpoll split into a number
+//! of shards, which is what `DEFAULT_WAIT_SHARDS` is picked from
+//!
+//! the wait stands in for demikernel's, looking every token up until it finds a completed one, so
+//! the numbers are the scans sharding saves and not the cost of a wait itself
+//!
+//! usage: wait_shards [items] [ready] [rounds], 65536 items, 256 completions ready per pwait and
+//! 20 rounds by default, needs the fuzzing feature for the internals, e.g. `cargo run --release
+//! --features fuzzing --example wait_shards`
+
+use std::{env, process::ExitCode};
+
+use demi_epoll::fuzzing::wait_shards;
+
+const SHARDS: [usize; 7] = [1, 2, 4, 8, 16, 32, 64];
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let items = args.next().map_or(Ok(65_536), |a| a.parse());
+    let ready = args.next().map_or(Ok(256), |a| a.parse());
+    let rounds = args.next().map_or(Ok(20), |a| a.parse());
+
+    let (Ok(items), Ok(ready), Ok(rounds)) = (items, ready, rounds) else {
+        eprintln!("usage: wait_shards [items] [ready] [rounds]");
+        return ExitCode::FAILURE;
+    };
+
+    println!("{items} items, {ready} completions ready per pwait, 64 completions per wait");
+    for shards in SHARDS {
+        let took = wait_shards(items, ready, 64, shards, rounds);
+        let completions = ready.min(items) * rounds;
+        println!(
+            "{shards:>3} shards: {:.1} us per completion",
+            took.as_nanos() as f64 / completions.max(1) as f64 / 1000.0
+        );
+    }
+    return ExitCode::SUCCESS;
+}
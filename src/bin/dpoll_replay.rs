@@ -0,0 +1,157 @@
+//! feeds a trace recorded with DPOLL_RECORD_FILE back through the C ABI and reports the calls
+//! whose results diverge from the recorded ones, failing at the first call of a binding that is not
+//! recorded
+//!
+//! usage: dpoll_replay <trace>, the calls run on the in-process mock backend whatever DPOLL_LIBOS
+//! says, see src/wrappers/mock.rs, so the replay neither depends on the network nor on a libOS
+
+use std::{collections::HashMap, env, fs::File, io::BufReader, mem, process::ExitCode, ptr};
+
+use demi_epoll::{
+    bindings::*,
+    recorder::{self, Call, Record},
+};
+use libc::{c_int, epoll_event, iovec, sockaddr, sockaddr_in};
+
+/// maps the fds of the trace to the ones of the replay
+#[derive(Default)]
+struct Fds {
+    map: HashMap<i64, c_int>,
+}
+
+impl Fds {
+    fn get(&self, fd: i64) -> c_int {
+        return self.map.get(&fd).copied().unwrap_or(fd as c_int);
+    }
+
+    fn insert(&mut self, recorded: i64, replayed: i64) {
+        if recorded >= 0 && replayed >= 0 {
+            self.map.insert(recorded, replayed as c_int);
+        }
+    }
+}
+
+fn replay(rec: &Record, fds: &mut Fds) -> i64 {
+    let [a, b, c, d] = rec.args;
+    let ret = match rec.call {
+        Call::Socket => dpoll_socket(a as c_int, b as c_int, c as c_int) as i64,
+        Call::Bind | Call::Connect => {
+            let addr = recorder::unpack_addr(b);
+            let addr = &addr as *const sockaddr_in as *const sockaddr;
+            let len = mem::size_of::<sockaddr_in>() as libc::socklen_t;
+            if rec.call == Call::Bind {
+                dpoll_bind(fds.get(a), addr, len) as i64
+            } else {
+                dpoll_connect(fds.get(a), addr, len) as i64
+            }
+        }
+        Call::Listen => dpoll_listen(fds.get(a), b as c_int) as i64,
+        Call::Accept => dpoll_accept(fds.get(a), ptr::null_mut(), ptr::null_mut()) as i64,
+        Call::Close => dpoll_close(fds.get(a)) as i64,
+        Call::Write | Call::Writev => {
+            let buf = vec![0u8; b as usize];
+            let vec = iovec {
+                iov_base: buf.as_ptr() as *mut _,
+                iov_len: buf.len(),
+            };
+            if rec.call == Call::Write {
+                dpoll_write(fds.get(a), buf.as_ptr() as *const _, buf.len()) as i64
+            } else {
+                dpoll_writev(fds.get(a), &vec, 1) as i64
+            }
+        }
+        Call::Read | Call::Readv => {
+            let mut buf = vec![0u8; b as usize];
+            let mut vec = iovec {
+                iov_base: buf.as_mut_ptr() as *mut _,
+                iov_len: buf.len(),
+            };
+            if rec.call == Call::Read {
+                dpoll_read(fds.get(a), buf.as_mut_ptr() as *mut _, buf.len()) as i64
+            } else {
+                dpoll_readv(fds.get(a), &mut vec, 1) as i64
+            }
+        }
+        Call::Create => dpoll_create(a as c_int) as i64,
+        Call::Ctl => {
+            let mut event = epoll_event {
+                events: (b >> 32) as u32,
+                u64: d as u64,
+            };
+            dpoll_ctl(fds.get(a), b as u32 as c_int, fds.get(c), &mut event) as i64
+        }
+        Call::Init => dpoll_init() as i64,
+        // stopped at by main
+        Call::Unrecorded => unreachable!(),
+        Call::Pwait => {
+            let mut events = vec![epoll_event { events: 0, u64: 0 }; b.max(1) as usize];
+            let pol = fds.get(a);
+            dpoll_pwait(
+                pol,
+                events.as_mut_ptr(),
+                b as c_int,
+                c as c_int,
+                ptr::null(),
+            ) as i64
+        }
+    };
+
+    if matches!(rec.call, Call::Socket | Call::Accept | Call::Create) {
+        fds.insert(rec.ret, ret);
+    }
+    return ret;
+}
+
+fn main() -> ExitCode {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: dpoll_replay <trace>");
+        return ExitCode::FAILURE;
+    };
+
+    let records = match File::open(&path).and_then(|f| recorder::read_trace(BufReader::new(f))) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("cannot read {path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut fds = Fds::default();
+    let mut diverged = 0;
+    for (i, rec) in records.iter().enumerate() {
+        if rec.call == Call::Unrecorded {
+            let name = recorder::unpack_name(&rec.args);
+            println!("#{i} {name} is not recorded, the calls after it cannot be replayed");
+            return ExitCode::FAILURE;
+        }
+
+        let ret = replay(rec, &mut fds);
+        let errno = if ret.is_negative() {
+            std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+        } else {
+            0
+        };
+
+        // fds and pwait counts depend on timing, only compare success and errors
+        let same = match rec.call {
+            Call::Socket | Call::Accept | Call::Create | Call::Pwait => {
+                ret.is_negative() == rec.ret.is_negative() && errno == rec.errno
+            }
+            _ => ret == rec.ret && errno == rec.errno,
+        };
+        if !same {
+            diverged += 1;
+            println!(
+                "#{i} {:?}{:?}: recorded {} (errno {}), replayed {ret} (errno {errno})",
+                rec.call, rec.args, rec.ret, rec.errno
+            );
+        }
+    }
+
+    println!("replayed {} calls, {diverged} diverged", records.len());
+    return if diverged == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    };
+}
@@ -1,32 +1,41 @@
-mod utils;
-use env_logger::{Builder, Env};
+// the bindings are the C ABI, taking whatever pointers C passes them. they check what can be
+// checked, null and lengths, and are safe to Rust callers under the same contract as the C ones
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+#[cfg(all(test, feature = "mock"))]
+mod tests;
+pub(crate) mod utils;
 use lazy_static::lazy_static;
 use log::trace;
-use utils::{cast_sockaddr, errno, result_as_errno};
+use utils::{SockaddrOut, c_str, errno, iovecs_len, result_as_errno};
 
+#[cfg(feature = "record")]
+use crate::recorder;
 use crate::{
     buffer::{self as buf, Index},
-    dpoll::{self, Dpoll},
+    config::Config,
+    dpoll::{self, Dpoll, stats::Phase},
+    fork, handoff, logging, operation, registered,
     shared::{Shared, ThreadBuffer, new_thread_buffer},
-    socket::Socket,
+    socket::{AcceptAutoreg, Socket},
+    watchdog::Watchdog,
     wrappers::{
+        backend::{Backend, Capabilities},
+        deadline::Deadline,
         demi,
         errno::{PosixError, PosixResult},
-        sigmask::Sigset,
+        sga_pool,
     },
 };
 use core::slice;
 use libc::{
-    AF_INET, SOCK_STREAM, epoll_event, iovec, sigset_t, size_t, sockaddr, sockaddr_in, socklen_t,
-    ssize_t,
+    AF_INET, EPOLL_CTL_DEL, SOCK_CLOEXEC, SOCK_NONBLOCK, SOCK_STREAM, c_char, epoll_event, iovec,
+    sigset_t, size_t, sockaddr, sockaddr_in, socklen_t, ssize_t,
 };
 use std::{
-    cell::RefCell,
-    env,
-    io::Write,
+    collections::HashMap,
     mem::{self, MaybeUninit},
     os::raw::{c_int, c_void},
-    rc::Rc,
     time::Duration,
 };
 
@@ -35,18 +44,87 @@ thread_local! {
     static SOCKETS: ThreadBuffer<true, Socket> = const { new_thread_buffer() };
 }
 
+/// evaluates `body` like `guarded`, recording the call with the record feature
+///
+/// the arguments are evaluated before `body`, a `return` in `body` only leaves it. the variants
+/// of `recorder::Call` are named after the bindings
+macro_rules! recorded {
+    ($call:ident, [$($arg:expr),*], $body:block) => {{
+        let name = concat!("dpoll_", stringify!($call));
+        #[cfg(feature = "record")]
+        {
+            let args: &[i64] = &[$($arg as i64),*];
+            let ret = utils::guard(name, || $body);
+            recorder::record(recorder::Call::$call, args, ret as i64);
+            ret
+        }
+        #[cfg(not(feature = "record"))]
+        utils::guard(name, || $body)
+    }};
+}
+
+/// evaluates `body`, turning a panic into an error of the binding `name`, see `utils::guard`
+///
+/// a `return` in `body` only leaves it. with the record feature the call leaves an `Unrecorded`
+/// record, the replay cannot know what it did and stops there
+macro_rules! guarded {
+    ($name:literal, $body:block) => {{
+        let ret = utils::guard($name, || $body);
+        #[cfg(feature = "record")]
+        recorder::unrecorded($name);
+        ret
+    }};
+}
+
+/// runs `func` on the socket without holding the borrow of SOCKETS, keeping the window for
+/// conflicting borrows as small as possible
+fn with_socket<R, F>(idx: Index, context: &str, func: F) -> PosixResult<R>
+where
+    F: FnOnce(&mut Socket) -> PosixResult<R>,
+{
+    let soc = SOCKETS.with_borrow(|socs| socs.get(idx).cloned());
+    let soc = soc.ok_or(PosixError::BADF)?;
+    let mut soc = soc.try_borrow_mut(context)?;
+    return func(&mut soc);
+}
+
+/// like `with_socket`, but for DPOLLS
+fn with_dpoll<R, F>(idx: Index, context: &str, func: F) -> PosixResult<R>
+where
+    F: FnOnce(&mut Dpoll) -> PosixResult<R>,
+{
+    let pol = DPOLLS.with_borrow(|polls| polls.get(idx).cloned());
+    let pol = pol.ok_or(PosixError::BADF)?;
+    let mut pol = pol.try_borrow_mut(context)?;
+    return func(&mut pol);
+}
+
+/// dpoll sockets are always non-blocking and never survive an exec, so SOCK_NONBLOCK and
+/// SOCK_CLOEXEC are accepted and change nothing
+///
+/// fails with EAFNOSUPPORT for any domain but AF_INET and with EPROTONOSUPPORT for any type but
+/// SOCK_STREAM
 #[unsafe(no_mangle)]
 pub extern "C" fn dpoll_socket(domain: c_int, r#type: c_int, proto: c_int) -> c_int {
-    trace!("creating new socket");
-    assert!(domain == AF_INET);
-    assert!(r#type == SOCK_STREAM);
-    let soc = match Socket::socket() {
-        Ok(s) => s,
-        Err(e) => return errno(e),
-    };
-    let idx = SOCKETS.with_borrow_mut(|socs| socs.allocate(Shared::new(soc)));
-    trace!("new socket {idx:?} created");
-    return idx.into();
+    return recorded!(Socket, [domain, r#type, proto], {
+        trace!("creating new socket");
+        if fork::is_child() {
+            return errno(PosixError::OPNOTSUPP);
+        }
+        if domain != AF_INET {
+            return errno(PosixError::AFNOSUPPORT);
+        }
+        if r#type & !(SOCK_NONBLOCK | SOCK_CLOEXEC) != SOCK_STREAM {
+            return errno(PosixError::PROTONOSUPPORT);
+        }
+        let soc = match Socket::socket() {
+            Ok(s) => s,
+            Err(e) => return errno(e),
+        };
+        let idx = SOCKETS.with_borrow_mut(|socs| socs.allocate(Shared::new(soc)));
+        trace!("new socket {idx:?} created");
+        return idx.into();
+    });
 }
 
 #[unsafe(no_mangle)]
@@ -55,233 +133,795 @@ pub extern "C" fn dpoll_bind(
     addr: *const sockaddr,
     addr_len: socklen_t,
 ) -> c_int {
-    assert!(addr_len as usize == mem::size_of::<libc::sockaddr_in>());
-    let addr = unsafe { (addr as *const sockaddr_in).as_ref() }.unwrap();
+    return recorded!(
+        Bind,
+        [socket_fd, unsafe {
+            recorder::pack_addr(addr as *const sockaddr_in)
+        }],
+        {
+            if addr_len as usize != mem::size_of::<libc::sockaddr_in>() {
+                return errno(PosixError::INVAL);
+            }
+            let Some(addr) = (unsafe { (addr as *const sockaddr_in).as_ref() }) else {
+                return errno(PosixError::FAULT);
+            };
+
+            let idx = match socket_index(socket_fd) {
+                Ok(idx) => idx,
+                Err(e) => return errno(e),
+            };
+            trace!("bind on {idx:?}");
+
+            let res = with_socket(idx, "bind", |soc| soc.bind(addr));
+
+            return result_as_errno(res);
+        }
+    );
+}
 
-    let idx = buf::Index::from(socket_fd);
-    trace!("bind on {idx:?}");
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_listen(socket_fd: c_int, backlog: c_int) -> c_int {
+    return recorded!(Listen, [socket_fd, backlog], {
+        let idx = match socket_index(socket_fd) {
+            Ok(idx) => idx,
+            Err(e) => return errno(e),
+        };
+        trace!("listen on {idx:?}");
 
-    let res = SOCKETS.with_borrow(|socs| socs.get(idx).unwrap().borrow_mut().bind(addr));
+        let res = with_socket(idx, "listen", |soc| soc.listen(backlog));
 
-    return result_as_errno(res);
+        return result_as_errno(res);
+    });
 }
 
+/// SHUT_RD or SHUT_RDWR on a listener stops it accepting until it listens again, so a server can
+/// drain the connections it accepted before closing it: the connections not accepted yet are
+/// closed and the listener is not reported as readable anymore
+///
+/// SHUT_WR leaves a listener as it is, connected dpoll sockets fail with EOPNOTSUPP as demikernel
+/// cannot half close a connection and unconnected ones with ENOTCONN, kernel fds are passed
+/// through to shutdown
 #[unsafe(no_mangle)]
-pub extern "C" fn dpoll_listen(socket_fd: c_int, backlog: c_int) -> c_int {
-    let idx = buf::Index::from(socket_fd);
-    trace!("listen on {idx:?}");
+pub extern "C" fn dpoll_shutdown(socket_fd: c_int, how: c_int) -> c_int {
+    return guarded!("dpoll_shutdown", {
+        let idx = match socket_or_kernel(socket_fd) {
+            Ok(Some(idx)) => idx,
+            Ok(None) => return unsafe { libc::shutdown(socket_fd, how) },
+            Err(e) => return errno(e),
+        };
+        trace!("shutdown {how} on {idx:?}");
+
+        let res = with_socket(idx, "shutdown", |soc| soc.shutdown(how));
+
+        return result_as_errno(res);
+    });
+}
 
-    let res = SOCKETS.with_borrow(|socs| socs.get(idx).unwrap().borrow_mut().listen(backlog));
+/// creates `n` sockets listening on `addr` with `backlog`, e.g. one per worker thread, and writes
+/// their fds to `fds`, which has to have room for `n`
+///
+/// without SO_REUSEPORT in demikernel the listeners cannot share the address, so they are bound to
+/// `n` consecutive ports starting at the one of `addr`, or to an ephemeral port each if it is 0
+///
+/// returns 0, or -1 and sets errno with none of the listeners left open, fails with EINVAL if `n`
+/// <= 0 or the ports would run past 65535
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_listen_sharded(
+    addr: *const sockaddr,
+    addr_len: socklen_t,
+    n: c_int,
+    backlog: c_int,
+    fds: *mut c_int,
+) -> c_int {
+    return guarded!("dpoll_listen_sharded", {
+        if fork::is_child() {
+            return errno(PosixError::OPNOTSUPP);
+        }
+        if addr_len as usize != mem::size_of::<sockaddr_in>() {
+            return errno(PosixError::INVAL);
+        }
+        let Some(addr) = (unsafe { (addr as *const sockaddr_in).as_ref() }) else {
+            return errno(PosixError::FAULT);
+        };
+        if fds.is_null() {
+            return errno(PosixError::FAULT);
+        }
+        let Ok(n) = u16::try_from(n) else {
+            return errno(PosixError::INVAL);
+        };
+        let base = u16::from_be(addr.sin_port);
+        let shared = Backend::current()
+            .capabilities()
+            .contains(Capabilities::REUSEPORT);
+        if n == 0 || (base != 0 && !shared && base.checked_add(n - 1).is_none()) {
+            return errno(PosixError::INVAL);
+        }
+        trace!("{n} listeners sharding port {base}");
+
+        let mut listeners = Vec::with_capacity(n.into());
+        for shard in 0..n {
+            let mut addr = *addr;
+            if base != 0 && !shared {
+                addr.sin_port = (base + shard).to_be();
+            }
+            let res = Socket::socket().and_then(|mut soc| {
+                let res = soc.bind(&addr).and_then(|()| soc.listen(backlog));
+                listeners.push(soc);
+                return res;
+            });
+
+            if let Err(e) = res {
+                trace!("listener {shard} failed with {e:?}");
+                for mut soc in listeners {
+                    let _ = soc.close();
+                }
+                return errno(e);
+            }
+        }
 
-    return result_as_errno(res);
+        let fds = unsafe { slice::from_raw_parts_mut(fds, n.into()) };
+        SOCKETS.with_borrow_mut(|socs| {
+            for (fd, soc) in fds.iter_mut().zip(listeners) {
+                *fd = socs.allocate(Shared::new(soc)).into();
+            }
+        });
+        return 0;
+    });
 }
 
+/// writes the address of the peer to `addr` if it is not NULL, truncated to `*addr_len` bytes, and
+/// sets `*addr_len` to its full length
 #[unsafe(no_mangle)]
 pub extern "C" fn dpoll_accept(
     socket_fd: c_int,
     addr: *mut sockaddr,
     addr_len: *mut socklen_t,
 ) -> c_int {
-    let addr = cast_sockaddr(addr, addr_len);
-    let idx = buf::Index::from(socket_fd);
-
-    trace!("accept on {idx:?}");
-    let new: PosixResult<Index> = SOCKETS.with_borrow_mut(|socs| {
-        let res = socs.get_mut(idx).unwrap().borrow_mut().accept(addr);
-        let soc = res?;
+    return recorded!(Accept, [socket_fd], {
+        let addr = match SockaddrOut::new(addr, addr_len) {
+            Ok(addr) => addr,
+            Err(e) => return errno(e),
+        };
+        let idx = match socket_index(socket_fd) {
+            Ok(idx) => idx,
+            Err(e) => return errno(e),
+        };
+
+        trace!("accept on {idx:?}");
+        let res = with_socket(idx, "accept", |soc| {
+            return soc.accept().map(|new| (new, soc.accept_autoreg()));
+        });
+        let (new, autoreg) = match res {
+            Ok(res) => res,
+            Err(e) => return errno(e),
+        };
+        if let Some(addr) = addr {
+            addr.write(&new.addr.unwrap());
+        }
+        let new = Shared::new(new);
+        let new_idx = SOCKETS.with_borrow_mut(|socs| socs.allocate(new.clone()));
+        trace!("accepted {new_idx:?}");
+
+        if let Some(autoreg) = autoreg {
+            let fd: c_int = new_idx.into();
+            let data = autoreg.data_base.wrapping_add(fd as u64);
+            let op = dpoll::Operation::add(new, autoreg.evs, data);
+            let res = with_dpoll(autoreg.dpoll, "accept", |pol| pol.ctl(op));
+            if let Err(e) = res {
+                // the connection is still handed out, only unregistered, and the registration
+                // only stops for good once the dpoll is gone
+                log::warn!(
+                    "registering {new_idx:?} in {:?} failed with {e:?}",
+                    autoreg.dpoll
+                );
+                if e == PosixError::BADF {
+                    let _ = with_socket(idx, "accept", |soc| soc.set_accept_autoreg(None));
+                }
+            }
+        }
 
-        return Ok(socs.allocate(Shared::new(soc)));
+        return new_idx.into();
     });
-    trace!("accepted {new:?}");
+}
+
+/// the dpolls of the thread, for the ones that are not borrowed right now, e.g. by a pwait
+/// running on another thread, `func` is skipped
+fn for_each_dpoll<F>(mut func: F)
+where
+    F: FnMut(&mut Dpoll),
+{
+    let pols: Vec<_> = DPOLLS.with_borrow(|polls| polls.iter().map(|(_, p)| p.clone()).collect());
+    for pol in pols {
+        match pol.try_borrow_mut_quiet() {
+            Some(mut pol) => func(&mut pol),
+            None => trace!("skipping a busy dpoll"),
+        }
+    }
+}
+
+/// deletes `fd` from the kernel epolls of the dpolls, which might otherwise keep reporting it
+/// through a dup, and closes it
+fn close_kernel(fd: c_int) -> PosixResult<()> {
+    // the epolls are shared with the parent
+    if !fork::is_child() {
+        for_each_dpoll(|pol| pol.forget_kernel_fd(fd));
+    }
+
+    if unsafe { libc::close(fd) }.is_negative() {
+        return PosixError::from_errno();
+    }
+    return Ok(());
+}
 
-    return match new {
-        Ok(idx) => idx.into(),
-        Err(e) => errno(e),
+/// closes the demikernel queue of the socket and unregisters it from the dpolls right away,
+/// instead of on their next pwait
+///
+/// a listener closes the connections it completed and cancels its running accept, the connection
+/// the accept still completes with is closed once it arrives
+fn close_socket(idx: Index) -> PosixResult<()> {
+    let soc = SOCKETS
+        .with_borrow_mut(|socs| socs.take(idx))
+        .ok_or(PosixError::BADF)?;
+    // the demikernel queue belongs to the parent
+    if fork::is_child() {
+        return Ok(());
+    }
+
+    let (res, qd) = {
+        let mut soc = soc.try_borrow_mut("close")?;
+        let res = soc.close().map_err(PosixError::from);
+        soc.reap_tombstones();
+        (res, soc.soc.qd)
     };
+
+    for_each_dpoll(|pol| {
+        if pol.registration(qd).is_some() {
+            let _ = pol.ctl(dpoll::Operation::del(qd));
+        }
+    });
+    return res;
 }
 
+/// frees the dpoll and stops the dpolls it is nested in from waiting on it, its sockets are
+/// unregistered once the last reference to it is gone, see `Dpoll::drop`
+fn close_dpoll(idx: Index) -> PosixResult<()> {
+    let pol = DPOLLS
+        .with_borrow_mut(|polls| polls.take(idx))
+        .ok_or(PosixError::BADF)?;
+    if !fork::is_child() {
+        for_each_dpoll(|parent| parent.unnest(&pol));
+    }
+    return Ok(());
+}
+
+/// closes any kind of fd, like close(2) the fd is released even if closing what is behind it fails,
+/// closing it again fails with EBADF
+///
+/// kernel fds are deleted from the dpolls first, dpoll sockets are unregistered from them and
+/// dpolls are no longer waited on by the dpolls they are nested in, so none of them is reported
+/// by a pwait starting after its close returned, events a pwait collected before are still
+/// delivered
 #[unsafe(no_mangle)]
 pub extern "C" fn dpoll_close(fd: c_int) -> c_int {
-    trace!("closing {fd}");
-    let idx: buf::Index = fd.into();
-
-    let res = if !idx.is_dpoll() {
-        unsafe { libc::close(fd) }
-    } else {
-        if idx.is_socket() {
-            SOCKETS.with_borrow_mut(|socs| socs.take(idx).borrow_mut().close());
+    return recorded!(Close, [fd], {
+        trace!("closing {fd}");
+        let idx = match Index::try_from(fd) {
+            Ok(idx) => idx,
+            Err(e) => return errno(e),
+        };
+
+        let res = if !idx.is_dpoll() {
+            close_kernel(fd)
+        } else if idx.is_socket() {
+            close_socket(idx)
         } else {
-            DPOLLS.with_borrow_mut(|polls| polls.free(idx))
-        }
-        0
-    };
+            close_dpoll(idx)
+        };
 
-    trace!("closed {fd}, ret: {res}");
-    return res;
+        trace!("closed {fd}, ret: {res:?}");
+        return result_as_errno(res);
+    });
 }
 
+/// once a write or read failed with an error that ended the connection, e.g. ECONNRESET, the next
+/// write fails with that error unless SO_ERROR took it already and every later one with EPIPE,
+/// like write(2). no SIGPIPE is ever raised, as if every write was sent with MSG_NOSIGNAL
 #[unsafe(no_mangle)]
 pub extern "C" fn dpoll_write(socket_fd: c_int, buf: *const c_void, len: size_t) -> ssize_t {
-    assert!(!buf.is_null());
-    let idx: buf::Index = socket_fd.into();
+    return recorded!(Write, [socket_fd, len], {
+        if buf.is_null() {
+            return errno(PosixError::FAULT) as isize;
+        }
+        let idx = match socket_or_kernel(socket_fd) {
+            Ok(Some(idx)) => idx,
+            Ok(None) => return unsafe { libc::write(socket_fd, buf, len) },
+            Err(e) => return errno(e) as isize,
+        };
 
-    trace!("writing {len} bytes to {idx:?}");
+        trace!("writing {len} bytes to {idx:?}");
 
-    if !idx.is_dpoll() {
-        return unsafe { libc::write(socket_fd, buf, len) };
-    }
+        if len == 0 {
+            return 0;
+        }
 
-    if len == 0 {
-        return 0;
-    }
+        let buf =
+            unsafe { std::ptr::slice_from_raw_parts(buf as *const u8, len).as_ref() }.unwrap();
+        let res = with_socket(idx, "write", |soc| soc.write(buf));
 
-    let buf = unsafe { std::ptr::slice_from_raw_parts(buf as *const u8, len).as_ref() }.unwrap();
-    let res = SOCKETS.with_borrow_mut(|socs| socs.get(idx).unwrap().borrow_mut().write(buf));
+        trace!("write res: {res:?}");
+        return match res {
+            Ok(len) => len.try_into().unwrap(),
+            Err(e) => errno(e) as isize,
+        };
+    });
+}
 
-    trace!("write res: {res:?}");
-    return match res {
-        Ok(len) => len.try_into().unwrap(),
-        Err(e) => errno(e) as isize,
-    };
+/// blocks until the writes queued on `socket_fd` completed or `timeout_ms` passed, a negative
+/// timeout blocks until they completed, returns the number of bytes left unflushed
+///
+/// kernel fds have nothing queued in dpoll and return 0 right away, a write that failed meanwhile
+/// fails the flush with its error, like the next write would
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_flush(socket_fd: c_int, timeout_ms: c_int) -> ssize_t {
+    return guarded!("dpoll_flush", {
+        let idx = match socket_or_kernel(socket_fd) {
+            Ok(Some(idx)) => idx,
+            Ok(None) => return 0,
+            Err(e) => return errno(e) as isize,
+        };
+        trace!("flushing {idx:?} for {timeout_ms}ms");
+
+        let timeout = if timeout_ms.is_negative() {
+            None
+        } else {
+            Some(Duration::from_millis(timeout_ms as u64))
+        };
+        let deadline = Deadline::after(timeout);
+
+        return match with_socket(idx, "flush", |soc| soc.flush(deadline)) {
+            Ok(left) => left.try_into().unwrap(),
+            Err(e) => errno(e) as isize,
+        };
+    });
 }
 
+/// like read(2), it returns 0 once the peer shut its side down and everything it sent before was
+/// read, and fails with EWOULDBLOCK only while nothing arrived on a connection still open
 #[unsafe(no_mangle)]
 pub extern "C" fn dpoll_read(socket_fd: c_int, buf: *mut c_void, len: size_t) -> ssize_t {
-    assert!(!buf.is_null());
-    let idx: buf::Index = socket_fd.into();
+    return recorded!(Read, [socket_fd, len], {
+        if buf.is_null() {
+            return errno(PosixError::FAULT) as isize;
+        }
+        let idx = match socket_or_kernel(socket_fd) {
+            Ok(Some(idx)) => idx,
+            Ok(None) => return unsafe { libc::read(socket_fd, buf, len) },
+            Err(e) => return errno(e) as isize,
+        };
 
-    trace!("reading {len} bytes to {idx:?}");
+        trace!("reading {len} bytes to {idx:?}");
 
-    if !idx.is_dpoll() {
-        return unsafe { libc::read(socket_fd, buf, len) };
-    }
+        if len == 0 {
+            return 0;
+        }
 
-    if len == 0 {
-        return 0;
-    }
+        let buf = unsafe {
+            std::ptr::slice_from_raw_parts_mut(buf as *mut MaybeUninit<u8>, len).as_mut()
+        }
+        .unwrap();
 
-    let buf =
-        unsafe { std::ptr::slice_from_raw_parts_mut(buf as *mut MaybeUninit<u8>, len).as_mut() }
-            .unwrap();
+        let res = with_socket(idx, "read", |soc| soc.read(buf));
 
-    let res = SOCKETS.with_borrow_mut(|socs| socs.get(idx).unwrap().borrow_mut().read(buf));
+        trace!("read res: {res:?}");
+        return match res {
+            Ok(len) => len.try_into().unwrap(),
+            Err(e) => errno(e) as isize,
+        };
+    });
+}
 
-    trace!("read res: {res:?}");
-    return match res {
-        Ok(len) => len.try_into().unwrap(),
-        Err(e) => errno(e) as isize,
-    };
+/// registers `n` buffers of this thread for `dpoll_read_fixed`, the memory has to stay valid until
+/// `dpoll_unregister_buffers`
+///
+/// none of the demikernel libOSes can pop into the memory of the application, so the data is
+/// still copied once, like by `dpoll_read`
+///
+/// fails with EBUSY if buffers are registered already and with EINVAL if `n` is not within 1 and
+/// 16384 or one of the buffers is NULL or empty
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_register_buffers(bufs: *const iovec, n: c_int) -> c_int {
+    return guarded!("dpoll_register_buffers", {
+        trace!("registering {n} buffers");
+        if bufs.is_null() {
+            return errno(PosixError::FAULT);
+        }
+        let Ok(n) = usize::try_from(n) else {
+            return errno(PosixError::INVAL);
+        };
+
+        let bufs = unsafe { slice::from_raw_parts(bufs, n) };
+        return result_as_errno(registered::register(bufs));
+    });
 }
 
+/// fails with ENXIO if no buffers are registered
 #[unsafe(no_mangle)]
-pub extern "C" fn dpoll_writev(
+pub extern "C" fn dpoll_unregister_buffers() -> c_int {
+    return guarded!("dpoll_unregister_buffers", {
+        return result_as_errno(registered::unregister());
+    });
+}
+
+/// like `dpoll_read`, into `len` bytes of the registered buffer `buf_index` starting `offset`
+/// bytes in
+///
+/// fails with ENXIO if no buffers are registered and with EINVAL if the range does not fit into
+/// the buffer
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_read_fixed(
     socket_fd: c_int,
-    vecs: *const iovec,
-    iovec_count: c_int,
+    buf_index: c_int,
+    offset: size_t,
+    len: size_t,
 ) -> ssize_t {
-    assert!(!vecs.is_null());
-    let idx: buf::Index = socket_fd.into();
+    return guarded!("dpoll_read_fixed", {
+        let Ok(buf_index) = usize::try_from(buf_index) else {
+            return errno(PosixError::INVAL) as isize;
+        };
+
+        return match registered::range(buf_index, offset, len) {
+            Ok(buf) => dpoll_read(socket_fd, buf, len),
+            Err(e) => errno(e) as isize,
+        };
+    });
+}
 
-    trace!("writev of {iovec_count} to {idx:?}");
+/// like `dpoll_read`, but only returns data that already arrived, it never polls demikernel nor
+/// starts a new read, which is left to the next `dpoll_read` or `dpoll_pwait`
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_try_read(socket_fd: c_int, buf: *mut c_void, len: size_t) -> ssize_t {
+    return guarded!("dpoll_try_read", {
+        if buf.is_null() {
+            return errno(PosixError::FAULT) as isize;
+        }
+        let idx = match socket_or_kernel(socket_fd) {
+            Ok(Some(idx)) => idx,
+            Ok(None) => return unsafe { libc::read(socket_fd, buf, len) },
+            Err(e) => return errno(e) as isize,
+        };
+        trace!("try reading {len} bytes from {idx:?}");
+        if len == 0 {
+            return 0;
+        }
 
-    if !idx.is_dpoll() {
-        return unsafe { libc::writev(socket_fd, vecs, iovec_count) };
-    }
+        let buf = unsafe { std::slice::from_raw_parts_mut(buf as *mut MaybeUninit<u8>, len) };
+        let res = with_socket(idx, "try_read", |soc| soc.try_read(buf));
 
-    if iovec_count == 0 || unsafe { *vecs }.iov_len == 0 {
-        return 0
-    }
+        trace!("try read res: {res:?}");
+        return match res {
+            Ok(len) => len.try_into().unwrap(),
+            Err(e) => errno(e) as isize,
+        };
+    });
+}
 
-    let vecs =
-        unsafe { std::ptr::slice_from_raw_parts(vecs, iovec_count.try_into().unwrap()).as_ref() }
+/// like `dpoll_write`, but fails with EWOULDBLOCK instead of polling demikernel when the send
+/// queue is full, only completions seen by `dpoll_pwait` make room
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_try_write(socket_fd: c_int, buf: *const c_void, len: size_t) -> ssize_t {
+    return guarded!("dpoll_try_write", {
+        if buf.is_null() {
+            return errno(PosixError::FAULT) as isize;
+        }
+        let idx = match socket_or_kernel(socket_fd) {
+            Ok(Some(idx)) => idx,
+            Ok(None) => return unsafe { libc::write(socket_fd, buf, len) },
+            Err(e) => return errno(e) as isize,
+        };
+        trace!("try writing {len} bytes to {idx:?}");
+        if len == 0 {
+            return 0;
+        }
+
+        let buf = unsafe { std::slice::from_raw_parts(buf as *const u8, len) };
+        let res = with_socket(idx, "try_write", |soc| soc.try_write(buf));
+
+        trace!("try write res: {res:?}");
+        return match res {
+            Ok(len) => len.try_into().unwrap(),
+            Err(e) => errno(e) as isize,
+        };
+    });
+}
+
+/// like `dpoll_write`, the iovecs of up to 64 KiB go out in a single push however many there
+/// are, longer writes are split into pushes of 64 KiB, unlike writev(2) more than IOV_MAX iovecs
+/// are taken
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_writev(
+    socket_fd: c_int,
+    vecs: *const iovec,
+    iovec_count: c_int,
+) -> ssize_t {
+    return recorded!(
+        Writev,
+        [socket_fd, unsafe {
+            recorder::iovecs_total(vecs, iovec_count)
+        }],
+        {
+            let idx = match socket_or_kernel(socket_fd) {
+                Ok(Some(idx)) => idx,
+                Ok(None) => return unsafe { libc::writev(socket_fd, vecs, iovec_count) },
+                Err(e) => return errno(e) as isize,
+            };
+
+            trace!("writev of {iovec_count} to {idx:?}");
+
+            // empty iovecs are skipped, so only an empty total means there is nothing to do
+            match iovecs_len(vecs, iovec_count) {
+                Ok(0) => return 0,
+                Ok(_) => {}
+                Err(e) => return errno(e) as isize,
+            }
+
+            let vecs = unsafe {
+                std::ptr::slice_from_raw_parts(vecs, iovec_count.try_into().unwrap()).as_ref()
+            }
             .unwrap();
 
-    let res = SOCKETS.with_borrow_mut(|socs| socs.get(idx).unwrap().borrow_mut().writev(vecs));
+            let res = with_socket(idx, "writev", |soc| soc.writev(vecs));
 
-    trace!("writev res: {res:?}");
-    return match res {
-        Ok(len) => len.try_into().unwrap(),
-        Err(e) => errno(e) as isize,
-    };
+            trace!("writev res: {res:?}");
+            return match res {
+                Ok(len) => len.try_into().unwrap(),
+                Err(e) => errno(e) as isize,
+            };
+        }
+    );
 }
 
+/// fills the iovecs with all the data received so far, not only with that of one demikernel pop
+/// like `dpoll_read`, unlike readv(2) more than IOV_MAX iovecs are taken
 #[unsafe(no_mangle)]
 pub extern "C" fn dpoll_readv(
     socket_fd: c_int,
     vecs: *mut iovec,
     iovec_count: c_int,
 ) -> ssize_t {
-    assert!(!vecs.is_null());
-    let idx: buf::Index = socket_fd.into();
+    return recorded!(
+        Readv,
+        [socket_fd, unsafe {
+            recorder::iovecs_total(vecs, iovec_count)
+        }],
+        {
+            let idx = match socket_or_kernel(socket_fd) {
+                Ok(Some(idx)) => idx,
+                Ok(None) => return unsafe { libc::readv(socket_fd, vecs, iovec_count) },
+                Err(e) => return errno(e) as isize,
+            };
+
+            trace!("readv of {iovec_count} to {idx:?}");
+
+            // empty iovecs are skipped, so only an empty total means there is nothing to do
+            match iovecs_len(vecs, iovec_count) {
+                Ok(0) => return 0,
+                Ok(_) => {}
+                Err(e) => return errno(e) as isize,
+            }
+
+            let vecs = unsafe {
+                std::ptr::slice_from_raw_parts_mut(vecs, iovec_count.try_into().unwrap()).as_mut()
+            }
+            .unwrap();
 
-    trace!("readv of {iovec_count} to {idx:?}");
+            let res = with_socket(idx, "readv", |soc| soc.readv(vecs));
 
-    if !idx.is_dpoll() {
-        return unsafe { libc::readv(socket_fd, vecs, iovec_count) };
-    }
+            trace!("readv res: {res:?}");
+            return match res {
+                Ok(len) => len.try_into().unwrap(),
+                Err(e) => errno(e) as isize,
+            };
+        }
+    );
+}
 
-    if iovec_count == 0 || unsafe { *vecs }.iov_len == 0 {
-        return 0
-    }
+/// initializes demikernel and registers the fork handlers, dpoll fds are not usable in a forked
+/// child
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_init() -> c_int {
+    return recorded!(Init, [], {
+        // first, so the failures below are logged
+        logging::init();
+        if result_as_errno(demi::meta_init(Backend::from_env())).is_negative() {
+            return -1;
+        }
 
-    let vecs = unsafe {
-        std::ptr::slice_from_raw_parts_mut(vecs, iovec_count.try_into().unwrap()).as_mut()
-    }
-    .unwrap();
+        if result_as_errno(fork::register_handlers()).is_negative() {
+            return -1;
+        }
 
-    let res = SOCKETS.with_borrow_mut(|socs| socs.get(idx).unwrap().borrow_mut().readv(vecs));
+        #[cfg(feature = "record")]
+        if result_as_errno(recorder::install()).is_negative() {
+            return -1;
+        }
 
-    trace!("readv res: {res:?}");
-    return match res {
-        Ok(len) => len.try_into().unwrap(),
-        Err(e) => errno(e) as isize,
-    };
+        if let Err(e) = Config::load_env() {
+            log::error!("{e}");
+            return errno(e.into());
+        }
+
+        #[cfg(feature = "metrics")]
+        if result_as_errno(crate::metrics::install()).is_negative() {
+            return -1;
+        }
+
+        #[cfg(feature = "faults")]
+        if result_as_errno(crate::wrappers::faults::install()).is_negative() {
+            return -1;
+        }
+
+        #[cfg(feature = "reactor")]
+        if result_as_errno(crate::wrappers::reactor::install()).is_negative() {
+            return -1;
+        }
+
+        dpoll::history::install_panic_hook();
+        log::info!(
+            "config: {}",
+            Config::current().dump().trim_end().replace('\n', " ")
+        );
+
+        return 0;
+    });
 }
 
+/// closes every demikernel queue of the process, of all its threads, to be called right before
+/// an exec, returns how many were closed
+///
+/// the exec does not keep any demikernel state, so queues left open would only leak what is
+/// behind them, e.g. DPDK queues and hugepages. from then on every dpoll fd fails with EBADF, as
+/// in a forked child, except for `dpoll_close`, which is left to release the fds if the exec fails
 #[unsafe(no_mangle)]
-pub extern "C" fn dpoll_init() -> c_int {
-    if unsafe { result_as_errno(demi::meta_init()) }.is_negative() {
-        return -1;
-    }
+pub extern "C" fn dpoll_prepare_exec() -> c_int {
+    return guarded!("dpoll_prepare_exec", {
+        return fork::prepare_exec().try_into().unwrap_or(c_int::MAX);
+    });
+}
 
-    let mut builder = Builder::new();
-    if let Ok(log) = env::var("DPOLL_LOG") {
-        builder.parse_filters(&log);
-    } else {
-        builder.parse_default_env();
-    }
+/// `dpoll_prepare_exec` followed by execve(2), only returns if the exec failed
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_execve(
+    path: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> c_int {
+    return guarded!("dpoll_execve", {
+        dpoll_prepare_exec();
+        return unsafe { libc::execve(path, argv, envp) };
+    });
+}
+
+/// the version of the ABI described by dpoll.h, bumped whenever a function or struct changes
+/// incompatibly
+pub const DPOLL_ABI_VERSION: u32 = 1;
 
-    builder.format(|buf, record| {
-        let ts = buf.timestamp();
-        writeln!(
-            buf,
-            "[{ts} {level} {file}:{line} {path}] {args}",
-            level = record.level(),
-            file = record.file().unwrap_or("unknown"),
-            line = record.line().unwrap_or(0),
-            path = record.target(),
-            args = record.args()
-        )
+/// the DPOLL_ABI_VERSION the library was built with, a program compiled against another version
+/// should refuse to run
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_abi_version() -> u32 {
+    return guarded!("dpoll_abi_version", {
+        return DPOLL_ABI_VERSION;
     });
+}
 
-    builder.init();
+/// dpoll sockets can connect, see `dpoll_connect`, if the libOS supports it
+pub const DPOLL_CAP_CONNECT: u64 = 1 << 0;
+/// dpoll sockets can be SOCK_DGRAM, not supported yet
+pub const DPOLL_CAP_UDP: u64 = 1 << 1;
+/// dpoll sockets can be registered with EPOLLET, not supported yet
+pub const DPOLL_CAP_EDGE_TRIGGERED: u64 = 1 << 2;
+/// dpoll sockets can be registered with EPOLLONESHOT, not supported yet
+pub const DPOLL_CAP_ONESHOT: u64 = 1 << 3;
+/// reads and writes can hand their buffers to demikernel without copying, not supported yet
+pub const DPOLL_CAP_ZERO_COPY: u64 = 1 << 4;
+/// sockets and dpolls are locked instead of borrowed, built with the thread-safe feature. fds are
+/// still per thread, only valid on the thread that created them or adopted them with `dpoll_adopt`
+pub const DPOLL_CAP_MULTITHREAD: u64 = 1 << 5;
+/// a background thread harvests the completions, built with the reactor feature, see
+/// `dpoll_get_wakeup_fd`
+pub const DPOLL_CAP_REACTOR: u64 = 1 << 6;
+/// the libOS bypasses the kernel network stack
+pub const DPOLL_CAP_KERNEL_BYPASS: u64 = 1 << 7;
+/// listeners can share an address, see `dpoll_listen_sharded`, no libOS supports it yet
+pub const DPOLL_CAP_REUSEPORT: u64 = 1 << 8;
+
+/// the `DPOLL_CAP_*` flags of what the library supports, for applications and interposition
+/// layers that detect features at runtime instead of assuming them from the header
+///
+/// the libOS ones are of the libOS in use, before `dpoll_init` of the one the library was built
+/// for. bits not known to the caller are for features added after it was compiled and must be
+/// ignored
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_capabilities() -> u64 {
+    return guarded!("dpoll_capabilities", {
+        let libos = Backend::current().capabilities();
+        let mut caps = 0;
+        if libos.contains(Capabilities::CONNECT) {
+            caps |= DPOLL_CAP_CONNECT;
+        }
+        if libos.contains(Capabilities::KERNEL_BYPASS) {
+            caps |= DPOLL_CAP_KERNEL_BYPASS;
+        }
+        if libos.contains(Capabilities::REUSEPORT) {
+            caps |= DPOLL_CAP_REUSEPORT;
+        }
+        if cfg!(feature = "thread-safe") {
+            caps |= DPOLL_CAP_MULTITHREAD;
+        }
+        if cfg!(feature = "reactor") {
+            caps |= DPOLL_CAP_REACTOR;
+        }
+        return caps;
+    });
+}
 
-    return 0;
+/// the version of the library, `major << 16 | minor << 8 | patch`
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_version() -> u32 {
+    return guarded!("dpoll_version", {
+        let part = |v: &str| v.parse::<u32>().unwrap_or(0).min(0xff);
+        return part(env!("CARGO_PKG_VERSION_MAJOR")) << 16
+            | part(env!("CARGO_PKG_VERSION_MINOR")) << 8
+            | part(env!("CARGO_PKG_VERSION_PATCH"));
+    });
+}
+
+/// the errno set by the last dpoll call of this thread that failed, for runtimes that cannot read
+/// errno itself reliably across their FFI layers
+///
+/// like errno, it is not cleared by successful calls, and calls on kernel fds that are passed
+/// through to libc do not set it
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_errno() -> c_int {
+    return guarded!("dpoll_errno", {
+        return utils::last_error();
+    });
+}
+
+/// the message of the errno `code`, a static string that must not be freed
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_strerror(code: c_int) -> *const c_char {
+    return guarded!("dpoll_strerror", {
+        return utils::strerror(code).as_ptr();
+    });
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn dpoll_create(flags: c_int) -> c_int {
-    let pol = match Dpoll::create(flags) {
-        Ok(s) => s,
-        Err(e) => return errno(e),
-    };
+    return recorded!(Create, [flags], {
+        if fork::is_child() {
+            return errno(PosixError::OPNOTSUPP);
+        }
 
-    let idx = DPOLLS.with_borrow_mut(|polls| polls.allocate(Shared::new(pol)));
+        let pol = match Dpoll::create(flags) {
+            Ok(s) => s,
+            Err(e) => return errno(e),
+        };
 
-    trace!("{:?}", idx);
-    return idx.into();
+        let idx = DPOLLS.with_borrow_mut(|polls| polls.allocate(Shared::new(pol)));
+
+        trace!("{:?}", idx);
+        return idx.into();
+    });
 }
 
+/// fails exactly like epoll_ctl would, e.g. with EEXIST for an add of a registered fd and with
+/// ENOENT for a modify or delete of one that is not, also if it is registered in another dpoll,
+/// fds that are not dpoll sockets or dpolls are passed through to the kernel epoll of `dpollfd`
+///
+/// for dpoll sockets EPOLLWAKEUP, EPOLLEXCLUSIVE and EPOLLPRI are accepted without any effect,
+/// EPOLLEXCLUSIVE only on an add like the kernel, any other bit but EPOLLIN, EPOLLOUT, EPOLLERR and
+/// EPOLLHUP fails with EINVAL
 #[unsafe(no_mangle)]
 pub extern "C" fn dpoll_ctl(
     dpollfd: c_int,
@@ -289,15 +929,337 @@ pub extern "C" fn dpoll_ctl(
     fd: c_int,
     event: *mut epoll_event,
 ) -> c_int {
-    let pol: buf::Index = dpollfd.into();
-    let soc: buf::Index = fd.into();
-    trace!("ctl pol {pol:?} on soc {soc:?}");
+    return recorded!(
+        Ctl,
+        [
+            dpollfd,
+            unsafe { recorder::pack_ctl(op, event) },
+            fd,
+            unsafe { recorder::ctl_data(event) }
+        ],
+        {
+            let idxs = dpoll_index(dpollfd).and_then(|pol| Ok((pol, Index::try_from(fd)?)));
+            let (pol, soc) = match idxs {
+                Ok(idxs) => idxs,
+                Err(e) => return errno(e),
+            };
+            trace!("ctl pol {pol:?} on soc {soc:?}");
+            if fork::is_inherited(soc) {
+                return errno(PosixError::BADF);
+            }
+
+            if let Err(e) = check_nesting(pol, soc) {
+                return errno(e);
+            }
+
+            let op = SOCKETS.with_borrow(|socs| {
+                DPOLLS.with_borrow(|polls| unsafe {
+                    dpoll::Operation::from_raw(socs, polls, op, fd, event)
+                })
+            });
+            let op = match op {
+                Ok(op) => op,
+                Err(e) => return errno(e),
+            };
+            let res = with_dpoll(pol, "ctl", |pol| pol.ctl(op));
+            return result_as_errno(res);
+        }
+    );
+}
+
+/// like with kernel epoll, a dpoll cannot be added to itself or to a dpoll nested in it
+fn check_nesting(pol: Index, fd: Index) -> PosixResult<()> {
+    if !fd.is_dpoll() || fd.is_socket() {
+        return Ok(());
+    }
+
+    if pol == fd {
+        return Err(PosixError::INVAL);
+    }
+
+    let (outer, inner) =
+        DPOLLS.with_borrow(|polls| (polls.get(pol).cloned(), polls.get(fd).cloned()));
+    let (Some(outer), Some(inner)) = (outer, inner) else {
+        return Err(PosixError::BADF);
+    };
+    return if inner.borrow().nests(&outer) {
+        Err(PosixError::LOOP)
+    } else {
+        Ok(())
+    };
+}
+
+/// fills `event` with the events and data `fd` is registered with in `dpollfd`, as the last
+/// `dpoll_ctl` add or modify left them, for debugging
+///
+/// fails with ENOENT if `fd` is not registered and with EOPNOTSUPP for kernel fds, the kernel
+/// epoll keeps those
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_get_registration(
+    dpollfd: c_int,
+    fd: c_int,
+    event: *mut epoll_event,
+) -> c_int {
+    return guarded!("dpoll_get_registration", {
+        let idxs = dpoll_index(dpollfd).and_then(|pol| Ok((pol, socket_index(fd)?)));
+        let (pol, soc) = match idxs {
+            Ok(idxs) => idxs,
+            Err(e) => return errno(e),
+        };
+        let Some(out) = (unsafe { event.as_mut() }) else {
+            return errno(PosixError::FAULT);
+        };
+
+        let qd = match with_socket(soc, "get_registration", |soc| Ok(soc.soc.qd)) {
+            Ok(qd) => qd,
+            Err(e) => return errno(e),
+        };
+        let res = with_dpoll(pol, "get_registration", |pol| {
+            let (evs, flags, data) = pol.registration(qd).ok_or(PosixError::NOENT)?;
+            *out = epoll_event {
+                events: evs.bits() | flags.bits(),
+                u64: data,
+            };
+            return Ok(());
+        });
+        return result_as_errno(res);
+    });
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct dpoll_registration {
+    /// -1 for a socket or nested dpoll whose fd was closed already
+    pub fd: c_int,
+    pub kind: dpoll_fd_kind,
+    /// the events and flags of the last add or modify
+    pub events: u32,
+    /// the registered events that are ready right now
+    pub ready: u32,
+    /// the data of the last add or modify
+    pub data: u64,
+}
+
+/// fills `out` with up to `cap` registrations of `dpollfd`, like the fdinfo of an epoll fd lists
+/// them, meant for debuggers and leak checkers
+///
+/// the sockets come first, then the kernel fds and nested dpolls sorted by fd, the kernel keeps
+/// the events and data of those and they are read from /proc, 0 if it is not mounted, with ERR and
+/// HUP always set in the events like the kernel does
+///
+/// returns the number of registrations, which is more than `cap` if `out` is too short, `out` can
+/// be null with a `cap` of 0 to only count them, or -1 and sets errno to EINVAL for a negative
+/// `cap` and to EFAULT for a null `out` otherwise
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_list(dpollfd: c_int, out: *mut dpoll_registration, cap: c_int) -> c_int {
+    return guarded!("dpoll_list", {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+        let Ok(cap) = usize::try_from(cap) else {
+            return errno(PosixError::INVAL);
+        };
+        if out.is_null() && cap > 0 {
+            return errno(PosixError::FAULT);
+        }
+
+        let regs: Vec<dpoll::Registration> =
+            match with_dpoll(pol, "list", |pol| Ok(pol.registered().collect())) {
+                Ok(regs) => regs,
+                Err(e) => return errno(e),
+            };
+        let socs: HashMap<demi::DemiQd, c_int> = SOCKETS.with_borrow(|socs| {
+            socs.iter()
+                .map(|(idx, soc)| (soc.borrow().soc.qd, idx.into()))
+                .collect()
+        });
+
+        let out: &mut [MaybeUninit<dpoll_registration>] = if cap == 0 {
+            &mut []
+        } else {
+            unsafe { slice::from_raw_parts_mut(out.cast(), cap) }
+        };
+        for (slot, reg) in out.iter_mut().zip(&regs) {
+            let (fd, kind) = match &reg.target {
+                dpoll::Target::Socket(soc) => (
+                    socs.get(&soc.borrow().soc.qd).copied().unwrap_or(-1),
+                    dpoll_fd_kind::DPOLL_FD_SOCKET,
+                ),
+                dpoll::Target::Kernel(fd) => (*fd, dpoll_fd_kind::DPOLL_FD_KERNEL),
+                dpoll::Target::Nested(nested) => (
+                    DPOLLS.with_borrow(|polls| {
+                        polls
+                            .iter()
+                            .find(|(_, pol)| pol.ptr_eq(nested))
+                            .map_or(-1, |(idx, _)| idx.into())
+                    }),
+                    dpoll_fd_kind::DPOLL_FD_INSTANCE,
+                ),
+            };
+            slot.write(dpoll_registration {
+                fd,
+                kind,
+                events: reg.events,
+                ready: reg.ready,
+                data: reg.data,
+            });
+        }
+
+        trace!("{} registrations in {pol:?}", regs.len());
+        return regs.len().try_into().unwrap_or(c_int::MAX);
+    });
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct dpoll_ctl_op {
+    pub op: c_int,
+    pub fd: c_int,
+    pub event: epoll_event,
+}
+
+/// or-ed into the `op` of a `dpoll_ctl_op`, an add or modify then overwrites the data of its event
+/// with `fd` in `data.fd`, zeroing the rest, for code written for epoll that expects to get its fds
+/// back from pwait without ever setting the data itself
+pub const DPOLL_CTL_DATA_FD: c_int = 0x100;
+
+/// or-ed into the `op` of a `dpoll_ctl_op`, an add or modify of a dpoll socket then keeps the data
+/// of its event and pwait reports a cookie of the registration instead, which `dpoll_cookie_data`
+/// turns back into the data as long as the registration was not deleted or modified since
+///
+/// this catches events still held for a fd that was closed or re-registered meanwhile, the fd the
+/// slot of a closed one is reused for never yields the same cookie. fails with EINVAL for kernel
+/// fds and together with `DPOLL_CTL_DATA_FD`
+pub const DPOLL_CTL_COOKIE: c_int = 0x200;
+
+/// applies `len` ctl operations on `dpollfd` in order, stopping at the first failing one, see
+/// `DPOLL_CTL_DATA_FD` for registering the fds as their data and `DPOLL_CTL_COOKIE` for reporting
+/// cookies instead
+///
+/// returns the number of applied operations, or -1 and sets errno if the first one failed. if fewer
+/// than `len` were applied, errno is set to why `ops[returned]` failed, like for a -1
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_ctl_batch(dpollfd: c_int, ops: *mut dpoll_ctl_op, len: c_int) -> c_int {
+    return guarded!("dpoll_ctl_batch", {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+        trace!("ctl batch of {len} on pol {pol:?}");
+
+        if len.is_negative() {
+            return errno(PosixError::INVAL);
+        }
+        if len == 0 {
+            // still EBADF for a dpoll that is not live
+            let live = DPOLLS.with_borrow(|polls| polls.get(pol).is_some());
+            return if live { 0 } else { errno(PosixError::BADF) };
+        }
+        if ops.is_null() {
+            return errno(PosixError::FAULT);
+        }
+        let ops = unsafe { std::slice::from_raw_parts_mut(ops, len as usize) };
+
+        // the batch stops at the first op failing the nesting check or naming a bad fd
+        let mut first_err = Ok(());
+        let ops: Vec<dpoll::Operation> = SOCKETS.with_borrow(|socs| {
+            DPOLLS.with_borrow(|polls| {
+                ops.iter_mut()
+                    .map_while(|op| {
+                        let code = op.op & !(DPOLL_CTL_DATA_FD | DPOLL_CTL_COOKIE);
+                        let data_fd = op.op & DPOLL_CTL_DATA_FD != 0 && code != EPOLL_CTL_DEL;
+                        let cookie = op.op & DPOLL_CTL_COOKIE != 0 && code != EPOLL_CTL_DEL;
+                        if data_fd {
+                            // the upper half stays zeroed, like in a zero-initialized epoll_event
+                            // whose data.fd is set
+                            op.event.u64 = op.fd as u32 as u64;
+                        }
+                        let res = if data_fd && cookie {
+                            Err(PosixError::INVAL)
+                        } else {
+                            Index::try_from(op.fd).and_then(|fd| check_nesting(pol, fd))
+                        };
+                        let res = res.and_then(|_| unsafe {
+                            dpoll::Operation::from_raw(socs, polls, code, op.fd, &mut op.event)
+                        });
+                        let res = match res {
+                            Ok(res) if cookie => res.with_cookie(op.fd),
+                            res => res,
+                        };
+                        first_err = res.as_ref().map(|_| ()).map_err(|e| *e);
+                        res.ok()
+                    })
+                    .collect()
+            })
+        });
+        let res = with_dpoll(pol, "ctl_batch", |pol| Ok(pol.ctl_many(ops.into_iter())));
+        let (applied, res) = match res {
+            Ok((applied, res)) => (applied, res.and(first_err)),
+            Err(e) => return errno(e),
+        };
+
+        trace!("ctl batch applied {applied}, res: {res:?}");
+        return match res {
+            Err(e) if applied == 0 => errno(e),
+            Err(e) => {
+                // a short count, errno tells why the next op failed
+                utils::set_errno(e.into());
+                applied.try_into().unwrap()
+            }
+            Ok(()) => applied.try_into().unwrap(),
+        };
+    });
+}
+
+/// the fd a cookie reported for a `DPOLL_CTL_COOKIE` registration was made for, which might have
+/// been closed since
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_cookie_fd(cookie: u64) -> c_int {
+    return guarded!("dpoll_cookie_fd", {
+        return (cookie >> 32) as u32 as c_int;
+    });
+}
 
-    let op = SOCKETS.with_borrow(|socs| unsafe { dpoll::Operation::from_raw(socs, op, fd, event) });
-    let res = DPOLLS.with_borrow_mut(|polls| polls.get(pol).unwrap().borrow_mut().ctl(op));
-    return result_as_errno(res);
+/// stores the data of the registration that `cookie` was reported for in `data`, see
+/// `DPOLL_CTL_COOKIE`
+///
+/// fails with ESTALE if its fd was closed or the registration deleted or modified since, with EBADF
+/// if `dpollfd` is not a dpoll and with EFAULT if `data` is NULL
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_cookie_data(dpollfd: c_int, cookie: u64, data: *mut u64) -> c_int {
+    return guarded!("dpoll_cookie_data", {
+        let fd = dpoll_cookie_fd(cookie);
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+        let Some(data) = (unsafe { data.as_mut() }) else {
+            return errno(PosixError::FAULT);
+        };
+        // the fd of a cookie is never negative, only the one of a forged cookie
+        let Ok(soc) = Index::try_from(fd) else {
+            return errno(PosixError::STALE);
+        };
+        let qd = SOCKETS.with_borrow(|socs| Some(socs.get(soc)?.borrow().soc.qd));
+        let res = with_dpoll(pol, "cookie_data", |pol| {
+            let qd = qd.ok_or(PosixError::STALE)?;
+            *data = pol.cookie_data(qd, cookie).ok_or(PosixError::STALE)?;
+            return Ok(());
+        });
+        return result_as_errno(res);
+    });
 }
 
+/// `sigmask`, if not NULL, replaces the signal mask only while blocked in demikernel or in the
+/// kernel, atomically for the latter like epoll_pwait
+///
+/// sockets are reported in the order they became ready, those that do not fit are reported first
+/// by the next call, and kernel fds left out of a full call go first in the next one, see
+/// src/dpoll/ready_list.rs
+///
+/// fails with EINVAL if `events_len` <= 0 and with EFAULT if `events` is NULL
 #[unsafe(no_mangle)]
 pub extern "C" fn dpoll_pwait(
     dpollfd: c_int,
@@ -306,37 +1268,81 @@ pub extern "C" fn dpoll_pwait(
     timeout: c_int,
     sigmask: *const sigset_t,
 ) -> c_int {
-    let old_set = Sigset::mask(sigmask);
-    let pol: buf::Index = dpollfd.into();
-
-    assert!(!events.is_null());
-    let evs = unsafe {
-        std::ptr::slice_from_raw_parts_mut(
-            events as *mut MaybeUninit<epoll_event>,
-            events_len.try_into().unwrap(),
-        )
-        .as_mut()
-    }
-    .unwrap();
-    let timeout = if timeout.is_negative() {
-        None
-    } else {
-        Some(Duration::from_millis(timeout as u64))
-    };
-
-    let tmp = pol;
-    let pol = DPOLLS.with_borrow(|polls| polls.get(pol).unwrap().clone());
-    trace!("pwait on {tmp:?} for {timeout:?}");
-    let res = pol.borrow_mut().pwait(evs, timeout);
-
-    trace!("pwait on {tmp:?} returned {res:?}");
-    return match res {
-        Ok(count) => count.try_into().unwrap(),
-        Err(PosixError::TIMEDOUT) => 0,
-        Err(err) => errno(err),
-    };
+    return recorded!(Pwait, [dpollfd, events_len, timeout], {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+        if events_len <= 0 {
+            return errno(PosixError::INVAL);
+        }
+        if events.is_null() {
+            return errno(PosixError::FAULT);
+        }
+        let evs = unsafe {
+            std::ptr::slice_from_raw_parts_mut(
+                events as *mut MaybeUninit<epoll_event>,
+                events_len as usize,
+            )
+            .as_mut()
+        }
+        .unwrap();
+        let timeout = if timeout.is_negative() {
+            None
+        } else {
+            Some(Duration::from_millis(timeout as u64))
+        };
+
+        trace!("pwait on {pol:?} for {timeout:?}");
+        let sigmask = unsafe { sigmask.as_ref() };
+        let res = with_dpoll(pol, "pwait", |pol| {
+            Ok((pol.pwait(evs, timeout, sigmask), pol.event_callback()))
+        })
+        .and_then(|(res, callback)| {
+            let count = res?;
+            if let Some(cb) = callback {
+                for ev in &evs[..count] {
+                    unsafe { (cb.func)(cb.ctx, ev.as_ptr()) };
+                }
+            }
+            return Ok(count);
+        });
+
+        trace!("pwait on {pol:?} returned {res:?}");
+
+        #[cfg(feature = "metrics")]
+        dump_metrics();
+
+        return match res {
+            Ok(count) => count.try_into().unwrap(),
+            Err(PosixError::TIMEDOUT) => 0,
+            Err(err) => errno(err),
+        };
+    });
 }
 
+/// the option level of the dpoll specific socket options
+pub const SOL_DPOLL: c_int = 0x4450;
+
+/// whether a pop is started as soon as EPOLLIN is requested, on by default and inherited by
+/// accepted sockets
+///
+/// when off, pops are only started by reads that find no data, so demikernel only buffers what
+/// the application asked for
+pub const DPOLL_SO_AUTOPOP: c_int = 1;
+
+/// SO_KEEPALIVE, TCP_KEEPIDLE, TCP_KEEPINTVL and TCP_KEEPCNT emulate keepalive on dpoll sockets, a
+/// connection idle for as long as the kernel would keep it is reported as EPOLLERR with ETIMEDOUT,
+/// DPOLL_SO_AUTOPOP at SOL_DPOLL controls when reads are started
+///
+/// SO_PRIORITY, 0 to 6, orders the sockets within their dpolls, those with a higher one are
+/// scheduled first and go ahead of the lower ones on the ready list, accepted sockets inherit it
+///
+/// SO_SNDBUF limits the bytes of the writes in flight, SO_RCVBUF the received bytes a socket
+/// buffers before it stops reading from demikernel, like with the kernel the value is doubled,
+/// raised to a minimum and reported doubled by `dpoll_getsockopt`, accepted sockets inherit both
+///
+/// other options are ignored on dpoll sockets
 #[unsafe(no_mangle)]
 pub extern "C" fn dpoll_setsockopt(
     socket: c_int,
@@ -345,33 +1351,67 @@ pub extern "C" fn dpoll_setsockopt(
     optval: *const c_void,
     optlen: socklen_t,
 ) -> c_int {
-    trace!("");
-    let idx: buf::Index = socket.into();
-    return if idx.is_dpoll() {
-        0
-    } else {
-        unsafe { libc::setsockopt(socket, level, optname, optval, optlen) }
-    };
+    return guarded!("dpoll_setsockopt", {
+        let idx = match socket_or_kernel(socket) {
+            Ok(Some(idx)) => idx,
+            Ok(None) => return unsafe { libc::setsockopt(socket, level, optname, optval, optlen) },
+            Err(e) => return errno(e),
+        };
+        trace!("setsockopt {level} {optname} on {idx:?}");
+
+        if optval.is_null() {
+            return errno(PosixError::FAULT);
+        }
+        if (optlen as usize) < mem::size_of::<c_int>() {
+            return errno(PosixError::INVAL);
+        }
+        let val = unsafe { (optval as *const c_int).read_unaligned() };
+
+        return match with_socket(idx, "setsockopt", |soc| soc.set_option(level, optname, val)) {
+            Ok(()) => 0,
+            // options without a demikernel equivalent are accepted and ignored
+            Err(PosixError::NOPROTOOPT) => 0,
+            Err(e) => errno(e),
+        };
+    });
 }
 
+/// writes the address of `socket` to `addr`, truncated to `*len` bytes, and sets `*len` to its full
+/// length
+///
+/// a socket neither bound nor connected has INADDR_ANY:0 as its address, like a kernel one
+///
+/// fails with EFAULT if `addr` or `len` is NULL
 #[unsafe(no_mangle)]
 pub extern "C" fn dpoll_getsockname(
     socket: c_int,
     addr: *mut sockaddr,
     len: *mut socklen_t,
 ) -> c_int {
-    assert!(!len.is_null() && !addr.is_null());
-    assert!(unsafe { *len } as usize >= mem::size_of::<sockaddr_in>());
-    let addr = addr as *mut sockaddr_in;
-
-    let idx: buf::Index = socket.into();
-    let soc_addr = SOCKETS.with_borrow(|socs| socs.get(idx).unwrap().borrow().addr.unwrap());
-    unsafe {
-        addr.write(soc_addr);
-        len.write(mem::size_of::<libc::sockaddr_in>() as u32);
-    }
+    return guarded!("dpoll_getsockname", {
+        let addr = match SockaddrOut::new(addr, len) {
+            Ok(Some(addr)) => addr,
+            Ok(None) => return errno(PosixError::FAULT),
+            Err(e) => return errno(e),
+        };
+
+        let idx = match socket_index(socket) {
+            Ok(idx) => idx,
+            Err(e) => return errno(e),
+        };
+        let soc_addr = match with_socket(idx, "getsockname", |soc| Ok(soc.addr)) {
+            Ok(Some(addr)) => addr,
+            Ok(None) => {
+                let mut any: sockaddr_in = unsafe { mem::zeroed() };
+                any.sin_family = AF_INET as libc::sa_family_t;
+                any
+            }
+            Err(e) => return errno(e),
+        };
+        addr.write(&soc_addr);
 
-    return 0;
+        return 0;
+    });
 }
 
 #[unsafe(no_mangle)]
@@ -380,7 +1420,12 @@ pub extern "C" fn dpoll_sendmsg(
     msg: *const libc::msghdr,
     flags: c_int,
 ) -> c_int {
-    unimplemented!();
+    return guarded!("dpoll_sendmsg", {
+        if let Err(e) = socket_index(socket) {
+            return errno(e);
+        }
+        unimplemented!();
+    });
 }
 
 #[unsafe(no_mangle)]
@@ -389,7 +1434,12 @@ pub extern "C" fn dpoll_recvmsg(
     msg: *mut libc::msghdr,
     flags: c_int,
 ) -> c_int {
-    unimplemented!();
+    return guarded!("dpoll_recvmsg", {
+        if let Err(e) = socket_index(socket) {
+            return errno(e);
+        }
+        unimplemented!();
+    });
 }
 
 #[unsafe(no_mangle)]
@@ -398,5 +1448,1221 @@ pub extern "C" fn dpoll_connect(
     addr: *const sockaddr,
     len: socklen_t,
 ) -> c_int {
-    unimplemented!();
+    return recorded!(
+        Connect,
+        [socket_fd, unsafe {
+            recorder::pack_addr(addr as *const sockaddr_in)
+        }],
+        {
+            let idx = match socket_or_kernel(socket_fd) {
+                Ok(Some(idx)) => idx,
+                Ok(None) => return unsafe { libc::connect(socket_fd, addr, len) },
+                Err(e) => return errno(e),
+            };
+            trace!("connect on {idx:?}");
+
+            if len as usize != mem::size_of::<sockaddr_in>() {
+                return errno(PosixError::INVAL);
+            }
+            let Some(addr) = (unsafe { (addr as *const sockaddr_in).as_ref() }) else {
+                return errno(PosixError::FAULT);
+            };
+
+            let res = with_socket(idx, "connect", |soc| soc.connect(addr));
+
+            return result_as_errno(res);
+        }
+    );
+}
+
+/// races connects to all `len` addresses in `addrs`, keeping the first one to succeed
+///
+/// behaves like `dpoll_connect` otherwise, only AF_INET addresses are supported
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_connect_addrs(
+    socket_fd: c_int,
+    addrs: *const *const sockaddr,
+    len: c_int,
+) -> c_int {
+    return guarded!("dpoll_connect_addrs", {
+        let idx = match socket_index(socket_fd) {
+            Ok(idx) => idx,
+            Err(e) => return errno(e),
+        };
+        trace!("connect to {len} addresses on {idx:?}");
+
+        if len <= 0 {
+            return errno(PosixError::INVAL);
+        }
+        if addrs.is_null() {
+            return errno(PosixError::FAULT);
+        }
+
+        let addrs = unsafe { std::slice::from_raw_parts(addrs, len as usize) };
+        let mut inet = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            let Some(addr) = (unsafe { addr.as_ref() }) else {
+                return errno(PosixError::FAULT);
+            };
+            if addr.sa_family as c_int != AF_INET {
+                return errno(PosixError::AFNOSUPPORT);
+            }
+            inet.push(unsafe { *(addr as *const sockaddr as *const sockaddr_in) });
+        }
+
+        let res = with_socket(idx, "connect_addrs", |soc| soc.connect_any(&inet));
+
+        return result_as_errno(res);
+    });
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct dpoll_connect_req {
+    pub fd: c_int,
+    pub addr: sockaddr_in,
+    /// the epoll data reported with the completion
+    pub data: u64,
+    /// set to the errno of a failed submission, 0 otherwise
+    pub err: c_int,
+}
+
+/// starts connecting every socket in `reqs` and registers it in `dpollfd` for EPOLLOUT
+///
+/// a failed connect is reported as EPOLLOUT, with the error available through SO_ERROR
+///
+/// returns the number of sockets connecting and registered, the `err` of every other request is
+/// set. a socket whose registration failed, e.g. with ENOSPC, keeps connecting unregistered. fails
+/// with EBADF if `dpollfd` is not valid, EINVAL for a negative `len` and EFAULT for a NULL `reqs`
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_connect_many(
+    dpollfd: c_int,
+    reqs: *mut dpoll_connect_req,
+    len: c_int,
+) -> c_int {
+    return guarded!("dpoll_connect_many", {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+        trace!("connect many of {len} on pol {pol:?}");
+
+        let Some(pol) = DPOLLS.with_borrow(|polls| polls.get(pol).cloned()) else {
+            return errno(PosixError::BADF);
+        };
+
+        if len.is_negative() {
+            return errno(PosixError::INVAL);
+        }
+        if len == 0 {
+            return 0;
+        }
+        if reqs.is_null() {
+            return errno(PosixError::FAULT);
+        }
+        let reqs = unsafe { slice::from_raw_parts_mut(reqs, len as usize) };
+
+        let mut pol = match pol.try_borrow_mut("connect_many") {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+        let mut submitted = 0;
+        for req in reqs.iter_mut() {
+            req.err = match connect_req(&mut pol, req) {
+                Ok(()) => 0,
+                Err(e) => e.into(),
+            };
+            submitted += (req.err == 0) as c_int;
+        }
+
+        trace!("connect many submitted {submitted} of {len}");
+        return submitted;
+    });
+}
+
+/// connects the socket of `req` and registers it in `pol` for EPOLLOUT
+fn connect_req(pol: &mut Dpoll, req: &dpoll_connect_req) -> PosixResult<()> {
+    let idx = socket_index(req.fd)?;
+    let soc = SOCKETS.with_borrow(|socs| socs.get(idx).cloned());
+    let soc = soc.ok_or(PosixError::BADF)?;
+
+    match soc.try_borrow_mut("connect_many")?.connect(&req.addr) {
+        Ok(()) | Err(PosixError::INPROGRESS) => {}
+        Err(e) => return Err(e),
+    }
+    return pol.ctl(dpoll::Operation::add(soc, dpoll::Event::OUT, req.data));
+}
+
+/// registers every socket accepted on `listenfd` in `dpollfd` with `events`, before `dpoll_accept`
+/// returns it, the data of an accepted socket is `data_base + its fd`
+///
+/// a `dpollfd` of -1 stops the registration, it also stops once `dpollfd` was closed. if
+/// registering an accepted socket fails, e.g. with ENOSPC for a dpoll at its max_items, the socket
+/// is still returned, only unregistered
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_set_accept_autoreg(
+    listenfd: c_int,
+    dpollfd: c_int,
+    events: u32,
+    data_base: u64,
+) -> c_int {
+    return guarded!("dpoll_set_accept_autoreg", {
+        let idx = match socket_index(listenfd) {
+            Ok(idx) => idx,
+            Err(e) => return errno(e),
+        };
+        trace!("accept autoreg of {idx:?} into {dpollfd} with {events:#x}, base {data_base}");
+
+        let autoreg = if dpollfd == -1 {
+            None
+        } else {
+            let pol = match dpoll_index(dpollfd) {
+                Ok(pol) => pol,
+                Err(e) => return errno(e),
+            };
+            if DPOLLS.with_borrow(|polls| polls.get(pol).is_none()) {
+                return errno(PosixError::BADF);
+            }
+            let Some(evs) = dpoll::Event::from_bits(events) else {
+                return errno(PosixError::INVAL);
+            };
+            Some(AcceptAutoreg {
+                dpoll: pol,
+                evs,
+                data_base,
+            })
+        };
+
+        let res = with_socket(idx, "set_accept_autoreg", |soc| {
+            return soc.set_accept_autoreg(autoreg);
+        });
+        return result_as_errno(res);
+    });
+}
+
+/// limits writes on `fd` to `bytes_per_sec` with bursts of up to `burst` bytes, writes over the
+/// budget fail with EWOULDBLOCK and EPOLLOUT is reported once it refills
+///
+/// a `bytes_per_sec` of 0 removes the limit
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_set_rate(fd: c_int, bytes_per_sec: u64, burst: u64) -> c_int {
+    return guarded!("dpoll_set_rate", {
+        trace!("set rate of {fd} to {bytes_per_sec}B/s, burst {burst}");
+        let res = socket_index(fd)
+            .and_then(|idx| with_socket(idx, "set_rate", |soc| soc.set_rate(bytes_per_sec, burst)));
+
+        return result_as_errno(res);
+    });
+}
+
+/// supports SO_ERROR and the options of `dpoll_setsockopt` on dpoll sockets, other
+/// options fail with ENOPROTOOPT
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_getsockopt(
+    socket: c_int,
+    level: c_int,
+    optname: c_int,
+    optval: *mut c_void,
+    optlen: *mut socklen_t,
+) -> c_int {
+    return guarded!("dpoll_getsockopt", {
+        let idx = match socket_or_kernel(socket) {
+            Ok(Some(idx)) => idx,
+            Ok(None) => return unsafe { libc::getsockopt(socket, level, optname, optval, optlen) },
+            Err(e) => return errno(e),
+        };
+        trace!("getsockopt {level} {optname} on {idx:?}");
+
+        if optval.is_null() || optlen.is_null() {
+            return errno(PosixError::FAULT);
+        }
+        if (unsafe { *optlen } as usize) < mem::size_of::<c_int>() {
+            return errno(PosixError::INVAL);
+        }
+
+        let val = with_socket(idx, "getsockopt", |soc| {
+            if level == libc::SOL_SOCKET && optname == libc::SO_ERROR {
+                return Ok(soc.take_error().map_or(0, Into::into));
+            }
+            return soc.get_option(level, optname);
+        });
+        let val = match val {
+            Ok(val) => val,
+            Err(e) => return errno(e),
+        };
+        unsafe {
+            (optval as *mut c_int).write(val);
+            optlen.write(mem::size_of::<c_int>() as socklen_t);
+        }
+
+        return 0;
+    });
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum dpoll_fd_kind {
+    DPOLL_FD_KERNEL = 0,
+    DPOLL_FD_SOCKET = 1,
+    DPOLL_FD_INSTANCE = 2,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum dpoll_op_state {
+    DPOLL_OP_NONE = 0,
+    DPOLL_OP_RUNNING = 1,
+    DPOLL_OP_COMPLETED = 2,
+}
+
+impl std::convert::From<operation::State> for dpoll_op_state {
+    fn from(value: operation::State) -> Self {
+        return match value {
+            operation::State::None => Self::DPOLL_OP_NONE,
+            operation::State::Running => Self::DPOLL_OP_RUNNING,
+            operation::State::Completed => Self::DPOLL_OP_COMPLETED,
+        };
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug)]
+pub struct dpoll_fd_info {
+    pub kind: dpoll_fd_kind,
+    /// the slot of the fd, only meaningful for dpoll fds
+    pub index: u32,
+    pub generation: u8,
+    /// false if the slot is free or was reused by a newer generation
+    pub live: bool,
+    /// the demikernel qd of a live socket
+    pub qd: u32,
+    /// whether a live socket was not closed yet
+    pub open: bool,
+    /// the number of sockets registered in a live dpoll instance
+    pub items: u32,
+    pub accept: dpoll_op_state,
+    pub connect: dpoll_op_state,
+    pub read: dpoll_op_state,
+    pub write: dpoll_op_state,
+}
+
+/// fills `info` with what is known about `fd`, meant for debugging
+///
+/// returns 0, or -1 and sets errno to EFAULT if `info` is null and to EBADF for a negative `fd`. a
+/// socket or dpoll borrowed by the call running, e.g. from an event callback, is not live
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_fd_info(fd: c_int, info: *mut dpoll_fd_info) -> c_int {
+    return guarded!("dpoll_fd_info", {
+        let Some(info) = (unsafe { info.cast::<MaybeUninit<dpoll_fd_info>>().as_mut() }) else {
+            return errno(PosixError::FAULT);
+        };
+        let idx = match Index::try_from(fd) {
+            Ok(idx) => idx,
+            Err(e) => return errno(e),
+        };
+        trace!("fd info of {idx:?}");
+
+        let mut out = dpoll_fd_info {
+            kind: dpoll_fd_kind::DPOLL_FD_KERNEL,
+            index: 0,
+            generation: 0,
+            live: false,
+            qd: 0,
+            open: false,
+            items: 0,
+            accept: dpoll_op_state::DPOLL_OP_NONE,
+            connect: dpoll_op_state::DPOLL_OP_NONE,
+            read: dpoll_op_state::DPOLL_OP_NONE,
+            write: dpoll_op_state::DPOLL_OP_NONE,
+        };
+
+        if idx.is_dpoll() {
+            out.index = idx.index();
+            out.generation = idx.generation_number();
+        }
+
+        if idx.is_dpoll() && idx.is_socket() {
+            out.kind = dpoll_fd_kind::DPOLL_FD_SOCKET;
+            SOCKETS.with_borrow(|socs| {
+                let Some(soc) = socs.get(idx).and_then(Shared::try_borrow_quiet) else {
+                    return;
+                };
+                let states = soc.operation_states();
+
+                out.live = true;
+                out.qd = soc.soc.qd;
+                out.open = soc.is_open();
+                out.accept = states.accept.into();
+                out.connect = states.connect.into();
+                out.read = states.read.into();
+                out.write = states.write.into();
+            });
+        } else if idx.is_dpoll() {
+            out.kind = dpoll_fd_kind::DPOLL_FD_INSTANCE;
+            DPOLLS.with_borrow(|polls| {
+                if let Some(pol) = polls.get(idx).and_then(Shared::try_borrow_quiet) {
+                    out.live = true;
+                    out.items = pol.len().try_into().unwrap_or(u32::MAX);
+                }
+            });
+        }
+
+        info.write(out);
+        return 0;
+    });
+}
+
+/// has to be called in the child by processes that fork without going through pthread_atfork
+/// handlers, e.g. with a raw clone, see `dpoll_init`
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_postfork() {
+    guarded!("dpoll_postfork", {
+        fork::mark_child();
+    });
+}
+
+#[cfg(feature = "metrics")]
+fn dump_metrics() {
+    if !crate::metrics::take_request() {
+        return;
+    }
+
+    let pols: Vec<(c_int, Shared<Dpoll>)> = DPOLLS.with_borrow(|polls| {
+        polls
+            .iter()
+            .map(|(idx, pol)| (idx.into(), pol.clone()))
+            .collect()
+    });
+    let borrowed: Vec<_> = pols.iter().map(|(fd, pol)| (*fd, pol.borrow())).collect();
+    let refs: Vec<(c_int, &Dpoll)> = borrowed.iter().map(|(fd, pol)| (*fd, &**pol)).collect();
+
+    if let Err(e) = crate::metrics::dump(&crate::metrics::format(&refs)) {
+        log::warn!("failed to dump metrics: {e}");
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct dpoll_stats {
+    pub pwait_calls: u64,
+    /// demikernel completions processed over all waits
+    pub completions: u64,
+    /// events returned by pwait, including kernel ones
+    pub events: u64,
+    /// the ready list length at the end of the last pwait
+    pub ready_list_depth: u64,
+    /// the number of registered dpoll sockets
+    pub items: u64,
+    /// demikernel operations of the registered sockets that did not complete yet
+    pub running_operations: u64,
+    /// received bytes the registered sockets hold that were not read yet
+    pub recv_buffered: u64,
+    /// registered sockets that stopped popping until their received bytes are read
+    pub recv_stopped: u64,
+    /// demikernel waits that returned at least one completion
+    pub demi_waits: u64,
+    /// the most completions processed by a single demikernel wait
+    pub max_completions_per_wait: u64,
+    /// listeners not waited on for the rest of a pwait as it completed its maximum of accepts
+    pub deferred_accepts: u64,
+    /// the most demikernel tokens a single pwait waited on
+    pub max_qtoks: u64,
+    /// pwaits that had to grow the buffer of the tokens they wait on
+    pub qtoks_grows: u64,
+    /// the most sockets registered at once
+    pub max_items: u64,
+    /// adds that failed with ENOSPC as the dpoll had its maximum of sockets registered, see
+    /// `dpoll_set_max_items`
+    pub refused_adds: u64,
+}
+
+/// fills `stats` with the statistics of `dpollfd`
+///
+/// returns 0, or -1 and sets errno
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_get_stats(dpollfd: c_int, stats: *mut dpoll_stats) -> c_int {
+    return guarded!("dpoll_get_stats", {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+
+        let Some(out) = (unsafe { stats.cast::<MaybeUninit<dpoll_stats>>().as_mut() }) else {
+            return errno(PosixError::FAULT);
+        };
+
+        let res = with_dpoll(pol, "get_stats", |pol| {
+            let stats = pol.stats();
+            let (recv_buffered, recv_stopped) = pol
+                .recv_buffered()
+                .fold((0, 0), |(bytes, stopped), (b, s)| {
+                    (bytes + b as u64, stopped + s as u64)
+                });
+            return Ok(dpoll_stats {
+                pwait_calls: stats.pwait_calls,
+                completions: stats.completions,
+                events: stats.events,
+                ready_list_depth: stats.ready_list_depth,
+                items: pol.len() as u64,
+                running_operations: pol.queue_depths().map(|(_, depth)| depth as u64).sum(),
+                recv_buffered,
+                recv_stopped,
+                demi_waits: stats.batches,
+                max_completions_per_wait: stats.max_batch,
+                deferred_accepts: stats.deferred_accepts,
+                max_qtoks: stats.max_qtoks,
+                qtoks_grows: stats.qtoks_grows,
+                max_items: stats.max_items,
+                refused_adds: stats.refused_adds,
+            });
+        });
+
+        return match res {
+            Ok(stats) => {
+                out.write(stats);
+                0
+            }
+            Err(e) => errno(e),
+        };
+    });
+}
+
+/// scanning the registered sockets for the operations to wait on and the ready ones
+pub const DPOLL_PHASE_SCHEDULE: c_int = 0;
+/// waiting for and processing demikernel completions
+pub const DPOLL_PHASE_DEMI_WAIT: c_int = 1;
+/// draining the ready sockets into the events
+pub const DPOLL_PHASE_DRAIN: c_int = 2;
+/// waiting on the kernel fds
+pub const DPOLL_PHASE_EPOLL: c_int = 3;
+
+/// the time the pwaits of a dpoll spent in a phase, in nanoseconds, the percentiles are exact to
+/// within 12.5%
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct dpoll_phase_latency {
+    /// the pwaits recorded
+    pub count: u64,
+    pub min_ns: u64,
+    pub max_ns: u64,
+    pub mean_ns: u64,
+    pub p50_ns: u64,
+    pub p90_ns: u64,
+    pub p99_ns: u64,
+    pub p999_ns: u64,
+}
+
+/// fills `latency` with the time the pwaits of `dpollfd` spent in `phase`, one of the
+/// `DPOLL_PHASE_*` constants, summed per pwait
+///
+/// returns 0, or -1 and sets errno to EINVAL for an unknown phase, EFAULT if `latency` is NULL or
+/// EBADF
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_get_phase_latency(
+    dpollfd: c_int,
+    phase: c_int,
+    latency: *mut dpoll_phase_latency,
+) -> c_int {
+    return guarded!("dpoll_get_phase_latency", {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+
+        let Some(phase) = usize::try_from(phase).ok().and_then(Phase::from_index) else {
+            return errno(PosixError::INVAL);
+        };
+        let Some(out) = (unsafe { latency.cast::<MaybeUninit<dpoll_phase_latency>>().as_mut() })
+        else {
+            return errno(PosixError::FAULT);
+        };
+
+        let res = with_dpoll(pol, "get_phase_latency", |pol| {
+            let hist = pol.stats().phase(phase);
+            return Ok(dpoll_phase_latency {
+                count: hist.count(),
+                min_ns: hist.min(),
+                max_ns: hist.max(),
+                mean_ns: hist.mean(),
+                p50_ns: hist.percentile(0.5),
+                p90_ns: hist.percentile(0.9),
+                p99_ns: hist.percentile(0.99),
+                p999_ns: hist.percentile(0.999),
+            });
+        });
+
+        return match res {
+            Ok(latency) => {
+                out.write(latency);
+                0
+            }
+            Err(e) => errno(e),
+        };
+    });
+}
+
+/// the cpu the last pwait of `dpollfd` that got completions harvested them on, the one of the
+/// reactor thread with the reactor feature and the one of the thread calling pwait without
+///
+/// returns the cpu, or -1 and sets errno to ENODATA if no pwait got completions yet, or EBADF
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_get_harvest_cpu(dpollfd: c_int) -> c_int {
+    return guarded!("dpoll_get_harvest_cpu", {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+
+        let res = with_dpoll(pol, "get_harvest_cpu", |pol| {
+            return pol.harvest_cpu().ok_or(PosixError::NODATA);
+        });
+        return match res {
+            Ok(cpu) => cpu,
+            Err(e) => errno(e),
+        };
+    });
+}
+
+/// pins the reactor thread, which harvests the completions with the reactor feature, to `cpu`, or
+/// lets it run on the cpus it could when it started if `cpu` is -1, whether it started yet or not.
+/// `dpoll_init` pins it to `DPOLL_REACTOR_CPU` if that is set
+///
+/// demikernel does not expose the cores it polls its devices from, with catnip the cpu should be
+/// one local to the NIC that DPDK was not given for an application thread
+///
+/// returns 0, or -1 and sets errno to EINVAL for a cpu the process cannot run on, or to EOPNOTSUPP
+/// without the reactor feature, where the threads calling pwait harvest the completions and have
+/// to be pinned by the application, see `dpoll_get_harvest_cpu`
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_set_reactor_cpu(cpu: c_int) -> c_int {
+    return guarded!("dpoll_set_reactor_cpu", {
+        let cpu = match cpu {
+            -1 => None,
+            cpu => match usize::try_from(cpu) {
+                Ok(cpu) => Some(cpu),
+                Err(_) => return errno(PosixError::INVAL),
+            },
+        };
+
+        #[cfg(feature = "reactor")]
+        return result_as_errno(crate::wrappers::reactor::pin(cpu));
+        #[cfg(not(feature = "reactor"))]
+        {
+            trace!("no reactor to pin to {cpu:?}");
+            return errno(PosixError::OPNOTSUPP);
+        }
+    });
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct dpoll_sga_pool_stats {
+    /// write allocations served by a cached sga
+    pub hits: u64,
+    /// write allocations that went to demikernel
+    pub misses: u64,
+    /// sgas cached right now
+    pub cached: u64,
+    /// sgas freed as their size class was full
+    pub freed: u64,
+    /// sgas allocated to prewarm the pool
+    pub prewarmed: u64,
+}
+
+/// fills `stats` with the statistics of the sga pool of the calling thread, see the sga_pool key of
+/// `dpoll_configure`
+///
+/// returns 0, or -1 and sets errno to EFAULT if `stats` is NULL
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_get_sga_pool_stats(stats: *mut dpoll_sga_pool_stats) -> c_int {
+    return guarded!("dpoll_get_sga_pool_stats", {
+        let out = unsafe { stats.cast::<MaybeUninit<dpoll_sga_pool_stats>>().as_mut() };
+        let Some(out) = out else {
+            return errno(PosixError::FAULT);
+        };
+
+        let stats = sga_pool::stats();
+        out.write(dpoll_sga_pool_stats {
+            hits: stats.hits,
+            misses: stats.misses,
+            cached: stats.cached,
+            freed: stats.freed,
+            prewarmed: stats.prewarmed,
+        });
+        return 0;
+    });
+}
+
+/// sockets of `dpollfd` that complete no operation for longer than `max_idle_ms` are reported as
+/// EPOLLHUP and their operations are not waited on anymore, until they are modified with
+/// EPOLL_CTL_MOD
+///
+/// a `max_idle_ms` <= 0 disables the sweeper
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_set_max_idle(dpollfd: c_int, max_idle_ms: c_int) -> c_int {
+    return guarded!("dpoll_set_max_idle", {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+        trace!("max idle of {pol:?} set to {max_idle_ms}ms");
+
+        let max_idle = (max_idle_ms > 0).then(|| Duration::from_millis(max_idle_ms as u64));
+        let res = with_dpoll(pol, "set_max_idle", |pol| Ok(pol.set_max_idle(max_idle)));
+
+        return result_as_errno(res);
+    });
+}
+
+/// operations of the sockets of `dpollfd` that run for longer than `threshold_ms` are logged as a
+/// warning with their qd, opcode and token, and if `fail` is not 0 they are also failed with
+/// ETIMEDOUT, which the socket reports as EPOLLERR like any other failed operation
+///
+/// the operations are checked as pwait schedules them, a `threshold_ms` <= 0 disables the watchdog
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_set_watchdog(dpollfd: c_int, threshold_ms: c_int, fail: c_int) -> c_int {
+    return guarded!("dpoll_set_watchdog", {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+        trace!("watchdog of {pol:?} set to {threshold_ms}ms, fail: {fail}");
+
+        let watchdog = Watchdog {
+            threshold: (threshold_ms > 0).then(|| Duration::from_millis(threshold_ms as u64)),
+            fail: fail != 0,
+        };
+        let res = with_dpoll(pol, "set_watchdog", |pol| Ok(pol.set_watchdog(watchdog)));
+
+        return result_as_errno(res);
+    });
+}
+
+/// `dpollfd` processes up to `max` demikernel completions per wait, the first one is waited for and
+/// the rest are only polled
+///
+/// fails with EINVAL if `max` <= 0
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_set_max_completions(dpollfd: c_int, max: c_int) -> c_int {
+    return guarded!("dpoll_set_max_completions", {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+        trace!("max completions of {pol:?} set to {max}");
+        if max <= 0 {
+            return errno(PosixError::INVAL);
+        }
+
+        let res = with_dpoll(pol, "set_max_completions", |pol| {
+            Ok(pol.set_max_completions(max as usize))
+        });
+
+        return result_as_errno(res);
+    });
+}
+
+/// moves the registrations `fd` has in the other dpolls of this thread to `dpollfd`, which waits on
+/// its running operations from then on
+///
+/// `dpollfd` keeps the events and data it registered `fd` with, otherwise it takes the ones of a
+/// dpoll `fd` is registered in, fails with ENOENT if there is none
+///
+/// fails with EOPNOTSUPP for a kernel `fd` and with EBADF for any other fd that is not a dpoll
+/// socket or a `dpollfd` that is not a dpoll
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_migrate(fd: c_int, dpollfd: c_int) -> c_int {
+    return guarded!("dpoll_migrate", {
+        let res = socket_index(fd).and_then(|soc| {
+            let pol = dpoll_index(dpollfd)?;
+            trace!("migrating {soc:?} to {pol:?}");
+            return migrate(soc, pol);
+        });
+        return result_as_errno(res);
+    });
+}
+
+fn migrate(soc: Index, pol: Index) -> PosixResult<()> {
+    let soc = SOCKETS.with_borrow(|socs| socs.get(soc).cloned());
+    let soc = soc.ok_or(PosixError::BADF)?;
+    let target = DPOLLS.with_borrow(|polls| polls.get(pol).cloned());
+    let target = target.ok_or(PosixError::BADF)?;
+    let others: Vec<_> = DPOLLS.with_borrow(|polls| {
+        polls
+            .iter()
+            .map(|(_, pol)| pol.clone())
+            .filter(|pol| !pol.ptr_eq(&target))
+            .collect()
+    });
+
+    let qd = soc.borrow().soc.qd;
+    let kept = target.try_borrow_mut("migrate")?.registration(qd);
+    let mut registration = kept;
+    for pol in others {
+        let mut pol = pol.try_borrow_mut("migrate")?;
+        if let Some(reg) = pol.registration(qd) {
+            pol.ctl(dpoll::Operation::del(qd))?;
+            registration.get_or_insert(reg);
+        }
+    }
+
+    let (evs, flags, data) = registration.ok_or(PosixError::NOENT)?;
+    if kept.is_none() {
+        let op = dpoll::Operation::add_flagged(soc, evs, flags, data);
+        target.try_borrow_mut("migrate")?.ctl(op)?;
+    }
+    return Ok(());
+}
+
+/// hands `fd` over to another thread, which takes it with `dpoll_adopt` and the returned ticket,
+/// `fd` is released on this thread while its running operations move with the socket
+///
+/// fails with EBUSY, leaving `fd` as it was, while `fd` is still registered in a dpoll, auto
+/// registers accepted sockets or, with the reactor feature, has running operations, with
+/// EOPNOTSUPP for a kernel `fd` and with EBADF for any other fd that is not a dpoll socket
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_handoff(fd: c_int) -> c_int {
+    return guarded!("dpoll_handoff", {
+        let idx = match socket_index(fd) {
+            Ok(idx) => idx,
+            Err(e) => return errno(e),
+        };
+        trace!("handing off {idx:?}");
+
+        let res = SOCKETS.with_borrow_mut(|socs| {
+            handoff::check(socs.get(idx).ok_or(PosixError::BADF)?)?;
+            let soc = socs.take(idx).ok_or(PosixError::BADF)?;
+            return Ok(handoff::park(soc));
+        });
+        return match res {
+            Ok(ticket) => ticket,
+            Err(e) => errno(e),
+        };
+    });
+}
+
+/// takes the socket handed off with `ticket` on this thread, returning its new fd
+///
+/// fails with ENOENT if no socket waits under `ticket`
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_adopt(ticket: c_int) -> c_int {
+    return guarded!("dpoll_adopt", {
+        trace!("adopting ticket {ticket}");
+        if fork::is_child() {
+            return errno(PosixError::OPNOTSUPP);
+        }
+
+        return match handoff::adopt(ticket) {
+            Ok(soc) => SOCKETS.with_borrow_mut(|socs| socs.allocate(soc)).into(),
+            Err(e) => errno(e),
+        };
+    });
+}
+
+/// a kernel fd that is readable whenever `dpollfd` has events to report, for event loops like
+/// asyncio or libuv to watch in place of blocking in `dpoll_pwait`, which they call with a
+/// timeout of 0 once it is readable
+///
+/// the fd belongs to the dpoll, it must not be closed nor read from
+///
+/// demikernel completions only make it readable with the reactor feature, which harvests them in
+/// the background for the operations a pwait waited on before. the completions go to the thread,
+/// so with several dpolls on one thread all of them are to be waited on once any fd is readable.
+/// without the feature only the kernel fds and the sockets changed by calls of the application
+/// make it readable, so `dpoll_pwait` has to be called periodically as well
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_get_wakeup_fd(dpollfd: c_int) -> c_int {
+    return guarded!("dpoll_get_wakeup_fd", {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+        trace!("wakeup fd of {pol:?}");
+
+        return match with_dpoll(pol, "get_wakeup_fd", |pol| pol.wakeup_fd()) {
+            Ok(fd) => fd,
+            Err(e) => errno(e),
+        };
+    });
+}
+
+/// invoked for every event `dpoll_pwait` returns, in order and before it returns
+#[allow(non_camel_case_types)]
+pub type dpoll_event_callback =
+    Option<unsafe extern "C" fn(ctx: *mut c_void, event: *const epoll_event)>;
+
+/// makes `dpoll_pwait` on `dpollfd` call `callback` with `ctx` for each event it returns, the
+/// events are still written to the array and counted as usual
+///
+/// the callback runs on the thread of the pwait and may call any dpoll function, including on
+/// `dpollfd`, a NULL `callback` removes it
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_set_event_callback(
+    dpollfd: c_int,
+    callback: dpoll_event_callback,
+    ctx: *mut c_void,
+) -> c_int {
+    return guarded!("dpoll_set_event_callback", {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+        trace!("event callback of {pol:?} set");
+
+        let callback = callback.map(|func| dpoll::EventCallback { func, ctx });
+        let res = with_dpoll(pol, "set_event_callback", |pol| {
+            Ok(pol.set_event_callback(callback))
+        });
+
+        return result_as_errno(res);
+    });
+}
+
+/// `dpollfd` completes at most `max` accepts per pwait, the connections of the listeners beyond
+/// that are left for the next one, which keeps a burst of connections from delaying the reads and
+/// writes of the established ones
+///
+/// 0 removes the limit, fails with EINVAL if `max` < 0
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_set_max_accepts(dpollfd: c_int, max: c_int) -> c_int {
+    return guarded!("dpoll_set_max_accepts", {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+        trace!("max accepts of {pol:?} set to {max}");
+        let Ok(max) = usize::try_from(max) else {
+            return errno(PosixError::INVAL);
+        };
+
+        let res = with_dpoll(pol, "set_max_accepts", |pol| {
+            Ok(pol.set_max_accepts((max > 0).then_some(max)))
+        });
+
+        return result_as_errno(res);
+    });
+}
+
+/// `dpollfd` registers at most `max` sockets, further adds fail with ENOSPC, which keeps one
+/// component of an application from taking all the tokens of demikernel, sockets already
+/// registered over a lowered limit stay registered
+///
+/// 0 removes the limit, fails with EINVAL if `max` < 0
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_set_max_items(dpollfd: c_int, max: c_int) -> c_int {
+    return guarded!("dpoll_set_max_items", {
+        let pol = match dpoll_index(dpollfd) {
+            Ok(pol) => pol,
+            Err(e) => return errno(e),
+        };
+        trace!("max items of {pol:?} set to {max}");
+        let Ok(max) = usize::try_from(max) else {
+            return errno(PosixError::INVAL);
+        };
+
+        let res = with_dpoll(pol, "set_max_items", |pol| {
+            Ok(pol.set_max_items((max > 0).then_some(max)))
+        });
+
+        return result_as_errno(res);
+    });
+}
+
+/// replaces the log filter, e.g. `demi_epoll::dpoll::ready_list=trace,warn`, NULL goes back to
+/// DPOLL_LOG, or RUST_LOG if it is not set
+///
+/// fails with EINVAL if `filter` is not valid UTF-8
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_set_log(filter: *const c_char) -> c_int {
+    return guarded!("dpoll_set_log", {
+        let filter = if filter.is_null() {
+            None
+        } else {
+            match unsafe { c_str(filter) } {
+                Some(filter) => Some(filter),
+                None => return errno(PosixError::INVAL),
+            }
+        };
+
+        logging::set_filter(filter);
+        return 0;
+    });
+}
+
+/// sets the config `key` to `value`, the keys are also read from DPOLL_<KEY> environment variables
+/// by `dpoll_init`:
+/// - send_queue_depth: pushes a socket can have in flight before writes would block, their bytes
+///   are limited by SO_SNDBUF, which is as much as the largest pushes fill by default
+/// - max_idle_ms: the idle budget new dpolls start with, see `dpoll_set_max_idle`
+/// - keepalive, keepalive_idle, keepalive_interval, keepalive_count: the keepalive settings new
+///   sockets start with, see `dpoll_setsockopt`
+/// - auto_pop: the DPOLL_SO_AUTOPOP new sockets start with
+/// - rcvbuf: the received bytes a socket buffers before it stops popping, it resumes once reads
+///   drain them below half of it, the SO_RCVBUF new sockets start with
+/// - max_completions_per_wait: the completions new dpolls process per demikernel wait, see
+///   `dpoll_set_max_completions`, 1 by default
+/// - wait_shards: the shards new dpolls split the operations they wait on into at most, so
+///   polling for more completions looks at a shard of at least 1024 operations instead of all of
+///   them, 8 by default, 1 waits on all of them at once
+/// - max_accepts_per_wait: the accepts new dpolls complete per pwait, see `dpoll_set_max_accepts`,
+///   0, the default, for no limit
+/// - max_items: the sockets new dpolls register at most, see `dpoll_set_max_items`, 0, the
+///   default, for no limit
+/// - watchdog_ms, watchdog_fail: the watchdog new dpolls start with, see `dpoll_set_watchdog`, off
+///   by default
+/// - sga_pool: the sgas per size class the writes of new sockets keep for reuse once their pushes
+///   completed, instead of allocating each from demikernel, 0, the default, for none, see
+///   `dpoll_get_sga_pool_stats`. <size>x<count>, e.g. 4096x1024, keeps count per class and
+///   prewarms the pool of each thread with count sgas for writes of up to size bytes on its first
+///   pooled write, smaller writes take them too if their own class has none cached
+/// - abort_on_panic: 1 aborts the process when a dpoll call panics, after dumping the last
+///   transitions of the dpolls of the thread to stderr, by default the panic is logged and the
+///   call fails with EFAULT, which leaves whatever it was changing in the state it panicked in
+///
+/// applies to sockets and dpolls created afterwards, fails with ENOENT for an unknown key and with
+/// EINVAL for an invalid value, leaving the config untouched
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_configure(key: *const c_char, value: *const c_char) -> c_int {
+    return guarded!("dpoll_configure", {
+        let (Some(key), Some(value)) = (unsafe { c_str(key) }, unsafe { c_str(value) }) else {
+            return errno(PosixError::INVAL);
+        };
+
+        return match Config::configure(key, value) {
+            Ok(()) => 0,
+            Err(e) => {
+                trace!("configure failed: {e}");
+                errno(e.into())
+            }
+        };
+    });
+}
+
+/// writes the value of the config `key` into `buf` as a NUL terminated string
+///
+/// returns the length of the value, or -1 and sets errno, to ERANGE if it does not fit in `len`
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_config_get(key: *const c_char, buf: *mut c_char, len: size_t) -> c_int {
+    return guarded!("dpoll_config_get", {
+        let Some(key) = (unsafe { c_str(key) }) else {
+            return errno(PosixError::INVAL);
+        };
+        if buf.is_null() {
+            return errno(PosixError::FAULT);
+        }
+
+        let value = match Config::current().get(key) {
+            Ok(value) => value,
+            Err(e) => return errno(e.into()),
+        };
+        if value.len() >= len {
+            return errno(PosixError::RANGE);
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(value.as_ptr() as *const c_char, buf, value.len());
+            buf.add(value.len()).write(0);
+        }
+        return value.len().try_into().unwrap();
+    });
+}
+
+/// writes a snapshot of the effective config into `buf` as a NUL terminated string, for bug
+/// reports, one `key=value` line per key of `dpoll_configure`, then `backend=` with the backend
+/// in use and the environment variables read outside of the config that are set, e.g. DPOLL_LOG
+///
+/// `dpoll_init` logs it at info level
+///
+/// returns the length of the snapshot, or -1 and sets errno to ERANGE if it does not fit in
+/// `len`, a null `buf` with a `len` of 0 only returns the length
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_dump_config(buf: *mut c_char, len: size_t) -> c_int {
+    return guarded!("dpoll_dump_config", {
+        let dump = Config::current().dump();
+        if buf.is_null() && len == 0 {
+            return dump.len().try_into().unwrap_or(c_int::MAX);
+        }
+        if buf.is_null() {
+            return errno(PosixError::FAULT);
+        }
+        if dump.len() >= len {
+            return errno(PosixError::RANGE);
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(dump.as_ptr() as *const c_char, buf, dump.len());
+            buf.add(dump.len()).write(0);
+        }
+        return dump.len().try_into().unwrap();
+    });
+}
+
+/// the demikernel qd behind the dpoll socket `fd`, for mixing direct demikernel calls with dpoll
+///
+/// fails with OPNOTSUPP for kernel fds and with BADF for dpoll instances
+pub fn demi_qd(fd: c_int) -> PosixResult<demi::DemiQd> {
+    let idx = socket_index(fd)?;
+    return with_socket(idx, "demi_qd", |soc| Ok(soc.soc.qd));
+}
+
+/// makes `dpollfd` wait on `qt`, an operation submitted to demikernel directly, and report its
+/// completion once as EPOLLIN with `data`
+///
+/// the result is kept until it is taken with `take_raw`, EEXIST if `qt` was already submitted
+pub fn submit_raw(dpollfd: c_int, qt: demi::QToken, data: u64) -> PosixResult<()> {
+    let pol = dpoll_index(dpollfd)?;
+    trace!("raw qt {qt} submitted to {pol:?} with {data}");
+    return with_dpoll(pol, "submit_raw", |pol| pol.submit_raw(qt, data));
+}
+
+/// the result of an operation passed to `submit_raw`, EWOULDBLOCK if it did not complete yet and
+/// ENOENT if it was not submitted or already taken
+pub fn take_raw(dpollfd: c_int, qt: demi::QToken) -> PosixResult<demi::RawQResult> {
+    let pol = dpoll_index(dpollfd)?;
+    return with_dpoll(pol, "take_raw", |pol| pol.take_raw(qt));
+}
+
+/// the index of the dpoll instance `dpollfd`, EBADF for any other fd, negative ones included
+fn dpoll_index(dpollfd: c_int) -> PosixResult<Index> {
+    let pol = Index::try_from(dpollfd)?;
+    if !pol.is_dpoll() || pol.is_socket() || fork::is_inherited(pol) {
+        return Err(PosixError::BADF);
+    }
+
+    return Ok(pol);
+}
+
+/// the demikernel qd behind the dpoll socket `fd`, for mixing direct demikernel calls with dpoll
+///
+/// returns the qd, or -1 and sets errno to EOPNOTSUPP for kernel fds and to EBADF for dpoll
+/// instances
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_get_qd(fd: c_int) -> c_int {
+    return guarded!("dpoll_get_qd", {
+        return match demi_qd(fd) {
+            Ok(qd) => qd as c_int,
+            Err(e) => errno(e),
+        };
+    });
+}
+
+/// makes `dpollfd` wait on `qt`, an operation submitted to demikernel directly, and report its
+/// completion once as EPOLLIN with `data`
+///
+/// `qt` has to be a pending operation nothing else waits on, as dpoll consumes its completion, the
+/// result is kept until it is taken with `dpoll_take_raw`, EEXIST if `qt` was already submitted
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_submit_raw(dpollfd: c_int, qt: demi::QToken, data: u64) -> c_int {
+    return guarded!("dpoll_submit_raw", {
+        return result_as_errno(submit_raw(dpollfd, qt, data));
+    });
+}
+
+/// the result of an operation passed to `dpoll_submit_raw`
+///
+/// returns 0 and fills `res`, or -1 and sets errno to EWOULDBLOCK if it did not complete yet and
+/// to ENOENT if it was not submitted or already taken, the sga of a pop is owned by the caller
+/// afterwards
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_take_raw(
+    dpollfd: c_int,
+    qt: demi::QToken,
+    res: *mut demi::RawQResult,
+) -> c_int {
+    return guarded!("dpoll_take_raw", {
+        if res.is_null() {
+            return errno(PosixError::FAULT);
+        }
+
+        return match take_raw(dpollfd, qt) {
+            Ok(qr) => {
+                unsafe { res.write_unaligned(qr) };
+                0
+            }
+            Err(e) => errno(e),
+        };
+    });
+}
+
+/// hands the dpoll socket `fd` over to the application until `dpoll_attach`, returning its qd for
+/// a TLS library working on memory buffers to push and pop on directly, `dpoll_submit_raw` makes a
+/// dpoll wait on those operations
+///
+/// `fd` stays registered, but starts no pops, writes on it fail with EBUSY and only EPOLLIN for
+/// the bytes received before is reported, which are to be read with `dpoll_read` before popping.
+/// fails with EBUSY while the pushes or the pop of `fd` are still running, the call is to be
+/// repeated once a pwait or read completed them, with ENOTCONN if `fd` is not connected
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_detach(fd: c_int) -> c_int {
+    return guarded!("dpoll_detach", {
+        let res = socket_index(fd).and_then(|idx| {
+            trace!("detaching {idx:?}");
+            return with_socket(idx, "detach", |soc| soc.detach());
+        });
+        return match res {
+            Ok(qd) => qd as c_int,
+            Err(e) => errno(e),
+        };
+    });
+}
+
+/// takes `fd` back from the application after `dpoll_detach`, its registrations report events
+/// and its reads and writes go through dpoll again
+///
+/// the operations the application started on the qd have to be completed, fails with EINVAL if
+/// `fd` is not detached
+#[unsafe(no_mangle)]
+pub extern "C" fn dpoll_attach(fd: c_int) -> c_int {
+    return guarded!("dpoll_attach", {
+        let res = socket_index(fd).and_then(|idx| {
+            trace!("attaching {idx:?}");
+            return with_socket(idx, "attach", Socket::attach);
+        });
+        return result_as_errno(res);
+    });
+}
+
+/// like `with_dpoll`, for the fuzz targets driving the bindings, see `crate::fuzzing`
+#[cfg(all(feature = "fuzzing", feature = "mock"))]
+pub(crate) fn with_dpoll_fd<R, F>(dpollfd: c_int, context: &str, func: F) -> PosixResult<R>
+where
+    F: FnOnce(&mut Dpoll) -> PosixResult<R>,
+{
+    return with_dpoll(dpoll_index(dpollfd)?, context, func);
+}
+
+/// like `with_socket`, for the Rust API taking fds, see `crate::stream`
+pub(crate) fn with_socket_fd<R, F>(fd: c_int, context: &str, func: F) -> PosixResult<R>
+where
+    F: FnOnce(&mut Socket) -> PosixResult<R>,
+{
+    return with_socket(socket_index(fd)?, context, func);
+}
+
+/// like `dpoll_index`, EOPNOTSUPP for kernel fds and EBADF for dpoll instances
+fn socket_index(fd: c_int) -> PosixResult<Index> {
+    let idx = Index::try_from(fd)?;
+    if !idx.is_dpoll() {
+        return Err(PosixError::OPNOTSUPP);
+    }
+    if !idx.is_socket() || fork::is_inherited(idx) {
+        return Err(PosixError::BADF);
+    }
+
+    return Ok(idx);
+}
+
+/// like `socket_index`, but `None` for kernel fds, which the bindings pass through to libc
+fn socket_or_kernel(fd: c_int) -> PosixResult<Option<Index>> {
+    return match socket_index(fd) {
+        Ok(idx) => Ok(Some(idx)),
+        Err(PosixError::OPNOTSUPP) => Ok(None),
+        Err(e) => Err(e),
+    };
 }
@@ -0,0 +1,624 @@
+//! the bindings against the mock backend, run with `cargo test --features mock`
+//!
+//! each test runs on a thread of its own, so with its own sockets and dpolls, while the mock queues
+//! are shared by the process, the tests listening use ports of their own
+
+use super::*;
+use crate::wrappers::clock;
+use libc::{EBADF, SO_ERROR, SO_KEEPALIVE, SOL_SOCKET};
+use std::{ptr, sync::Once, time::Instant};
+
+/// fds no binding may take for one of its own: negative ones, and dpoll and socket fds that were
+/// never handed out
+const BAD_FDS: [c_int; 4] = [-1, c_int::MIN, c_int::MAX, 0x6000_0000 | 12345];
+
+fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| assert_eq!(dpoll_init(), 0));
+}
+
+/// asserts `ret` is a failure with `code`
+#[track_caller]
+fn fails_with<R: TryInto<i64>>(ret: R, code: c_int) {
+    assert_eq!(ret.try_into().ok(), Some(-1));
+    assert_eq!(dpoll_errno(), code);
+}
+
+#[test]
+fn bad_fds() {
+    init();
+    let pol = dpoll_create(0);
+    let soc = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    assert!(pol >= 0 && soc >= 0);
+
+    let mut byte = 0u8;
+    let buf = &raw mut byte as *mut c_void;
+    let mut vec = iovec {
+        iov_base: buf,
+        iov_len: 1,
+    };
+    let mut ev = epoll_event {
+        events: libc::EPOLLIN as u32,
+        u64: 0,
+    };
+    let mut addr: sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_family = AF_INET as libc::sa_family_t;
+    let addr_ptr = &raw const addr as *const sockaddr;
+    let mut addr_len = mem::size_of::<sockaddr_in>() as socklen_t;
+    let mut opt: c_int = 1;
+    let mut opt_len = mem::size_of::<c_int>() as socklen_t;
+    let opt_ptr = &raw mut opt as *mut c_void;
+    let mut regs: [dpoll_registration; 1] = unsafe { mem::zeroed() };
+    let mut info: dpoll_fd_info = unsafe { mem::zeroed() };
+    let mut stats: dpoll_stats = unsafe { mem::zeroed() };
+    let mut latency: dpoll_phase_latency = unsafe { mem::zeroed() };
+    let mut raw: demi::RawQResult = unsafe { mem::zeroed() };
+    let mut data = 0u64;
+
+    for fd in BAD_FDS {
+        let mut op = dpoll_ctl_op {
+            op: libc::EPOLL_CTL_ADD,
+            fd,
+            event: ev,
+        };
+        let mut req = dpoll_connect_req {
+            fd,
+            addr,
+            data: 0,
+            err: 0,
+        };
+
+        // the socket bindings
+        fails_with(dpoll_bind(fd, addr_ptr, addr_len), EBADF);
+        fails_with(dpoll_listen(fd, 1), EBADF);
+        fails_with(dpoll_shutdown(fd, libc::SHUT_RDWR), EBADF);
+        fails_with(dpoll_accept(fd, ptr::null_mut(), ptr::null_mut()), EBADF);
+        fails_with(dpoll_write(fd, buf, 1), EBADF);
+        fails_with(dpoll_flush(fd, 0), EBADF);
+        fails_with(dpoll_read(fd, buf, 1), EBADF);
+        fails_with(dpoll_try_read(fd, buf, 1), EBADF);
+        fails_with(dpoll_try_write(fd, buf, 1), EBADF);
+        fails_with(dpoll_writev(fd, &vec, 1), EBADF);
+        fails_with(dpoll_readv(fd, &mut vec, 1), EBADF);
+        fails_with(
+            dpoll_setsockopt(fd, SOL_SOCKET, SO_KEEPALIVE, opt_ptr, opt_len),
+            EBADF,
+        );
+        fails_with(
+            dpoll_getsockopt(fd, SOL_SOCKET, SO_ERROR, opt_ptr, &mut opt_len),
+            EBADF,
+        );
+        fails_with(
+            dpoll_getsockname(fd, addr_ptr as *mut _, &mut addr_len),
+            EBADF,
+        );
+        fails_with(dpoll_connect(fd, addr_ptr, addr_len), EBADF);
+        fails_with(dpoll_connect_addrs(fd, &addr_ptr, 1), EBADF);
+        fails_with(dpoll_set_accept_autoreg(fd, -1, 0, 0), EBADF);
+        // -1 turns the autoreg off
+        if fd != -1 {
+            fails_with(dpoll_set_accept_autoreg(soc, fd, 0, 0), EBADF);
+        }
+        fails_with(dpoll_set_rate(fd, 1, 1), EBADF);
+        // forged fds are only reported as not live
+        if fd < 0 {
+            fails_with(dpoll_fd_info(fd, &mut info), EBADF);
+        } else {
+            assert_eq!(dpoll_fd_info(fd, &mut info), 0);
+            assert!(!info.live);
+        }
+        fails_with(dpoll_migrate(fd, pol), EBADF);
+        fails_with(dpoll_migrate(soc, fd), EBADF);
+        fails_with(dpoll_handoff(fd), EBADF);
+        fails_with(dpoll_get_qd(fd), EBADF);
+        fails_with(dpoll_detach(fd), EBADF);
+        fails_with(dpoll_attach(fd), EBADF);
+        fails_with(dpoll_close(fd), EBADF);
+
+        // the dpoll bindings
+        fails_with(dpoll_ctl(fd, libc::EPOLL_CTL_ADD, soc, &mut ev), EBADF);
+        fails_with(dpoll_ctl(pol, libc::EPOLL_CTL_ADD, fd, &mut ev), EBADF);
+        fails_with(dpoll_get_registration(fd, soc, &mut ev), EBADF);
+        fails_with(dpoll_get_registration(pol, fd, &mut ev), EBADF);
+        fails_with(dpoll_list(fd, regs.as_mut_ptr(), 1), EBADF);
+        fails_with(dpoll_ctl_batch(fd, &mut op, 0), EBADF);
+        fails_with(dpoll_ctl_batch(pol, &mut op, 1), EBADF);
+        fails_with(dpoll_cookie_data(fd, 0, &mut data), EBADF);
+        fails_with(dpoll_pwait(fd, &mut ev, 1, 0, ptr::null()), EBADF);
+        fails_with(dpoll_connect_many(fd, &mut req, 1), EBADF);
+        fails_with(dpoll_get_stats(fd, &mut stats), EBADF);
+        fails_with(dpoll_get_phase_latency(fd, 0, &mut latency), EBADF);
+        fails_with(dpoll_get_harvest_cpu(fd), EBADF);
+        fails_with(dpoll_set_max_idle(fd, 1), EBADF);
+        fails_with(dpoll_set_watchdog(fd, 1, 0), EBADF);
+        fails_with(dpoll_set_max_completions(fd, 1), EBADF);
+        fails_with(dpoll_get_wakeup_fd(fd), EBADF);
+        fails_with(dpoll_set_event_callback(fd, None, ptr::null_mut()), EBADF);
+        fails_with(dpoll_set_max_accepts(fd, 1), EBADF);
+        fails_with(dpoll_set_max_items(fd, 1), EBADF);
+        fails_with(dpoll_submit_raw(fd, 1, 0), EBADF);
+        fails_with(dpoll_take_raw(fd, 1, &mut raw), EBADF);
+    }
+
+    // not implemented past checking the fd is not negative
+    fails_with(dpoll_sendmsg(-1, ptr::null(), 0), EBADF);
+    fails_with(dpoll_recvmsg(-1, ptr::null_mut(), 0), EBADF);
+
+    // a dpoll fd is not a socket and the other way around
+    fails_with(dpoll_read(pol, buf, 1), EBADF);
+    fails_with(dpoll_pwait(soc, &mut ev, 1, 0, ptr::null()), EBADF);
+
+    assert_eq!(dpoll_close(soc), 0);
+    assert_eq!(dpoll_close(pol), 0);
+}
+
+#[test]
+fn getsockname_unbound() {
+    init();
+    let soc = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    assert!(soc >= 0);
+
+    let mut addr: sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_port = 1;
+    let mut len = mem::size_of::<sockaddr_in>() as socklen_t;
+    assert_eq!(
+        dpoll_getsockname(soc, &raw mut addr as *mut sockaddr, &mut len),
+        0
+    );
+    assert_eq!(len as usize, mem::size_of::<sockaddr_in>());
+    assert_eq!(addr.sin_family, AF_INET as libc::sa_family_t);
+    assert_eq!((addr.sin_addr.s_addr, addr.sin_port), (0, 0));
+
+    assert_eq!(dpoll_close(soc), 0);
+}
+
+/// a socket connected to 127.0.0.1:`port` and the socket the listener accepted for it, registered
+/// in the returned dpoll for IN with their fd as the data, the listener is closed again
+fn connected(port: u16) -> (c_int, c_int, c_int) {
+    init();
+    let mut addr: sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_family = AF_INET as libc::sa_family_t;
+    addr.sin_port = port.to_be();
+    addr.sin_addr.s_addr = u32::from_be_bytes([127, 0, 0, 1]).to_be();
+    let addr_ptr = &raw const addr as *const sockaddr;
+    let addr_len = mem::size_of::<sockaddr_in>() as socklen_t;
+
+    let pol = dpoll_create(0);
+    let listener = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    let soc = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    assert_eq!(dpoll_bind(listener, addr_ptr, addr_len), 0);
+    assert_eq!(dpoll_listen(listener, 1), 0);
+    register(pol, listener, libc::EPOLLIN);
+    fails_with(dpoll_connect(soc, addr_ptr, addr_len), libc::EINPROGRESS);
+    register(pol, soc, libc::EPOLLOUT);
+
+    wait_for(pol, listener, libc::EPOLLIN);
+    wait_for(pol, soc, libc::EPOLLOUT);
+    let peer = dpoll_accept(listener, ptr::null_mut(), ptr::null_mut());
+    assert!(peer >= 0);
+    assert_eq!(dpoll_close(listener), 0);
+
+    let mut ev = epoll_event {
+        events: libc::EPOLLIN as u32,
+        u64: soc as u64,
+    };
+    assert_eq!(dpoll_ctl(pol, libc::EPOLL_CTL_MOD, soc, &mut ev), 0);
+    register(pol, peer, libc::EPOLLIN);
+    return (pol, soc, peer);
+}
+
+fn register(pol: c_int, fd: c_int, events: c_int) {
+    let mut ev = epoll_event {
+        events: events as u32,
+        u64: fd as u64,
+    };
+    assert_eq!(dpoll_ctl(pol, libc::EPOLL_CTL_ADD, fd, &mut ev), 0);
+}
+
+/// waits until `pol` reports `events` for `fd`, a pwait whose completions were all of other
+/// operations, e.g. pushes, times out without looking at the rest
+fn wait_for(pol: c_int, fd: c_int, wanted: c_int) {
+    let mut evs = [epoll_event { events: 0, u64: 0 }; 4];
+    for _ in 0..100 {
+        let n = dpoll_pwait(pol, evs.as_mut_ptr(), 4, 10, ptr::null());
+        assert!(n >= 0);
+        let ready = evs[..n as usize].iter().any(|ev| {
+            let (data, events) = (ev.u64, ev.events);
+            return data == fd as u64 && events & wanted as u32 != 0;
+        });
+        if ready {
+            return;
+        }
+    }
+    panic!("{fd} did not become ready");
+}
+
+fn iovecs(bufs: &mut [Vec<u8>]) -> Vec<iovec> {
+    return bufs
+        .iter_mut()
+        .map(|buf| iovec {
+            iov_base: buf.as_mut_ptr() as *mut c_void,
+            iov_len: buf.len(),
+        })
+        .collect();
+}
+
+#[test]
+fn vectored_empty_iovecs() {
+    let (pol, soc, peer) = connected(7201);
+
+    // empty iovecs first, in between and last, which writev skips instead of stopping at
+    let mut src: Vec<Vec<u8>> = [&b""[..], b"ab", b"", b"", b"cde", b""]
+        .iter()
+        .map(|s| s.to_vec())
+        .collect();
+    let vecs = iovecs(&mut src);
+    assert_eq!(dpoll_writev(soc, vecs.as_ptr(), vecs.len() as c_int), 5);
+
+    wait_for(pol, peer, libc::EPOLLIN);
+    let mut dst: Vec<Vec<u8>> = [0, 1, 0, 3, 0, 8].iter().map(|len| vec![0; *len]).collect();
+    let mut vecs = iovecs(&mut dst);
+    assert_eq!(dpoll_readv(peer, vecs.as_mut_ptr(), vecs.len() as c_int), 5);
+    assert_eq!(dst.concat()[..5], *b"abcde");
+
+    // only empty iovecs and none at all move nothing
+    let mut empty = vec![Vec::new(); 3];
+    let vecs = iovecs(&mut empty);
+    assert_eq!(dpoll_writev(soc, vecs.as_ptr(), 3), 0);
+    assert_eq!(dpoll_writev(soc, ptr::null(), 0), 0);
+    fails_with(dpoll_writev(soc, vecs.as_ptr(), -1), libc::EINVAL);
+    fails_with(dpoll_readv(peer, ptr::null_mut(), 1), libc::EFAULT);
+
+    for fd in [soc, peer, pol] {
+        assert_eq!(dpoll_close(fd), 0);
+    }
+}
+
+#[test]
+fn vectored_past_iov_max() {
+    let (pol, soc, peer) = connected(7202);
+
+    // more iovecs than IOV_MAX, of sizes crossing the segments of the pushes
+    let count = libc::UIO_MAXIOV as usize + 3;
+    let mut src: Vec<Vec<u8>> = (0..count)
+        .map(|i| (0..i % 5).map(|j| (i + j) as u8).collect())
+        .collect();
+    let total: usize = src.iter().map(Vec::len).sum();
+    let want = src.concat();
+    let vecs = iovecs(&mut src);
+    assert_eq!(
+        dpoll_writev(soc, vecs.as_ptr(), count as c_int),
+        total as isize
+    );
+
+    let mut got = Vec::new();
+    while got.len() < total {
+        wait_for(pol, peer, libc::EPOLLIN);
+        let mut dst: Vec<Vec<u8>> = (0..count).map(|i| vec![0; i % 3]).collect();
+        let mut vecs = iovecs(&mut dst);
+        let n = dpoll_readv(peer, vecs.as_mut_ptr(), count as c_int);
+        assert!(n > 0);
+        got.extend_from_slice(&dst.concat()[..n as usize]);
+    }
+    assert_eq!(got, want);
+
+    for fd in [soc, peer, pol] {
+        assert_eq!(dpoll_close(fd), 0);
+    }
+}
+
+static HANDLED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+extern "C" fn on_usr2(_: c_int) {
+    HANDLED.store(true, std::sync::atomic::Ordering::SeqCst);
+}
+
+/// the signal mask of the calling thread
+fn thread_mask() -> sigset_t {
+    let mut set: sigset_t = unsafe { mem::zeroed() };
+    assert_eq!(
+        unsafe { libc::pthread_sigmask(libc::SIG_SETMASK, ptr::null(), &mut set) },
+        0
+    );
+    return set;
+}
+
+#[test]
+fn pwait_sigmask() {
+    init();
+    let handled = || HANDLED.swap(false, std::sync::atomic::Ordering::SeqCst);
+    unsafe {
+        let mut act: libc::sigaction = mem::zeroed();
+        act.sa_sigaction = on_usr2 as extern "C" fn(c_int) as usize;
+        assert_eq!(libc::sigaction(libc::SIGUSR2, &act, ptr::null_mut()), 0);
+    }
+
+    // SIGUSR1 and SIGUSR2 stay blocked in the thread, but pending for it
+    let mut usr1: sigset_t = unsafe { mem::zeroed() };
+    unsafe {
+        libc::sigemptyset(&mut usr1);
+        libc::sigaddset(&mut usr1, libc::SIGUSR1);
+    }
+    let mut blocked = usr1;
+    unsafe {
+        libc::sigaddset(&mut blocked, libc::SIGUSR2);
+        assert_eq!(
+            libc::pthread_sigmask(libc::SIG_BLOCK, &blocked, ptr::null_mut()),
+            0
+        );
+        assert_eq!(libc::pthread_kill(libc::pthread_self(), libc::SIGUSR1), 0);
+    }
+    let old = thread_mask();
+
+    let sfd = unsafe { libc::signalfd(-1, &usr1, libc::SFD_NONBLOCK) };
+    assert!(sfd >= 0);
+    let pol = dpoll_create(0);
+    register(pol, sfd, libc::EPOLLIN);
+
+    // a mask blocking both leaves SIGUSR1 to the signalfd, which the kernel wait reports
+    let mut evs = [epoll_event { events: 0, u64: 0 }; 2];
+    assert_eq!(dpoll_pwait(pol, evs.as_mut_ptr(), 2, 1000, &blocked), 1);
+    assert_eq!({ evs[0].u64 }, sfd as u64);
+    let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+    let size = mem::size_of::<libc::signalfd_siginfo>();
+    let read = unsafe { libc::read(sfd, &raw mut info as *mut c_void, size) };
+    assert_eq!(
+        (read as usize, info.ssi_signo),
+        (size, libc::SIGUSR1 as u32)
+    );
+    assert!(!handled());
+
+    // a mask unblocking SIGUSR2 has the kernel wait take it, as epoll_pwait does
+    unsafe { assert_eq!(libc::pthread_kill(libc::pthread_self(), libc::SIGUSR2), 0) };
+    fails_with(
+        dpoll_pwait(pol, evs.as_mut_ptr(), 2, 1000, &usr1),
+        libc::EINTR,
+    );
+    assert!(handled());
+    assert_eq!(
+        unsafe { libc::sigismember(&thread_mask(), libc::SIGUSR2) },
+        1
+    );
+
+    // without kernel fds only the demikernel wait blocks, and takes it as well
+    let idle = dpoll_create(0);
+    let listener = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    let mut addr: sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_family = AF_INET as libc::sa_family_t;
+    addr.sin_port = 7203u16.to_be();
+    let addr_len = mem::size_of::<sockaddr_in>() as socklen_t;
+    assert_eq!(
+        dpoll_bind(listener, &raw const addr as *const sockaddr, addr_len),
+        0
+    );
+    assert_eq!(dpoll_listen(listener, 1), 0);
+    register(idle, listener, libc::EPOLLIN);
+    unsafe { assert_eq!(libc::pthread_kill(libc::pthread_self(), libc::SIGUSR2), 0) };
+    assert_eq!(dpoll_pwait(idle, evs.as_mut_ptr(), 2, 10, &usr1), 0);
+    assert!(handled());
+
+    // the mask of the thread is the same after every wait, and nothing else was delivered
+    let now = thread_mask();
+    for sig in 1..libc::SIGRTMIN() {
+        let member = |set: &sigset_t| unsafe { libc::sigismember(set, sig) };
+        assert_eq!(member(&now), member(&old), "signal {sig}");
+    }
+    assert!(!handled());
+
+    for fd in [listener, idle, pol] {
+        assert_eq!(dpoll_close(fd), 0);
+    }
+    unsafe {
+        libc::close(sfd);
+        libc::pthread_sigmask(libc::SIG_UNBLOCK, &blocked, ptr::null_mut());
+    }
+}
+
+#[test]
+fn pwait_kernel_fds_not_starved() {
+    let (pol, soc, peer) = connected(7204);
+    let efd = unsafe { libc::eventfd(1, libc::EFD_NONBLOCK) };
+    assert!(efd >= 0);
+    register(pol, efd, libc::EPOLLIN);
+
+    // the peer stays readable and would fill every pwait of a single event by itself
+    assert_eq!(dpoll_write(soc, b"ab".as_ptr() as *const c_void, 2), 2);
+    wait_for(pol, peer, libc::EPOLLIN);
+    let mut ev = [epoll_event { events: 0, u64: 0 }; 1];
+    let mut reported = Vec::new();
+    for _ in 0..4 {
+        assert_eq!(dpoll_pwait(pol, ev.as_mut_ptr(), 1, 0, ptr::null()), 1);
+        reported.push(ev[0].u64 as c_int);
+    }
+    // whichever went first, the two take turns
+    assert!(reported.contains(&efd) && reported.contains(&peer));
+    assert!(
+        reported.windows(2).all(|pair| pair[0] != pair[1]),
+        "{reported:?}"
+    );
+
+    for fd in [soc, peer, pol] {
+        assert_eq!(dpoll_close(fd), 0);
+    }
+    unsafe { libc::close(efd) };
+}
+
+#[test]
+fn pwait_phases_share_deadline() {
+    init();
+    // the time pwait reads stands still, only the waits themselves take real time
+    clock::install(Some(Box::new(clock::MockClock::new())));
+    let pol = dpoll_create(0);
+    let listener = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    let mut addr: sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_family = AF_INET as libc::sa_family_t;
+    addr.sin_port = 7205u16.to_be();
+    let addr_len = mem::size_of::<sockaddr_in>() as socklen_t;
+    assert_eq!(
+        dpoll_bind(listener, &raw const addr as *const sockaddr, addr_len),
+        0
+    );
+    assert_eq!(dpoll_listen(listener, 1), 0);
+    register(pol, listener, libc::EPOLLIN);
+    let efd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK) };
+    register(pol, efd, libc::EPOLLIN);
+
+    // the demikernel phase takes the whole timeout, after which the kernel fds are only polled
+    // instead of waited on for the timeout again
+    let mut evs = [epoll_event { events: 0, u64: 0 }; 2];
+    let start = Instant::now();
+    assert_eq!(dpoll_pwait(pol, evs.as_mut_ptr(), 2, 50, ptr::null()), 0);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(50), "{elapsed:?}");
+    assert!(elapsed < Duration::from_millis(100), "{elapsed:?}");
+
+    for fd in [listener, pol] {
+        assert_eq!(dpoll_close(fd), 0);
+    }
+    unsafe { libc::close(efd) };
+    clock::install(None);
+}
+
+#[test]
+fn ctl_errnos() {
+    init();
+    let pol = dpoll_create(0);
+    let soc = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    let efd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK) };
+    let epfd = unsafe { libc::epoll_create1(0) };
+    assert!(pol >= 0 && soc >= 0 && efd >= 0 && epfd >= 0);
+
+    // dpoll sockets and kernel fds behave like a kernel epoll does for kernel fds
+    for fd in [soc, efd] {
+        for (op, code) in [
+            (libc::EPOLL_CTL_MOD, libc::ENOENT),
+            (libc::EPOLL_CTL_DEL, libc::ENOENT),
+            (libc::EPOLL_CTL_ADD, 0),
+            (libc::EPOLL_CTL_ADD, libc::EEXIST),
+            (libc::EPOLL_CTL_MOD, 0),
+            (libc::EPOLL_CTL_DEL, 0),
+            (libc::EPOLL_CTL_DEL, libc::ENOENT),
+            (libc::EPOLL_CTL_MOD, libc::ENOENT),
+            (libc::EPOLL_CTL_ADD, 0),
+        ] {
+            let mut ev = epoll_event {
+                events: libc::EPOLLIN as u32,
+                u64: fd as u64,
+            };
+            let ret = dpoll_ctl(pol, op, fd, &mut ev);
+            if fd == efd {
+                let kernel = unsafe { libc::epoll_ctl(epfd, op, fd, &mut ev) };
+                assert_eq!(kernel, ret, "op {op}");
+            }
+            match code {
+                0 => assert_eq!(ret, 0, "op {op} on {fd}"),
+                code => fails_with(ret, code),
+            }
+        }
+    }
+
+    // a failed ctl leaves the registration as it was
+    let mut ev = epoll_event {
+        events: libc::EPOLLOUT as u32,
+        u64: 7,
+    };
+    fails_with(
+        dpoll_ctl(pol, libc::EPOLL_CTL_ADD, soc, &mut ev),
+        libc::EEXIST,
+    );
+    let mut regs: [dpoll_registration; 2] = unsafe { mem::zeroed() };
+    assert_eq!(dpoll_list(pol, regs.as_mut_ptr(), 2), 2);
+    assert_eq!((regs[0].fd, regs[0].data), (soc, soc as u64));
+    assert_eq!(regs[0].events, libc::EPOLLIN as u32);
+
+    // nor does a dpoll take itself or an unknown op
+    fails_with(
+        dpoll_ctl(pol, libc::EPOLL_CTL_ADD, pol, &mut ev),
+        libc::EINVAL,
+    );
+    fails_with(dpoll_ctl(pol, 42, soc, &mut ev), libc::EINVAL);
+
+    for fd in [soc, pol] {
+        assert_eq!(dpoll_close(fd), 0);
+    }
+    unsafe {
+        libc::close(efd);
+        libc::close(epfd);
+    }
+}
+
+#[test]
+fn read_until_eof() {
+    let (pol, soc, peer) = connected(7206);
+    let mut buf = [0u8; 8];
+    let dst = buf.as_mut_ptr() as *mut c_void;
+    fails_with(dpoll_read(peer, dst, 8), libc::EWOULDBLOCK);
+
+    // the peer closes with data still on its way
+    assert_eq!(dpoll_write(soc, b"abcde".as_ptr() as *const c_void, 5), 5);
+    assert_eq!(dpoll_close(soc), 0);
+    wait_for(pol, peer, libc::EPOLLIN);
+    assert_eq!(dpoll_read(peer, dst, 2), 2);
+    let mut got = buf[..2].to_vec();
+    loop {
+        let read = dpoll_read(peer, dst, 8);
+        assert!(read >= 0, "{}", dpoll_errno());
+        if read == 0 {
+            break;
+        }
+        got.extend_from_slice(&buf[..read as usize]);
+    }
+    assert_eq!(got, b"abcde");
+
+    // the end of the stream stays readable, and reads keep returning it
+    wait_for(pol, peer, libc::EPOLLIN);
+    assert_eq!(dpoll_read(peer, dst, 8), 0);
+    let mut vec = iovec {
+        iov_base: dst,
+        iov_len: 8,
+    };
+    assert_eq!(dpoll_readv(peer, &mut vec, 1), 0);
+
+    for fd in [peer, pol] {
+        assert_eq!(dpoll_close(fd), 0);
+    }
+}
+
+#[test]
+fn ctl_batch_short_count() {
+    init();
+    let pol = dpoll_create(0);
+    let soc = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    let other = dpoll_socket(AF_INET, SOCK_STREAM, 0);
+    assert!(pol >= 0 && soc >= 0 && other >= 0);
+    let op = |op, fd| {
+        let event = epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: fd as u64,
+        };
+        return dpoll_ctl_op { op, fd, event };
+    };
+
+    // the dpoll rejects the second op, the third is never applied
+    let mut ops = [
+        op(libc::EPOLL_CTL_ADD, soc),
+        op(libc::EPOLL_CTL_ADD, soc),
+        op(libc::EPOLL_CTL_ADD, other),
+    ];
+    assert_eq!(dpoll_ctl_batch(pol, ops.as_mut_ptr(), 3), 1);
+    assert_eq!(dpoll_errno(), libc::EEXIST);
+    assert_eq!(dpoll_list(pol, ptr::null_mut(), 0), 1);
+
+    // as does the check of the fds before the dpoll sees any op
+    let mut ops = [op(libc::EPOLL_CTL_ADD, other), op(libc::EPOLL_CTL_ADD, -1)];
+    assert_eq!(dpoll_ctl_batch(pol, ops.as_mut_ptr(), 2), 1);
+    assert_eq!(dpoll_errno(), EBADF);
+    assert_eq!(dpoll_list(pol, ptr::null_mut(), 0), 2);
+
+    // a first op failing fails the call
+    fails_with(dpoll_ctl_batch(pol, ops.as_mut_ptr(), 2), libc::EEXIST);
+
+    for fd in [soc, other, pol] {
+        assert_eq!(dpoll_close(fd), 0);
+    }
+}
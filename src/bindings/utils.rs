@@ -1,42 +1,213 @@
-use std::mem::{self, MaybeUninit};
+use std::{
+    cell::Cell,
+    ffi::{CStr, CString},
+    mem,
+    panic::{self, AssertUnwindSafe},
+    process, ptr,
+};
 
-use libc::{c_int, sockaddr, sockaddr_in, socklen_t};
-use log::trace;
+use lazy_static::lazy_static;
 
-use crate::wrappers::errno::{PosixError, PosixResult};
+use libc::{c_char, c_int, iovec, sockaddr, sockaddr_in, socklen_t, ssize_t};
+use log::{error, trace};
 
-pub fn cast_sockaddr<'a>(
+use crate::{
+    config::Config,
+    dpoll::history,
+    wrappers::{
+        errno::{PosixError, PosixResult},
+        platform,
+    },
+};
+
+/// where accept and getsockname write an address
+///
+/// like POSIX requires, the address is truncated to the buffer of the caller while the full length
+/// is always written back, so a too small buffer can be detected
+#[derive(Debug)]
+pub struct SockaddrOut {
     addr: *mut sockaddr,
     len: *mut socklen_t,
-) -> Option<&'a mut MaybeUninit<sockaddr_in>> {
-    assert_eq!(addr.is_null(), len.is_null());
-    if addr.is_null() {
-        return None;
+}
+
+impl SockaddrOut {
+    /// `None` if `addr` is NULL, fails with EFAULT if only `len` is
+    pub fn new(addr: *mut sockaddr, len: *mut socklen_t) -> PosixResult<Option<Self>> {
+        if addr.is_null() {
+            return Ok(None);
+        }
+        if len.is_null() {
+            return Err(PosixError::FAULT);
+        }
+
+        return Ok(Some(Self { addr, len }));
+    }
+
+    /// copies as much of `val` as fits into the buffer and sets the length to the full one
+    pub fn write(self, val: &sockaddr_in) {
+        let full = mem::size_of::<sockaddr_in>();
+        unsafe {
+            let fits = (self.len.read() as usize).min(full);
+            ptr::copy_nonoverlapping(
+                (val as *const sockaddr_in).cast::<u8>(),
+                self.addr.cast::<u8>(),
+                fits,
+            );
+            self.len.write(full as socklen_t);
+        }
+    }
+}
+
+/// validates `count` iovecs like readv/writev do and returns their total length, dpoll sockets go
+/// through the iovecs with a `demi::IovecCursor` and take more than IOV_MAX of them
+///
+/// fails with EINVAL if `count` is negative or if the total overflows ssize_t, and with EFAULT if
+/// `vecs` is NULL
+pub fn iovecs_len(vecs: *const iovec, count: c_int) -> PosixResult<usize> {
+    if count.is_negative() {
+        return Err(PosixError::INVAL);
+    }
+
+    if count == 0 {
+        return Ok(0);
     }
 
-    assert!(*unsafe { len.as_ref().unwrap() } as usize >= mem::size_of::<sockaddr_in>());
+    if vecs.is_null() {
+        return Err(PosixError::FAULT);
+    }
+    let vecs = unsafe { std::slice::from_raw_parts(vecs, count as usize) };
+
+    return vecs
+        .iter()
+        .try_fold(0usize, |total, vec| total.checked_add(vec.iov_len))
+        .filter(|total| *total <= isize::MAX as usize)
+        .ok_or(PosixError::INVAL);
+}
+
+thread_local! {
+    /// the last error set by `set_errno`, read by `dpoll_errno`
+    static LAST_ERROR: Cell<c_int> = const { Cell::new(0) };
+}
+
+lazy_static! {
+    /// the messages of the codes up to the last one linux defines, see `strerror`
+    static ref MESSAGES: Vec<CString> = (0..=PosixError::HWPOISON as c_int)
+        .map(|code| {
+            let msg = match PosixError::from_code(code) {
+                Some(err) => err.to_string(),
+                None if code == 0 => "Success".to_owned(),
+                None => format!("Unknown error {code}"),
+            };
+            CString::new(msg).unwrap()
+        })
+        .collect();
+}
+
+/// sets both errno and the error returned by `last_error`, every error reported by the C ABI goes
+/// through here
+pub fn set_errno(code: c_int) {
+    platform::set_errno(code);
+    LAST_ERROR.set(code);
+}
 
-    return unsafe { (addr as *mut sockaddr_in).as_uninit_mut() };
+pub fn last_error() -> c_int {
+    return LAST_ERROR.get();
+}
+
+pub fn strerror(code: c_int) -> &'static CStr {
+    return usize::try_from(code)
+        .ok()
+        .and_then(|code| MESSAGES.get(code))
+        .map_or(c"Unknown error", |msg| msg.as_c_str());
 }
 
 pub fn errno(err: PosixError) -> c_int {
-    unsafe {
-        *libc::__errno_location() = err.into();
-    }
+    set_errno(err.into());
     return -1;
 }
 
 /// returns 0 or -1, sets errno on error
 pub fn result_as_errno(result: PosixResult<()>) -> c_int {
     trace!("result: {:?}", result);
-    let error_code: c_int = match result {
-        Ok(_) => return 0,
-        Err(e) => e.into(),
+    return match result {
+        Ok(_) => 0,
+        Err(e) => errno(e),
     };
+}
+
+/// what a binding returns when it panicked, next to the errno `guard` sets
+pub trait Panicked {
+    const PANICKED: Self;
+}
+
+impl Panicked for c_int {
+    const PANICKED: Self = -1;
+}
+
+impl Panicked for ssize_t {
+    const PANICKED: Self = -1;
+}
+
+/// only the versions and capabilities return them, 0 is none of them
+impl Panicked for u32 {
+    const PANICKED: Self = 0;
+}
 
-    unsafe {
-        *libc::__errno_location() = error_code;
+impl Panicked for u64 {
+    const PANICKED: Self = 0;
+}
+
+impl Panicked for *const c_char {
+    const PANICKED: Self = ptr::null();
+}
+
+impl Panicked for () {
+    const PANICKED: Self = ();
+}
+
+/// runs the body of the binding `name`, catching a panic before it unwinds into C
+///
+/// the payload is logged and the call fails with EFAULT. the bindings check their arguments
+/// themselves, negative and forged fds included, so this only catches a bug. with the
+/// abort_on_panic config the process aborts instead, whatever panicked might have left a dpoll or
+/// socket half updated for the calls after it, dumping the history of the dpolls first, which the
+/// panic hook leaves to it
+pub fn guard<R, F>(name: &str, func: F) -> R
+where
+    R: Panicked,
+    F: FnOnce() -> R,
+{
+    let outer = history::set_caught(true);
+    let res = panic::catch_unwind(AssertUnwindSafe(func));
+    history::set_caught(outer);
+    let payload = match res {
+        Ok(ret) => return ret,
+        Err(payload) => payload,
+    };
+
+    let msg = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("a payload that is not a string");
+    // `recorded` names the bindings after the variants of `recorder::Call`, e.g. dpoll_Readv
+    let name = name.to_lowercase();
+    error!("{name} panicked: {msg}");
+    if Config::current().abort_on_panic {
+        eprintln!("dpoll: {name} panicked, aborting");
+        history::dump();
+        process::abort();
     }
 
-    return -1;
+    set_errno(PosixError::FAULT.into());
+    return R::PANICKED;
+}
+
+/// `None` for a null or non UTF-8 string
+pub unsafe fn c_str<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        return None;
+    }
+
+    return unsafe { CStr::from_ptr(ptr) }.to_str().ok();
 }
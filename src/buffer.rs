@@ -2,6 +2,8 @@ use bitfields::bitfield;
 use log::trace;
 use std::{default::Default, mem};
 
+use crate::wrappers::errno::{PosixError, PosixResult};
+
 pub struct Buffer<const S: bool, T> {
     items: Vec<Entry<T>>,
     next_free: Option<usize>,
@@ -40,29 +42,39 @@ impl<const S: bool, T> Buffer<S, T> {
         return idx;
     }
 
-    pub fn take(&mut self, idx: Index) -> T {
-        assert!(idx.is_dpoll());
+    /// `None` if `idx` does not point to a live item
+    pub fn take(&mut self, idx: Index) -> Option<T> {
+        if !idx.is_dpoll() {
+            return None;
+        }
         let next_free = self.next_free;
-        self.next_free = Some(idx.index() as usize);
-        let entry = self.get_entry_mut(idx).unwrap();
-
-        assert!(idx.generation() == entry.generation);
+        let entry = self.get_entry_mut(idx)?;
+        if matches!(entry.field, Field::Free(_)) {
+            return None;
+        }
 
         let item = match mem::replace(&mut entry.field, Field::Free(next_free)) {
             Field::Item(it) => it,
-            Field::Free(_) => panic!("trying to take an already existing item"),
+            Field::Free(_) => unreachable!(),
         };
+        // like `free`, the fd of the next item in the slot must not match the taken one
+        entry.generation = entry.generation.next();
+        self.next_free = Some(idx.index() as usize);
 
-        return item;
+        return Some(item);
     }
 
-    pub fn free(&mut self, idx: Index) {
-        assert!(idx.is_dpoll());
+    /// `None` if `idx` does not point to a live item, e.g. on a double free
+    #[allow(dead_code)]
+    pub fn free(&mut self, idx: Index) -> Option<()> {
+        if !idx.is_dpoll() {
+            return None;
+        }
         let next_free = self.next_free;
-        let entry = self.get_entry_mut(idx).unwrap();
-
-        if entry.generation != idx.generation() || matches!(entry.field, Field::Free(_)) {
-            panic!("trying to double free or free an old item: {idx:?}");
+        let entry = self.get_entry_mut(idx)?;
+        if matches!(entry.field, Field::Free(_)) {
+            trace!("trying to double free {idx:?}");
+            return None;
         }
 
         *entry = Entry {
@@ -70,6 +82,8 @@ impl<const S: bool, T> Buffer<S, T> {
             field: Field::Free(next_free),
         };
         self.next_free = Some(idx.index() as usize);
+
+        return Some(());
     }
 
     pub fn get(&self, idx: Index) -> Option<&T> {
@@ -83,6 +97,7 @@ impl<const S: bool, T> Buffer<S, T> {
         };
     }
 
+    #[allow(dead_code)]
     pub fn get_mut(&mut self, idx: Index) -> Option<&mut T> {
         if !idx.is_dpoll() {
             return None;
@@ -93,8 +108,26 @@ impl<const S: bool, T> Buffer<S, T> {
         };
     }
 
+    /// yields every live item with its index
+    #[allow(dead_code)]
+    pub fn iter(&self) -> impl Iterator<Item = (Index, &T)> {
+        return self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| match &entry.field {
+                Field::Item(it) => Some((Index::from_parts(i, entry.generation, S), it)),
+                Field::Free(_) => None,
+            });
+    }
+
+    /// `None` for indices past the end and of the other kind of buffer as well, fds can be forged
+    /// by the application
     fn get_entry(&self, idx: Index) -> Option<&Entry<T>> {
-        let entry = &self.items[idx.index() as usize];
+        if idx.is_socket() != S {
+            return None;
+        }
+        let entry = self.items.get(idx.index() as usize)?;
         if entry.generation != idx.generation() {
             return None;
         }
@@ -102,7 +135,10 @@ impl<const S: bool, T> Buffer<S, T> {
     }
 
     fn get_entry_mut(&mut self, idx: Index) -> Option<&mut Entry<T>> {
-        let entry = &mut self.items[idx.index() as usize];
+        if idx.is_socket() != S {
+            return None;
+        }
+        let entry = self.items.get_mut(idx.index() as usize)?;
         if entry.generation != idx.generation() {
             return None;
         }
@@ -178,6 +214,11 @@ pub struct Index {
 }
 
 impl Index {
+    /// the raw generation, for diagnostics
+    pub fn generation_number(&self) -> u8 {
+        return self.generation().into_bits();
+    }
+
     fn from_parts(index: usize, gene: Generation, is_socket: bool) -> Self {
         return IndexBuilder::new()
             .with_index(index.try_into().unwrap())
@@ -187,9 +228,13 @@ impl Index {
     }
 }
 
-impl std::convert::From<i32> for Index {
-    fn from(value: i32) -> Self {
-        return Self::from_bits(value.try_into().expect("a fd cannot be negative"));
+/// EBADF for a negative fd, which the application might pass for any of them
+impl TryFrom<i32> for Index {
+    type Error = PosixError;
+
+    fn try_from(value: i32) -> PosixResult<Self> {
+        let bits = u32::try_from(value).map_err(|_| PosixError::BADF)?;
+        return Ok(Self::from_bits(bits));
     }
 }
 
@@ -0,0 +1,266 @@
+//! the tunables of dpoll, bootstrapped from `DPOLL_<KEY>` environment variables by `dpoll_init`
+//! and changed at runtime with `dpoll_configure`
+//!
+//! changes apply to sockets and dpolls created afterwards
+
+use std::{env, fmt::Write, sync::RwLock, time::Duration};
+
+use log::trace;
+use thiserror::Error;
+
+use crate::{
+    dpoll::DEFAULT_WAIT_SHARDS,
+    keepalive::Keepalive,
+    recv_queue::DEFAULT_RCVBUF,
+    send_queue::DEFAULT_SEND_QUEUE_DEPTH,
+    watchdog::Watchdog,
+    wrappers::{backend::Backend, demi::SgArray, errno::PosixError, sga_pool::PoolConfig},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// pushes a socket can have in flight before writes would block
+    pub send_queue_depth: usize,
+    /// the idle budget new dpolls start with, see `Dpoll::set_max_idle`
+    pub max_idle: Option<Duration>,
+    /// the keepalive settings new sockets start with
+    pub keepalive: Keepalive,
+    /// whether new sockets pop as soon as EPOLLIN is requested, see `DPOLL_SO_AUTOPOP`
+    pub auto_pop: bool,
+    /// the received bytes a socket buffers before it stops popping, it resumes below half of it
+    pub rcvbuf: usize,
+    /// completions new dpolls process per demikernel wait, see `Dpoll::set_max_completions`
+    pub max_completions_per_wait: usize,
+    /// the shards new dpolls poll their tokens in at most, see `dpoll::shards`
+    pub wait_shards: usize,
+    /// accepts new dpolls complete per pwait, see `Dpoll::set_max_accepts`
+    pub max_accepts_per_wait: Option<usize>,
+    /// the sockets new dpolls register at most, see `Dpoll::set_max_items`
+    pub max_items: Option<usize>,
+    /// the watchdog new dpolls start with, see `Dpoll::set_watchdog`
+    pub watchdog: Watchdog,
+    /// how the writes of new sockets use the sga pool of their thread
+    pub sga_pool: PoolConfig,
+    /// whether a panic in a binding aborts the process instead of failing the call, see
+    /// `bindings::utils::guard`
+    pub abort_on_panic: bool,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("unknown config key {0:?}")]
+    UnknownKey(String),
+    #[error("invalid value {value:?} for {key}")]
+    InvalidValue { key: &'static str, value: String },
+}
+
+impl From<ConfigError> for PosixError {
+    fn from(err: ConfigError) -> Self {
+        return match err {
+            ConfigError::UnknownKey(_) => PosixError::NOENT,
+            ConfigError::InvalidValue { .. } => PosixError::INVAL,
+        };
+    }
+}
+
+static CONFIG: RwLock<Config> = RwLock::new(Config::new());
+
+/// the environment variables read outside of the config, by `dpoll_init` or the features using
+/// them, `Config::dump` lists the ones that are set
+pub const ENV_VARS: [&str; 8] = [
+    "DPOLL_LIBOS",
+    "LIBOS",
+    "DPOLL_LOG",
+    "RUST_LOG",
+    "DPOLL_RECORD_FILE",
+    "DPOLL_METRICS_FILE",
+    "DPOLL_FAULTS",
+    "DPOLL_REACTOR_CPU",
+];
+
+impl Config {
+    pub const KEYS: [&str; 16] = [
+        "send_queue_depth",
+        "max_idle_ms",
+        "keepalive",
+        "keepalive_idle",
+        "keepalive_interval",
+        "keepalive_count",
+        "auto_pop",
+        "rcvbuf",
+        "max_completions_per_wait",
+        "wait_shards",
+        "max_accepts_per_wait",
+        "max_items",
+        "watchdog_ms",
+        "watchdog_fail",
+        "sga_pool",
+        "abort_on_panic",
+    ];
+
+    pub const fn new() -> Self {
+        return Self {
+            send_queue_depth: DEFAULT_SEND_QUEUE_DEPTH,
+            max_idle: None,
+            keepalive: Keepalive::new(),
+            auto_pop: true,
+            rcvbuf: DEFAULT_RCVBUF,
+            max_completions_per_wait: 1,
+            wait_shards: DEFAULT_WAIT_SHARDS,
+            max_accepts_per_wait: None,
+            max_items: None,
+            watchdog: Watchdog::new(),
+            sga_pool: PoolConfig::off(),
+            abort_on_panic: false,
+        };
+    }
+
+    /// a snapshot of the current config
+    pub fn current() -> Self {
+        return *CONFIG.read().unwrap();
+    }
+
+    /// sets `key` to `value`, leaving the config untouched if it fails
+    pub fn configure(key: &str, value: &str) -> Result<(), ConfigError> {
+        let mut config = CONFIG.write().unwrap();
+        let mut new = *config;
+        new.set(key, value)?;
+
+        trace!("config {key} set to {value}");
+        *config = new;
+        return Ok(());
+    }
+
+    /// reads `DPOLL_<KEY>` for every key, applying all of them or none
+    pub fn load_env() -> Result<(), ConfigError> {
+        let mut config = CONFIG.write().unwrap();
+        let mut new = *config;
+        for key in Self::KEYS {
+            if let Ok(value) = env::var(format!("DPOLL_{}", key.to_uppercase())) {
+                new.set(key, &value)?;
+            }
+        }
+
+        *config = new;
+        return Ok(());
+    }
+
+    pub fn get(&self, key: &str) -> Result<String, ConfigError> {
+        let ka = &self.keepalive;
+        let value = match key {
+            "send_queue_depth" => self.send_queue_depth.to_string(),
+            "max_idle_ms" => self.max_idle.map_or(0, |d| d.as_millis()).to_string(),
+            "keepalive" => (ka.enabled as u8).to_string(),
+            "keepalive_idle" => ka.idle.as_secs().to_string(),
+            "keepalive_interval" => ka.interval.as_secs().to_string(),
+            "keepalive_count" => ka.count.to_string(),
+            "auto_pop" => (self.auto_pop as u8).to_string(),
+            "rcvbuf" => self.rcvbuf.to_string(),
+            "max_completions_per_wait" => self.max_completions_per_wait.to_string(),
+            "wait_shards" => self.wait_shards.to_string(),
+            "max_accepts_per_wait" => self.max_accepts_per_wait.unwrap_or(0).to_string(),
+            "max_items" => self.max_items.unwrap_or(0).to_string(),
+            "watchdog_ms" => self
+                .watchdog
+                .threshold
+                .map_or(0, |d| d.as_millis())
+                .to_string(),
+            "watchdog_fail" => (self.watchdog.fail as u8).to_string(),
+            "sga_pool" => match self.sga_pool.prewarm {
+                Some((size, count)) => format!("{size}x{count}"),
+                None => self.sga_pool.per_class.to_string(),
+            },
+            "abort_on_panic" => (self.abort_on_panic as u8).to_string(),
+            _ => return Err(ConfigError::UnknownKey(key.to_owned())),
+        };
+
+        return Ok(value);
+    }
+
+    /// every key with its value, one `key=value` per line, then the backend in use and the set
+    /// `ENV_VARS`, so bug reports carry the exact configuration
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        for key in Self::KEYS {
+            writeln!(out, "{key}={}", self.get(key).unwrap()).unwrap();
+        }
+        writeln!(out, "backend={}", Backend::current().name()).unwrap();
+        for var in ENV_VARS {
+            if let Ok(value) = env::var(var) {
+                writeln!(out, "{var}={value}").unwrap();
+            }
+        }
+
+        return out;
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let Some(&key) = Self::KEYS.iter().find(|k| **k == key) else {
+            return Err(ConfigError::UnknownKey(key.to_owned()));
+        };
+        let invalid = || ConfigError::InvalidValue {
+            key,
+            value: value.to_owned(),
+        };
+        // <size>x<count> prewarms the pool with count sgas of the class of size
+        if key == "sga_pool"
+            && let Some((size, count)) = value.trim().split_once('x')
+        {
+            let size: usize = size.parse().map_err(|_| invalid())?;
+            let count: usize = count.parse().map_err(|_| invalid())?;
+            if size == 0 || size > SgArray::MAX_LEN || count == 0 {
+                return Err(invalid());
+            }
+            self.sga_pool = PoolConfig {
+                per_class: count,
+                prewarm: Some((size, count)),
+            };
+            return Ok(());
+        }
+        let num: u64 = value.trim().parse().map_err(|_| invalid())?;
+
+        let ka = &mut self.keepalive;
+        match key {
+            "max_idle_ms" => self.max_idle = (num > 0).then(|| Duration::from_millis(num)),
+            "keepalive" if num > 1 => return Err(invalid()),
+            "keepalive" => ka.enabled = num == 1,
+            "auto_pop" if num > 1 => return Err(invalid()),
+            "auto_pop" => self.auto_pop = num == 1,
+            "watchdog_ms" => {
+                self.watchdog.threshold = (num > 0).then(|| Duration::from_millis(num))
+            }
+            "watchdog_fail" if num > 1 => return Err(invalid()),
+            "watchdog_fail" => self.watchdog.fail = num == 1,
+            "abort_on_panic" if num > 1 => return Err(invalid()),
+            "abort_on_panic" => self.abort_on_panic = num == 1,
+            "sga_pool" => {
+                self.sga_pool = PoolConfig {
+                    per_class: num.try_into().map_err(|_| invalid())?,
+                    prewarm: None,
+                }
+            }
+            "max_accepts_per_wait" => {
+                let max = num.try_into().map_err(|_| invalid())?;
+                self.max_accepts_per_wait = (max > 0).then_some(max);
+            }
+            "max_items" => {
+                let max = num.try_into().map_err(|_| invalid())?;
+                self.max_items = (max > 0).then_some(max);
+            }
+            // the rest have to be positive
+            _ if num == 0 => return Err(invalid()),
+            "send_queue_depth" => self.send_queue_depth = num.try_into().map_err(|_| invalid())?,
+            "keepalive_idle" => ka.idle = Duration::from_secs(num),
+            "keepalive_interval" => ka.interval = Duration::from_secs(num),
+            "keepalive_count" => ka.count = num.try_into().map_err(|_| invalid())?,
+            "rcvbuf" => self.rcvbuf = num.try_into().map_err(|_| invalid())?,
+            "max_completions_per_wait" => {
+                self.max_completions_per_wait = num.try_into().map_err(|_| invalid())?
+            }
+            "wait_shards" => self.wait_shards = num.try_into().map_err(|_| invalid())?,
+            _ => unreachable!(),
+        }
+
+        return Ok(());
+    }
+}
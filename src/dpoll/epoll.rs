@@ -1,17 +1,28 @@
-use std::{mem::MaybeUninit, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    mem::MaybeUninit,
+    str::SplitWhitespace,
+};
 
-use libc::epoll_event;
+use libc::{
+    EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLLERR, EPOLLHUP, c_int, epoll_event, pollfd, sigset_t,
+};
 use log::trace;
 
 use crate::{
     dpoll::operation::EpollOperation,
-    wrappers::errno::{PosixError, PosixResult},
+    wrappers::{
+        deadline::Deadline,
+        errno::{PosixError, PosixResult},
+    },
 };
 
-#[repr(transparent)]
 #[derive(Debug)]
 pub struct Epoll {
     fd: i32,
+    /// fds added and not deleted since, only fds closed through `dpoll_close` are forgotten, see
+    /// `forget`
+    registered: HashSet<i32>,
 }
 
 impl Drop for Epoll {
@@ -30,33 +41,112 @@ impl Epoll {
         }
 
         trace!("new epoll: {fd}");
-        return Ok(Self { fd });
+        return Ok(Self {
+            fd,
+            registered: HashSet::new(),
+        });
+    }
+
+    pub fn fd(&self) -> i32 {
+        return self.fd;
+    }
+
+    /// whether no fd is registered, so waiting can only time out
+    pub fn is_empty(&self) -> bool {
+        return self.registered.is_empty();
     }
 
     pub fn ctl(&mut self, op: EpollOperation) -> PosixResult<()> {
         let EpollOperation { op, fd, event } = op;
         let res = unsafe { libc::epoll_ctl(self.fd, op, fd, event) };
 
-        return if res.is_negative() {
-            PosixError::from_errno()
-        } else {
-            Ok(())
+        if res.is_negative() {
+            return PosixError::from_errno();
+        }
+
+        match op {
+            EPOLL_CTL_ADD => self.registered.insert(fd),
+            EPOLL_CTL_DEL => self.registered.remove(&fd),
+            _ => false,
         };
+        return Ok(());
+    }
+
+    /// deletes `fd` before it is closed, the kernel would keep it registered while another fd
+    /// refers to the same file, e.g. a dup of it
+    pub fn forget(&mut self, fd: i32) {
+        if self.registered.remove(&fd) {
+            trace!("forgetting {fd} in {}", self.fd);
+            unsafe { libc::epoll_ctl(self.fd, EPOLL_CTL_DEL, fd, std::ptr::null_mut()) };
+        }
+    }
+
+    /// yields the registered fds sorted, with the events and data the kernel keeps for them and
+    /// the registered events that are ready now
+    ///
+    /// the events and data come from the fdinfo of the epoll, they are 0 if /proc is not mounted,
+    /// and the kernel adds ERR and HUP to the events of every fd
+    pub fn registrations(&self) -> Vec<(c_int, u32, u64, u32)> {
+        let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", self.fd));
+        let kept = fdinfo.map_or_else(|_| HashMap::new(), |info| parse_fdinfo(&info));
+
+        let mut fds: Vec<c_int> = self.registered.iter().copied().collect();
+        fds.sort_unstable();
+        let mut polled: Vec<pollfd> = fds
+            .iter()
+            .map(|fd| pollfd {
+                fd: *fd,
+                // poll reports errors and hangups regardless, only the bits it knows are kept
+                events: kept.get(fd).map_or(0, |(evs, _)| *evs as i16),
+                revents: 0,
+            })
+            .collect();
+        let res = unsafe { libc::poll(polled.as_mut_ptr(), polled.len() as libc::nfds_t, 0) };
+        if res.is_negative() {
+            trace!(
+                "polling the fds of {} failed with {:?}",
+                self.fd,
+                PosixError::from_errno()
+            );
+            polled.iter_mut().for_each(|p| p.revents = 0);
+        }
+
+        return polled
+            .into_iter()
+            .map(|p| {
+                let (evs, data) = kept.get(&p.fd).copied().unwrap_or((0, 0));
+                let ready = (p.revents as u16 as u32) & (evs | (EPOLLERR | EPOLLHUP) as u32);
+                (p.fd, evs, data, ready)
+            })
+            .collect();
     }
 
+    /// waits for at most the time left until `deadline`, with `sigmask` applied atomically for
+    /// the duration of the wait like epoll_pwait does
+    ///
+    /// polling without any registered fd skips the syscall
     pub fn wait(
         &mut self,
         evs: &mut [MaybeUninit<epoll_event>],
-        timeout: Option<Duration>,
+        deadline: Deadline,
+        sigmask: Option<&sigset_t>,
     ) -> PosixResult<usize> {
-        let timeout: i32 = timeout.map_or(-1, |d| d.as_millis().try_into().unwrap());
+        if self.is_empty() && deadline.has_passed() {
+            return Ok(0);
+        }
+
+        // rounding down, so the wait never outlasts the deadline
+        let timeout: i32 = deadline
+            .remaining()
+            .map_or(-1, |d| d.as_millis().try_into().unwrap_or(i32::MAX));
         trace!("waiting for {timeout}");
         let res = unsafe {
-            libc::epoll_wait(
+            libc::epoll_pwait(
                 self.fd,
                 evs.as_mut_ptr() as *mut epoll_event,
                 evs.len().try_into().unwrap(),
                 timeout,
+                sigmask.map_or(std::ptr::null(), |set| set),
             )
         };
 
@@ -67,3 +157,30 @@ impl Epoll {
         };
     }
 }
+
+/// the events and data of every fd in the fdinfo of an epoll
+fn parse_fdinfo(info: &str) -> HashMap<c_int, (u32, u64)> {
+    return info
+        .lines()
+        .filter_map(parse_fdinfo_line)
+        .map(|(fd, evs, data)| (fd, (evs, data)))
+        .collect();
+}
+
+/// parses a line like `tfd:        5 events:       19 data:                5  pos:0 ino:6c9`, the
+/// events and data are in hex
+fn parse_fdinfo_line(line: &str) -> Option<(c_int, u32, u64)> {
+    let mut fields = line.split_whitespace();
+    let fd = field(&mut fields, "tfd:")?.parse().ok()?;
+    let evs = u32::from_str_radix(field(&mut fields, "events:")?, 16).ok()?;
+    let data = u64::from_str_radix(field(&mut fields, "data:")?, 16).ok()?;
+    return Some((fd, evs, data));
+}
+
+/// the value after `key` if it is the next field
+fn field<'a>(fields: &mut SplitWhitespace<'a>, key: &str) -> Option<&'a str> {
+    if fields.next()? != key {
+        return None;
+    }
+    return fields.next();
+}
@@ -0,0 +1,762 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    mem::{self, MaybeUninit},
+    time::{Duration, Instant},
+};
+
+use libc::{EFD_NONBLOCK, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD, c_int, epoll_event};
+
+use crate::{
+    bindings::{DPOLL_SO_AUTOPOP, SOL_DPOLL},
+    dpoll::{
+        Dpoll, Event, Target,
+        item::Item,
+        items::Items,
+        operation::{DpollOperation, EpollOperation, NestedOperation, Operation},
+        ready_list::ReadyList,
+        shards::{MIN_SHARD_LEN, Shards},
+    },
+    shared::Shared,
+    socket::Socket,
+    wrappers::{deadline::Deadline, demi, errno::PosixError},
+};
+
+const ITEMS: usize = 8;
+
+/// checks that `Item::on_readylist` matches the membership of the ready list of `pol` for every
+/// item on it or registered in it
+#[cfg(feature = "mock")]
+pub fn check_ready_list(pol: &Dpoll) {
+    for it in pol.ready_list.iter() {
+        let on_list = pol
+            .ready_list
+            .iter()
+            .filter(|other| other.ptr_eq(it))
+            .count();
+        assert_eq!(on_list, 1, "an item is on the list {on_list} times");
+        assert!(
+            it.borrow().on_readylist,
+            "an item on the list is not flagged"
+        );
+    }
+    for it in pol.items.iter() {
+        let on_list = pol.ready_list.iter().any(|other| other.ptr_eq(it));
+        assert_eq!(
+            it.borrow().on_readylist,
+            on_list,
+            "qd {}",
+            it.borrow().get_qd()
+        );
+    }
+}
+
+/// drives a ready list with push, remove, drain and append decoded from `data`, checking that
+/// `Item::on_readylist` matches list membership after every step, that drains only ever take
+/// the front of the list and that the items of a higher priority are always ahead
+pub fn ready_list(data: &[u8]) {
+    let items: Vec<Shared<Item>> = (0..ITEMS)
+        .map(|i| {
+            let soc = Socket::new(demi::SocketQd::from(i as i32));
+            let mut item = Item::new(Shared::new(soc), Event::IN, i as u64);
+            item.priority = (i % 3) as u8;
+            Shared::new(item)
+        })
+        .collect();
+    let mut list = ReadyList::new();
+
+    for (step, byte) in data.iter().enumerate() {
+        let item = &items[(byte >> 2) as usize % ITEMS];
+        match byte & 0b11 {
+            0 => list.push(item.clone()),
+            1 => list.remove(item),
+            2 => {
+                let max = (byte >> 2) as usize % (ITEMS + 1);
+                let before: Vec<_> = list.iter().cloned().collect();
+                let reported = list.drain(max, |_, it| it.data % 2 == step as u64 % 2);
+                assert!(reported <= max);
+
+                let after: Vec<_> = list.iter().cloned().collect();
+                let waiting = &before[before.len() - after.len()..];
+                assert!(
+                    waiting.iter().zip(&after).all(|(a, b)| a.ptr_eq(b)),
+                    "order changed"
+                );
+            }
+            _ => {
+                let mut other = ReadyList::new();
+                if !item.borrow().on_readylist {
+                    other.push(item.clone());
+                }
+                list.append(other);
+            }
+        }
+
+        let priorities: Vec<u8> = list.iter().map(|it| it.borrow().priority).collect();
+        assert!(
+            priorities.is_sorted_by(|a, b| a >= b),
+            "out of order: {priorities:?}"
+        );
+
+        for (i, it) in items.iter().enumerate() {
+            let on_list = list.iter().filter(|other| other.ptr_eq(it)).count();
+            assert!(on_list <= 1, "item {i} is on the list {on_list} times");
+            assert_eq!(it.borrow().on_readylist, on_list == 1, "item {i}");
+        }
+    }
+}
+
+/// registers sockets and kernel eventfds in a dpoll, adding, modifying and deleting them with data
+/// decoded from `data`, and checks every drained event carries the data of the latest add or
+/// modify of its fd, also when the modify came after the socket entered the ready list
+///
+/// some adds go to registered fds and some modifies and deletes to unregistered ones, which have
+/// to fail with EEXIST and ENOENT for the sockets like they do for the eventfds in the kernel
+pub fn ctl_data(data: &[u8]) {
+    let mut pol = Dpoll::create(0).unwrap();
+    let socs: Vec<Shared<Socket>> = (0..ITEMS)
+        .map(|i| Shared::new(Socket::new(demi::SocketQd::from(i as i32))))
+        .collect();
+    // always readable
+    let fds: Vec<c_int> = (0..ITEMS)
+        .map(|_| unsafe { libc::eventfd(1, EFD_NONBLOCK) })
+        .collect();
+    assert!(fds.iter().all(|fd| *fd >= 0));
+    // the data each fd is registered with, the sockets first
+    let mut model: Vec<Option<u64>> = vec![None; 2 * ITEMS];
+
+    for (step, byte) in data.iter().enumerate() {
+        let target = (byte >> 3) as usize % (2 * ITEMS);
+        // unique per step with the high bits set like a pointer, the target in the low byte
+        let val = (step as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15) << 8 | target as u64;
+
+        let wrong = byte & 0b100 != 0;
+        let (op, expected) = match (byte & 0b11, model[target]) {
+            (0 | 1, None) if wrong => (EPOLL_CTL_MOD, Err(PosixError::NOENT)),
+            (0 | 1, None) => (EPOLL_CTL_ADD, Ok(())),
+            (0 | 1, Some(_)) if wrong => (EPOLL_CTL_ADD, Err(PosixError::EXIST)),
+            (0 | 1, Some(_)) => (EPOLL_CTL_MOD, Ok(())),
+            (2, Some(_)) => (EPOLL_CTL_DEL, Ok(())),
+            (2, None) => (EPOLL_CTL_DEL, Err(PosixError::NOENT)),
+            (3, Some(_)) if wrong && target < ITEMS => {
+                let it = pol.items.get(target as demi::DemiQd).unwrap();
+                pol.ready_list.push(it);
+                continue;
+            }
+            (3, _) => {
+                drain_checked(&mut pol, &model);
+                continue;
+            }
+            _ => unreachable!(),
+        };
+
+        let mut ev = epoll_event {
+            events: Event::IN.bits(),
+            u64: val,
+        };
+        if expected.is_ok() {
+            model[target] = (op != EPOLL_CTL_DEL).then_some(val);
+        }
+        let raw = op;
+        let op = if target < ITEMS {
+            let soc = socs[target].clone();
+            Operation::Dpoll(DpollOperation::new(soc, op, Some(&ev)).unwrap())
+        } else {
+            let fd = fds[target - ITEMS];
+            Operation::Epoll(EpollOperation {
+                op,
+                fd,
+                event: &mut ev,
+            })
+        };
+        assert_eq!(pol.ctl(op), expected, "op {raw} on fd {target}");
+    }
+    drain_checked(&mut pol, &model);
+
+    for fd in fds {
+        unsafe { libc::close(fd) };
+    }
+}
+
+/// adds and deletes sockets decoded from `data` in a dpoll with a maximum of sockets that changes
+/// in between, checking adds fail with ENOSPC exactly when it is reached, that sockets over a
+/// lowered maximum stay registered and that the stats count the peak and the refused adds
+pub fn max_items(data: &[u8]) {
+    let mut pol = Dpoll::create(0).unwrap();
+    let socs: Vec<Shared<Socket>> = (0..ITEMS)
+        .map(|i| Shared::new(Socket::new(demi::SocketQd::from(i as i32))))
+        .collect();
+    let mut registered = [false; ITEMS];
+    let mut max = None;
+    let (mut peak, mut refused) = (0, 0);
+
+    for byte in data {
+        let target = (byte >> 3) as usize % ITEMS;
+        let len = registered.iter().filter(|r| **r).count();
+        let (op, expected) = match (byte & 0b111, registered[target]) {
+            (0, _) => {
+                max = (target > 0).then_some(target);
+                pol.set_max_items(max);
+                continue;
+            }
+            (1..=4, true) => (EPOLL_CTL_ADD, Err(PosixError::EXIST)),
+            (1..=4, false) if max.is_some_and(|max| len >= max) => {
+                refused += 1;
+                (EPOLL_CTL_ADD, Err(PosixError::NOSPC))
+            }
+            (1..=4, false) => (EPOLL_CTL_ADD, Ok(())),
+            (_, true) => (EPOLL_CTL_DEL, Ok(())),
+            (_, false) => (EPOLL_CTL_DEL, Err(PosixError::NOENT)),
+        };
+        if expected.is_ok() {
+            registered[target] = op == EPOLL_CTL_ADD;
+        }
+
+        let ev = epoll_event {
+            events: Event::IN.bits(),
+            u64: target as u64,
+        };
+        let soc = socs[target].clone();
+        let op = Operation::Dpoll(DpollOperation::new(soc, op, Some(&ev)).unwrap());
+        assert_eq!(
+            pol.ctl(op),
+            expected,
+            "socket {target} with {len} of {max:?}"
+        );
+
+        let len = registered.iter().filter(|r| **r).count();
+        assert_eq!(pol.len(), len);
+        peak = peak.max(len as u64);
+        assert_eq!(pol.stats().max_items, peak);
+        assert_eq!(pol.stats().refused_adds, refused);
+    }
+}
+
+/// adds, modifies and deletes sockets, eventfds and nested dpolls decoded from `data` and checks
+/// `Dpoll::registered` lists exactly the registered ones with the events and data of their last
+/// add or modify, sockets first and the rest sorted by fd
+///
+/// the kernel keeps ERR and HUP in the events of its fds regardless of the ctl, like epoll reports
+/// them regardless
+///
+/// the sockets never connect and the eventfds are always readable and writable, so their ready
+/// events are known too
+pub fn registered(data: &[u8]) {
+    const NESTED: usize = 2;
+    let mut pol = Dpoll::create(0).unwrap();
+    let socs: Vec<Shared<Socket>> = (0..ITEMS)
+        .map(|i| Shared::new(Socket::new(demi::SocketQd::from(i as i32))))
+        .collect();
+    let fds: Vec<c_int> = (0..ITEMS)
+        .map(|_| unsafe { libc::eventfd(1, EFD_NONBLOCK) })
+        .collect();
+    assert!(fds.iter().all(|fd| *fd >= 0));
+    let nested: Vec<Shared<Dpoll>> = (0..NESTED)
+        .map(|_| Shared::new(Dpoll::create(0).unwrap()))
+        .collect();
+    // the events and data each target is registered with, the sockets, eventfds and then dpolls
+    let mut model: Vec<Option<(u32, u64)>> = vec![None; 2 * ITEMS + NESTED];
+
+    for (step, byte) in data.iter().enumerate() {
+        let target = (byte >> 3) as usize % model.len();
+        let evs = [Event::IN, Event::OUT, Event::IN | Event::OUT][step % 3].bits();
+        let val = (step as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let op = match (byte & 0b11, model[target]) {
+            (0 | 1, None) => EPOLL_CTL_ADD,
+            (0 | 1, Some(_)) => EPOLL_CTL_MOD,
+            (2, Some(_)) => EPOLL_CTL_DEL,
+            _ => {
+                check_registered(&pol, &model, &fds, &nested);
+                continue;
+            }
+        };
+        model[target] = (op != EPOLL_CTL_DEL).then_some((evs, val));
+
+        let mut ev = epoll_event {
+            events: evs,
+            u64: val,
+        };
+        let op = if target < ITEMS {
+            let soc = socs[target].clone();
+            Operation::Dpoll(DpollOperation::new(soc, op, Some(&ev)).unwrap())
+        } else if target < 2 * ITEMS {
+            Operation::Epoll(EpollOperation {
+                op,
+                fd: fds[target - ITEMS],
+                event: &mut ev,
+            })
+        } else {
+            Operation::Nested(NestedOperation {
+                op,
+                pol: nested[target - 2 * ITEMS].clone(),
+                event: &mut ev,
+            })
+        };
+        pol.ctl(op).unwrap();
+    }
+    check_registered(&pol, &model, &fds, &nested);
+
+    for fd in fds {
+        unsafe { libc::close(fd) };
+    }
+}
+
+fn check_registered(
+    pol: &Dpoll,
+    model: &[Option<(u32, u64)>],
+    fds: &[c_int],
+    nested: &[Shared<Dpoll>],
+) {
+    let always = (Event::ERR | Event::HUP).bits();
+    let mut sockets = Vec::new();
+    let mut kernel = Vec::new();
+    for reg in pol.registered() {
+        let mut events = reg.events;
+        let idx = match &reg.target {
+            Target::Socket(soc) => {
+                assert!(kernel.is_empty(), "a socket came after a kernel fd");
+                let qd = soc.borrow().soc.qd as usize;
+                assert_eq!(reg.ready, 0, "socket {qd} never connected");
+                sockets.push(qd);
+                qd
+            }
+            Target::Kernel(fd) => {
+                let idx = fds
+                    .iter()
+                    .position(|f| f == fd)
+                    .expect("an unknown kernel fd");
+                assert_eq!(
+                    reg.ready,
+                    reg.events & !always,
+                    "eventfd {fd} is always ready"
+                );
+                assert_eq!(events & always, always);
+                events &= !always;
+                kernel.push(*fd);
+                ITEMS + idx
+            }
+            Target::Nested(inner) => {
+                let idx = nested
+                    .iter()
+                    .position(|n| n.ptr_eq(inner))
+                    .expect("an unknown dpoll");
+                let fd = inner.borrow().wakeup.as_ref().unwrap().fd();
+                assert_eq!(events & always, always);
+                events &= !always;
+                kernel.push(fd);
+                2 * ITEMS + idx
+            }
+        };
+        assert_eq!(model[idx], Some((events, reg.data)), "target {idx}");
+    }
+
+    let listed = sockets.len() + kernel.len();
+    assert_eq!(
+        listed,
+        model.iter().flatten().count(),
+        "registrations are missing"
+    );
+    assert!(kernel.is_sorted(), "the kernel fds are not sorted");
+}
+
+/// registers connected sockets for IN and OUT in a dpoll and feeds them failed completions,
+/// idle marks and taken errors decoded from `data`, draining the ready list with varying room
+///
+/// like with the kernel, every drain has to report a socket at most once, with all of its events
+/// at that point merged into one epoll_event, however many completions it got since it was last
+/// reported, and the sockets have to come out in the order they first became ready
+pub fn coalesce(data: &[u8]) {
+    let mut pol = Dpoll::create(0).unwrap();
+    let socs: Vec<Shared<Socket>> = (0..ITEMS)
+        .map(|i| {
+            let acc = demi::AcceptResult {
+                qd: demi::SocketQd::from(i as i32),
+                addr: unsafe { mem::zeroed() },
+            };
+            Shared::new(Socket::from(acc))
+        })
+        .collect();
+    for (i, soc) in socs.iter().enumerate() {
+        let ev = epoll_event {
+            events: Event::IN.union(Event::OUT).bits(),
+            u64: i as u64,
+        };
+        let op = DpollOperation::new(soc.clone(), EPOLL_CTL_ADD, Some(&ev)).unwrap();
+        pol.ctl(Operation::Dpoll(op)).unwrap();
+    }
+
+    // the sockets in the order they became ready, each at most once
+    let mut pending: Vec<usize> = Vec::new();
+    let mut failed = [false; ITEMS];
+    let mut idle = [false; ITEMS];
+
+    for (step, byte) in data.iter().enumerate() {
+        let target = (byte >> 2) as usize % ITEMS;
+        match byte & 0b11 {
+            // a completion nothing waits on, only recorded as the pending error of the socket
+            0 => {
+                let res = demi::QResult {
+                    qd: target as demi::DemiQd,
+                    qt: step as demi::QToken,
+                    value: Err(PosixError::CONNRESET),
+                };
+                pol.process(res).unwrap();
+                failed[target] = true;
+                idle[target] = false;
+            }
+            // what the idle sweeper does
+            1 => {
+                let it = pol.items.get(target as demi::DemiQd).unwrap();
+                it.borrow_mut().idle = true;
+                pol.ready_list.push(it);
+                idle[target] = true;
+            }
+            2 => {
+                socs[target].borrow_mut().take_error();
+                failed[target] = false;
+                continue;
+            }
+            _ => {
+                let max = (byte >> 2) as usize % (ITEMS + 1);
+                let mut evs = vec![MaybeUninit::uninit(); max];
+                let reported = pol.drain_ready_list(&mut evs);
+                let expected: Vec<usize> = pending.drain(..reported.min(pending.len())).collect();
+                assert_eq!(reported, expected.len(), "drained {reported} of {max}");
+
+                for (ev, target) in evs[..reported].iter().zip(expected) {
+                    let ev = unsafe { ev.assume_init() };
+                    let (data, events) = (ev.u64, ev.events);
+                    let mut want = Event::OUT;
+                    if failed[target] {
+                        want |= Event::ERR;
+                    }
+                    if idle[target] {
+                        want |= Event::HUP;
+                    }
+                    assert_eq!(data, target as u64, "reported out of order");
+                    assert_eq!(Event::from_bits_retain(events), want, "socket {target}");
+                }
+                continue;
+            }
+        }
+        if !pending.contains(&target) {
+            pending.push(target);
+        }
+    }
+}
+
+/// registers connected sockets without any data to read, for IN and, as decoded from `data`, OUT,
+/// and puts them on the ready list like a completion would before a read consumed its data
+///
+/// a pwait has to report OUT for exactly the sockets asking for it and IN for none, and when no
+/// socket asks for OUT it has to block for its timeout instead of returning early for the stale
+/// entries
+pub fn stale_ready(data: &[u8]) {
+    const TIMEOUT: Duration = Duration::from_millis(2);
+
+    let mut pol = Dpoll::create(0).unwrap();
+    let socs: Vec<Shared<Socket>> = (0..ITEMS)
+        .map(|i| {
+            let acc = demi::AcceptResult {
+                qd: demi::SocketQd::from(i as i32),
+                addr: unsafe { mem::zeroed() },
+            };
+            let mut soc = Socket::from(acc);
+            // no pop is started, which would need demikernel
+            soc.set_option(SOL_DPOLL, DPOLL_SO_AUTOPOP, 0).unwrap();
+            Shared::new(soc)
+        })
+        .collect();
+    let mut out = [false; ITEMS];
+    for (i, soc) in socs.iter().enumerate() {
+        let ev = epoll_event {
+            events: Event::IN.bits(),
+            u64: i as u64,
+        };
+        let op = DpollOperation::new(soc.clone(), EPOLL_CTL_ADD, Some(&ev)).unwrap();
+        pol.ctl(Operation::Dpoll(op)).unwrap();
+    }
+
+    for byte in data {
+        let target = (byte >> 2) as usize % ITEMS;
+        match byte & 0b11 {
+            0 => {
+                let it = pol.items.get(target as demi::DemiQd).unwrap();
+                pol.ready_list.push(it);
+            }
+            1 => {
+                out[target] = !out[target];
+                let evs = if out[target] {
+                    Event::IN.union(Event::OUT)
+                } else {
+                    Event::IN
+                };
+                let ev = epoll_event {
+                    events: evs.bits(),
+                    u64: target as u64,
+                };
+                let op = DpollOperation::new(socs[target].clone(), EPOLL_CTL_MOD, Some(&ev));
+                pol.ctl(Operation::Dpoll(op.unwrap())).unwrap();
+            }
+            _ => {
+                let mut evs = vec![MaybeUninit::uninit(); ITEMS];
+                let start = Instant::now();
+                let res = pol.pwait(&mut evs, Some(TIMEOUT), None);
+                let took = start.elapsed();
+
+                let expected = out.iter().filter(|o| **o).count();
+                if expected == 0 {
+                    assert_eq!(res, Err(PosixError::TIMEDOUT));
+                    // the kernel wait rounds down to milliseconds
+                    assert!(
+                        took >= TIMEOUT / 2,
+                        "returned after {took:?} for stale entries"
+                    );
+                    continue;
+                }
+                assert_eq!(res, Ok(expected));
+                for ev in &evs[..expected] {
+                    let ev = unsafe { ev.assume_init() };
+                    let (data, events) = (ev.u64, ev.events);
+                    assert!(out[data as usize], "{data} reported without asking for OUT");
+                    assert_eq!(Event::from_bits_retain(events), Event::OUT, "socket {data}");
+                }
+            }
+        }
+    }
+}
+
+/// inserts, takes, removes and looks up the items of sockets decoded from `data`, and moves the
+/// sockets to other qds in between like a raced connect does, to fresh ones and to ones another
+/// socket gave up, checking the items agree with a map of the registered sockets and that
+/// iterating them keeps the order of the ones that stay
+pub fn items(data: &[u8]) {
+    let socs: Vec<Shared<Socket>> = (0..ITEMS)
+        .map(|i| Shared::new(Socket::new(demi::SocketQd::from(i as i32))))
+        .collect();
+    let qd_of = |i: usize| socs[i].borrow().soc.qd;
+    let index = |it: &Shared<Item>| {
+        return socs
+            .iter()
+            .position(|soc| it.borrow().soc.ptr_eq(soc))
+            .unwrap();
+    };
+    let mut fresh = 2 * ITEMS as demi::DemiQd;
+    let mut items = Items::new();
+    let mut model: BTreeMap<usize, u64> = BTreeMap::new();
+    let mut order: Vec<usize> = Vec::new();
+
+    for (step, byte) in data.iter().enumerate() {
+        let i = (byte >> 3) as usize % ITEMS;
+        let soc = &socs[i];
+        let qd = qd_of(i);
+
+        match (byte & 0b111, model.contains_key(&i)) {
+            (0, false) => {
+                items.insert(Item::new(soc.clone(), Event::IN, step as u64));
+                model.insert(i, step as u64);
+            }
+            (1, true) => {
+                let it = items.take(qd).unwrap();
+                assert!(it.borrow().soc.ptr_eq(soc));
+                model.remove(&i);
+            }
+            (2, true) => {
+                let it = items.get(qd).unwrap();
+                items.remove(&it).unwrap();
+                assert_eq!(items.remove(&it), Err(PosixError::NOENT));
+                model.remove(&i);
+            }
+            (3 | 4, _) => {
+                // no two sockets ever hold the same qd, like in demikernel
+                let reused = (step % (2 * ITEMS)) as demi::DemiQd;
+                let new = if byte & 0b111 == 4 && (0..ITEMS).all(|j| qd_of(j) != reused) {
+                    reused
+                } else {
+                    fresh += 1;
+                    fresh
+                };
+                soc.borrow_mut().soc = demi::SocketQd::from(new as i32);
+            }
+            _ => {
+                let it = items.get(qd);
+                assert_eq!(it.map(|it| it.borrow().data), model.get(&i).copied());
+            }
+        }
+
+        assert_eq!(items.len(), model.len());
+        let now: Vec<usize> = items.iter().map(index).collect();
+        let kept: Vec<_> = order.iter().filter(|i| now.contains(i)).collect();
+        let still: Vec<_> = now.iter().filter(|i| order.contains(i)).collect();
+        assert_eq!(kept, still, "the order of the items changed");
+        assert!(now.iter().all(|i| model.contains_key(i)));
+        order = now;
+    }
+}
+
+/// registers `items` sockets and looks `lookups` of them up in a pseudo random order, like the
+/// completions of a busy dpoll do, returning how long the lookups took
+pub fn items_lookup(items: usize, lookups: usize) -> Duration {
+    let mut all = Items::new();
+    for i in 0..items {
+        let soc = Shared::new(Socket::new(demi::SocketQd::from(i as i32)));
+        all.insert(Item::new(soc, Event::IN, i as u64));
+    }
+
+    let mut x: u64 = 0x9e37_79b9_7f4a_7c15;
+    let start = Instant::now();
+    for _ in 0..lookups {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        let qd = (x % items as u64) as demi::DemiQd;
+        assert!(all.get(qd).is_some());
+    }
+    return start.elapsed();
+}
+
+/// pushes `items` items onto a ready list, removes the most recently pushed quarter of them like
+/// a ctl deleting sockets that just became ready and drains the rest 64 at a time, `rounds` times,
+/// returning how long the rounds took
+pub fn ready_list_ops(items: usize, rounds: usize) -> Duration {
+    let all: Vec<Shared<Item>> = (0..items)
+        .map(|i| {
+            let soc = Socket::new(demi::SocketQd::from(i as i32));
+            Shared::new(Item::new(Shared::new(soc), Event::IN, i as u64))
+        })
+        .collect();
+    let mut list = ReadyList::new();
+
+    let start = Instant::now();
+    for _ in 0..rounds {
+        for it in &all {
+            list.push(it.clone());
+        }
+        for it in all.iter().rev().take(items / 4) {
+            list.remove(it);
+        }
+        while list.drain(64, |_, _| true) != 0 {}
+        assert!(list.is_empty());
+    }
+    return start.elapsed();
+}
+
+/// polls tokens split into shards for completions decoded from `data` until there are none left,
+/// checking that a poll finds a completion whenever there is one and that no shard is shorter
+/// than `MIN_SHARD_LEN` or outnumbers the configured ones
+pub fn shards(data: &[u8]) {
+    let [max, len_lo, len_hi, rest @ ..] = data else {
+        return;
+    };
+    let max = *max as usize % 16 + 1;
+    let len = u16::from_le_bytes([*len_lo, *len_hi]) as usize % (20 * MIN_SHARD_LEN) + 1;
+    let mut toks: Vec<demi::QToken> = (0..len as demi::QToken).collect();
+    let mut completed: HashSet<demi::QToken> = rest
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]) as demi::QToken % len as demi::QToken)
+        .collect();
+
+    let mut shards = Shards::new(max);
+    loop {
+        let count = shards.count(toks.len());
+        assert!(count <= max);
+        assert!(count == 1 || toks.len() / count >= MIN_SHARD_LEN);
+
+        let mut seen = 0;
+        let res = shards.poll(&toks, |shard| {
+            seen += shard.len();
+            return shard
+                .iter()
+                .position(|qt| completed.contains(qt))
+                .map(|idx| (idx, shard[idx]))
+                .ok_or(PosixError::TIMEDOUT);
+        });
+        let Ok((idx, qt)) = res else {
+            assert_eq!(res.map(|_| ()), Err(PosixError::TIMEDOUT));
+            assert_eq!(seen, toks.len(), "the shards missed tokens");
+            assert!(
+                completed.is_empty(),
+                "{} completions were not found",
+                completed.len()
+            );
+            break;
+        };
+        assert_eq!(toks.swap_remove(idx), qt);
+        assert!(completed.remove(&qt), "{qt} did not complete");
+    }
+}
+
+/// drains `ready` completions spread over `items` tokens `batch` at a time like `Dpoll::wait`
+/// does with `shards` shards, `rounds` times, returning how long the rounds took
+///
+/// the wait stands in for demikernel's, which looks every token it is given up in its scheduler
+/// until it finds a completed one
+pub fn wait_shards(
+    items: usize,
+    ready: usize,
+    batch: usize,
+    shards: usize,
+    rounds: usize,
+) -> Duration {
+    let all: Vec<demi::QToken> = (0..items as demi::QToken).collect();
+    let mut completed = HashSet::new();
+    let mut shards = Shards::new(shards);
+    let wait = |completed: &HashSet<demi::QToken>, toks: &[demi::QToken]| {
+        return toks
+            .iter()
+            .position(|qt| completed.contains(qt))
+            .map(|idx| (idx, toks[idx]))
+            .ok_or(PosixError::TIMEDOUT);
+    };
+
+    let mut x: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut took = Duration::ZERO;
+    for _ in 0..rounds {
+        let mut toks = all.clone();
+        while completed.len() < ready.min(items) {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            completed.insert(x % items as u64);
+        }
+
+        let start = Instant::now();
+        while !completed.is_empty() {
+            for _ in 0..batch {
+                let Ok((idx, qt)) = shards.poll(&toks, |toks| wait(&completed, toks)) else {
+                    break;
+                };
+                assert_eq!(toks.swap_remove(idx), qt);
+                completed.remove(&qt);
+            }
+        }
+        took += start.elapsed();
+    }
+    return took;
+}
+
+fn drain_checked(pol: &mut Dpoll, model: &[Option<u64>]) {
+    let mut evs = vec![MaybeUninit::uninit(); 2 * ITEMS];
+    // makes the sockets on the list report `Event::HUP` without any completion
+    for it in pol.ready_list.iter() {
+        it.borrow_mut().idle = true;
+    }
+    let from_list = pol.drain_ready_list(&mut evs);
+    let from_epoll = pol
+        .epoll
+        .wait(&mut evs[from_list..], Deadline::now(), None)
+        .unwrap();
+    assert_eq!(from_epoll, model[ITEMS..].iter().flatten().count());
+
+    let mut seen = HashSet::new();
+    for (i, ev) in evs[..from_list + from_epoll].iter().enumerate() {
+        let data = unsafe { ev.assume_init() }.u64;
+        let target = (data & 0xff) as usize;
+        assert_eq!(model[target], Some(data), "event {i} has stale data");
+        assert_eq!(
+            target < ITEMS,
+            i < from_list,
+            "event {i} from the wrong source"
+        );
+        assert!(seen.insert(target), "{target} reported twice");
+    }
+}
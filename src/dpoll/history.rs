@@ -0,0 +1,135 @@
+//! the last state transitions of every dpoll, kept in memory and dumped to stderr when a panic is
+//! not caught, as the trace log is far too verbose to be left on in production
+//!
+//! the rings live outside of the dpolls, so they can be read while a panicking dpoll is borrowed,
+//! and per thread, so recording takes no lock
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    panic,
+    sync::{
+        Once,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use crate::wrappers::demi;
+
+use super::Event;
+
+/// transitions kept per dpoll
+pub const LEN: usize = 256;
+/// dpolls whose rings are kept per thread, the rings of the oldest ones are dropped first
+const DPOLLS: usize = 64;
+
+/// the fields are only read by the Debug impl when dumping
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub enum Transition {
+    /// operations were started for `evs`
+    Schedule {
+        qd: demi::DemiQd,
+        evs: Event,
+    },
+    Completion {
+        qd: demi::DemiQd,
+        qt: demi::QToken,
+        ok: bool,
+    },
+    ReadyPush {
+        qd: demi::DemiQd,
+    },
+    ReadyRemove {
+        qd: demi::DemiQd,
+    },
+    /// `evs` were reported
+    Drain {
+        qd: demi::DemiQd,
+        evs: Event,
+    },
+}
+
+struct Ring {
+    id: u64,
+    entries: VecDeque<(Instant, Transition)>,
+}
+
+thread_local! {
+    static RINGS: RefCell<VecDeque<Ring>> = const { RefCell::new(VecDeque::new()) };
+    /// set while the panics of the thread are caught by `bindings::utils::guard`, which dumps the
+    /// rings itself if it aborts instead
+    static CAUGHT: Cell<bool> = const { Cell::new(false) };
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// a new id to record the transitions of a dpoll under
+pub fn new_id() -> u64 {
+    return NEXT_ID.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record(id: u64, transition: Transition) {
+    let _ = RINGS.try_with(|rings| {
+        let Ok(mut rings) = rings.try_borrow_mut() else {
+            return;
+        };
+
+        let ring = match rings.iter().position(|r| r.id == id) {
+            Some(pos) => &mut rings[pos],
+            None => {
+                if rings.len() == DPOLLS {
+                    rings.pop_front();
+                }
+                rings.push_back(Ring {
+                    id,
+                    entries: VecDeque::with_capacity(LEN),
+                });
+                rings.back_mut().unwrap()
+            }
+        };
+
+        if ring.entries.len() == LEN {
+            ring.entries.pop_front();
+        }
+        ring.entries.push_back((Instant::now(), transition));
+    });
+}
+
+/// writes the rings of the current thread to stderr, oldest transition first
+pub fn dump() {
+    let _ = RINGS.try_with(|rings| {
+        let Ok(rings) = rings.try_borrow() else {
+            return;
+        };
+
+        let now = Instant::now();
+        for ring in rings.iter() {
+            eprintln!("dpoll {}: last {} transitions", ring.id, ring.entries.len());
+            for (at, transition) in &ring.entries {
+                eprintln!("  -{:?} {transition:?}", now.saturating_duration_since(*at));
+            }
+        }
+    });
+}
+
+/// marks whether the panics of the current thread are caught, returns the previous mark
+pub fn set_caught(caught: bool) -> bool {
+    return CAUGHT.try_with(|c| c.replace(caught)).unwrap_or(false);
+}
+
+/// dumps the rings before running the previous panic hook unless the panic is caught, can be
+/// called more than once
+pub fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CAUGHT.try_with(Cell::get).unwrap_or(false) {
+                dump();
+            }
+            previous(info);
+        }));
+    });
+}
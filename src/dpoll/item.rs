@@ -1,27 +1,79 @@
-use crate::{shared::Shared, socket::Socket, wrappers::demi};
+use std::{mem, time::Instant};
 
-use super::Event;
+use crate::{
+    shared::Shared,
+    socket::Socket,
+    wrappers::{clock, demi},
+};
+
+use super::{Event, IgnoredFlags};
 
 #[derive(Debug)]
 pub struct Item {
     pub soc: Shared<Socket>,
     pub evs: Event,
+    /// accepted by ctl but without any effect, kept for when they get one
+    pub flags: IgnoredFlags,
     pub data: u64,
+    /// reported instead of `data` if the item was registered with one, see `Dpoll::cookie_data`
+    pub cookie: Option<u64>,
     pub on_readylist: bool,
+    /// the SO_PRIORITY of the socket as of its last scheduling, orders the ready list
+    pub priority: u8,
+    /// when the item last reported an event, in events reported by the dpoll, 0 if never
+    pub last_reported: u64,
+    /// when the socket last completed an operation or the item was last modified
+    pub last_activity: Instant,
+    /// set by the idle sweeper, the operations of the socket are not waited on anymore and
+    /// `Event::HUP` is reported until the item is modified
+    pub idle: bool,
+    /// the key of the item, the qd of the socket might change after a raced connect
+    qd: demi::DemiQd,
 }
 
 impl Item {
     pub fn new(soc: Shared<Socket>, evs: Event, data: u64) -> Self {
+        let (qd, priority) = {
+            let soc = soc.borrow();
+            (soc.soc.qd, soc.priority())
+        };
         return Self {
             soc,
             evs,
+            flags: IgnoredFlags::empty(),
             data,
+            cookie: None,
             on_readylist: false,
+            priority,
+            last_reported: 0,
+            last_activity: clock::now(),
+            idle: false,
+            qd,
         };
     }
 
+    pub fn touch(&mut self) {
+        self.last_activity = clock::now();
+        self.idle = false;
+    }
+
+    /// the data pwait reports for the item
+    pub fn reported_data(&self) -> u64 {
+        return self.cookie.unwrap_or(self.data);
+    }
+
     pub fn get_qd(&self) -> demi::DemiQd {
-        return self.soc.borrow().soc.qd;
+        return self.qd;
+    }
+
+    /// updates the key to the current qd of the socket, returning the old one if it changed
+    pub fn rekey(&mut self) -> Option<demi::DemiQd> {
+        let qd = self.soc.borrow().soc.qd;
+        if qd == self.qd {
+            return None;
+        }
+
+        return Some(mem::replace(&mut self.qd, qd));
     }
 }
 
@@ -33,7 +85,7 @@ impl Ord for Item {
 
 impl PartialOrd for Item {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        return self.get_qd().partial_cmp(&other.get_qd());
+        return Some(self.cmp(other));
     }
 }
 
@@ -1,45 +1,199 @@
-use std::collections::{BTreeMap, btree_map::Values};
+//! the items of a dpoll, kept in a slab of dense slots with a hash map from the qd of their socket
+//! to their slot, so a completion finds its item with a single hash lookup
+//!
+//! iterating goes through the slots in order, which stays the same from one pwait to the next, a
+//! freed slot is reused by the next insert
 
-use crate::{shared::Shared, wrappers::demi};
+use std::collections::HashMap;
+
+use crate::{
+    shared::Shared,
+    wrappers::{
+        demi,
+        errno::{PosixError, PosixResult},
+    },
+};
 
 use super::item::Item;
 
 #[derive(Debug)]
 pub struct Items {
-    inner: BTreeMap<demi::DemiQd, Shared<Item>>,
+    slots: Vec<Option<Shared<Item>>>,
+    /// the slots that were freed, reused before the slab grows
+    free: Vec<usize>,
+    by_qd: HashMap<demi::DemiQd, usize>,
 }
 
 impl Items {
     pub fn new() -> Self {
         return Self {
-            inner: BTreeMap::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+            by_qd: HashMap::new(),
         };
     }
 
     pub fn insert(&mut self, it: Item) {
         let qd = it.get_qd();
-        self.inner.insert(qd, Shared::new(it));
+        // demikernel might have reused the qd a socket had before winning a raced connect
+        if let Some(&slot) = self.by_qd.get(&qd) {
+            self.rekey(slot);
+        }
+
+        let it = Some(Shared::new(it));
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot] = it;
+                slot
+            }
+            None => {
+                self.slots.push(it);
+                self.slots.len() - 1
+            }
+        };
+
+        if let Some(old) = self.by_qd.insert(qd, slot) {
+            self.release(old);
+        }
     }
 
     pub fn take(&mut self, qd: demi::DemiQd) -> Option<Shared<Item>> {
-        let ret = self.inner.remove(&qd);
-        return ret;
+        let slot = self.find(qd)?;
+        let it = self.slots[slot].take()?;
+        self.by_qd.remove(&it.borrow().get_qd());
+        self.free.push(slot);
+        return Some(it);
     }
 
     pub fn get(&mut self, qd: demi::DemiQd) -> Option<Shared<Item>> {
-        let ret = self.inner.get(&qd).map(|rc| rc.clone());
-        return ret;
+        let slot = self.find(qd)?;
+        return self.slots[slot].clone();
+    }
+
+    /// the slot of the item whose socket owns `qd`, falling back to a scan for the qds of racing
+    /// connects and sockets that changed their qd, which are rekeyed on the way
+    fn find(&mut self, qd: demi::DemiQd) -> Option<usize> {
+        if let Some(&slot) = self.by_qd.get(&qd) {
+            self.rekey(slot);
+        }
+        if let Some(&slot) = self.by_qd.get(&qd) {
+            return Some(slot);
+        }
+
+        let slot = self.slots.iter().position(|it| {
+            it.as_ref()
+                .is_some_and(|it| it.borrow().soc.borrow().owns_qd(qd))
+        })?;
+
+        self.rekey(slot);
+        return Some(slot);
+    }
+
+    /// moves the item in `slot` to the current qd of its socket, first moving an item that still
+    /// holds that qd
+    fn rekey(&mut self, slot: usize) {
+        let Some(it) = self.slots[slot].clone() else {
+            return;
+        };
+        let Some(old) = it.borrow_mut().rekey() else {
+            return;
+        };
+        let qd = it.borrow().get_qd();
+
+        if self.by_qd.get(&old) == Some(&slot) {
+            self.by_qd.remove(&old);
+        }
+        if let Some(&other) = self.by_qd.get(&qd) {
+            self.rekey(other);
+        }
+        if let Some(old) = self.by_qd.insert(qd, slot) {
+            self.release(old);
+        }
+    }
+
+    /// empties `slot`, the item in it is not reachable through `by_qd` anymore
+    fn release(&mut self, slot: usize) {
+        if self.slots[slot].take().is_some() {
+            self.free.push(slot);
+        }
     }
 
     pub fn len(&self) -> usize {
-        return self.inner.len();
+        return self.by_qd.len();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Shared<Item>> {
+        return self.slots.iter().flatten();
+    }
+
+    /// removes `it` by identity, fails with ENOENT if it is not in the slab
+    ///
+    /// its key might be held by another item, e.g. when demikernel reused the qd of a socket that
+    /// won a raced connect while the old one was still registered, so the slot is checked first
+    pub fn remove(&mut self, it: &Shared<Item>) -> PosixResult<()> {
+        let qd = it.borrow().get_qd();
+        let holds = |slot: &Option<Shared<Item>>| slot.as_ref().is_some_and(|s| s.ptr_eq(it));
+        let slot = match self.by_qd.get(&qd) {
+            Some(&slot) if holds(&self.slots[slot]) => {
+                self.by_qd.remove(&qd);
+                slot
+            }
+            _ => self.slots.iter().position(holds).ok_or(PosixError::NOENT)?,
+        };
+
+        self.release(slot);
+        return Ok(());
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dpoll::Event, socket::Socket};
 
-    pub fn iter(&self) -> Values<'_, demi::DemiQd, Shared<Item>> {
-        return self.inner.values();
+    fn socket(qd: i32) -> Shared<Socket> {
+        return Shared::new(Socket::new(demi::SocketQd::from(qd)));
     }
 
-    pub fn remove(&mut self, needle: &Item) {
-        _ = self.inner.remove(&needle.get_qd()).unwrap();
+    #[test]
+    fn remove_after_swap() {
+        let (a, b) = (socket(1), socket(2));
+        let mut items = Items::new();
+        items.insert(Item::new(a.clone(), Event::IN, 1));
+        items.insert(Item::new(b.clone(), Event::IN, 2));
+
+        // a won a raced connect on a qd of its own, the old one is free for b
+        a.borrow_mut().soc = demi::SocketQd::from(3);
+        let it = items.get(3).unwrap();
+        assert!(it.borrow().soc.ptr_eq(&a));
+        b.borrow_mut().soc = demi::SocketQd::from(1);
+        let it = items.get(1).unwrap();
+        assert!(it.borrow().soc.ptr_eq(&b));
+
+        let a = items.get(3).unwrap();
+        assert_eq!(items.remove(&a), Ok(()));
+        assert_eq!(items.remove(&a), Err(PosixError::NOENT));
+        assert!(items.get(3).is_none());
+        assert_eq!(items.remove(&it), Ok(()));
+        assert_eq!(items.len(), 0);
+    }
+
+    #[test]
+    fn remove_key_taken() {
+        let (a, b) = (socket(1), socket(2));
+        let mut items = Items::new();
+        items.insert(Item::new(a.clone(), Event::IN, 1));
+        items.insert(Item::new(b.clone(), Event::IN, 2));
+        let b_item = items.get(2).unwrap();
+
+        // a moved to the qd b still holds, e.g. one reused while b was closed but registered, b
+        // loses its slot and has to fail instead of taking a with it
+        a.borrow_mut().soc = demi::SocketQd::from(2);
+        assert!(items.get(1).is_none());
+        let a_item = items.get(2).unwrap();
+        assert!(a_item.borrow().soc.ptr_eq(&a));
+        assert_eq!(items.remove(&b_item), Err(PosixError::NOENT));
+        assert_eq!(items.remove(&a_item), Ok(()));
+        assert_eq!(items.len(), 0);
     }
 }
@@ -1,198 +1,1075 @@
 mod epoll;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+pub mod history;
 mod item;
 mod items;
 mod operation;
+mod raw_ops;
 mod ready_list;
+mod shards;
+pub mod stats;
+mod waker;
+mod wakeup;
 
-use crate::wrappers::{
-    demi,
-    errno::{PosixError, PosixResult},
+use crate::{
+    config::Config,
+    fork,
+    shared::Shared,
+    socket::Socket,
+    watchdog::Watchdog,
+    wrappers::{
+        clock,
+        deadline::Deadline,
+        demi,
+        errno::{PosixError, PosixResult},
+        platform::Sigset,
+    },
 };
 use bitflags::bitflags;
-use libc::{EPOLLIN, EPOLLOUT, epoll_event};
-use log::trace;
-use std::{convert, mem::MaybeUninit, time::Duration};
+use libc::{
+    EPOLL_CLOEXEC, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLLERR, EPOLLEXCLUSIVE, EPOLLHUP, EPOLLIN,
+    EPOLLOUT, EPOLLPRI, EPOLLWAKEUP, c_int, epoll_event, sigset_t,
+};
+use log::{trace, warn};
+use std::{
+    cmp::Reverse,
+    convert,
+    mem::{self, MaybeUninit},
+    os::raw::c_void,
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 
 use epoll::Epoll;
+use history::Transition;
 use item::Item;
 use items::Items;
 pub use operation::Operation;
+use raw_ops::RawOps;
 use ready_list::ReadyList;
+pub use shards::DEFAULT_WAIT_SHARDS;
+use shards::Shards;
+use stats::{Phase, Stats};
+pub use waker::Waker;
+use wakeup::Wakeup;
 
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct Event: u32 {
         const IN = EPOLLIN as u32;
         const OUT = EPOLLOUT as u32;
+        const ERR = EPOLLERR as u32;
+        const HUP = EPOLLHUP as u32;
     }
 }
 
 impl convert::TryFrom<u32> for Event {
-    type Error = DpollErrors;
+    type Error = DpollError;
 
     fn try_from(evs: u32) -> Result<Self, Self::Error> {
         match Self::from_bits(evs) {
             Some(evs) => return Ok(evs),
-            None => return Err(DpollErrors::InvalidEvent(evs)),
+            None => return Err(DpollError::InvalidEvent(evs)),
         }
     }
 }
 
-#[derive(Debug, Error)]
-pub enum DpollErrors {
-    #[error("invalid error value: {:b}", 0)]
+bitflags! {
+    /// flags applications set defensively that mean nothing for dpoll sockets, ctl accepts them
+    /// and records them on the item, any other unknown bit still fails it with EINVAL
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct IgnoredFlags: u32 {
+        /// there is no autosleep wakeup source to hold while events are pending
+        const WAKEUP = EPOLLWAKEUP as u32;
+        /// a dpoll socket only ever wakes the thread of its dpolls, like the kernel it is refused
+        /// by EPOLL_CTL_MOD
+        const EXCLUSIVE = EPOLLEXCLUSIVE as u32;
+        /// demikernel has no urgent data, so it is never reported
+        const PRI = EPOLLPRI as u32;
+    }
+}
+
+impl Event {
+    /// splits the events of a ctl into the ones dpoll reports and the ignored flags
+    pub fn with_flags(bits: u32) -> DpollResult<(Self, IgnoredFlags)> {
+        let flags = IgnoredFlags::from_bits_truncate(bits);
+        let evs = Self::from_bits(bits & !flags.bits()).ok_or(DpollError::InvalidEvent(bits))?;
+        return Ok((evs, flags));
+    }
+}
+
+/// what went wrong inside dpoll and where, instead of a panic taking the application down
+///
+/// it is logged when turned into the PosixError the application sees
+#[derive(Debug, Clone, Copy, Error, PartialEq, Eq)]
+pub enum DpollError {
+    #[error("invalid event mask {0:#b}")]
     InvalidEvent(u32),
+    #[error("invalid ctl op {op} on qd {qd}")]
+    InvalidOp { qd: demi::DemiQd, op: c_int },
+    #[error("ctl op {op} on qd {qd} needs an event")]
+    MissingEvent { qd: demi::DemiQd, op: c_int },
+    #[error("{op} on qd {qd} failed: {err}")]
+    Demi {
+        op: &'static str,
+        qd: demi::DemiQd,
+        err: PosixError,
+    },
+    /// a completion that does not fit the state of its socket
+    #[error("qd {qd} got a {got} completion while {state}")]
+    UnexpectedCompletion {
+        qd: demi::DemiQd,
+        got: &'static str,
+        state: &'static str,
+    },
+    /// a completion of an operation nothing waits on
+    #[error("nothing waits on qt {qt} of qd {qd}")]
+    UnknownCompletion { qd: demi::DemiQd, qt: demi::QToken },
+    #[error(transparent)]
+    Posix(#[from] PosixError),
+}
+
+pub type DpollResult<T> = Result<T, DpollError>;
+
+impl From<DpollError> for PosixError {
+    fn from(err: DpollError) -> Self {
+        let posix = match err {
+            DpollError::InvalidEvent(_) | DpollError::InvalidOp { .. } => PosixError::INVAL,
+            DpollError::MissingEvent { .. } => PosixError::FAULT,
+            DpollError::Demi { err, .. } => err,
+            DpollError::UnexpectedCompletion { .. } | DpollError::UnknownCompletion { .. } => {
+                PosixError::PROTO
+            }
+            DpollError::Posix(err) => return err,
+        };
+
+        warn!("{err}");
+        return posix;
+    }
+}
+
+/// called with `ctx` for every event a pwait returns, see `Dpoll::set_event_callback`
+#[derive(Debug, Clone, Copy)]
+pub struct EventCallback {
+    pub func: unsafe extern "C" fn(ctx: *mut c_void, event: *const epoll_event),
+    pub ctx: *mut c_void,
+}
+
+/// what a registration listed by `Dpoll::registered` is of
+#[derive(Debug, Clone)]
+pub enum Target {
+    Socket(Shared<Socket>),
+    Kernel(c_int),
+    Nested(Shared<Dpoll>),
+}
+
+/// a registration as `Dpoll::registered` lists it, like a line of the fdinfo of an epoll fd
+#[derive(Debug, Clone)]
+pub struct Registration {
+    pub target: Target,
+    /// the events and flags of the last add or modify
+    pub events: u32,
+    /// the data of the last add or modify, not the cookie reported instead of it
+    pub data: u64,
+    /// the registered events that are ready right now
+    pub ready: u32,
 }
 
 #[derive(Debug)]
 pub struct Dpoll {
+    /// the transitions of the dpoll are recorded under it, see `history`
+    id: u64,
     items: Items,
+    /// counts the registrations made with a cookie, the lower half of their cookies
+    registrations: u32,
 
     ready_list: ReadyList,
+    /// operations submitted with `submit_raw`
+    raw_ops: RawOps,
     qtoks: Vec<demi::QToken>,
     epoll: Epoll,
+    /// whether the dpoll was created with EPOLL_CLOEXEC
+    cloexec: bool,
+    stats: Stats,
+    /// the time the running pwait spent in each phase so far, indexed by `Phase`
+    phase_times: [Duration; 4],
+    /// see `Dpoll::harvest_cpu`
+    harvest_cpu: Option<c_int>,
+    /// whether the harvesting moved to another cpu yet, which is only warned about once
+    harvest_moved: bool,
+    /// pinged by the registered sockets when their state changes
+    waker: Waker,
+    /// created when the dpoll is first nested in another one
+    wakeup: Option<Wakeup>,
+    /// dpolls nested in this one, their sockets are scheduled and waited on together with ours
+    nested: Vec<Shared<Dpoll>>,
+    /// sockets without completions for longer are reported as `Event::HUP` and not waited on
+    max_idle: Option<Duration>,
+    watchdog: Watchdog,
+    /// completions processed per demikernel wait, the first one blocks and the rest are polled
+    max_completions: usize,
+    /// the shards `qtoks` are polled in
+    shards: Shards,
+    /// accepts completed per pwait before the listeners are not waited on anymore until the next
+    /// one, `None` for no limit
+    max_accepts: Option<usize>,
+    /// the accepts completed by the running pwait
+    accepts: usize,
+    /// sockets registered at most, adds beyond fail with ENOSPC, `None` for no limit
+    max_items: Option<usize>,
+    /// the tokens of `qtoks` that are accepts of listeners
+    accept_qtoks: Vec<demi::QToken>,
+    /// the items in the order they are scheduled in, kept empty between scans for its allocation
+    scan: Vec<Shared<Item>>,
+    /// the last pwait was filled by the ready list without looking at the kernel fds, which go
+    /// first in the next one
+    epoll_starved: bool,
+    event_callback: Option<EventCallback>,
 }
 
 impl Dpoll {
+    /// fails with EINVAL if `flags` has anything but EPOLL_CLOEXEC set
     pub fn create(flags: i32) -> PosixResult<Self> {
+        if flags & !EPOLL_CLOEXEC != 0 {
+            trace!("invalid dpoll flags: {flags:#x}");
+            return Err(PosixError::INVAL);
+        }
+
+        let config = Config::current();
         return Ok(Self {
+            id: history::new_id(),
             items: Items::new(),
+            registrations: 0,
             qtoks: Vec::with_capacity(1024),
             epoll: Epoll::create(flags)?,
             ready_list: ReadyList::new(),
+            raw_ops: RawOps::new(),
+            cloexec: flags & EPOLL_CLOEXEC != 0,
+            stats: Stats::new(),
+            phase_times: [Duration::ZERO; 4],
+            harvest_cpu: None,
+            harvest_moved: false,
+            waker: Waker::new(),
+            wakeup: None,
+            nested: Vec::new(),
+            max_idle: config.max_idle,
+            watchdog: config.watchdog,
+            max_completions: config.max_completions_per_wait,
+            shards: Shards::new(config.wait_shards),
+            max_accepts: config.max_accepts_per_wait,
+            max_items: config.max_items,
+            accepts: 0,
+            accept_qtoks: Vec::new(),
+            scan: Vec::new(),
+            epoll_starved: false,
+            event_callback: None,
+        });
+    }
+
+    #[allow(dead_code)]
+    pub fn is_cloexec(&self) -> bool {
+        return self.cloexec;
+    }
+
+    /// the number of registered dpoll sockets
+    pub fn len(&self) -> usize {
+        return self.items.len();
+    }
+
+    pub fn stats(&self) -> &Stats {
+        return &self.stats;
+    }
+
+    /// yields the qd and the number of running operations of every registered socket
+    pub fn queue_depths(&self) -> impl Iterator<Item = (demi::DemiQd, usize)> + '_ {
+        return self.items.iter().map(|it| {
+            let it = it.borrow();
+            let soc = it.soc.borrow();
+            (soc.soc.qd, soc.running_operations())
         });
     }
 
+    /// yields the received bytes not read yet of every registered socket and whether it stopped
+    /// popping because of them
+    pub fn recv_buffered(&self) -> impl Iterator<Item = (usize, bool)> + '_ {
+        return self
+            .items
+            .iter()
+            .map(|it| it.borrow().soc.borrow().recv_buffered());
+    }
+
+    /// applies `ops` in order, stopping at the first failing one
+    ///
+    /// returns the number of applied operations and the error that stopped the batch, if any
+    pub fn ctl_many<I>(&mut self, ops: I) -> (usize, PosixResult<()>)
+    where
+        I: ExactSizeIterator<Item = Operation>,
+    {
+        self.qtoks.reserve((self.items.len() + ops.len()) * 2);
+
+        let mut applied = 0;
+        for op in ops {
+            if let Err(e) = self.ctl(op) {
+                return (applied, Err(e));
+            }
+            applied += 1;
+        }
+
+        return (applied, Ok(()));
+    }
+
+    /// whether `pol` is nested in this dpoll, directly or not
+    pub fn nests(&self, pol: &Shared<Dpoll>) -> bool {
+        return self
+            .nested
+            .iter()
+            .any(|n| n.ptr_eq(pol) || n.borrow().nests(pol));
+    }
+
+    /// sockets that complete no operation for longer than `max_idle` are reported as
+    /// `Event::HUP` and their operations are not waited on anymore, until they are modified
+    pub fn set_max_idle(&mut self, max_idle: Option<Duration>) {
+        self.max_idle = max_idle;
+    }
+
+    /// see `Watchdog`, it looks at the running operations of the sockets as they are scheduled
+    pub fn set_watchdog(&mut self, watchdog: Watchdog) {
+        self.watchdog = watchdog;
+    }
+
+    /// processes up to `max` completions per demikernel wait, `max` has to be positive
+    pub fn set_max_completions(&mut self, max: usize) {
+        assert!(max > 0);
+        self.max_completions = max;
+    }
+
+    /// completes at most `max` accepts per pwait, the listeners with more connections waiting stay
+    /// pending in demikernel until the next one, `None` removes the limit
+    pub fn set_max_accepts(&mut self, max: Option<usize>) {
+        assert!(max != Some(0));
+        self.max_accepts = max;
+    }
+
+    /// registers at most `max` sockets, adds beyond fail with ENOSPC so one component can not take
+    /// all the tokens of demikernel, sockets already registered over it stay, `None` removes the
+    /// limit
+    pub fn set_max_items(&mut self, max: Option<usize>) {
+        assert!(max != Some(0));
+        self.max_items = max;
+    }
+
+    /// `None` removes the callback
+    pub fn set_event_callback(&mut self, callback: Option<EventCallback>) {
+        self.event_callback = callback;
+    }
+
+    /// the callback to invoke for every returned event, it is up to the caller so it runs without
+    /// the dpoll borrowed and can call back into it
+    pub fn event_callback(&self) -> Option<EventCallback> {
+        return self.event_callback;
+    }
+
+    /// the kernel fd that is readable whenever this dpoll has ready events
+    pub fn wakeup_fd(&mut self) -> PosixResult<c_int> {
+        if self.wakeup.is_none() {
+            self.wakeup = Some(Wakeup::new(&self.epoll, &self.waker)?);
+            self.update_wakeup();
+        }
+
+        return Ok(self.wakeup.as_ref().unwrap().fd());
+    }
+
+    /// waits on `qt`, an operation the application submitted to demikernel itself, reporting its
+    /// completion once as `Event::IN` with `data`
+    ///
+    /// the result is kept until it is taken with `take_raw`
+    pub fn submit_raw(&mut self, qt: demi::QToken, data: u64) -> PosixResult<()> {
+        return self.raw_ops.submit(qt, data);
+    }
+
+    /// the result of an operation passed to `submit_raw`, WOULDBLOCK if it did not complete yet
+    pub fn take_raw(&mut self, qt: demi::QToken) -> PosixResult<demi::RawQResult> {
+        let res = self.raw_ops.take(qt);
+        self.update_wakeup();
+        return res;
+    }
+
+    /// the events and data `qd` is registered with, if it is
+    pub fn registration(&mut self, qd: demi::DemiQd) -> Option<(Event, IgnoredFlags, u64)> {
+        let it = self.items.get(qd)?;
+        let it = it.borrow();
+        return Some((it.evs, it.flags, it.data));
+    }
+
+    /// yields everything registered in the dpoll, the sockets first and then the kernel fds and
+    /// nested dpolls sorted by fd
+    ///
+    /// the kernel keeps the events and data of the latter, they are read from /proc, see
+    /// `Epoll::registrations`
+    pub fn registered(&self) -> impl Iterator<Item = Registration> + '_ {
+        let sockets = self.items.iter().map(|it| {
+            let it = it.borrow();
+            return Registration {
+                target: Target::Socket(it.soc.clone()),
+                events: it.evs.bits() | it.flags.bits(),
+                data: it.data,
+                ready: Self::reportable(&it).bits(),
+            };
+        });
+        let kernel = self
+            .epoll
+            .registrations()
+            .into_iter()
+            .map(|(fd, events, data, ready)| {
+                let nested = self.nested.iter().find(|pol| {
+                    let wakeup = pol.borrow().wakeup.as_ref().map(Wakeup::fd);
+                    wakeup == Some(fd)
+                });
+                let target = match nested {
+                    Some(pol) => Target::Nested(pol.clone()),
+                    None => Target::Kernel(fd),
+                };
+                return Registration {
+                    target,
+                    events,
+                    data,
+                    ready,
+                };
+            });
+
+        return sockets.chain(kernel);
+    }
+
+    /// like epoll_ctl, fails with EEXIST for an add of a socket that is registered already and with
+    /// ENOENT for a modify or delete of one that is not
     pub fn ctl(&mut self, op: Operation) -> PosixResult<()> {
         let op = match op {
             Operation::Epoll(op) => return self.epoll.ctl(op),
+            Operation::Nested(op) => return self.ctl_nested(op),
             Operation::Dpoll(op) => op,
         };
 
         match op {
-            operation::DpollOperation::Add { soc, evs, data } => {
-                self.items.insert(Item::new(soc, evs, data));
+            operation::DpollOperation::Add {
+                soc,
+                evs,
+                flags,
+                data,
+                cookie,
+            } => {
+                let qd = soc.borrow().soc.qd;
+                if self
+                    .items
+                    .get(qd)
+                    .is_some_and(|it| it.borrow().soc.ptr_eq(&soc))
+                {
+                    return Err(PosixError::EXIST);
+                }
+                if self.max_items.is_some_and(|max| self.items.len() >= max) {
+                    trace!("{} is full, refusing qd {qd}", self.id);
+                    self.stats.refused_adds += 1;
+                    return Err(PosixError::NOSPC);
+                }
+
+                soc.borrow_mut().watch(self.waker.clone());
+                let mut it = Item::new(soc, evs, data);
+                it.flags = flags;
+                it.cookie = cookie.map(|fd| self.next_cookie(fd));
+                self.items.insert(it);
+                self.stats.max_items = self.stats.max_items.max(self.items.len() as u64);
             }
             operation::DpollOperation::Del { qd } => {
-                let it = self.items.take(qd).unwrap();
+                let it = self.items.take(qd).ok_or(PosixError::NOENT)?;
+                it.borrow().soc.borrow_mut().unwatch(&self.waker);
 
                 if it.borrow().on_readylist {
                     self.ready_list.remove(&it);
+                    history::record(self.id, Transition::ReadyRemove { qd });
+                    self.update_wakeup();
                 }
             }
-            operation::DpollOperation::Mod { qd, evs } => {
-                self.items.get(qd).unwrap().borrow_mut().evs = evs
+            operation::DpollOperation::Mod {
+                qd,
+                evs,
+                flags,
+                data,
+                cookie,
+            } => {
+                let it = self.items.get(qd).ok_or(PosixError::NOENT)?;
+                let cookie = cookie.map(|fd| self.next_cookie(fd));
+                let mut it = it.borrow_mut();
+                it.evs = evs;
+                it.flags = flags;
+                it.data = data;
+                it.cookie = cookie;
+                it.touch();
             }
         }
 
         return Ok(());
     }
 
-    fn wait(&mut self, timeout: Option<Duration>) -> PosixResult<()> {
+    /// the fd of the socket in the upper half, so a reused slot never matches, and a count of the
+    /// registrations in the lower one, so neither does a later registration of the same fd
+    fn next_cookie(&mut self, fd: c_int) -> u64 {
+        self.registrations = self.registrations.wrapping_add(1);
+        return (fd as u32 as u64) << 32 | self.registrations as u64;
+    }
+
+    /// the data of the registration of `qd` that reports `cookie`, `None` if it was deleted or
+    /// modified since
+    pub fn cookie_data(&mut self, qd: demi::DemiQd, cookie: u64) -> Option<u64> {
+        let it = self.items.get(qd)?;
+        let it = it.borrow();
+        return (it.cookie == Some(cookie)).then_some(it.data);
+    }
+
+    /// forgets the kernel fd `fd`, which is about to be closed
+    pub fn forget_kernel_fd(&mut self, fd: c_int) {
+        self.epoll.forget(fd);
+    }
+
+    /// stops waiting on `pol`, which is about to be closed, if it is nested in this dpoll
+    pub fn unnest(&mut self, pol: &Shared<Dpoll>) {
+        if !self.nested.iter().any(|n| n.ptr_eq(pol)) {
+            return;
+        }
+        let op = operation::NestedOperation {
+            op: EPOLL_CTL_DEL,
+            pol: pol.clone(),
+            event: std::ptr::null_mut(),
+        };
+        if let Err(e) = self.ctl_nested(op) {
+            trace!("unnesting from {} failed with {e:?}", self.id);
+            self.nested.retain(|n| !n.ptr_eq(pol));
+        }
+    }
+
+    fn ctl_nested(&mut self, op: operation::NestedOperation) -> PosixResult<()> {
+        let operation::NestedOperation { op, pol, event } = op;
+        let fd = pol.borrow_mut().wakeup_fd()?;
+        self.epoll
+            .ctl(operation::EpollOperation { op, fd, event })?;
+
+        match op {
+            EPOLL_CTL_ADD => self.nested.push(pol),
+            EPOLL_CTL_DEL => self.nested.retain(|n| !n.ptr_eq(&pol)),
+            _ => {}
+        }
+
+        return Ok(());
+    }
+
+    fn update_wakeup(&mut self) {
+        self.waker
+            .set(!self.ready_list.is_empty() || self.raw_ops.has_ready());
+    }
+
+    /// stores `res` if it completes a raw operation of ours or of a nested dpoll
+    ///
+    /// returns `res` back if it does not
+    fn process_raw(&mut self, res: demi::RawQResult) -> Result<(), demi::RawQResult> {
+        let mut res = match self.raw_ops.complete(res) {
+            Ok(()) => {
+                self.update_wakeup();
+                return Ok(());
+            }
+            Err(res) => res,
+        };
+
+        for pol in &self.nested {
+            match pol.borrow_mut().process_raw(res) {
+                Ok(()) => return Ok(()),
+                Err(r) => res = r,
+            }
+        }
+
+        return Err(res);
+    }
+
+    /// passes `res` to the socket it belongs to, either ours or one of a nested dpoll
+    ///
+    /// returns `res` back if no socket was found
+    fn process(&mut self, res: demi::QResult) -> Result<(), demi::QResult> {
+        if let Some(item) = self.items.get(res.qd) {
+            let transition = Transition::Completion {
+                qd: res.qd,
+                qt: res.qt,
+                ok: res.value.is_ok(),
+            };
+            history::record(self.id, transition);
+            item.borrow_mut().touch();
+            item.borrow()
+                .soc
+                .borrow_mut()
+                .process_event(res.qt, res.value);
+            let qd = item.borrow().get_qd();
+            if !item.borrow().on_readylist {
+                history::record(self.id, Transition::ReadyPush { qd });
+            }
+            self.ready_list.push(item);
+            self.update_wakeup();
+            return Ok(());
+        }
+
+        let mut res = res;
+        for pol in &self.nested {
+            match pol.borrow_mut().process(res) {
+                Ok(()) => return Ok(()),
+                Err(r) => res = r,
+            }
+        }
+
+        return Err(res);
+    }
+
+    /// returns the number of processed completions
+    ///
+    /// `sigmask` is applied only while blocking in demikernel, a poll is not interruptible anyway
+    fn wait(&mut self, deadline: Deadline, sigmask: Option<&sigset_t>) -> PosixResult<u64> {
         trace!("waiting on {:?}", self.qtoks);
         if self.qtoks.is_empty() {
             trace!("there are no qtoks, not going to wait");
-            return Ok(());
+            return Ok(0);
+        }
+        // with more than one shard the first completion is polled for in them too, only blocking
+        // waits on all the tokens
+        let polled = (self.shards.count(self.qtoks.len()) > 1).then(|| self.poll_shards());
+        let res = match polled {
+            Some(Err(PosixError::TIMEDOUT)) if deadline.has_passed() => Err(PosixError::TIMEDOUT),
+            Some(Err(PosixError::TIMEDOUT)) | None => {
+                let _old_set = sigmask.filter(|_| !deadline.has_passed()).map(Sigset::mask);
+                demi::wait_any_raw_retrying(self.qtoks.as_slice(), deadline)
+            }
+            Some(res) => res,
+        };
+        let (mut idx, mut res) = res?;
+        let mut count = 0;
+        loop {
+            let accepted = res.qr_opcode == demi::Opcode::ACCEPT as u32;
+            self.complete(res);
+            count += 1;
+            // a completed token must not be waited on again
+            self.qtoks.swap_remove(idx);
+            if accepted {
+                self.accepts += 1;
+                self.defer_accepts();
+            }
+            if count == self.max_completions || self.qtoks.is_empty() {
+                break;
+            }
+            match self.poll_shards() {
+                Ok(next) => (idx, res) = next,
+                Err(PosixError::TIMEDOUT) => break,
+                Err(e) => {
+                    trace!("polling for more completions failed with {e:?}");
+                    break;
+                }
+            }
         }
-        let (_, res) = demi::wait_any(self.qtoks.as_slice(), timeout)?;
-        trace!("got {res:?}");
-        let res = res.unwrap();
-        let item = self.items.get(res.qd).unwrap();
-        item.borrow()
-            .soc
-            .borrow_mut()
-            .process_event(res.value.unwrap());
-        self.ready_list.push(item);
 
-        return Ok(());
+        self.stats.record_batch(count);
+        return Ok(count as u64);
     }
 
-    fn get_and_schedule_events(&mut self) {
+    fn poll_shards(&mut self) -> PosixResult<(usize, demi::RawQResult)> {
+        return self.shards.poll(&self.qtoks, |toks| {
+            demi::wait_any_raw(toks, Some(Duration::ZERO))
+        });
+    }
+
+    /// stops waiting on the accepts of the listeners once `max_accepts` completed in this pwait,
+    /// their connections are left for the next one
+    fn defer_accepts(&mut self) {
+        if self.max_accepts.is_none_or(|max| self.accepts < max) || self.accept_qtoks.is_empty() {
+            return;
+        }
+
+        let before = self.qtoks.len();
+        let accept_qtoks = &self.accept_qtoks;
+        self.qtoks.retain(|qt| !accept_qtoks.contains(qt));
+        let deferred = before - self.qtoks.len();
+        if deferred > 0 {
+            trace!(
+                "{} accepts completed, deferring {deferred} listeners",
+                self.accepts
+            );
+            self.stats.deferred_accepts += deferred as u64;
+        }
+        self.accept_qtoks.clear();
+    }
+
+    fn complete(&mut self, res: demi::RawQResult) {
+        let Err(res) = self.process_raw(res) else {
+            trace!("got a raw completion");
+            return;
+        };
+
+        let res = demi::QResult::from(res);
+        trace!("got {res:?}");
+        // e.g. the pop of a socket deleted before it completed
+        if let Err(res) = self.process(res) {
+            let err = DpollError::UnknownCompletion {
+                qd: res.qd,
+                qt: res.qt,
+            };
+            warn!("dropping the completion: {err}");
+        }
+    }
+
+    /// returns the time until the first socket timer fires, either a paced socket waiting for
+    /// `Event::OUT` being able to write again, a keepalive running out or a socket going idle
+    fn get_and_schedule_events(&mut self) -> Option<Duration> {
         trace!("starting to schedule events");
+        // keeps its allocation across pwaits, `Vec` only grows it geometrically when it runs out
         self.qtoks.clear();
-        self.qtoks.reserve(self.items.len() * 2);
+        let capacity = self.qtoks.capacity();
+        self.accept_qtoks.clear();
 
         let mut list = ReadyList::new();
-        let mut delete_list = ReadyList::new();
-
-        for item in self.items.iter() {
-            let it = item.borrow();
-            let mut soc = it.soc.borrow_mut();
-            if !soc.open {
-                trace!("socket {:?} is not open, adding it to delete_list", soc);
-                delete_list.push(item.clone());
-                continue;
-            }
+        let mut delete_list = Vec::new();
+        let mut timer = None;
+        let now = clock::now();
+
+        // higher priorities first, the sort is stable so the slot order holds within a priority
+        let mut scan = mem::take(&mut self.scan);
+        scan.extend(self.items.iter().cloned());
+        scan.sort_by_key(|item| Reverse(item.borrow().soc.borrow().priority()));
+
+        for item in scan.drain(..) {
+            let scheduled = Self::schedule_item(
+                self.id,
+                &mut item.borrow_mut(),
+                now,
+                self.max_idle,
+                &self.watchdog,
+                &mut self.qtoks,
+                &mut timer,
+            );
 
-            let evs = it.evs;
-            let ready = soc.available_events(evs);
-            let evs_to_schedule = evs.difference(ready);
-            soc.schedule_events(evs_to_schedule, &mut self.qtoks);
-            if !ready.is_empty() && !it.on_readylist {
-                list.push(item.clone());
+            self.accept_qtoks
+                .extend(item.borrow().soc.borrow().accept_token());
+            match scheduled {
+                Scheduled::Closed => delete_list.push(item),
+                Scheduled::Ready => list.push(item),
+                Scheduled::Waiting => {}
             }
         }
+        self.scan = scan;
 
-        for it in delete_list.into_iter().map(|(item, _)| item) {
-            let item = it.borrow_mut();
+        // `ReadyList::remove` borrows the item itself
+        for it in delete_list {
+            let (on_readylist, qd) = {
+                let item = it.borrow();
+                item.soc.borrow_mut().unwatch(&self.waker);
+                (item.on_readylist, item.get_qd())
+            };
 
-            if item.on_readylist {
+            if on_readylist {
                 self.ready_list.remove(&it);
+                history::record(self.id, Transition::ReadyRemove { qd });
             }
 
-            self.items.remove(&item);
+            if let Err(e) = self.items.remove(&it) {
+                trace!("removing the closed {qd} failed with {e:?}");
+            }
         }
 
         trace!("list: {:?}", list);
+        for item in list.iter() {
+            let qd = item.borrow().get_qd();
+            history::record(self.id, Transition::ReadyPush { qd });
+        }
         self.ready_list.append(list);
+        self.qtoks.extend(self.raw_ops.qtoks());
+
+        for pol in &self.nested {
+            let mut pol = pol.borrow_mut();
+            timer = earliest(timer, pol.get_and_schedule_events());
+            self.qtoks.extend_from_slice(&pol.qtoks);
+            self.accept_qtoks.extend_from_slice(&pol.accept_qtoks);
+            pol.update_wakeup();
+        }
+
+        // a pwait resumed after a socket timer keeps its accept budget
+        self.defer_accepts();
+        self.stats
+            .record_qtoks(self.qtoks.len(), self.qtoks.capacity() > capacity);
+
+        return timer;
+    }
+
+    fn schedule_item(
+        id: u64,
+        it: &mut Item,
+        now: Instant,
+        max_idle: Option<Duration>,
+        watchdog: &Watchdog,
+        qtoks: &mut Vec<demi::QToken>,
+        timer: &mut Option<Duration>,
+    ) -> Scheduled {
+        let mut soc = it.soc.borrow_mut();
+        // the ready list stays ordered by the priorities the items entered it with
+        if !it.on_readylist {
+            it.priority = soc.priority();
+        }
+        if !soc.is_open() {
+            trace!("socket {:?} is not open, adding it to delete_list", soc);
+            return Scheduled::Closed;
+        }
+
+        if let Some(max_idle) = max_idle {
+            let idle_for = now.saturating_duration_since(it.last_activity);
+            if idle_for < max_idle {
+                *timer = earliest(*timer, Some(max_idle - idle_for));
+            } else if !it.idle {
+                trace!("socket {} is idle for {idle_for:?}", soc.soc.qd);
+                it.idle = true;
+            }
+        }
+        if it.idle {
+            return Scheduled::ready(!it.on_readylist);
+        }
+
+        *timer = earliest(*timer, soc.check_keepalive(now));
+        *timer = earliest(*timer, soc.check_watchdog(now, watchdog));
+
+        let evs = it.evs;
+        let ready = soc.available_events(evs);
+        let evs_to_schedule = evs.difference(ready);
+        let ready = match soc.schedule_events(evs_to_schedule, qtoks) {
+            Ok(()) => ready,
+            // reported as EPOLLERR rather than failing the wait of every other socket
+            Err(err) => {
+                soc.pending_error = Some(err.into());
+                soc.available_events(evs)
+            }
+        };
+        if !evs_to_schedule.is_empty() {
+            let qd = it.get_qd();
+            history::record(
+                id,
+                Transition::Schedule {
+                    qd,
+                    evs: evs_to_schedule,
+                },
+            );
+        }
+
+        if evs_to_schedule.contains(Event::OUT) {
+            *timer = earliest(*timer, soc.pacing_delay());
+        }
+
+        return Scheduled::ready(!ready.is_empty() && !it.on_readylist);
+    }
+
+    /// the events `item` has to report right now, its socket might have changed since it entered
+    /// the ready list, e.g. a read consumed the data of the completion that put it there
+    fn reportable(item: &Item) -> Event {
+        // a completion might not be of interest, e.g. a push with only IN requested
+        let mut events = item.soc.borrow().available_events(item.evs);
+        if item.idle {
+            events |= Event::HUP;
+        }
+        return events;
     }
 
     fn drain_ready_list(&mut self, evs: &mut [MaybeUninit<epoll_event>]) -> usize {
-        return self.ready_list.drain(evs.len(), |i, soc, data| {
-            let events = soc.available_events(Event::all());
+        let id = self.id;
+        let len = self.ready_list.drain(evs.len(), |i, item| {
+            let events = Self::reportable(item);
+            if events.is_empty() {
+                return false;
+            }
+
+            let qd = item.get_qd();
+            history::record(id, Transition::Drain { qd, evs: events });
             evs[i] = MaybeUninit::new(epoll_event {
                 events: events.bits(),
-                u64: data,
+                u64: item.reported_data(),
             });
+            return true;
         });
+
+        let raw = self.raw_ops.drain(evs.len() - len);
+        return len
+            + evs[len..]
+                .iter_mut()
+                .zip(raw)
+                .map(|(ev, data)| {
+                    ev.write(epoll_event {
+                        events: Event::IN.bits(),
+                        u64: data,
+                    });
+                })
+                .count();
     }
 
+    /// blocks for at most `timeout` in total, across both the demikernel and the kernel wait
+    ///
+    /// the kernel fds are always polled last, so those ready by the deadline are reported even if
+    /// the demikernel wait used up the whole timeout
+    ///
+    /// `sigmask` replaces the signal mask during those waits only, like with epoll_pwait
+    ///
+    /// fails with EINVAL if `events` is empty, like epoll_wait does for maxevents <= 0
     pub fn pwait(
         &mut self,
         events: &mut [MaybeUninit<epoll_event>],
-        mut timeout: Option<Duration>,
+        timeout: Option<Duration>,
+        sigmask: Option<&sigset_t>,
+    ) -> PosixResult<usize> {
+        if events.is_empty() {
+            return Err(PosixError::INVAL);
+        }
+
+        let start = clock::now();
+        let mut completions = 0;
+        self.accepts = 0;
+        self.phase_times = [Duration::ZERO; 4];
+        let res = self.pwait_impl(events, Deadline::after(timeout), sigmask, &mut completions);
+
+        let evs = *res.as_ref().unwrap_or(&0) as u64;
+        let took = clock::now().saturating_duration_since(start);
+        self.stats.record_pwait(took, completions, evs);
+        self.stats.record_phases(&self.phase_times);
+        self.stats.ready_list_depth = self.ready_list.len() as u64;
+        if completions > 0 {
+            self.note_harvest_cpu();
+        }
+
+        return res;
+    }
+
+    /// remembers the cpu the completions were harvested on, warning the first time it changed, as
+    /// a poller moving between cores loses its warm caches and, with a kernel-bypass libOS, its
+    /// locality to the NIC queue
+    fn note_harvest_cpu(&mut self) {
+        let Some(cpu) = demi::harvest_cpu() else {
+            return;
+        };
+        if let Some(old) = self.harvest_cpu
+            && old != cpu
+            && !self.harvest_moved
+        {
+            self.harvest_moved = true;
+            warn!(
+                "the completions of dpoll {} moved from cpu {old} to {cpu}, pin the thread \
+                 polling demikernel",
+                self.id
+            );
+        }
+        self.harvest_cpu = Some(cpu);
+    }
+
+    /// the cpu the last pwait that got completions harvested them on, see `demi::harvest_cpu`
+    pub fn harvest_cpu(&self) -> Option<c_int> {
+        return self.harvest_cpu;
+    }
+
+    fn pwait_impl(
+        &mut self,
+        events: &mut [MaybeUninit<epoll_event>],
+        deadline: Deadline,
+        sigmask: Option<&sigset_t>,
+        completions: &mut u64,
     ) -> PosixResult<usize> {
-        self.get_and_schedule_events();
+        loop {
+            let wait = match self.timed(Phase::Schedule, Self::get_and_schedule_events) {
+                Some(delay) => deadline.cap(delay),
+                None => deadline,
+            };
 
+            match self.pwait_once(events, wait, sigmask, completions) {
+                // the wait was cut short for a socket timer, which might have made it ready, once
+                // the deadline passed meanwhile the next round only polls, so the timer and any
+                // kernel fd that became ready by the deadline are still reported
+                Err(PosixError::TIMEDOUT) if wait != deadline => {}
+                res => return res,
+            }
+        }
+    }
+
+    /// runs `func`, adding the time it took to `phase` of the running pwait
+    fn timed<R>(&mut self, phase: Phase, func: impl FnOnce(&mut Self) -> R) -> R {
+        let start = clock::now();
+        let res = func(self);
+        self.phase_times[phase as usize] += clock::now().saturating_duration_since(start);
+        return res;
+    }
+
+    fn pwait_once(
+        &mut self,
+        events: &mut [MaybeUninit<epoll_event>],
+        mut deadline: Deadline,
+        sigmask: Option<&sigset_t>,
+        completions: &mut u64,
+    ) -> PosixResult<usize> {
+        // stale entries would make the wait return early without any event to report
         if !self.ready_list.is_empty() {
+            let before = self.ready_list.len();
+            self.ready_list
+                .retain(|item| !Self::reportable(item).is_empty());
+            if self.ready_list.len() < before {
+                trace!(
+                    "dropped {} stale ready list entries",
+                    before - self.ready_list.len()
+                );
+                self.update_wakeup();
+            }
+        }
+        if !self.ready_list.is_empty() || self.raw_ops.has_ready() {
             trace!("ready_list is not empty, only going to poll");
-            timeout = Some(Duration::ZERO);
+            deadline = Deadline::now();
         }
 
         trace!("going to wait");
-        match self.wait(timeout) {
-            Ok(()) => {}
-            Err(PosixError::TIMEDOUT) => timeout = Some(Duration::ZERO),
+        match self.timed(Phase::DemiWait, |pol| pol.wait(deadline, sigmask)) {
+            Ok(count) => *completions += count,
+            Err(PosixError::TIMEDOUT) => deadline = Deadline::now(),
             Err(e) => {
                 trace!("self.wait failed with {e:?}");
                 return Err(e);
             }
         }
 
+        let mut evs_len = 0;
+        if self.epoll_starved {
+            trace!("polling the kernel fds first, they were left out last time");
+            let polled = self.timed(Phase::Epoll, |pol| {
+                pol.epoll.wait(events, Deadline::now(), None)
+            });
+            evs_len = polled?;
+        }
+
         trace!("draining list");
-        let mut evs_len = self.drain_ready_list(events);
+        evs_len += self.timed(Phase::Drain, |pol| {
+            return pol.drain_ready_list(&mut events[evs_len..]);
+        });
+
+        if evs_len == events.len() {
+            // epoll_wait would fail with EINVAL for an empty slice, kernel events wait for next time
+            self.epoll_starved = !self.epoll_starved;
+            self.update_wakeup();
+            return Ok(evs_len);
+        }
+        self.epoll_starved = false;
 
         if evs_len > 0 {
-            timeout = Some(Duration::ZERO);
+            deadline = Deadline::now();
         }
 
         trace!(
-            "{epoll:?} going to wait on epoll for {timeout:?}",
-            epoll = self.epoll
+            "{epoll:?} going to wait on epoll for {remaining:?}",
+            epoll = self.epoll,
+            remaining = deadline.remaining()
         );
 
-        evs_len += match self.epoll.wait(&mut events[evs_len..], timeout) {
+        let polled = self.timed(Phase::Epoll, |pol| {
+            pol.epoll.wait(&mut events[evs_len..], deadline, sigmask)
+        });
+        evs_len += match polled {
             Ok(len) => len,
             Err(e) => {
                 trace!("epoll.wait failed with {e:?}");
@@ -200,6 +1077,8 @@ impl Dpoll {
             }
         };
 
+        self.update_wakeup();
+
         if evs_len == 0 {
             trace!("epoll: {self:?} timed out");
             return Err(PosixError::TIMEDOUT);
@@ -208,3 +1087,87 @@ impl Dpoll {
         return Ok(evs_len);
     }
 }
+
+/// unregisters the sockets, whose running operations are left to them, except for sockets whose fd
+/// was closed already, which only the dpoll kept alive, their operations are cancelled and the
+/// completions that arrived dropped
+///
+/// never panics, it might run while unwinding, items borrowed meanwhile are only logged
+impl Drop for Dpoll {
+    fn drop(&mut self) {
+        self.ready_list.clear();
+        // the sockets and their operations belong to the parent
+        if fork::is_child() {
+            return;
+        }
+
+        let (mut released, mut shared, mut busy, mut abandoned) = (0, 0, 0, 0);
+        for it in self.items.iter() {
+            let Some(mut it) = it.try_borrow_mut_quiet() else {
+                busy += 1;
+                continue;
+            };
+            it.on_readylist = false;
+            let Some(mut soc) = it.soc.try_borrow_mut_quiet() else {
+                busy += 1;
+                continue;
+            };
+            soc.unwatch(&self.waker);
+
+            if !it.soc.is_unique() {
+                shared += 1;
+                continue;
+            }
+            soc.cancel_operations();
+            if soc.reap_tombstones() {
+                abandoned += 1;
+            }
+            released += 1;
+        }
+
+        trace!(
+            "dpoll {} dropped: {released} sockets released, {shared} still referenced elsewhere",
+            self.id
+        );
+        if busy > 0 {
+            warn!(
+                "dpoll {} dropped with {busy} items borrowed, left as they were",
+                self.id
+            );
+        }
+        if abandoned > 0 {
+            warn!(
+                "dpoll {} dropped {abandoned} sockets whose cancelled operations did not complete",
+                self.id
+            );
+        }
+        if self.raw_ops.running() > 0 {
+            warn!(
+                "dpoll {} dropped with {} raw operations running, their tokens stay with the app",
+                self.id,
+                self.raw_ops.running()
+            );
+        }
+    }
+}
+
+/// what `Dpoll::schedule_item` found out about an item
+enum Scheduled {
+    Closed,
+    /// ready and not on the ready list yet
+    Ready,
+    Waiting,
+}
+
+impl Scheduled {
+    fn ready(ready: bool) -> Self {
+        return if ready { Self::Ready } else { Self::Waiting };
+    }
+}
+
+fn earliest(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    return match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+}
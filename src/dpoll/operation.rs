@@ -4,16 +4,28 @@ use crate::{
     buffer::{Buffer, Index},
     shared::Shared,
     socket::Socket,
-    wrappers::demi,
+    wrappers::{
+        demi,
+        errno::{PosixError, PosixResult},
+    },
 };
 
-use super::Event;
+use super::{Dpoll, DpollError, DpollResult, Event, IgnoredFlags};
 
 #[allow(private_interfaces)]
 #[derive(Debug)]
 pub enum Operation {
     Epoll(EpollOperation),
     Dpoll(DpollOperation),
+    Nested(NestedOperation),
+}
+
+/// registers the wakeup fd of a dpoll in the kernel epoll of another one
+#[derive(Debug)]
+pub(super) struct NestedOperation {
+    pub op: c_int,
+    pub pol: Shared<Dpoll>,
+    pub event: *mut epoll_event,
 }
 
 #[derive(Debug)]
@@ -24,20 +36,57 @@ pub(super) struct EpollOperation {
 }
 
 impl Operation {
+    pub fn add(soc: Shared<Socket>, evs: Event, data: u64) -> Self {
+        return Self::add_flagged(soc, evs, IgnoredFlags::empty(), data);
+    }
+
+    /// like `add`, also recording the ignored flags the registration was made with
+    pub fn add_flagged(soc: Shared<Socket>, evs: Event, flags: IgnoredFlags, data: u64) -> Self {
+        return Self::Dpoll(DpollOperation::Add {
+            soc,
+            evs,
+            flags,
+            data,
+            cookie: None,
+        });
+    }
+
+    /// makes an add or modify of the dpoll socket `fd` report a cookie instead of its data, fails
+    /// with EINVAL for any other operation
+    pub fn with_cookie(mut self, fd: c_int) -> PosixResult<Self> {
+        match &mut self {
+            Self::Dpoll(
+                DpollOperation::Add { cookie, .. } | DpollOperation::Mod { cookie, .. },
+            ) => *cookie = Some(fd),
+            _ => return Err(PosixError::INVAL),
+        }
+        return Ok(self);
+    }
+
+    pub fn del(qd: demi::DemiQd) -> Self {
+        return Self::Dpoll(DpollOperation::Del { qd });
+    }
+
     pub unsafe fn from_raw(
         socs: &Buffer<true, Shared<Socket>>,
+        polls: &Buffer<false, Shared<Dpoll>>,
         op: c_int,
         fd: c_int,
         event: *mut epoll_event,
-    ) -> Self {
-        let idx: Index = fd.into();
+    ) -> PosixResult<Self> {
+        let idx = Index::try_from(fd)?;
         if !idx.is_dpoll() {
-            return Self::Epoll(EpollOperation { op, fd, event });
+            return Ok(Self::Epoll(EpollOperation { op, fd, event }));
+        }
+
+        if !idx.is_socket() {
+            let pol = polls.get(idx).ok_or(PosixError::BADF)?.clone();
+            return Ok(Self::Nested(NestedOperation { op, pol, event }));
         }
 
         let event = unsafe { event.as_ref() };
-        let soc = socs.get(idx).unwrap().clone();
-        return Self::Dpoll(DpollOperation::new(soc, op, event));
+        let soc = socs.get(idx).ok_or(PosixError::BADF)?.clone();
+        return Ok(Self::Dpoll(DpollOperation::new(soc, op, event)?));
     }
 }
 
@@ -46,7 +95,10 @@ pub(super) enum DpollOperation {
     Add {
         soc: Shared<Socket>,
         evs: Event,
+        flags: IgnoredFlags,
         data: u64,
+        /// the fd of the socket when the registration reports a cookie instead of `data`
+        cookie: Option<c_int>,
     },
     Del {
         qd: demi::DemiQd,
@@ -54,29 +106,44 @@ pub(super) enum DpollOperation {
     Mod {
         qd: demi::DemiQd,
         evs: Event,
+        flags: IgnoredFlags,
+        data: u64,
+        cookie: Option<c_int>,
     },
 }
 
 impl DpollOperation {
-    pub fn new(soc: Shared<Socket>, op: c_int, event: Option<&epoll_event>) -> Self {
-        let evs = event.map(|ev| ev.events.try_into().unwrap());
-        return match op {
-            EPOLL_CTL_ADD => {
-                let event = event.unwrap();
-                Self::Add {
-                    soc,
-                    evs: evs.unwrap(),
-                    data: event.u64,
-                }
-            }
-            EPOLL_CTL_DEL => Self::Del {
-                qd: soc.borrow().soc.qd,
-            },
-            EPOLL_CTL_MOD => Self::Mod {
-                qd: soc.borrow().soc.qd,
-                evs: evs.unwrap(),
-            },
-            _ => panic!("invalid op: {}", op),
+    pub fn new(soc: Shared<Socket>, op: c_int, event: Option<&epoll_event>) -> DpollResult<Self> {
+        let qd = soc.borrow().soc.qd;
+        let event = match op {
+            EPOLL_CTL_DEL => return Ok(Self::Del { qd }),
+            EPOLL_CTL_ADD | EPOLL_CTL_MOD => event.ok_or(DpollError::MissingEvent { qd, op })?,
+            _ => return Err(DpollError::InvalidOp { qd, op }),
         };
+
+        let (evs, flags) = Event::with_flags(event.events)?;
+        if op == EPOLL_CTL_MOD && flags.contains(IgnoredFlags::EXCLUSIVE) {
+            return Err(DpollError::InvalidEvent(event.events));
+        }
+
+        // the whole union, whichever member the application set
+        let data = event.u64;
+        return Ok(if op == EPOLL_CTL_ADD {
+            Self::Add {
+                soc,
+                evs,
+                flags,
+                data,
+                cookie: None,
+            }
+        } else {
+            Self::Mod {
+                qd,
+                evs,
+                flags,
+                data,
+                cookie: None,
+            }
+        });
     }
 }
@@ -0,0 +1,94 @@
+//! demikernel operations submitted by the application itself, whose completions are routed
+//! through the dpoll like the ones of its sockets
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+};
+
+use crate::wrappers::{
+    demi::{QToken, RawQResult},
+    errno::{PosixError, PosixResult},
+};
+
+#[derive(Default)]
+pub struct RawOps {
+    /// the data to report each running operation with
+    pending: HashMap<QToken, u64>,
+    /// completed operations whose event was not reported yet
+    ready: VecDeque<(QToken, u64)>,
+    /// results not taken by the application yet
+    done: HashMap<QToken, RawQResult>,
+}
+
+impl RawOps {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// fails with EEXIST if `qt` is already known
+    pub fn submit(&mut self, qt: QToken, data: u64) -> PosixResult<()> {
+        if self.pending.contains_key(&qt) || self.done.contains_key(&qt) {
+            return Err(PosixError::EXIST);
+        }
+
+        self.pending.insert(qt, data);
+        return Ok(());
+    }
+
+    pub fn qtoks(&self) -> impl Iterator<Item = QToken> + '_ {
+        return self.pending.keys().copied();
+    }
+
+    /// stores `res` if it belongs to one of the operations, returning it back otherwise
+    pub fn complete(&mut self, res: RawQResult) -> Result<(), RawQResult> {
+        let qt = res.qr_qt;
+        let Some(data) = self.pending.remove(&qt) else {
+            return Err(res);
+        };
+
+        self.ready.push_back((qt, data));
+        self.done.insert(qt, res);
+        return Ok(());
+    }
+
+    /// the operations that did not complete yet
+    pub fn running(&self) -> usize {
+        return self.pending.len();
+    }
+
+    pub fn has_ready(&self) -> bool {
+        return !self.ready.is_empty();
+    }
+
+    /// pops up to `max` ready operations, yielding their data
+    pub fn drain(&mut self, max: usize) -> impl Iterator<Item = u64> + '_ {
+        let len = max.min(self.ready.len());
+        return self.ready.drain(..len).map(|(_, data)| data);
+    }
+
+    /// the result of `qt`, WOULDBLOCK if it is still running and NOENT if it is not known
+    pub fn take(&mut self, qt: QToken) -> PosixResult<RawQResult> {
+        if let Some(res) = self.done.remove(&qt) {
+            self.ready.retain(|(t, _)| *t != qt);
+            return Ok(res);
+        }
+
+        return if self.pending.contains_key(&qt) {
+            Err(PosixError::WOULDBLOCK)
+        } else {
+            Err(PosixError::NOENT)
+        };
+    }
+}
+
+impl fmt::Debug for RawOps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("RawOps")
+            .field("pending", &self.pending)
+            .field("ready", &self.ready)
+            .field("done", &self.done.keys().collect::<Vec<_>>())
+            .finish();
+    }
+}
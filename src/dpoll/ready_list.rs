@@ -1,84 +1,233 @@
-use std::collections::LinkedList;
+//! the sockets with events to report, in the order they are reported in
+//!
+//! the policy is FIFO by the time a socket became ready: it enters the back of the list once and
+//! keeps its place until it is reported, however many completions it gets meanwhile. a reported
+//! socket that is still ready enters again behind every socket already waiting, so when the events
+//! do not fit into one pwait the sockets are served round robin. sockets found ready by the same
+//! scan enter least recently reported first, so the scan order cannot favour any of them
+//!
+//! like with the kernel, a socket is on the list at most once and its events are only looked at
+//! when it is reported, so completions making it both readable and writable end up in a single
+//! event carrying both
+//!
+//! all of the above holds within a priority class, see SO_PRIORITY: a socket enters behind the ones
+//! of its class and of the higher ones, but ahead of every socket of a lower class, so a latency
+//! critical connection is never reported after a bulk one that became ready before it
 
-use crate::{shared::Shared, socket::Socket};
+use std::collections::VecDeque;
+
+use crate::shared::Shared;
 
 use super::item::Item;
 
 #[derive(Debug)]
 pub struct ReadyList {
-    list: LinkedList<(Shared<Item>, u64)>,
+    list: VecDeque<Shared<Item>>,
+    /// the number of events reported so far, stamped on the items as `Item::last_reported`
+    reported: u64,
 }
 
 impl ReadyList {
     pub fn new() -> Self {
         return Self {
-            list: LinkedList::new(),
+            list: VecDeque::new(),
+            reported: 0,
         };
     }
 
     pub fn push(&mut self, item: Shared<Item>) {
-        let data = {
+        let priority = {
             let mut item = item.borrow_mut();
             if item.on_readylist {
                 return;
             }
             item.on_readylist = true;
-            item.data
+            item.priority
         };
-        self.list.push_back((item, data));
+
+        // usually everything is of the same class and the item goes to the back
+        let pos = self
+            .list
+            .iter()
+            .rposition(|other| other.borrow().priority >= priority)
+            .map_or(0, |pos| pos + 1);
+        self.list.insert(pos, item);
     }
 
+    /// removes `item` by identity, its qd might have changed since it was pushed
     pub fn remove(&mut self, item: &Shared<Item>) {
-        let needle = {
+        {
             let mut item = item.borrow_mut();
             if !item.on_readylist {
                 return;
             }
             item.on_readylist = false;
-            item.get_qd()
-        };
-        let mut cursor = self.list.cursor_back_mut();
-
-        while let Some(current) = cursor.current() {
-            let current = current.0.borrow().get_qd();
-            if current == needle {
-                cursor.remove_current();
-                break;
-            }
-            cursor.move_prev();
+        }
+        // removed items are usually the recently pushed ones
+        let pos = self.list.iter().rposition(|current| current.ptr_eq(item));
+        if let Some(pos) = pos {
+            self.list.remove(pos);
         }
     }
 
-    pub fn append(&mut self, mut other: Self) {
-        self.list.append(&mut other.list);
+    /// pushes the items of `other` least recently reported first
+    pub fn append(&mut self, other: Self) {
+        let mut items: Vec<_> = other.list.into_iter().collect();
+        items.sort_by_key(|item| item.borrow().last_reported);
+        for item in items {
+            item.borrow_mut().on_readylist = false;
+            self.push(item);
+        }
     }
 
+    /// `func` gets the item and returns whether it reported an event, with the data the item has by
+    /// then rather than the one it had when it entered the list
+    ///
+    /// reports at most `max` events, the items that did not fit stay on the list
     pub fn drain<F>(&mut self, max: usize, mut func: F) -> usize
     where
-        F: FnMut(usize, &Socket, u64),
+        F: FnMut(usize, &Item) -> bool,
     {
-        if self.list.is_empty() {
-            return 0;
-        }
         let mut idx = 0;
 
-        while let Some(curr) = self.list.pop_front()
-            && idx < max
+        while idx < max
+            && let Some(curr) = self.list.pop_front()
         {
-            let mut item = curr.0.borrow_mut();
+            let mut item = curr.borrow_mut();
             item.on_readylist = false;
-            func(idx, &item.soc.borrow(), curr.1);
-            idx += 1;
+            if func(idx, &item) {
+                self.reported += 1;
+                item.last_reported = self.reported;
+                idx += 1;
+            }
         }
 
         return idx;
     }
 
+    /// keeps the items `func` returns true for, in order
+    pub fn retain<F>(&mut self, mut func: F)
+    where
+        F: FnMut(&Item) -> bool,
+    {
+        self.list.retain(|curr| {
+            let mut item = curr.borrow_mut();
+            let keep = func(&item);
+            item.on_readylist = keep;
+            return keep;
+        });
+    }
+
+    /// empties the list, clearing the flag of the items that are not borrowed
+    pub fn clear(&mut self) {
+        for item in self.list.drain(..) {
+            if let Some(mut item) = item.try_borrow_mut_quiet() {
+                item.on_readylist = false;
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        return self.list.len();
+    }
+
     pub fn is_empty(&self) -> bool {
         return self.list.is_empty();
     }
 
-    pub fn into_iter(self) -> std::collections::linked_list::IntoIter<(Shared<Item>, u64)> {
-        return self.list.into_iter();
+    #[allow(dead_code)]
+    pub fn iter(&self) -> impl Iterator<Item = &Shared<Item>> {
+        return self.list.iter();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dpoll::Event, socket::Socket, wrappers::demi};
+
+    fn items(count: usize) -> Vec<Shared<Item>> {
+        return (0..count)
+            .map(|i| {
+                let soc = Socket::new(demi::SocketQd::from(i as i32));
+                return Shared::new(Item::new(Shared::new(soc), Event::IN, i as u64));
+            })
+            .collect();
+    }
+
+    /// drains up to `max` items, reporting every one of them
+    fn drain(list: &mut ReadyList, max: usize) -> Vec<u64> {
+        let mut reported = Vec::new();
+        list.drain(max, |_, item| {
+            reported.push(item.data);
+            return true;
+        });
+        return reported;
+    }
+
+    #[test]
+    fn fifo() {
+        let items = items(3);
+        let mut list = ReadyList::new();
+        for item in &items {
+            list.push(item.clone());
+        }
+        // becoming ready again does not move an item back
+        list.push(items[0].clone());
+        assert_eq!(drain(&mut list, 2), [0, 1]);
+
+        // a reported item still ready goes behind the ones waiting
+        list.push(items[0].clone());
+        assert_eq!(drain(&mut list, 3), [2, 0]);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn round_robin() {
+        let items = items(5);
+        let mut list = ReadyList::new();
+        for item in &items {
+            list.push(item.clone());
+        }
+
+        // everything stays ready, but only two events fit into a drain
+        let mut reported = Vec::new();
+        for _ in 0..5 {
+            let drained = drain(&mut list, 2);
+            for data in &drained {
+                list.push(items[*data as usize].clone());
+            }
+            reported.extend(drained);
+        }
+        assert_eq!(reported, [0, 1, 2, 3, 4, 0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn append_least_recently_reported() {
+        let items = items(3);
+        let mut list = ReadyList::new();
+        list.push(items[1].clone());
+        list.push(items[0].clone());
+        assert_eq!(drain(&mut list, 2), [1, 0]);
+
+        // the scan found them in index order, the one never reported goes first
+        let mut found = ReadyList::new();
+        for item in &items {
+            found.push(item.clone());
+        }
+        list.append(found);
+        assert_eq!(drain(&mut list, 3), [2, 1, 0]);
+    }
+
+    #[test]
+    fn priority_classes() {
+        let items = items(4);
+        items[2].borrow_mut().priority = 1;
+        let mut list = ReadyList::new();
+        for item in &items {
+            list.push(item.clone());
+        }
+        // the higher class goes ahead, FIFO holds within each
+        assert_eq!(drain(&mut list, 4), [2, 0, 1, 3]);
     }
 }
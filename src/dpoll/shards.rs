@@ -0,0 +1,64 @@
+//! splits the tokens a dpoll waits on into shards, so polling for more completions after the
+//! first one scans a shard of the tokens instead of all of them
+//!
+//! demikernel looks at every token given to a wait, a dpoll with tens of thousands of sockets
+//! pays for all of them on each completion it polls for, see `examples/wait_shards.rs`
+
+use crate::wrappers::errno::{PosixError, PosixResult};
+
+/// shards are never shorter, an empty poll waits once per shard and on short ones those waits
+/// cost more than the scan they save
+pub const MIN_SHARD_LEN: usize = 1024;
+
+/// the shards new dpolls split their tokens into at most, in `examples/wait_shards.rs` 8 shards
+/// drain the completions of 64k tokens 5 to 10 times faster than 1, more keep helping there but
+/// make an empty poll wait once per shard, which the example leaves out
+pub const DEFAULT_WAIT_SHARDS: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Shards {
+    /// the most shards the tokens are split into
+    max: usize,
+    /// the shard the next poll starts with
+    next: usize,
+}
+
+impl Shards {
+    /// `max` has to be positive, 1 waits on all the tokens at once
+    pub fn new(max: usize) -> Self {
+        assert!(max > 0);
+        return Self { max, next: 0 };
+    }
+
+    /// the number of shards `len` tokens are split into
+    pub fn count(&self, len: usize) -> usize {
+        return (len / MIN_SHARD_LEN).clamp(1, self.max);
+    }
+
+    /// polls the shards of `toks` with `poll` until one of them has a completion, starting with
+    /// the one after the shard of the previous completion so a busy shard does not starve the rest
+    ///
+    /// returns the index into `toks` of the completed token, TIMEDOUT if no shard had one
+    pub fn poll<T, R, F>(&mut self, toks: &[T], mut poll: F) -> PosixResult<(usize, R)>
+    where
+        F: FnMut(&[T]) -> PosixResult<(usize, R)>,
+    {
+        let count = self.count(toks.len());
+        let len = toks.len().div_ceil(count);
+        for i in 0..count {
+            let shard = (self.next + i) % count;
+            let start = shard * len;
+            let end = toks.len().min(start + len);
+            match poll(&toks[start..end]) {
+                Ok((idx, res)) => {
+                    self.next = (shard + 1) % count;
+                    return Ok((start + idx, res));
+                }
+                Err(PosixError::TIMEDOUT) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        return Err(PosixError::TIMEDOUT);
+    }
+}
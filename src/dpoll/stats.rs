@@ -0,0 +1,272 @@
+use std::time::Duration;
+
+/// upper bounds of the pwait latency buckets, in seconds
+pub const LATENCY_BOUNDS: &[f64] = &[1e-6, 1e-5, 1e-4, 1e-3, 1e-2, 1e-1, 1.0];
+/// upper bounds of the completions per wait buckets
+pub const COMPLETION_BOUNDS: &[f64] = &[0.0, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0];
+
+/// a fixed bucket histogram, counts are not cumulative
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// one more than bounds, the last one is +Inf
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        return Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        };
+    }
+
+    pub fn observe(&mut self, val: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|b| val <= *b)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += val;
+        self.count += 1;
+    }
+
+    /// yields (upper bound, count) for every bucket, the last bound is `f64::INFINITY`
+    #[allow(dead_code)]
+    pub fn buckets(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        return self
+            .bounds
+            .iter()
+            .copied()
+            .chain(std::iter::once(f64::INFINITY))
+            .zip(self.counts.iter().copied());
+    }
+
+    #[allow(dead_code)]
+    pub fn sum(&self) -> f64 {
+        return self.sum;
+    }
+
+    #[allow(dead_code)]
+    pub fn count(&self) -> u64 {
+        return self.count;
+    }
+}
+
+/// the phases of a pwait, timed separately in `Stats::phases`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// scanning the items for the operations to wait on and the ready ones
+    Schedule = 0,
+    /// waiting for and processing demikernel completions
+    DemiWait = 1,
+    /// draining the ready list into the events
+    Drain = 2,
+    /// waiting on the kernel epoll
+    Epoll = 3,
+}
+
+impl Phase {
+    pub const ALL: [Self; 4] = [Self::Schedule, Self::DemiWait, Self::Drain, Self::Epoll];
+
+    pub fn from_index(idx: usize) -> Option<Self> {
+        return Self::ALL.get(idx).copied();
+    }
+
+    /// the name of the phase, for the metrics
+    #[allow(dead_code)]
+    pub fn name(self) -> &'static str {
+        return match self {
+            Self::Schedule => "schedule",
+            Self::DemiWait => "demi_wait",
+            Self::Drain => "drain",
+            Self::Epoll => "epoll",
+        };
+    }
+}
+
+/// bits of the sub-buckets of every power of two, the buckets are at most 1/8 of their value wide
+const SUB_BITS: u32 = 3;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+/// the largest power of two recorded on its own, anything longer than about 18 minutes goes into
+/// the last bucket
+const MAX_EXP: u32 = 39;
+const LOG_BUCKETS: usize = SUB_BUCKETS + (MAX_EXP - SUB_BITS + 1) as usize * SUB_BUCKETS;
+
+/// a histogram of durations in nanoseconds with log-linear buckets like HDR histograms, so
+/// percentiles are exact to within 12.5% at any scale and recording only indexes an array
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    counts: Box<[u64; LOG_BUCKETS]>,
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        return Self {
+            counts: Box::new([0; LOG_BUCKETS]),
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        };
+    }
+
+    fn bucket(ns: u64) -> usize {
+        if ns < SUB_BUCKETS as u64 {
+            return ns as usize;
+        }
+        let exp = (63 - ns.leading_zeros()).min(MAX_EXP);
+        let sub = (ns >> (exp - SUB_BITS)).min(2 * SUB_BUCKETS as u64 - 1) as usize - SUB_BUCKETS;
+        return SUB_BUCKETS + (exp - SUB_BITS) as usize * SUB_BUCKETS + sub;
+    }
+
+    /// the largest value that falls into `bucket`
+    fn highest(bucket: usize) -> u64 {
+        if bucket < SUB_BUCKETS {
+            return bucket as u64;
+        }
+        let exp = ((bucket - SUB_BUCKETS) / SUB_BUCKETS) as u32 + SUB_BITS;
+        let sub = ((bucket - SUB_BUCKETS) % SUB_BUCKETS + SUB_BUCKETS) as u64;
+        return ((sub + 1) << (exp - SUB_BITS)) - 1;
+    }
+
+    pub fn record(&mut self, took: Duration) {
+        let ns = took.as_nanos().try_into().unwrap_or(u64::MAX);
+        self.counts[Self::bucket(ns)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(ns);
+        self.min = self.min.min(ns);
+        self.max = self.max.max(ns);
+    }
+
+    pub fn count(&self) -> u64 {
+        return self.count;
+    }
+
+    pub fn min(&self) -> u64 {
+        return if self.count == 0 { 0 } else { self.min };
+    }
+
+    pub fn max(&self) -> u64 {
+        return self.max;
+    }
+
+    pub fn mean(&self) -> u64 {
+        return self.sum.checked_div(self.count).unwrap_or(0);
+    }
+
+    #[allow(dead_code)]
+    pub fn sum(&self) -> u64 {
+        return self.sum;
+    }
+
+    /// the value `quantile` of the recorded values are at most, rounded up to the end of its
+    /// bucket but never past the largest one recorded, 0 if nothing was recorded
+    pub fn percentile(&self, quantile: f64) -> u64 {
+        let rank = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            // the last bucket also holds everything longer
+            if seen >= rank && bucket < LOG_BUCKETS - 1 {
+                return Self::highest(bucket).min(self.max);
+            }
+        }
+        return self.max;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub pwait_calls: u64,
+    /// demikernel completions processed over all waits
+    pub completions: u64,
+    /// events returned to the application, including kernel ones
+    pub events: u64,
+    /// the ready list length at the end of the last pwait
+    pub ready_list_depth: u64,
+    pub pwait_latency: Histogram,
+    pub completions_per_wait: Histogram,
+    /// demikernel waits that returned at least one completion
+    pub batches: u64,
+    /// the most completions processed by a single demikernel wait
+    pub max_batch: u64,
+    /// completions processed per demikernel wait, a pwait can wait more than once
+    pub batch_sizes: Histogram,
+    /// listeners not waited on for the rest of a pwait as it completed its maximum of accepts
+    pub deferred_accepts: u64,
+    /// the most tokens a single pwait scheduled, the high-water mark of the token buffer
+    pub max_qtoks: u64,
+    /// pwaits that had to grow the token buffer
+    pub qtoks_grows: u64,
+    /// the most sockets registered at once
+    pub max_items: u64,
+    /// adds refused with ENOSPC as the dpoll had its maximum of sockets registered
+    pub refused_adds: u64,
+    /// the time every pwait spent in each phase, indexed by `Phase`
+    pub phases: [LatencyHistogram; 4],
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        return Self {
+            pwait_calls: 0,
+            completions: 0,
+            events: 0,
+            ready_list_depth: 0,
+            pwait_latency: Histogram::new(LATENCY_BOUNDS),
+            completions_per_wait: Histogram::new(COMPLETION_BOUNDS),
+            batches: 0,
+            max_batch: 0,
+            batch_sizes: Histogram::new(COMPLETION_BOUNDS),
+            deferred_accepts: 0,
+            max_qtoks: 0,
+            qtoks_grows: 0,
+            max_items: 0,
+            refused_adds: 0,
+            phases: Phase::ALL.map(|_| LatencyHistogram::new()),
+        };
+    }
+
+    /// records the time a pwait spent in each phase, indexed by `Phase`
+    pub fn record_phases(&mut self, took: &[Duration; 4]) {
+        for (hist, took) in self.phases.iter_mut().zip(took) {
+            hist.record(*took);
+        }
+    }
+
+    pub fn phase(&self, phase: Phase) -> &LatencyHistogram {
+        return &self.phases[phase as usize];
+    }
+
+    pub fn record_pwait(&mut self, took: Duration, completions: u64, events: u64) {
+        self.pwait_calls += 1;
+        self.completions += completions;
+        self.events += events;
+        self.pwait_latency.observe(took.as_secs_f64());
+        self.completions_per_wait.observe(completions as f64);
+    }
+
+    pub fn record_batch(&mut self, size: usize) {
+        self.batches += 1;
+        self.max_batch = self.max_batch.max(size as u64);
+        self.batch_sizes.observe(size as f64);
+    }
+
+    /// a pwait scheduled `len` tokens, `grew` if the buffer had to be reallocated for them
+    pub fn record_qtoks(&mut self, len: usize, grew: bool) {
+        self.max_qtoks = self.max_qtoks.max(len as u64);
+        if grew {
+            self.qtoks_grows += 1;
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use libc::{EFD_CLOEXEC, EFD_NONBLOCK, c_int};
+use log::trace;
+
+use crate::{
+    shared::Shared,
+    wrappers::errno::{PosixError, PosixResult},
+};
+
+/// the wake channel of a dpoll
+///
+/// every socket registered in the dpoll holds a clone and pings it when its state changes, so
+/// anything blocked on the wakeup fd of the dpoll notices events it did not process itself
+#[derive(Debug, Clone)]
+pub struct Waker {
+    inner: Shared<Signal>,
+}
+
+#[derive(Debug)]
+struct Signal {
+    /// only created once someone can block on it, until then pings are dropped
+    eventfd: Option<c_int>,
+    signaled: bool,
+}
+
+impl Drop for Signal {
+    fn drop(&mut self) {
+        if let Some(fd) = self.eventfd {
+            trace!("dropping wakeup eventfd {fd}");
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+impl Waker {
+    pub fn new() -> Self {
+        return Self {
+            inner: Shared::new(Signal {
+                eventfd: None,
+                signaled: false,
+            }),
+        };
+    }
+
+    /// whether both are the channel of the same dpoll
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        return self.inner.ptr_eq(&other.inner);
+    }
+
+    /// the eventfd that is readable while the channel is signaled, created on first use
+    pub fn eventfd(&self) -> PosixResult<c_int> {
+        let mut signal = self.inner.borrow_mut();
+        if let Some(fd) = signal.eventfd {
+            return Ok(fd);
+        }
+
+        let fd = unsafe { libc::eventfd(0, EFD_NONBLOCK | EFD_CLOEXEC) };
+        if fd.is_negative() {
+            return PosixError::from_errno().map(|_| unreachable!());
+        }
+        signal.eventfd = Some(fd);
+
+        return Ok(fd);
+    }
+
+    /// signals a state change of one of the sockets of the dpoll
+    #[inline]
+    pub fn ping(&self) {
+        self.set(true);
+    }
+
+    pub fn set(&self, ready: bool) {
+        let mut signal = self.inner.borrow_mut();
+        let fd = match signal.eventfd {
+            Some(fd) if signal.signaled != ready => fd,
+            _ => return,
+        };
+
+        let res = if ready {
+            let val: u64 = 1;
+            unsafe { libc::write(fd, &val as *const u64 as *const libc::c_void, 8) }
+        } else {
+            let mut val: u64 = 0;
+            unsafe { libc::read(fd, &mut val as *mut u64 as *mut libc::c_void, 8) }
+        };
+        assert_eq!(
+            res,
+            8,
+            "eventfd {fd} failed: {:?}",
+            PosixError::from_errno()
+        );
+
+        signal.signaled = ready;
+    }
+}
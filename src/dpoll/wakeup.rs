@@ -0,0 +1,48 @@
+use libc::{EPOLL_CLOEXEC, EPOLL_CTL_ADD, EPOLLIN, c_int, epoll_event};
+use log::trace;
+
+use crate::{
+    dpoll::{epoll::Epoll, operation::EpollOperation, waker::Waker},
+    wrappers::{demi, errno::PosixResult},
+};
+
+/// a kernel fd that is readable whenever the dpoll has ready events, so the dpoll can be
+/// registered in another dpoll
+///
+/// it is an epoll containing the kernel epoll of the dpoll and the eventfd of its `Waker`, which
+/// is signaled while the ready list is not empty or after one of its sockets changed state, and
+/// with the reactor feature also the eventfd the reactor signals when completions arrive, see
+/// `demi::completion_fd`
+#[derive(Debug)]
+pub struct Wakeup {
+    epoll: Epoll,
+}
+
+impl Wakeup {
+    pub fn new(kernel: &Epoll, waker: &Waker) -> PosixResult<Self> {
+        let eventfd = waker.eventfd()?;
+        let mut wakeup = Self {
+            epoll: Epoll::create(EPOLL_CLOEXEC)?,
+        };
+
+        let completions = demi::completion_fd()?;
+        for fd in [eventfd, kernel.fd()].into_iter().chain(completions) {
+            let mut event = epoll_event {
+                events: EPOLLIN as u32,
+                u64: fd as u64,
+            };
+            wakeup.epoll.ctl(EpollOperation {
+                op: EPOLL_CTL_ADD,
+                fd,
+                event: &mut event,
+            })?;
+        }
+
+        trace!("new wakeup {} with eventfd {eventfd}", wakeup.fd());
+        return Ok(wakeup);
+    }
+
+    pub fn fd(&self) -> c_int {
+        return self.epoll.fd();
+    }
+}
@@ -0,0 +1,60 @@
+//! demikernel state (and the DPDK rings behind it) cannot be shared with or re-initialized in a
+//! forked child, so none of the dpoll sockets and dpoll instances survive a fork
+//!
+//! in the child every dpoll fd fails with EBADF, except for close which only releases the
+//! bookkeeping without touching demikernel, and no new dpoll fds can be created.
+//! kernel fds passed through the shim are not affected
+//!
+//! an exec does not keep any demikernel state either, whether the fds were created with
+//! SOCK_CLOEXEC or not, so `prepare_exec` closes all of it up front and leaves the process like a
+//! forked child, in case the exec fails
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::trace;
+
+use crate::{
+    buffer::Index,
+    wrappers::{
+        demi,
+        errno::{PosixError, PosixResult},
+    },
+};
+
+static IS_CHILD: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn child_handler() {
+    IS_CHILD.store(true, Ordering::Relaxed);
+}
+
+/// has to be called once, before any fork
+pub fn register_handlers() -> PosixResult<()> {
+    trace!("registering fork handlers");
+    let res = unsafe { libc::pthread_atfork(None, None, Some(child_handler)) };
+    return PosixError::from_error_code(res);
+}
+
+/// for processes forking without going through the atfork handlers, e.g. with a raw clone
+pub fn mark_child() {
+    child_handler();
+}
+
+/// closes every demikernel queue of the process, of all threads, returning how many there were,
+/// from then on the dpoll fds behave like in a forked child
+pub fn prepare_exec() -> usize {
+    let closed = demi::close_all();
+    trace!("closed {closed} queues before exec");
+    child_handler();
+    return closed;
+}
+
+#[inline]
+pub fn is_child() -> bool {
+    return IS_CHILD.load(Ordering::Relaxed);
+}
+
+/// whether `idx` is a dpoll fd inherited from the parent
+#[inline]
+pub fn is_inherited(idx: Index) -> bool {
+    return idx.is_dpoll() && is_child();
+}
@@ -0,0 +1,1509 @@
+//! entry points for the cargo-fuzz targets in fuzz/, enabled with the fuzzing feature
+//!
+//! they drive the pure bookkeeping of the crate, nothing here calls into demikernel
+//!
+//! the microbenchmarks of the internals in examples/ and benches/ also go through here
+
+use std::{
+    collections::{HashMap, VecDeque},
+    mem,
+    mem::MaybeUninit,
+    ptr,
+    time::Duration,
+};
+
+use libc::{
+    EFD_NONBLOCK, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD, EPOLLEXCLUSIVE, EPOLLIN,
+    O_DIRECTORY, O_RDONLY, SHUT_RD, SHUT_RDWR, SHUT_WR, SO_RCVBUF, SO_SNDBUF, SOL_SOCKET, c_int,
+    c_void, epoll_event, sockaddr_in, socklen_t,
+};
+
+use crate::{
+    bindings::{self, DPOLL_SO_AUTOPOP, SOL_DPOLL, utils::SockaddrOut},
+    buffer::{Buffer, Index},
+    dpoll::Event,
+    dpoll::stats::LatencyHistogram,
+    recv_queue::DEFAULT_RCVBUF,
+    send_queue::SendQueue,
+    socket::{MIN_RCVBUF, MIN_SNDBUF, Phase, Socket, Transition},
+    wrappers::{
+        demi::{self, Opcode, QResultValue, SgArray},
+        errno::PosixError,
+        faults::{self, Faults},
+        platform,
+    },
+};
+
+/// drives a buffer with allocate, free, take and get decoded from `data` against a model,
+/// getting stale indices has to fail and live ones have to return their item
+///
+/// every step also forges a socket fd from the byte, which may point past the end of the buffer,
+/// and checks it is rejected unless it happens to be live
+pub fn buffer(data: &[u8]) {
+    let mut buf: Buffer<true, u32> = Buffer::new();
+    let mut live: HashMap<u32, u32> = HashMap::new();
+    let mut seen: Vec<Index> = Vec::new();
+
+    for (step, byte) in data.iter().enumerate() {
+        let step = step as u32;
+        let known = (!seen.is_empty()).then(|| seen[(byte >> 2) as usize % seen.len()]);
+
+        // the dpoll and socket bits set, the byte spread over the index bits
+        let forged = Index::from_bits(0x6000_0000 | (*byte as u32) << 13);
+        if !live.contains_key(&forged.into_bits()) {
+            assert_eq!(buf.get(forged), None);
+            assert_eq!(buf.take(forged), None);
+            assert_eq!(buf.free(forged), None);
+        }
+
+        match (byte & 0b11, known) {
+            (0, _) | (_, None) => {
+                let idx = buf.allocate(step);
+                live.insert(idx.into_bits(), step);
+                seen.push(idx);
+            }
+            (1, Some(idx)) if live.contains_key(&idx.into_bits()) => {
+                assert_eq!(buf.free(idx), Some(()));
+                live.remove(&idx.into_bits());
+            }
+            (2, Some(idx)) if live.contains_key(&idx.into_bits()) => {
+                assert_eq!(buf.take(idx), live.remove(&idx.into_bits()));
+            }
+            (_, Some(idx)) => assert_eq!(buf.get(idx), live.get(&idx.into_bits())),
+        }
+    }
+
+    for (idx, it) in buf.iter() {
+        assert_eq!(live.get(&idx.into_bits()), Some(it));
+    }
+    assert_eq!(buf.iter().count(), live.len());
+}
+
+pub fn ready_list(data: &[u8]) {
+    crate::dpoll::fuzzing::ready_list(data);
+}
+
+pub fn ctl_data(data: &[u8]) {
+    crate::dpoll::fuzzing::ctl_data(data);
+}
+
+pub fn coalesce(data: &[u8]) {
+    crate::dpoll::fuzzing::coalesce(data);
+}
+
+pub fn stale_ready(data: &[u8]) {
+    crate::dpoll::fuzzing::stale_ready(data);
+}
+
+pub fn items(data: &[u8]) {
+    crate::dpoll::fuzzing::items(data);
+}
+
+/// see `examples/items_lookup.rs`
+pub fn items_lookup(items: usize, lookups: usize) -> Duration {
+    return crate::dpoll::fuzzing::items_lookup(items, lookups);
+}
+
+/// see `benches/ready_list_ops.rs`
+pub fn ready_list_ops(items: usize, rounds: usize) -> Duration {
+    return crate::dpoll::fuzzing::ready_list_ops(items, rounds);
+}
+
+pub fn shards(data: &[u8]) {
+    crate::dpoll::fuzzing::shards(data);
+}
+
+pub fn max_items(data: &[u8]) {
+    crate::dpoll::fuzzing::max_items(data);
+}
+
+pub fn registered(data: &[u8]) {
+    crate::dpoll::fuzzing::registered(data);
+}
+
+/// see `examples/wait_shards.rs`
+pub fn wait_shards(
+    items: usize,
+    ready: usize,
+    batch: usize,
+    shards: usize,
+    rounds: usize,
+) -> Duration {
+    return crate::dpoll::fuzzing::wait_shards(items, ready, batch, shards, rounds);
+}
+
+/// writes an address decoded from `data` through `SockaddrOut` into buffers of every length
+/// between 0 and twice the size of `sockaddr_in`
+///
+/// checks that exactly the bytes that fit are written, nothing past the buffer is touched and
+/// the full length is always reported back
+pub fn sockaddr(data: &[u8]) {
+    const FULL: usize = mem::size_of::<sockaddr_in>();
+    let mut bytes = [0u8; FULL];
+    bytes.iter_mut().zip(data).for_each(|(b, d)| *b = *d);
+    let addr: sockaddr_in = unsafe { mem::transmute(bytes) };
+
+    let mut len: socklen_t = 0;
+    assert!(matches!(
+        SockaddrOut::new(ptr::null_mut(), &mut len),
+        Ok(None)
+    ));
+
+    for cap in 0..=2 * FULL {
+        let mut out = [0xa5u8; 2 * FULL];
+        let mut len = cap as socklen_t;
+        let addr_out = SockaddrOut::new(out.as_mut_ptr().cast(), &mut len)
+            .unwrap()
+            .unwrap();
+        addr_out.write(&addr);
+
+        let fits = cap.min(FULL);
+        assert_eq!(len as usize, FULL);
+        assert_eq!(out[..fits], bytes[..fits]);
+        assert!(out[fits..].iter().all(|b| *b == 0xa5));
+    }
+}
+
+/// splits the bytes of `data` into up to 20 segments, some of them empty, and consumes them by
+/// copying into slices and iovecs and by advancing, with sizes decoded from `data`
+///
+/// checks after every step that the consumed bytes came out in order and that `len`,
+/// `remaining`, `is_empty` and the segments agree with what is left
+pub fn sga_iter(data: &[u8]) {
+    let Some((&count, data)) = data.split_first() else {
+        return;
+    };
+    let count = count as usize % 21;
+    let mut segs: Vec<Vec<u8>> = (0..count)
+        .map(|i| {
+            let len = data.get(i).map_or(0, |b| *b as usize % 8);
+            (0..len).map(|off| (i * 8 + off) as u8).collect()
+        })
+        .collect();
+    let model: Vec<u8> = segs.concat();
+    let mut iter = SgArray::from_segments(&mut segs).into_iter();
+    let mut pos = 0;
+
+    for byte in data.iter().skip(count) {
+        let size = (byte >> 2) as usize % 12;
+        let before = pos;
+        match byte & 0b11 {
+            0 => {
+                let mut dst = vec![MaybeUninit::new(0u8); size];
+                let copied = iter.copy_bytes(&mut dst);
+                assert_eq!(
+                    copied,
+                    (pos < model.len()).then(|| size.min(model.len() - pos))
+                );
+                let copied = copied.unwrap_or(0);
+                let dst: Vec<u8> = dst[..copied]
+                    .iter()
+                    .map(|b| unsafe { b.assume_init() })
+                    .collect();
+                assert_eq!(dst, model[pos..pos + copied]);
+                pos += copied;
+            }
+            1 => {
+                let mut bufs = [vec![0u8; size / 2], vec![0u8; size - size / 2]];
+                let mut vecs = bufs.each_mut().map(|buf| libc::iovec {
+                    iov_base: buf.as_mut_ptr().cast(),
+                    iov_len: buf.len(),
+                });
+                let copied = iter.copy_into_iovecs(&mut vecs).unwrap_or(0);
+                assert_eq!(copied, size.min(model.len() - pos));
+                assert_eq!(bufs.concat()[..copied], model[pos..pos + copied]);
+                pos += copied;
+            }
+            _ => {
+                let advanced = iter.advance(size);
+                assert_eq!(advanced, size.min(model.len() - pos));
+                pos += advanced;
+            }
+        }
+        assert!(pos >= before);
+
+        assert_eq!(iter.len(), model.len());
+        assert_eq!(iter.remaining(), model.len() - pos);
+        assert_eq!(iter.is_empty(), pos == model.len());
+        let left: Vec<u8> = iter.segments().flatten().copied().collect();
+        assert_eq!(left, model[pos..]);
+        assert!((&iter).into_iter().all(|seg| !seg.is_empty()));
+    }
+}
+
+/// moves a cursor through thousands of iovecs of lengths decoded from `data`, some of them empty,
+/// seeking, advancing and copying in and out of them in steps decoded from `data` against a flat
+/// model of their bytes
+///
+/// then readvs the data of several pops of a connected socket into such iovecs, which has to take
+/// the data of as many pops as fit, in order
+pub fn iovecs(data: &[u8]) {
+    let Some((&count, data)) = data.split_first() else {
+        return;
+    };
+    let count = count as usize * 16;
+    let mut bufs: Vec<Vec<u8>> = (0..count)
+        .map(|i| {
+            let byte = data.get(i % data.len().max(1)).map_or(0, |b| *b);
+            return vec![0; byte as usize % 5];
+        })
+        .collect();
+    let mut model: Vec<u8> = (0..bufs.iter().map(Vec::len).sum::<usize>())
+        .map(|i| i as u8)
+        .collect();
+    bufs.iter_mut()
+        .flatten()
+        .zip(&model)
+        .for_each(|(b, m)| *b = *m);
+    let vecs: Vec<libc::iovec> = bufs
+        .iter_mut()
+        .map(|buf| libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: buf.len(),
+        })
+        .collect();
+
+    let mut cursor = demi::IovecCursor::new(&vecs);
+    for (step, byte) in data.iter().enumerate() {
+        let len = *byte as usize * 3;
+        let pos = cursor.position();
+        let end = (pos + len).min(model.len());
+        match byte % 4 {
+            0 => {
+                let to = (step * 97 + len) % (model.len() + 8);
+                cursor.seek(to);
+                assert_eq!(cursor.position(), to.min(model.len()));
+            }
+            1 => assert_eq!(cursor.advance(len), end - pos),
+            2 => {
+                let mut dst = vec![0u8; len];
+                assert_eq!(cursor.read_into(&mut dst), end - pos);
+                assert_eq!(dst[..end - pos], model[pos..end]);
+            }
+            _ => {
+                let src: Vec<u8> = (0..len).map(|i| (i as u8) ^ byte).collect();
+                assert_eq!(cursor.write_from(&src), end - pos);
+                model[pos..end].copy_from_slice(&src[..end - pos]);
+            }
+        }
+        assert_eq!(cursor.is_end(), cursor.position() == model.len());
+    }
+    let flat: Vec<u8> = bufs.concat();
+    assert_eq!(flat, model, "the iovecs do not hold what was written");
+
+    let mut soc = Socket::from(demi::AcceptResult {
+        qd: demi::SocketQd::from(0),
+        addr: unsafe { mem::zeroed() },
+    });
+    soc.set_option(SOL_DPOLL, DPOLL_SO_AUTOPOP, 0).unwrap();
+    let mut segs: Vec<Vec<Vec<u8>>> = Vec::new();
+    let mut pending: VecDeque<u8> = VecDeque::new();
+    for (step, chunk) in data.chunks(3).enumerate() {
+        let tok = step as u64 + 1;
+        assert!(soc.start_pop(tok));
+        let seg: Vec<u8> = chunk.iter().map(|b| b ^ step as u8).collect();
+        pending.extend(&seg);
+        segs.push(vec![seg]);
+        let sga = SgArray::from_segments(segs.last_mut().unwrap());
+        soc.process_event(tok, Ok(QResultValue::Pop(sga)));
+    }
+
+    while !pending.is_empty() {
+        let room = flat.len().min(pending.len() + count % 7);
+        let read = soc.readv(&mut cursor_vecs(&mut bufs, room)).unwrap();
+        assert_eq!(
+            read,
+            room.min(pending.len()),
+            "readv stopped at the end of a pop"
+        );
+        let got: Vec<u8> = bufs.concat()[..read].to_vec();
+        let want: Vec<u8> = pending.drain(..read).collect();
+        assert_eq!(got, want, "the data came out of order");
+        if room == 0 {
+            break;
+        }
+    }
+}
+
+/// iovecs over `bufs` with `room` bytes in total
+fn cursor_vecs(bufs: &mut [Vec<u8>], mut room: usize) -> Vec<libc::iovec> {
+    return bufs
+        .iter_mut()
+        .map(|buf| {
+            let len = buf.len().min(room);
+            room -= len;
+            libc::iovec {
+                iov_base: buf.as_mut_ptr().cast(),
+                iov_len: len,
+            }
+        })
+        .collect();
+}
+
+/// checks every transition of every socket phase, then walks the phases from `Unbound` with
+/// transitions decoded from `data`
+///
+/// a closed socket rejects everything with EBADF, an open one can always be closed, only a
+/// completed connect makes a socket active, and a socket that was bound, listened or connected
+/// never goes back to being unbound, nor does a listener ever connect
+pub fn socket_phases(data: &[u8]) {
+    for from in Phase::ALL {
+        for t in Transition::ALL {
+            let to = from.transition(t);
+            match (from, t) {
+                (Phase::Closing, _) => assert_eq!(to, Err(PosixError::BADF)),
+                (_, Transition::Close) => assert_eq!(to, Ok(Phase::Closing)),
+                _ => assert_ne!(to, Ok(Phase::Closing)),
+            }
+            if to == Ok(Phase::Active) {
+                assert_eq!((from, t), (Phase::Connecting, Transition::Connected));
+            }
+            if t == Transition::Bind && to.is_ok() {
+                assert_eq!(from, Phase::Unbound);
+            }
+        }
+    }
+
+    let mut reached = vec![Phase::Unbound];
+    while let Some(next) = reached.iter().find_map(|phase| {
+        Transition::ALL
+            .iter()
+            .filter_map(|t| phase.transition(*t).ok())
+            .find(|to| !reached.contains(to))
+    }) {
+        reached.push(next);
+    }
+    assert_eq!(reached.len(), Phase::ALL.len(), "unreachable phases");
+
+    let mut phase = Phase::Unbound;
+    let mut listened = false;
+    for byte in data {
+        let t = Transition::ALL[*byte as usize % Transition::ALL.len()];
+        let Ok(to) = phase.transition(t) else {
+            continue;
+        };
+
+        assert!(phase == Phase::Unbound || to != Phase::Unbound);
+        listened |= to == Phase::Passive;
+        if listened {
+            assert!(!matches!(to, Phase::Connecting | Phase::Active));
+        }
+        phase = to;
+    }
+}
+
+/// applies ctl ops decoded from `data` to kernel fds through two dpolls and the same ops to two
+/// kernel epolls, checking every op fails with the errno epoll_ctl fails with and that the dpolls
+/// report the same events as the epolls
+///
+/// the ops include deletes with a NULL event, modifies of fds only added to the other dpoll,
+/// invalid ops, EPOLLEXCLUSIVE and fds epoll rejects, a directory, a negative fd and a closed one
+pub fn ctl_passthrough(data: &[u8]) {
+    const OPS: [c_int; 4] = [EPOLL_CTL_ADD, EPOLL_CTL_MOD, EPOLL_CTL_DEL, 0];
+
+    let pols = [bindings::dpoll_create(0), bindings::dpoll_create(0)];
+    let epolls = [0; 2].map(|_| unsafe { libc::epoll_create1(0) });
+    assert!(pols.iter().chain(&epolls).all(|fd| *fd >= 0));
+
+    // readable ones first, then ones epoll cannot poll or that are not open at all
+    let mut fds: Vec<c_int> = (0..4)
+        .map(|i| unsafe { libc::eventfd(i % 2, EFD_NONBLOCK) })
+        .collect();
+    fds.push(unsafe { libc::open(c"/".as_ptr(), O_RDONLY | O_DIRECTORY) });
+    assert!(fds.iter().all(|fd| *fd >= 0));
+    fds.push(-1);
+    fds.push(c_int::MAX >> 2);
+
+    for (step, byte) in data.iter().enumerate() {
+        let side = (byte >> 7) as usize;
+        let fd = fds[(byte >> 4 & 0b111) as usize % fds.len()];
+        let op = OPS[(byte & 0b11) as usize];
+
+        if byte & 0b1100 == 0b1100 {
+            let mut got = vec![epoll_event { events: 0, u64: 0 }; fds.len()];
+            let mut want = got.clone();
+            let len = got.len() as c_int;
+            let n = bindings::dpoll_pwait(pols[side], got.as_mut_ptr(), len, 0, ptr::null());
+            let m = unsafe { libc::epoll_wait(epolls[side], want.as_mut_ptr(), len, 0) };
+            assert!(n >= 0, "pwait failed: {}", std::io::Error::last_os_error());
+
+            let sorted = |evs: &[epoll_event]| {
+                let mut evs: Vec<(u64, u32)> = evs.iter().map(|ev| (ev.u64, ev.events)).collect();
+                evs.sort();
+                evs
+            };
+            assert_eq!(sorted(&got[..n as usize]), sorted(&want[..m as usize]));
+            continue;
+        }
+
+        let mut events = EPOLLIN as u32;
+        if byte & 0b100 != 0 {
+            events |= EPOLLEXCLUSIVE as u32;
+        }
+        let mut ev = epoll_event {
+            events,
+            u64: step as u64,
+        };
+        let mut want_ev = ev;
+        let (ev, want_ev) = if byte & 0b1000 != 0 && op == EPOLL_CTL_DEL {
+            (ptr::null_mut(), ptr::null_mut())
+        } else {
+            (
+                &mut ev as *mut epoll_event,
+                &mut want_ev as *mut epoll_event,
+            )
+        };
+
+        let got = bindings::dpoll_ctl(pols[side], op, fd, ev);
+        let got = (got, (got < 0).then(platform::errno));
+        let want = unsafe { libc::epoll_ctl(epolls[side], op, fd, want_ev) };
+        let want = (want, (want < 0).then(platform::errno));
+        assert_eq!(got, want, "op {op} on fd {fd}");
+    }
+
+    for pol in pols {
+        bindings::dpoll_close(pol);
+    }
+    for fd in epolls
+        .iter()
+        .chain(&fds)
+        .filter(|fd| **fd >= 0 && **fd != c_int::MAX >> 2)
+    {
+        unsafe { libc::close(*fd) };
+    }
+}
+
+/// closes the fds queued by the test of a pwait from its event callback, at the first event
+extern "C" fn close_queued(ctx: *mut c_void, _: *const epoll_event) {
+    let queued = unsafe { &mut *(ctx as *mut Vec<c_int>) };
+    for fd in queued.drain(..) {
+        assert_eq!(bindings::dpoll_close(fd), 0);
+    }
+}
+
+/// creates, dups, registers and closes readable kernel fds through `dpoll_close`, also from the
+/// event callback of a running pwait, and closes a dpoll nested in the one waited on
+///
+/// a pwait has to report exactly the registered fds and the nested dpoll that are still open, even
+/// if a dup keeps the file of a closed fd open, and closing anything twice has to fail with EBADF
+pub fn close(data: &[u8]) {
+    const MAX_FDS: usize = 32;
+
+    let outer = bindings::dpoll_create(0);
+    let inner = bindings::dpoll_create(0);
+    // keeps the nested dpoll readable
+    let inner_fd = unsafe { libc::eventfd(1, EFD_NONBLOCK) };
+    assert!(outer >= 0 && inner >= 0 && inner_fd >= 0);
+    for (pol, fd) in [(inner, inner_fd), (outer, inner)] {
+        let mut ev = epoll_event {
+            events: EPOLLIN as u32,
+            u64: fd as u64,
+        };
+        assert_eq!(bindings::dpoll_ctl(pol, EPOLL_CTL_ADD, fd, &mut ev), 0);
+    }
+    let mut inner_open = true;
+
+    // the open fds with whether they are registered in `outer`, and the closed ones not reused
+    let mut open: HashMap<c_int, bool> = HashMap::new();
+    let mut closed: Vec<c_int> = Vec::new();
+    let mut queued: Vec<c_int> = Vec::new();
+    let closes_twice = |fd: c_int| {
+        assert_eq!(bindings::dpoll_close(fd), -1, "second close of {fd}");
+        assert_eq!(platform::errno(), PosixError::BADF as c_int);
+    };
+
+    for byte in data {
+        let mut fds: Vec<c_int> = open.keys().copied().collect();
+        fds.sort();
+        let picked = (!fds.is_empty()).then(|| fds[(byte >> 3) as usize % fds.len()]);
+
+        match (byte & 0b111, picked) {
+            (0 | 1, _) if open.len() >= MAX_FDS => {}
+            (0, _) | (1, None) => {
+                let fd = unsafe { libc::eventfd(1, EFD_NONBLOCK) };
+                assert!(fd >= 0);
+                open.insert(fd, false);
+                closed.retain(|c| *c != fd);
+            }
+            (1, Some(fd)) => {
+                let dup = unsafe { libc::dup(fd) };
+                assert!(dup >= 0);
+                open.insert(dup, false);
+                closed.retain(|c| *c != dup);
+            }
+            (2, Some(fd)) if !open[&fd] => {
+                let mut ev = epoll_event {
+                    events: EPOLLIN as u32,
+                    u64: fd as u64,
+                };
+                assert_eq!(bindings::dpoll_ctl(outer, EPOLL_CTL_ADD, fd, &mut ev), 0);
+                open.insert(fd, true);
+            }
+            (3, Some(fd)) => {
+                assert_eq!(bindings::dpoll_close(fd), 0);
+                open.remove(&fd);
+                closed.push(fd);
+            }
+            (4, _) if !closed.is_empty() => closes_twice(closed[*byte as usize % closed.len()]),
+            (5, Some(fd)) => {
+                open.remove(&fd);
+                queued.push(fd);
+            }
+            (6, _) => {
+                let callback = (!queued.is_empty()).then_some(close_queued as _);
+                let ctx = &mut queued as *mut Vec<c_int> as *mut c_void;
+                assert_eq!(bindings::dpoll_set_event_callback(outer, callback, ctx), 0);
+                let doomed = queued.clone();
+
+                let mut evs = vec![epoll_event { events: 0, u64: 0 }; 2 * MAX_FDS];
+                let len = evs.len() as c_int;
+                let n = bindings::dpoll_pwait(outer, evs.as_mut_ptr(), len, 0, ptr::null());
+                assert!(n >= 0, "pwait failed: {}", std::io::Error::last_os_error());
+
+                // the events of the pwait were collected before its callback closed the fds
+                let evs = &evs[..n as usize];
+                let mut got: Vec<c_int> = evs.iter().map(|ev| ev.u64 as c_int).collect();
+                got.retain(|fd| !doomed.contains(fd));
+                got.sort();
+                let registered = open.iter().filter(|(_, reg)| **reg);
+                let mut want: Vec<c_int> = registered.map(|(fd, _)| *fd).collect();
+                want.extend(inner_open.then_some(inner));
+                want.sort();
+                assert_eq!(got, want);
+
+                if n > 0 {
+                    assert!(queued.is_empty());
+                    for fd in doomed {
+                        closes_twice(fd);
+                        closed.push(fd);
+                    }
+                }
+            }
+            (7, _) if inner_open => {
+                assert_eq!(bindings::dpoll_close(inner), 0);
+                closes_twice(inner);
+                inner_open = false;
+            }
+            _ => {}
+        }
+    }
+
+    for fd in open.keys().chain(&queued) {
+        assert_eq!(bindings::dpoll_close(*fd), 0);
+    }
+    if inner_open {
+        bindings::dpoll_close(inner);
+    }
+    bindings::dpoll_close(outer);
+    unsafe { libc::close(inner_fd) };
+}
+
+/// injects the faults set from the first bytes of `data` into pushes and forged completions
+/// decoded from the rest, and converts the completions like a wait does
+///
+/// every nth push has to fail with ENOBUFS and every nth completion with the error injected into
+/// it, a FAILED completion has to fail with its errno and one demikernel should never return, an
+/// INVALID or unknown opcode or a FAILED one without a valid errno, with EIO, nothing may panic
+///
+/// pops and accepts carry an sga or a queue that only demikernel can release, so only whether a
+/// fault is injected into them is checked
+pub fn faults(data: &[u8]) {
+    let Some((every, steps)) = data.split_first_chunk::<3>() else {
+        return;
+    };
+    let every = every.map(|e| (e % 8) as u32);
+    faults::set(Faults {
+        push: every[0],
+        wait: every[1],
+        accept: every[2],
+    });
+    let mut calls = [0u32; 3];
+    let mut hit = |fault: usize| {
+        calls[fault] += 1;
+        return every[fault] != 0 && calls[fault] % every[fault] == 0;
+    };
+
+    for step in steps.chunks(2) {
+        let (op, ret) = (step[0], step.get(1).copied().unwrap_or(0));
+        let opcode = match op % 8 {
+            0 => {
+                let want = hit(0).then_some(PosixError::NOBUFS);
+                assert_eq!(faults::push().err(), want);
+                continue;
+            }
+            1 => Opcode::PUSH as u32,
+            2 => Opcode::CONNECT as u32,
+            3 => Opcode::CLOSE as u32,
+            4 => Opcode::FAILED as u32,
+            5 => Opcode::INVALID as u32,
+            6 if op & 0x80 != 0 => Opcode::ACCEPT as u32,
+            6 => Opcode::POP as u32,
+            _ => 7 + op as u32,
+        };
+
+        let accepted = opcode == Opcode::ACCEPT as u32 && hit(2);
+        let want = if accepted {
+            Some(PosixError::CONNABORTED)
+        } else if opcode != Opcode::FAILED as u32 && hit(1) {
+            Some(PosixError::CONNRESET)
+        } else {
+            None
+        };
+        if opcode == Opcode::ACCEPT as u32 || opcode == Opcode::POP as u32 {
+            assert_eq!(faults::injected(opcode), want);
+            continue;
+        }
+
+        let mut res: demi::RawQResult = unsafe { mem::zeroed() };
+        res.qr_opcode = opcode;
+        // the errnos, 0, a negative one and one past the last
+        res.qr_ret = ret as i64 - 2;
+        faults::completion(&mut res);
+        let got = demi::QResult::from(res).value.map(|_| ());
+
+        let want = match want {
+            Some(err) => Err(err),
+            None if opcode == Opcode::FAILED as u32 => Err(c_int::try_from(ret as i64 - 2)
+                .ok()
+                .and_then(PosixError::from_code)
+                .unwrap_or(PosixError::IO)),
+            None if opcode <= Opcode::CLOSE as u32 && opcode != Opcode::INVALID as u32 => Ok(()),
+            None => Err(PosixError::IO),
+        };
+        assert_eq!(got, want, "opcode {opcode}, ret {}", ret as i64 - 2);
+    }
+
+    faults::set(Faults::default());
+}
+
+/// a push payload of `len` bytes over memory kept in `bufs`, which has to outlive it
+fn payload(bufs: &mut Vec<Vec<Vec<u8>>>, len: usize) -> SgArray {
+    bufs.push(vec![vec![0xa5; len]]);
+    return SgArray::from_segments(bufs.last_mut().unwrap());
+}
+
+/// sets SO_SNDBUF and SO_RCVBUF of a connected socket with pops completing and reads in between
+/// decoded from `data`, checking getsockopt reports them like the kernel does and that popping
+/// stops at SO_RCVBUF and resumes below half of it, also right after it changed
+///
+/// then pushes into a send queue limited by bytes, checking it is full exactly when either its
+/// depth or its bytes ran out
+pub fn socket_buffers(data: &[u8]) {
+    let mut soc = Socket::from(demi::AcceptResult {
+        qd: demi::SocketQd::from(0),
+        addr: unsafe { mem::zeroed() },
+    });
+    soc.set_option(SOL_DPOLL, DPOLL_SO_AUTOPOP, 0).unwrap();
+    let mut bufs: Vec<Vec<Vec<u8>>> = Vec::new();
+
+    let (mut rcvbuf, mut buffered, mut stopped) = (DEFAULT_RCVBUF, 0, false);
+    let mut running: Option<u64> = None;
+    for (step, byte) in data.iter().enumerate() {
+        let tok = step as u64 + 1;
+        let val = (*byte as c_int >> 3) * 397 - 64;
+        match byte % 6 {
+            0 => {
+                soc.set_option(SOL_SOCKET, SO_SNDBUF, val).unwrap();
+                let want = ((val as u32).min(c_int::MAX as u32 / 2) * 2).max(MIN_SNDBUF as u32);
+                assert_eq!(soc.get_option(SOL_SOCKET, SO_SNDBUF), Ok(want as c_int));
+            }
+            1 => {
+                soc.set_option(SOL_SOCKET, SO_RCVBUF, val).unwrap();
+                rcvbuf =
+                    ((val as u32).min(c_int::MAX as u32 / 2) * 2).max(MIN_RCVBUF as u32) as usize;
+                assert_eq!(soc.get_option(SOL_SOCKET, SO_RCVBUF), Ok(rcvbuf as c_int));
+                stopped = buffered >= if stopped { rcvbuf / 2 } else { rcvbuf };
+            }
+            2 => {
+                let started = soc.start_pop(tok);
+                assert_eq!(started, running.is_none() && !stopped, "pop");
+                if started {
+                    running = Some(tok);
+                }
+            }
+            3 | 4 if running.is_some() => {
+                let seg = vec![*byte; *byte as usize * 8 + 1];
+                buffered += seg.len();
+                stopped |= buffered >= rcvbuf;
+                bufs.push(vec![seg]);
+                let sga = SgArray::from_segments(bufs.last_mut().unwrap());
+                soc.process_event(running.take().unwrap(), Ok(QResultValue::Pop(sga)));
+            }
+            _ => {
+                let mut dst = vec![MaybeUninit::new(0u8); *byte as usize * 4];
+                let read = soc.try_read(&mut dst).unwrap_or(0);
+                buffered -= read;
+                stopped &= buffered >= rcvbuf / 2;
+            }
+        }
+        assert_eq!(soc.recv_buffered(), (buffered, stopped));
+    }
+
+    let Some((depth, steps)) = data.split_first() else {
+        return;
+    };
+    let depth = *depth as usize % 8 + 1;
+    let max = steps.first().map_or(1, |b| *b as usize * 4 + 1);
+    let mut pushes = SendQueue::new(depth);
+    pushes.set_max_bytes(max);
+    let mut tombstones = Vec::new();
+    let mut running: VecDeque<(u64, usize)> = VecDeque::new();
+    for (step, byte) in steps.iter().enumerate() {
+        let queued: usize = running.iter().map(|(_, len)| len).sum();
+        assert_eq!(pushes.room(), max.saturating_sub(queued));
+        assert_eq!(pushes.has_capacity(), running.len() < depth && queued < max);
+
+        if byte % 3 != 0 && pushes.has_capacity() {
+            let len = (*byte as usize).min(pushes.room());
+            pushes.push(step as u64 + 1, payload(&mut bufs, len));
+            running.push_back((step as u64 + 1, len));
+        } else if let Some((tok, _)) = running.pop_front() {
+            tombstones.extend(pushes.time_out(tok));
+        }
+    }
+    tombstones.extend(pushes.cancel_all());
+    for tombstone in tombstones {
+        tombstone.bury(Ok(QResultValue::Push));
+    }
+}
+
+/// detaches and attaches a connected socket with pops, reads and writes decoded from `data` in
+/// between, checking a detached socket only hands out its qd once its pop completed, starts no
+/// pops even with DPOLL_SO_AUTOPOP, refuses writes and reports only the bytes received before
+pub fn detach(data: &[u8]) {
+    let mut soc = Socket::from(demi::AcceptResult {
+        qd: demi::SocketQd::from(7),
+        addr: unsafe { mem::zeroed() },
+    });
+    let mut bufs: Vec<Vec<Vec<u8>>> = Vec::new();
+
+    let (mut buffered, mut detached) = (0, false);
+    let mut running: Option<u64> = None;
+    for (step, byte) in data.iter().enumerate() {
+        let tok = step as u64 + 1;
+        match byte % 6 {
+            // the dpoll only pops for an attached socket
+            0 if !detached => {
+                if soc.start_pop(tok) {
+                    running = Some(tok);
+                }
+            }
+            1 if running.is_some() => {
+                let seg = vec![*byte; *byte as usize + 1];
+                buffered += seg.len();
+                bufs.push(vec![seg]);
+                let sga = SgArray::from_segments(bufs.last_mut().unwrap());
+                soc.process_event(running.take().unwrap(), Ok(QResultValue::Pop(sga)));
+            }
+            2 => {
+                let want = if running.is_some() {
+                    Err(PosixError::BUSY)
+                } else {
+                    Ok(7)
+                };
+                assert_eq!(soc.detach(), want);
+                detached = true;
+            }
+            3 => {
+                let want = if detached {
+                    Ok(())
+                } else {
+                    Err(PosixError::INVAL)
+                };
+                assert_eq!(soc.attach(), want);
+                detached = false;
+            }
+            4 => {
+                let mut dst = vec![MaybeUninit::new(0u8); *byte as usize * 2];
+                // a read of a detached socket starts no pop, an attached one would need demikernel
+                let res = if detached && running.is_none() {
+                    soc.read(&mut dst)
+                } else {
+                    soc.try_read(&mut dst)
+                };
+                let read = res.unwrap_or(0);
+                assert_eq!(res.is_ok(), buffered > 0, "read of {buffered}");
+                buffered -= read;
+            }
+            _ => {
+                let want = if detached {
+                    Err(PosixError::BUSY)
+                } else {
+                    Ok(0)
+                };
+                assert_eq!(soc.write(&[]), want);
+            }
+        }
+
+        assert_eq!(soc.is_detached(), detached);
+        assert_eq!(soc.recv_buffered().0, buffered);
+        let mut want = if detached { Event::empty() } else { Event::OUT };
+        if buffered > 0 {
+            want |= Event::IN;
+        }
+        assert_eq!(soc.available_events(Event::IN | Event::OUT), want);
+        if detached {
+            // popping here would call into demikernel
+            let mut qtoks = Vec::new();
+            soc.schedule_events(Event::IN | Event::OUT, &mut qtoks)
+                .unwrap();
+            assert_eq!(qtoks, Vec::from_iter(running));
+        }
+    }
+}
+
+/// reads a connected socket through a `DemiStream` with pops completing and empty reads, writes
+/// and flushes in between, decoded from `data`, checking the bytes come out in order and `Ok(0)`
+/// once the peer shut its side down
+///
+/// the socket gets its fd by being adopted, as only demikernel creates sockets otherwise
+#[cfg(feature = "stream")]
+pub fn stream(data: &[u8]) {
+    use std::io::{IoSliceMut, Read, Write};
+
+    use crate::{handoff, shared::Shared, stream::DemiStream};
+
+    let not_socket = DemiStream::from_fd(0).unwrap_err();
+    assert_eq!(
+        not_socket.raw_os_error(),
+        Some(PosixError::OPNOTSUPP as c_int)
+    );
+    let bad = DemiStream::from_fd(-1).unwrap_err();
+    assert_eq!(bad.raw_os_error(), Some(PosixError::BADF as c_int));
+
+    let mut soc = Socket::from(demi::AcceptResult {
+        qd: adopted_qd(),
+        addr: unsafe { mem::zeroed() },
+    });
+    soc.set_option(SOL_DPOLL, DPOLL_SO_AUTOPOP, 0).unwrap();
+    let fd = bindings::dpoll_adopt(handoff::park(Shared::new(soc)));
+    let mut stream = DemiStream::from_fd(fd).unwrap();
+    let mut bufs: Vec<Vec<Vec<u8>>> = Vec::new();
+    let mut next = 0u8;
+
+    let mut pending: VecDeque<u8> = VecDeque::new();
+    let mut running: Option<u64> = None;
+    let mut eof = false;
+    for (step, byte) in data.iter().enumerate() {
+        let tok = step as u64 + 1;
+        let len = (byte >> 3) as usize % 24;
+        match byte % 4 {
+            0 if running.is_none() => {
+                let started = bindings::with_socket_fd(fd, "fuzz", |soc| Ok(soc.start_pop(tok)));
+                assert_eq!(started, Ok(!eof));
+                running = started.unwrap().then_some(tok);
+            }
+            1 if running.is_some() => {
+                // a pop without data is the peer shutting down
+                let seg: Vec<u8> = (0..len)
+                    .map(|_| {
+                        next = next.wrapping_add(1);
+                        next
+                    })
+                    .collect();
+                pending.extend(&seg);
+                eof = seg.is_empty();
+                bufs.push(vec![seg]);
+                let sga = SgArray::from_segments(bufs.last_mut().unwrap());
+                let res = bindings::with_socket_fd(fd, "fuzz", |soc| {
+                    soc.process_event(running.take().unwrap(), Ok(QResultValue::Pop(sga)));
+                    return Ok(());
+                });
+                assert_eq!(res, Ok(()));
+            }
+            // a read would poll a running pop and start one if it blocks, which needs demikernel
+            2 | 3 if running.is_none() && (!pending.is_empty() || eof) => {
+                let mut dst = vec![0u8; len + 1];
+                let res = if byte % 4 == 2 {
+                    stream.read(&mut dst)
+                } else {
+                    let (a, b) = dst.split_at_mut(len / 2);
+                    stream.read_vectored(&mut [IoSliceMut::new(a), IoSliceMut::new(b)])
+                };
+                let read = res.unwrap();
+                assert!(read <= len + 1, "read {read} of {}", len + 1);
+                assert!(
+                    read > 0 || pending.is_empty(),
+                    "read nothing of {}",
+                    pending.len()
+                );
+                let want: Vec<u8> = pending.drain(..read).collect();
+                assert_eq!(dst[..read], want, "the data came out of order");
+            }
+            _ => {
+                assert_eq!(stream.read(&mut []).unwrap(), 0);
+                assert_eq!(stream.write(&[]).unwrap(), 0);
+                stream.flush().unwrap();
+            }
+        }
+    }
+
+    // closing a socket waits for the pop it cancelled
+    if let Some(tok) = running {
+        let res = bindings::with_socket_fd(fd, "fuzz", |soc| {
+            soc.process_event(tok, Err(PosixError::CONNRESET));
+            return Ok(());
+        });
+        assert_eq!(res, Ok(()));
+    }
+    if data.first().is_some_and(|b| b & 0x80 != 0) {
+        let fd = stream.into_fd();
+        assert_eq!(bindings::dpoll_close(fd), 0);
+    } else {
+        drop(stream);
+    }
+    assert!(
+        DemiStream::from_fd(fd).is_err(),
+        "the stream did not close its socket"
+    );
+}
+
+/// fails the pops of a connected socket with errors decoded from `data`, with empty writes, reads
+/// and SO_ERROR in between
+///
+/// like with the kernel, once an error ended the connection the next write fails with it unless
+/// SO_ERROR or a read took it already, and every write after that with EPIPE, while the errors
+/// that do not end it fail nothing but the call they are reported to
+pub fn write_reset(data: &[u8]) {
+    const ERRS: [PosixError; 5] = [
+        PosixError::CONNRESET,
+        PosixError::PIPE,
+        PosixError::NOBUFS,
+        PosixError::CONNABORTED,
+        PosixError::INTR,
+    ];
+    let mut soc = Socket::from(demi::AcceptResult {
+        qd: demi::SocketQd::from(0),
+        addr: unsafe { mem::zeroed() },
+    });
+    soc.set_option(SOL_DPOLL, DPOLL_SO_AUTOPOP, 0).unwrap();
+
+    // the running pop, the error of the failed one not read yet, the pending error and the one
+    // that ended the connection
+    let mut running: Option<u64> = None;
+    let mut failed: Option<PosixError> = None;
+    let mut pending: Option<PosixError> = None;
+    let mut broken: Option<PosixError> = None;
+    for (step, byte) in data.iter().enumerate() {
+        let tok = step as u64 + 1;
+        match (byte % 5, running) {
+            (0, None) if failed.is_none() => {
+                assert!(soc.start_pop(tok));
+                running = Some(tok);
+            }
+            (1, Some(tok)) => {
+                let err = ERRS[(byte >> 3) as usize % ERRS.len()];
+                soc.process_event(tok, Err(err));
+                running = None;
+                failed = Some(err);
+                pending = Some(err);
+                let ends = !matches!(err, PosixError::NOBUFS | PosixError::INTR);
+                if ends && broken.is_none() {
+                    broken = Some(err);
+                }
+            }
+            (2, _) => {
+                // nothing to push, which only demikernel can do
+                let want = match broken {
+                    Some(_) => Err(pending.take().unwrap_or(PosixError::PIPE)),
+                    None => Ok(0),
+                };
+                assert_eq!(soc.try_write(&[]), want, "write");
+            }
+            (3, _) => assert_eq!(soc.take_error(), pending.take()),
+            (4, _) => {
+                let res = soc.try_read(&mut [MaybeUninit::new(0); 8]);
+                match failed.take() {
+                    Some(err) => {
+                        assert_eq!(res, Err(err));
+                        pending = None;
+                    }
+                    None => assert_eq!(res, Err(PosixError::WOULDBLOCK)),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// starts and completes the accepts of a listener, accepts the connections and shuts it down with
+/// steps decoded from `data`
+///
+/// like with the kernel, a listener is readable exactly while it has connections to accept, and
+/// once shut down for reading it closes the ones it did not hand out, fails accepts with EINVAL and
+/// is never readable again, while the connections it accepted before stay connected. the accept it
+/// was running is cancelled, its completion is dropped when it arrives
+pub fn listener(data: &[u8]) {
+    let mut soc = Socket::listening(demi::SocketQd::from(0), 4);
+    let mut accepted: Vec<Socket> = Vec::new();
+    let mut backlog = 0;
+    let mut stopped = false;
+
+    for (step, byte) in data.iter().enumerate() {
+        match byte & 0b11 {
+            0 if !stopped => {
+                let tok = soc.accept_token().unwrap_or_else(|| {
+                    soc.start_accept(step as demi::QToken);
+                    step as demi::QToken
+                });
+                // otherwise left running
+                if byte & 0b100 == 0 {
+                    let acc = demi::AcceptResult {
+                        qd: demi::SocketQd::from(step as i32 + 1),
+                        addr: unsafe { mem::zeroed() },
+                    };
+                    soc.process_event(tok, Ok(QResultValue::Accept(acc)));
+                    backlog += 1;
+                }
+            }
+            // the accept is not started again, the one running was cancelled
+            0 => assert_eq!(soc.accept_token(), None),
+            1 if stopped => assert_eq!(soc.accept().err(), Some(PosixError::INVAL)),
+            1 if backlog > 0 => {
+                accepted.push(soc.accept().unwrap());
+                backlog -= 1;
+            }
+            2 => {
+                let how = if byte & 0b100 == 0 {
+                    SHUT_RD
+                } else {
+                    SHUT_RDWR
+                };
+                assert_eq!(soc.shutdown(how), Ok(()));
+                stopped = true;
+                backlog = 0;
+            }
+            3 => assert_eq!(soc.shutdown(SHUT_WR), Ok(())),
+            _ => {}
+        }
+
+        if stopped {
+            assert_eq!(soc.running_operations(), 0);
+        }
+        let readable = soc.available_events(Event::IN).contains(Event::IN);
+        assert_eq!(readable, !stopped && backlog > 0, "step {step}");
+        assert_eq!(soc.phase(), Phase::Passive);
+        assert!(accepted.iter().all(|acc| acc.phase() == Phase::Active));
+    }
+}
+
+/// records durations from nanoseconds to minutes decoded from `data` into a latency histogram
+///
+/// every percentile has to be at least the exact one of the recorded values and at most 12.5%
+/// above it, never past the largest one, and the count, minimum, maximum and mean have to be exact
+pub fn latency(data: &[u8]) {
+    let mut hist = LatencyHistogram::new();
+    let mut values: Vec<u64> = data
+        .chunks_exact(2)
+        .map(|pair| (pair[0] as u64 + 1) << (pair[1] % 31))
+        .collect();
+    for ns in &values {
+        hist.record(Duration::from_nanos(*ns));
+    }
+    values.sort();
+
+    assert_eq!(hist.count(), values.len() as u64);
+    assert_eq!(hist.min(), values.first().copied().unwrap_or(0));
+    assert_eq!(hist.max(), values.last().copied().unwrap_or(0));
+    let sum: u64 = values.iter().sum();
+    assert_eq!(
+        hist.mean(),
+        sum.checked_div(values.len() as u64).unwrap_or(0)
+    );
+
+    for quantile in [0.0, 0.1, 0.5, 0.9, 0.99, 0.999, 1.0] {
+        let got = hist.percentile(quantile);
+        let rank = (quantile * values.len() as f64).ceil().max(1.0) as usize - 1;
+        let Some(&exact) = values.get(rank) else {
+            assert_eq!(got, 0);
+            continue;
+        };
+        assert!(got >= exact, "p{quantile} {got} < {exact}");
+        assert!(
+            got <= exact + exact / 8 && got <= hist.max(),
+            "p{quantile} {got} >> {exact}"
+        );
+    }
+}
+
+/// calls the bindings with invalid arguments decoded from `data`, NULL pointers, negative lengths,
+/// address and option lengths that are too short, sockets that are not AF_INET SOCK_STREAM and
+/// fds that are negative or past the end of the buffers, checking every call fails with the errno
+/// of its argument instead of panicking
+///
+/// the socket gets its fd by being adopted, as only demikernel creates sockets otherwise
+pub fn arguments(data: &[u8]) {
+    use libc::{
+        AF_INET, AF_INET6, AF_PACKET, AF_UNIX, SOCK_DGRAM, SOCK_RAW, SOCK_SEQPACKET, SOCK_STREAM,
+    };
+
+    use crate::{handoff, shared::Shared};
+
+    let soc = Socket::from(demi::AcceptResult {
+        qd: adopted_qd(),
+        addr: unsafe { mem::zeroed() },
+    });
+    let fd = bindings::dpoll_adopt(handoff::park(Shared::new(soc)));
+    let pol = bindings::dpoll_create(0);
+    assert!(fd >= 0 && pol >= 0);
+
+    let addr: sockaddr_in = unsafe { mem::zeroed() };
+    let addr_ptr = &addr as *const sockaddr_in as *const libc::sockaddr;
+    for byte in data {
+        let n = (byte >> 4) as usize;
+        let null = byte & 0x08 != 0;
+        // never the length of a sockaddr_in
+        let short = (n % mem::size_of::<sockaddr_in>()) as socklen_t;
+        let (ret, want) = match byte % 10 {
+            0 => {
+                let domain = [AF_INET6, AF_UNIX, AF_PACKET, -1][n % 4];
+                let ret = bindings::dpoll_socket(domain, SOCK_STREAM, 0);
+                (ret, PosixError::AFNOSUPPORT)
+            }
+            1 => {
+                let r#type = [SOCK_DGRAM, SOCK_RAW, SOCK_SEQPACKET, 0][n % 4];
+                let ret = bindings::dpoll_socket(AF_INET, r#type, 0);
+                (ret, PosixError::PROTONOSUPPORT)
+            }
+            2 => {
+                let ret = bindings::dpoll_write(fd, ptr::null(), n);
+                (ret as c_int, PosixError::FAULT)
+            }
+            3 => {
+                let ret = bindings::dpoll_read(fd, ptr::null_mut(), n);
+                (ret as c_int, PosixError::FAULT)
+            }
+            4 | 5 => {
+                let (addr, len, want) = if null {
+                    let len = mem::size_of::<sockaddr_in>() as socklen_t;
+                    (ptr::null(), len, PosixError::FAULT)
+                } else {
+                    (addr_ptr, short, PosixError::INVAL)
+                };
+                let ret = if byte % 10 == 4 {
+                    bindings::dpoll_bind(fd, addr, len)
+                } else {
+                    bindings::dpoll_connect(fd, addr, len)
+                };
+                (ret, want)
+            }
+            6 if null => {
+                let ret = bindings::dpoll_ctl_batch(pol, ptr::null_mut(), n as c_int + 1);
+                (ret, PosixError::FAULT)
+            }
+            6 => {
+                let ret = bindings::dpoll_ctl_batch(pol, ptr::null_mut(), -(n as c_int) - 1);
+                (ret, PosixError::INVAL)
+            }
+            7 => {
+                let mut val: c_int = 0;
+                let mut len = (n % mem::size_of::<c_int>()) as socklen_t;
+                let (val, want) = if null {
+                    (ptr::null_mut(), PosixError::FAULT)
+                } else {
+                    (&mut val as *mut c_int as *mut c_void, PosixError::INVAL)
+                };
+                let ret = bindings::dpoll_getsockopt(fd, SOL_SOCKET, SO_RCVBUF, val, &mut len);
+                (ret, want)
+            }
+            8 => {
+                let ret = bindings::dpoll_writev(fd, ptr::null(), n as c_int + 1);
+                (ret as c_int, PosixError::FAULT)
+            }
+            _ => {
+                // negative, or a socket or dpoll fd whose slot was never handed out
+                let bad = match n % 4 {
+                    0 => -1 - n as c_int,
+                    1 => c_int::MIN + n as c_int,
+                    2 => 0x6000_0000 | (n as c_int + 1) << 10,
+                    _ => 0x4000_0000 | (n as c_int + 1) << 10,
+                };
+                bad_fd(bad, fd, pol);
+                continue;
+            }
+        };
+        let want = want as c_int;
+        assert_eq!((ret, platform::errno()), (-1, want), "byte {byte:#x}");
+    }
+
+    assert_eq!(bindings::dpoll_close(fd), 0);
+    assert_eq!(bindings::dpoll_close(pol), 0);
+}
+
+/// the qd of a socket adopted through the bindings, one the mock knows when it stands in for
+/// demikernel so that closing the socket succeeds
+fn adopted_qd() -> demi::SocketQd {
+    #[cfg(feature = "mock")]
+    return demi::SocketQd::new().unwrap();
+    #[cfg(not(feature = "mock"))]
+    return demi::SocketQd::from(0);
+}
+
+/// calls every binding taking a fd with `bad` for it, `fd` and `pol` standing in for the others
+fn bad_fd(bad: c_int, fd: c_int, pol: c_int) {
+    use bindings::*;
+
+    let mut byte = 0u8;
+    let buf = &mut byte as *mut u8 as *mut c_void;
+    let mut vec = libc::iovec {
+        iov_base: buf,
+        iov_len: 1,
+    };
+    let mut ev = epoll_event {
+        events: EPOLLIN as u32,
+        u64: 0,
+    };
+    let mut addr: sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_family = libc::AF_INET as libc::sa_family_t;
+    let addr_ptr = &addr as *const sockaddr_in as *const libc::sockaddr;
+    let mut addr_len = mem::size_of::<sockaddr_in>() as socklen_t;
+    let mut opt: c_int = 0;
+    let opt_ptr = &mut opt as *mut c_int as *mut c_void;
+    let mut opt_len = mem::size_of::<c_int>() as socklen_t;
+    let mut op = dpoll_ctl_op {
+        op: EPOLL_CTL_ADD,
+        fd: bad,
+        event: ev,
+    };
+    let mut req = dpoll_connect_req {
+        fd: bad,
+        addr,
+        data: 0,
+        err: 0,
+    };
+    let mut regs: [dpoll_registration; 1] = unsafe { mem::zeroed() };
+    let mut stats: dpoll_stats = unsafe { mem::zeroed() };
+    let mut latency: dpoll_phase_latency = unsafe { mem::zeroed() };
+    let mut raw: demi::RawQResult = unsafe { mem::zeroed() };
+    let mut data = 0u64;
+
+    let want = PosixError::BADF as c_int;
+    macro_rules! check {
+        ($call:expr) => {
+            let ret = $call as c_int;
+            let what = stringify!($call);
+            assert_eq!((ret, platform::errno()), (-1, want), "{what} with {bad:#x}");
+        };
+    }
+
+    check!(dpoll_bind(bad, addr_ptr, addr_len));
+    check!(dpoll_listen(bad, 1));
+    check!(dpoll_shutdown(bad, SHUT_RDWR));
+    check!(dpoll_accept(bad, ptr::null_mut(), ptr::null_mut()));
+    check!(dpoll_write(bad, buf, 1));
+    check!(dpoll_flush(bad, 0));
+    check!(dpoll_read(bad, buf, 1));
+    check!(dpoll_try_read(bad, buf, 1));
+    check!(dpoll_try_write(bad, buf, 1));
+    check!(dpoll_writev(bad, &vec, 1));
+    check!(dpoll_readv(bad, &mut vec, 1));
+    check!(dpoll_setsockopt(
+        bad, SOL_SOCKET, SO_RCVBUF, opt_ptr, opt_len
+    ));
+    check!(dpoll_getsockopt(
+        bad,
+        SOL_SOCKET,
+        SO_RCVBUF,
+        opt_ptr,
+        &mut opt_len
+    ));
+    check!(dpoll_getsockname(bad, addr_ptr as *mut _, &mut addr_len));
+    check!(dpoll_connect(bad, addr_ptr, addr_len));
+    check!(dpoll_connect_addrs(bad, &addr_ptr, 1));
+    check!(dpoll_set_accept_autoreg(bad, -1, 0, 0));
+    check!(dpoll_set_rate(bad, 1, 1));
+    check!(dpoll_migrate(bad, pol));
+    check!(dpoll_migrate(fd, bad));
+    check!(dpoll_handoff(bad));
+    check!(dpoll_get_qd(bad));
+    check!(dpoll_detach(bad));
+    check!(dpoll_attach(bad));
+    check!(dpoll_close(bad));
+    check!(dpoll_ctl(bad, EPOLL_CTL_ADD, fd, &mut ev));
+    check!(dpoll_ctl(pol, EPOLL_CTL_ADD, bad, &mut ev));
+    check!(dpoll_get_registration(bad, fd, &mut ev));
+    check!(dpoll_get_registration(pol, bad, &mut ev));
+    check!(dpoll_list(bad, regs.as_mut_ptr(), 1));
+    check!(dpoll_ctl_batch(bad, &mut op, 0));
+    check!(dpoll_ctl_batch(pol, &mut op, 1));
+    check!(dpoll_cookie_data(bad, 0, &mut data));
+    check!(dpoll_pwait(bad, &mut ev, 1, 0, ptr::null()));
+    check!(dpoll_connect_many(bad, &mut req, 1));
+    check!(dpoll_get_stats(bad, &mut stats));
+    check!(dpoll_get_phase_latency(bad, 0, &mut latency));
+    check!(dpoll_get_harvest_cpu(bad));
+    check!(dpoll_set_max_idle(bad, 1));
+    check!(dpoll_set_watchdog(bad, 1, 0));
+    check!(dpoll_set_max_completions(bad, 1));
+    check!(dpoll_get_wakeup_fd(bad));
+    check!(dpoll_set_event_callback(bad, None, ptr::null_mut()));
+    check!(dpoll_set_max_accepts(bad, 1));
+    check!(dpoll_set_max_items(bad, 1));
+    check!(dpoll_submit_raw(bad, 1, 0));
+    check!(dpoll_take_raw(bad, 1, &mut raw));
+}
+
+/// connects sockets on the mock backend and drives them through the bindings with ctl, pwait,
+/// read, write and close decoded from `data`
+///
+/// checks that no call panics, which the bindings report as EFAULT, that the fd of a closed socket
+/// fails with EBADF instead of freeing its slot again once it is reused, and that the ready list
+/// of the dpoll agrees with the flags of its items after every step
+#[cfg(feature = "mock")]
+pub fn sequence(data: &[u8]) {
+    use libc::{EPOLLOUT, SOCK_STREAM};
+    use std::sync::Once;
+
+    use crate::dpoll;
+
+    const SLOTS: usize = 4;
+    static INIT: Once = Once::new();
+    INIT.call_once(|| assert_eq!(bindings::dpoll_init(), 0));
+
+    /// -1 only with an errno other than EFAULT and EBADF, the sockets passed are all open
+    fn checked(ret: isize, what: &str) {
+        let err = platform::errno();
+        let bad = [PosixError::FAULT as c_int, PosixError::BADF as c_int];
+        assert!(ret >= 0 || !bad.contains(&err), "{what} failed with {err}");
+    }
+
+    let mut addr: sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_family = libc::AF_INET as libc::sa_family_t;
+    addr.sin_port = 7100u16.to_be();
+    addr.sin_addr.s_addr = u32::from_be_bytes([127, 0, 0, 1]).to_be();
+    let addr_ptr = &addr as *const sockaddr_in as *const libc::sockaddr;
+    let addr_len = mem::size_of::<sockaddr_in>() as socklen_t;
+
+    let pol = bindings::dpoll_create(0);
+    // the listener is kept out of `pol`, whose events are all of the connected sockets
+    let accepts = bindings::dpoll_create(0);
+    let listener = bindings::dpoll_socket(libc::AF_INET, SOCK_STREAM, 0);
+    assert!(pol >= 0 && accepts >= 0 && listener >= 0);
+    assert_eq!(bindings::dpoll_bind(listener, addr_ptr, addr_len), 0);
+    assert_eq!(bindings::dpoll_listen(listener, SLOTS as c_int), 0);
+    let mut ev = epoll_event {
+        events: EPOLLIN as u32,
+        u64: 0,
+    };
+    assert_eq!(
+        bindings::dpoll_ctl(accepts, EPOLL_CTL_ADD, listener, &mut ev),
+        0
+    );
+
+    // the connected socket and its accepted peer, which is closed on its own to end the stream
+    let mut slots: [Option<(c_int, Option<c_int>)>; SLOTS] = [None; SLOTS];
+    let mut registered = [false; SLOTS];
+    let mut closed: Vec<c_int> = Vec::new();
+    let mut buf = [0u8; 64];
+
+    for byte in data {
+        let slot = (byte >> 3) as usize % SLOTS;
+        let arg = (byte >> 5) as usize;
+        let (kind, open) = (byte & 0b111, slots[slot]);
+        match (kind, open) {
+            (0, None) => {
+                let fd = bindings::dpoll_socket(libc::AF_INET, SOCK_STREAM, 0);
+                assert!(fd >= 0);
+                // the mock queues the connection on the listener right away
+                let ret = bindings::dpoll_connect(fd, addr_ptr, addr_len);
+                let inprogress = PosixError::INPROGRESS as c_int;
+                assert_eq!((ret, platform::errno()), (-1, inprogress));
+                // the accept completes in a pwait of the dpoll of the listener
+                let mut ev = epoll_event { events: 0, u64: 0 };
+                assert_eq!(
+                    bindings::dpoll_pwait(accepts, &mut ev, 1, 1000, ptr::null()),
+                    1
+                );
+                let peer = bindings::dpoll_accept(listener, ptr::null_mut(), ptr::null_mut());
+                assert!(peer >= 0);
+                slots[slot] = Some((fd, Some(peer)));
+            }
+            (1, Some((fd, _))) => {
+                let op = [EPOLL_CTL_ADD, EPOLL_CTL_MOD, EPOLL_CTL_DEL][arg % 3];
+                let events = if arg & 4 != 0 {
+                    EPOLLIN | EPOLLOUT
+                } else {
+                    EPOLLIN
+                };
+                let mut ev = epoll_event {
+                    events: events as u32,
+                    u64: slot as u64,
+                };
+                let ret = bindings::dpoll_ctl(pol, op, fd, &mut ev);
+                let want = match (op, registered[slot]) {
+                    (EPOLL_CTL_ADD, true) => Some(PosixError::EXIST),
+                    (EPOLL_CTL_ADD, false) => None,
+                    (_, false) => Some(PosixError::NOENT),
+                    (_, true) => None,
+                };
+                match want {
+                    Some(e) => assert_eq!((ret, platform::errno()), (-1, e as c_int)),
+                    None => {
+                        assert_eq!(ret, 0);
+                        registered[slot] = op != EPOLL_CTL_DEL;
+                    }
+                }
+            }
+            (2, _) => {
+                let mut evs = [epoll_event { events: 0, u64: 0 }; SLOTS];
+                let n =
+                    bindings::dpoll_pwait(pol, evs.as_mut_ptr(), SLOTS as c_int, 0, ptr::null());
+                checked(n as isize, "pwait");
+                for ev in &evs[..n.max(0) as usize] {
+                    let data = ev.u64 as usize;
+                    assert!(registered[data], "{data} reported without being registered");
+                }
+            }
+            (3, Some((fd, _))) => {
+                let ret = bindings::dpoll_try_write(fd, buf.as_ptr() as *const c_void, arg + 1);
+                checked(ret, "write");
+            }
+            (4, Some((_, Some(peer)))) => {
+                let ret = bindings::dpoll_try_write(peer, buf.as_ptr() as *const c_void, arg + 1);
+                checked(ret, "write of the peer");
+            }
+            (5, Some((fd, _))) => {
+                let ret = bindings::dpoll_try_read(fd, buf.as_mut_ptr() as *mut c_void, buf.len());
+                checked(ret, "read");
+            }
+            (6, Some((fd, peer))) => {
+                assert_eq!(bindings::dpoll_close(fd), 0);
+                if let Some(peer) = peer {
+                    assert_eq!(bindings::dpoll_close(peer), 0);
+                }
+                slots[slot] = None;
+                registered[slot] = false;
+                closed.push(fd);
+            }
+            (7, Some((fd, Some(peer)))) => {
+                assert_eq!(bindings::dpoll_close(peer), 0);
+                slots[slot] = Some((fd, None));
+            }
+            _ => continue,
+        }
+
+        for fd in &closed {
+            let ret = bindings::dpoll_close(*fd);
+            assert_eq!(
+                (ret, platform::errno()),
+                (-1, PosixError::BADF as c_int),
+                "{fd:#x}"
+            );
+        }
+        let res = bindings::with_dpoll_fd(pol, "sequence", |pol| {
+            dpoll::fuzzing::check_ready_list(pol);
+            return Ok(());
+        });
+        assert_eq!(res, Ok(()));
+    }
+
+    for (fd, peer) in slots.into_iter().flatten() {
+        assert_eq!(bindings::dpoll_close(fd), 0);
+        if let Some(peer) = peer {
+            assert_eq!(bindings::dpoll_close(peer), 0);
+        }
+    }
+    assert_eq!(bindings::dpoll_close(listener), 0);
+    assert_eq!(bindings::dpoll_close(accepts), 0);
+    assert_eq!(bindings::dpoll_close(pol), 0);
+}
@@ -0,0 +1,70 @@
+//! sockets moving between threads, `dpoll_handoff` parks a socket under a ticket and
+//! `dpoll_adopt` takes it on the thread that continues with it
+//!
+//! only sockets nothing else references are parked, so the `Shared` crossing threads is never
+//! reachable from the thread it came from
+
+use std::{
+    collections::BTreeMap,
+    os::raw::c_int,
+    sync::{Mutex, PoisonError},
+};
+
+use log::trace;
+
+use crate::{
+    shared::Shared,
+    socket::Socket,
+    wrappers::errno::{PosixError, PosixResult},
+};
+
+/// a parked socket, the only reference to it
+struct Parked(Shared<Socket>);
+
+// `park` asserts there are no other references, so the socket moves as a whole, demikernel does
+// not care which thread its queues are used from
+unsafe impl Send for Parked {}
+
+struct Tickets {
+    next: c_int,
+    parked: BTreeMap<c_int, Parked>,
+}
+
+static TICKETS: Mutex<Tickets> = Mutex::new(Tickets {
+    next: 0,
+    parked: BTreeMap::new(),
+});
+
+/// whether `soc` can be parked, fails with EBUSY if anything else still references it or if it
+/// cannot move, see `Socket::check_movable`
+pub fn check(soc: &Shared<Socket>) -> PosixResult<()> {
+    if !soc.is_unique() {
+        return Err(PosixError::BUSY);
+    }
+    return soc.borrow().check_movable();
+}
+
+/// parks `soc` until it is adopted, returning its ticket, `check` has to pass first
+pub fn park(soc: Shared<Socket>) -> c_int {
+    assert!(soc.is_unique(), "parking a socket still referenced");
+
+    let mut tickets = TICKETS.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut ticket = tickets.next;
+    while tickets.parked.contains_key(&ticket) {
+        ticket = ticket.checked_add(1).unwrap_or(0);
+    }
+    tickets.next = ticket.checked_add(1).unwrap_or(0);
+    tickets.parked.insert(ticket, Parked(soc));
+
+    trace!("socket parked with ticket {ticket}");
+    return ticket;
+}
+
+/// takes the socket parked under `ticket`, fails with ENOENT if there is none
+pub fn adopt(ticket: c_int) -> PosixResult<Shared<Socket>> {
+    let mut tickets = TICKETS.lock().unwrap_or_else(PoisonError::into_inner);
+    let Parked(soc) = tickets.parked.remove(&ticket).ok_or(PosixError::NOENT)?;
+
+    trace!("socket with ticket {ticket} adopted");
+    return Ok(soc);
+}
@@ -0,0 +1,43 @@
+use std::time::{Duration, Instant};
+
+/// application level keepalive, kernel-bypass stacks have none of their own
+///
+/// no probes are sent, a connection is declared dead once it saw no completions for as long as
+/// the kernel would take to give up on it: `idle + interval * count`
+#[derive(Debug, Clone, Copy)]
+pub struct Keepalive {
+    /// SO_KEEPALIVE
+    pub enabled: bool,
+    /// TCP_KEEPIDLE
+    pub idle: Duration,
+    /// TCP_KEEPINTVL
+    pub interval: Duration,
+    /// TCP_KEEPCNT
+    pub count: u32,
+}
+
+impl Keepalive {
+    /// the linux defaults
+    pub const fn new() -> Self {
+        return Self {
+            enabled: false,
+            idle: Duration::from_secs(7200),
+            interval: Duration::from_secs(75),
+            count: 9,
+        };
+    }
+
+    pub fn timeout(&self) -> Duration {
+        return self.idle + self.interval * self.count;
+    }
+
+    /// the time left until a connection last active at `last` is dead, `None` if keepalive is
+    /// disabled
+    pub fn remaining(&self, last: Instant, now: Instant) -> Option<Duration> {
+        if !self.enabled {
+            return None;
+        }
+
+        return Some((last + self.timeout()).saturating_duration_since(now));
+    }
+}
@@ -1,11 +1,33 @@
-#![feature(ptr_as_uninit, linked_list_cursors)]
+// epoll, eventfd and the demikernel libOSes only exist there
+#[cfg(not(target_os = "linux"))]
+compile_error!("demi_epoll only supports linux");
 
 #[allow(unused)]
 pub mod bindings;
 
 mod buffer;
+mod config;
 mod dpoll;
+mod fork;
+// only for the fuzz targets and benchmarks, not part of the API
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+mod handoff;
+mod keepalive;
+mod logging;
+#[cfg(feature = "metrics")]
+mod metrics;
 mod operation;
+mod pacer;
+#[cfg(feature = "record")]
+pub mod recorder;
+mod recv_queue;
+mod registered;
+mod send_queue;
 mod shared;
 mod socket;
+#[cfg(feature = "stream")]
+pub mod stream;
+mod watchdog;
 mod wrappers;
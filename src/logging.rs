@@ -0,0 +1,72 @@
+//! the logger of dpoll, set up from DPOLL_LOG (or RUST_LOG if it is not set) by `dpoll_init` and
+//! replaceable at runtime with `dpoll_set_log`
+//!
+//! filters use the env_logger syntax, e.g. `demi_epoll::dpoll::ready_list=trace,warn`
+
+use std::{env, io::Write, sync::RwLock};
+
+use env_logger::{Builder, Logger};
+use log::{Log, Metadata, Record};
+
+static LOGGER: RwLock<Option<Logger>> = RwLock::new(None);
+
+/// forwards to the current logger, so it can be swapped after `log::set_logger`
+struct Reloadable;
+
+impl Log for Reloadable {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        return LOGGER
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|l| l.enabled(metadata));
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some(logger) = LOGGER.read().unwrap().as_ref() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = LOGGER.read().unwrap().as_ref() {
+            logger.flush();
+        }
+    }
+}
+
+/// installs the logger, configured from the environment, can be called more than once
+pub fn init() {
+    let _ = log::set_logger(&Reloadable);
+    set_filter(None);
+}
+
+/// replaces the filter of the logger, `None` goes back to the one from the environment
+pub fn set_filter(filter: Option<&str>) {
+    let mut builder = Builder::new();
+    match filter
+        .map(str::to_owned)
+        .or_else(|| env::var("DPOLL_LOG").ok())
+    {
+        Some(filter) => builder.parse_filters(&filter),
+        None => builder.parse_default_env(),
+    };
+
+    builder.format(|buf, record| {
+        let ts = buf.timestamp();
+        writeln!(
+            buf,
+            "[{ts} {level} {file}:{line} {path}] {args}",
+            level = record.level(),
+            file = record.file().unwrap_or("unknown"),
+            line = record.line().unwrap_or(0),
+            path = record.target(),
+            args = record.args()
+        )
+    });
+
+    let logger = builder.build();
+    let max_level = logger.filter();
+    *LOGGER.write().unwrap() = Some(logger);
+    log::set_max_level(max_level);
+}
@@ -0,0 +1,266 @@
+//! Prometheus text format dumps of the dpoll statistics
+//!
+//! on SIGUSR1 the statistics of every dpoll of the thread are written to `DPOLL_METRICS_FILE`,
+//! or to /tmp/demi_epoll.<pid>.prom if it is not set. the signal handler only sets a flag, the
+//! dump itself happens at the end of the next dpoll_pwait
+
+use std::{
+    env,
+    fmt::Write,
+    fs, io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use libc::c_int;
+use log::trace;
+
+use crate::{
+    dpoll::{
+        Dpoll,
+        stats::{Histogram, Phase},
+    },
+    wrappers::{
+        errno::{PosixError, PosixResult},
+        sga_pool,
+    },
+};
+
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigusr1(_: c_int) {
+    DUMP_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+pub fn install() -> PosixResult<()> {
+    trace!("installing the SIGUSR1 metrics handler");
+    let handler: extern "C" fn(c_int) = on_sigusr1;
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = handler as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+
+    let res = unsafe { libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut()) };
+    return if res.is_negative() {
+        PosixError::from_errno()
+    } else {
+        Ok(())
+    };
+}
+
+/// returns true once per SIGUSR1
+pub fn take_request() -> bool {
+    return DUMP_REQUESTED.swap(false, Ordering::Relaxed);
+}
+
+/// the name, help text and getter of a metric exported for every dpoll
+type Metric = (&'static str, &'static str, fn(&Dpoll) -> u64);
+
+/// formats the statistics of `pols`, given as (fd, dpoll) pairs
+pub fn format(pols: &[(i32, &Dpoll)]) -> String {
+    let mut out = String::new();
+
+    let counters: [Metric; 6] = [
+        ("dpoll_pwait_calls_total", "pwait calls", |p| {
+            p.stats().pwait_calls
+        }),
+        ("dpoll_completions_total", "demikernel completions", |p| {
+            p.stats().completions
+        }),
+        ("dpoll_events_total", "events returned by pwait", |p| {
+            p.stats().events
+        }),
+        (
+            "dpoll_deferred_accepts_total",
+            "listeners left for the next pwait by the accept limit",
+            |p| p.stats().deferred_accepts,
+        ),
+        (
+            "dpoll_refused_adds_total",
+            "adds refused as the dpoll had its maximum of sockets",
+            |p| p.stats().refused_adds,
+        ),
+        (
+            "dpoll_qtoks_grows_total",
+            "pwaits that grew the token buffer",
+            |p| p.stats().qtoks_grows,
+        ),
+    ];
+    for (name, help, get) in counters {
+        header(&mut out, name, help, "counter");
+        for (fd, pol) in pols {
+            writeln!(out, "{name}{{dpoll=\"{fd}\"}} {}", get(pol)).unwrap();
+        }
+    }
+
+    let gauges: [Metric; 4] = [
+        (
+            "dpoll_ready_list_depth",
+            "ready list length after the last pwait",
+            |p| p.stats().ready_list_depth,
+        ),
+        (
+            "dpoll_registered_sockets",
+            "registered dpoll sockets",
+            |p| p.len() as u64,
+        ),
+        (
+            "dpoll_max_registered_sockets",
+            "the most dpoll sockets registered at once",
+            |p| p.stats().max_items,
+        ),
+        (
+            "dpoll_max_qtoks",
+            "the most tokens a single pwait waited on",
+            |p| p.stats().max_qtoks,
+        ),
+    ];
+    for (name, help, get) in gauges {
+        header(&mut out, name, help, "gauge");
+        for (fd, pol) in pols {
+            writeln!(out, "{name}{{dpoll=\"{fd}\"}} {}", get(pol)).unwrap();
+        }
+    }
+
+    let pool = sga_pool::stats();
+    for (name, help, val) in [
+        (
+            "dpoll_sga_pool_hits_total",
+            "writes staged in a pooled sga",
+            pool.hits,
+        ),
+        (
+            "dpoll_sga_pool_misses_total",
+            "writes that allocated an sga",
+            pool.misses,
+        ),
+    ] {
+        header(&mut out, name, help, "counter");
+        writeln!(out, "{name} {val}").unwrap();
+    }
+
+    header(
+        &mut out,
+        "dpoll_pwait_seconds",
+        "pwait latency",
+        "histogram",
+    );
+    for (fd, pol) in pols {
+        histogram(
+            &mut out,
+            "dpoll_pwait_seconds",
+            *fd,
+            &pol.stats().pwait_latency,
+        );
+    }
+
+    header(
+        &mut out,
+        "dpoll_completions_per_wait",
+        "completions per pwait",
+        "histogram",
+    );
+    for (fd, pol) in pols {
+        histogram(
+            &mut out,
+            "dpoll_completions_per_wait",
+            *fd,
+            &pol.stats().completions_per_wait,
+        );
+    }
+
+    header(
+        &mut out,
+        "dpoll_completions_per_demi_wait",
+        "completions per demikernel wait",
+        "histogram",
+    );
+    for (fd, pol) in pols {
+        histogram(
+            &mut out,
+            "dpoll_completions_per_demi_wait",
+            *fd,
+            &pol.stats().batch_sizes,
+        );
+    }
+
+    header(
+        &mut out,
+        "dpoll_pwait_phase_seconds",
+        "time a pwait spent in a phase",
+        "summary",
+    );
+    for (fd, pol) in pols {
+        for phase in Phase::ALL {
+            let hist = pol.stats().phase(phase);
+            let labels = format!("dpoll=\"{fd}\",phase=\"{}\"", phase.name());
+            for quantile in [0.5, 0.9, 0.99, 0.999] {
+                let secs = hist.percentile(quantile) as f64 / 1e9;
+                writeln!(
+                    out,
+                    "dpoll_pwait_phase_seconds{{{labels},quantile=\"{quantile}\"}} {secs}"
+                )
+                .unwrap();
+            }
+            let sum = hist.sum() as f64 / 1e9;
+            writeln!(out, "dpoll_pwait_phase_seconds_sum{{{labels}}} {sum}").unwrap();
+            writeln!(
+                out,
+                "dpoll_pwait_phase_seconds_count{{{labels}}} {}",
+                hist.count()
+            )
+            .unwrap();
+        }
+    }
+
+    header(
+        &mut out,
+        "dpoll_socket_queue_depth",
+        "running demikernel operations",
+        "gauge",
+    );
+    for (fd, pol) in pols {
+        for (qd, depth) in pol.queue_depths() {
+            writeln!(
+                out,
+                "dpoll_socket_queue_depth{{dpoll=\"{fd}\",qd=\"{qd}\"}} {depth}"
+            )
+            .unwrap();
+        }
+    }
+
+    return out;
+}
+
+pub fn dump(contents: &str) -> io::Result<()> {
+    let path = env::var("DPOLL_METRICS_FILE")
+        .unwrap_or_else(|_| format!("/tmp/demi_epoll.{}.prom", std::process::id()));
+    trace!("dumping metrics to {path}");
+
+    // scrapers should never see a partially written file
+    let tmp = format!("{path}.tmp");
+    fs::write(&tmp, contents)?;
+    return fs::rename(tmp, path);
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+}
+
+fn histogram(out: &mut String, name: &str, fd: i32, hist: &Histogram) {
+    let mut cumulative = 0;
+    for (bound, count) in hist.buckets() {
+        cumulative += count;
+        let le = if bound.is_infinite() {
+            "+Inf".to_owned()
+        } else {
+            bound.to_string()
+        };
+        writeln!(
+            out,
+            "{name}_bucket{{dpoll=\"{fd}\",le=\"{le}\"}} {cumulative}"
+        )
+        .unwrap();
+    }
+    writeln!(out, "{name}_sum{{dpoll=\"{fd}\"}} {}", hist.sum()).unwrap();
+    writeln!(out, "{name}_count{{dpoll=\"{fd}\"}} {}", hist.count()).unwrap();
+}
@@ -1,20 +1,22 @@
 use std::{
     fmt::Debug,
-    mem::{self},
-    time::Duration,
+    mem::{self, ManuallyDrop},
+    time::Instant,
 };
 
 use log::trace;
 
 use crate::wrappers::{
-    demi::{self, QResult, QToken},
+    clock,
+    deadline::Deadline,
+    demi::{self, QResultValue, QToken},
     errno::{PosixError, PosixResult},
 };
 
 pub trait Schedulable: Sized {
-    type Payload: Debug;
+    type Payload: Debug + 'static;
 
-    fn from_qresult(result: QResult) -> Self;
+    fn from_qresult(val: QResultValue) -> Self;
 
     fn schedule(soc: &mut demi::SocketQd, payload: &mut Self::Payload) -> demi::QToken;
 }
@@ -22,8 +24,7 @@ pub trait Schedulable: Sized {
 impl Schedulable for demi::AcceptResult {
     type Payload = ();
 
-    fn from_qresult(result: QResult) -> Self {
-        let val = result.value.unwrap();
+    fn from_qresult(val: QResultValue) -> Self {
         if let demi::QResultValue::Accept(accept_res) = val {
             return accept_res;
         } else {
@@ -36,11 +37,27 @@ impl Schedulable for demi::AcceptResult {
     }
 }
 
+impl Schedulable for demi::ConnectResult {
+    type Payload = libc::sockaddr_in;
+
+    fn from_qresult(val: QResultValue) -> Self {
+        if let demi::QResultValue::Connect = val {
+            return demi::ConnectResult;
+        } else {
+            panic!("cannot create ConnectResult from {:?}", val);
+        }
+    }
+
+    fn schedule(soc: &mut demi::SocketQd, addr: &mut Self::Payload) -> demi::QToken {
+        return soc.connect(addr).unwrap();
+    }
+}
+
 impl Schedulable for () {
     type Payload = demi::SgArray;
 
-    fn from_qresult(val: QResult) -> Self {
-        assert!(matches!(val.value.unwrap(), demi::QResultValue::Push));
+    fn from_qresult(val: QResultValue) -> Self {
+        assert!(matches!(val, demi::QResultValue::Push));
     }
 
     fn schedule(soc: &mut demi::SocketQd, sga: &mut Self::Payload) -> demi::QToken {
@@ -51,8 +68,7 @@ impl Schedulable for () {
 impl Schedulable for demi::SgArrayByteIter {
     type Payload = ();
 
-    fn from_qresult(result: QResult) -> Self {
-        let val = result.value.unwrap();
+    fn from_qresult(val: QResultValue) -> Self {
         if let demi::QResultValue::Pop(buf) = val {
             return buf.into_iter();
         } else {
@@ -65,6 +81,49 @@ impl Schedulable for demi::SgArrayByteIter {
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    #[default]
+    None,
+    Running,
+    Completed,
+}
+
+/// a cancelled operation that did not complete yet, demikernel cannot cancel operations, so it
+/// keeps the payload alive until the completion arrives and then drops it
+///
+/// a tombstone dropped before that leaks the payload, demikernel might still use it, e.g. the sga
+/// of a push that could otherwise go back to the sga pool
+#[derive(Debug)]
+pub struct Tombstone {
+    tok: QToken,
+    payload: ManuallyDrop<Box<dyn Debug>>,
+}
+
+impl Tombstone {
+    pub fn token(&self) -> QToken {
+        return self.tok;
+    }
+
+    /// drops the completion of the cancelled operation, closing the socket of an accept
+    pub fn bury(mut self, val: PosixResult<QResultValue>) {
+        trace!("dropping the completion of cancelled {}: {val:?}", self.tok);
+        unsafe { ManuallyDrop::drop(&mut self.payload) };
+        if let Ok(QResultValue::Accept(mut acc)) = val {
+            let _ = acc.qd.close();
+        }
+    }
+
+    /// the completion of the cancelled operation, if it arrived
+    pub fn poll(&self) -> Option<PosixResult<QResultValue>> {
+        return match demi::wait_retrying(self.tok, Deadline::now()) {
+            Ok(res) => Some(res.value),
+            Err(PosixError::TIMEDOUT) => None,
+            Err(err) => Some(Err(err)),
+        };
+    }
+}
+
 /// takes ownership of payload P, which will be dropped in transition to Completed
 #[derive(Debug)]
 pub enum Operation<T>
@@ -72,7 +131,12 @@ where
     T: Schedulable + Debug,
 {
     None,
-    Running { _payload: T::Payload, tok: QToken },
+    Running {
+        _payload: T::Payload,
+        tok: QToken,
+        /// when the operation was started, for the watchdog
+        since: Instant,
+    },
     Completed(PosixResult<T>),
 }
 
@@ -90,6 +154,7 @@ where
         *self = Self::Running {
             _payload: payload,
             tok,
+            since: clock::now(),
         };
     }
 
@@ -98,6 +163,17 @@ where
         *self = Self::Completed(result);
     }
 
+    /// completes the operation with `err` if it is running `tok`
+    pub fn fail(&mut self, tok: QToken, err: PosixError) -> bool {
+        return match self {
+            Self::Running { tok: running, .. } if *running == tok => {
+                *self = Self::Completed(Err(err));
+                true
+            }
+            _ => false,
+        };
+    }
+
     pub fn get(&mut self) -> PosixResult<T> {
         match mem::replace(self, Operation::None) {
             Operation::Completed(res) => return res,
@@ -126,6 +202,7 @@ where
                 *self = Op::Running {
                     _payload: payload,
                     tok,
+                    since: clock::now(),
                 };
                 return None;
             }
@@ -153,6 +230,7 @@ where
                 *self = Op::Running {
                     _payload: payload,
                     tok,
+                    since: clock::now(),
                 };
                 return None;
             }
@@ -167,6 +245,58 @@ where
         }
     }
 
+    /// abandons the operation, returning the tombstone of a running one, whose completion has to be
+    /// dropped when it arrives
+    pub fn cancel(&mut self) -> Option<Tombstone> {
+        return match mem::replace(self, Self::None) {
+            Self::Running { _payload, tok, .. } => {
+                trace!("cancelling {tok}");
+                Some(Tombstone {
+                    tok,
+                    payload: ManuallyDrop::new(Box::new(_payload)),
+                })
+            }
+            _ => None,
+        };
+    }
+
+    /// fails the running operation with ETIMEDOUT, returning its tombstone, the completion that
+    /// might still arrive has to be dropped like the one of a cancelled operation
+    ///
+    /// an operation that is not running is left as it is, a completed one keeps its result
+    pub fn time_out(&mut self) -> Option<Tombstone> {
+        if !self.is_running() {
+            return None;
+        }
+        let tombstone = self.cancel()?;
+        *self = Self::Completed(Err(PosixError::TIMEDOUT));
+        return Some(tombstone);
+    }
+
+    /// the token of the running operation and when it was started
+    pub fn running_since(&self) -> Option<(QToken, Instant)> {
+        return match self {
+            Self::Running { tok, since, .. } => Some((*tok, *since)),
+            _ => None,
+        };
+    }
+
+    /// the token of the running operation
+    pub fn token(&self) -> Option<QToken> {
+        return match self {
+            Self::Running { tok, .. } => Some(*tok),
+            _ => None,
+        };
+    }
+
+    pub fn state(&self) -> State {
+        return match self {
+            Self::None => State::None,
+            Self::Running { .. } => State::Running,
+            Self::Completed(_) => State::Completed,
+        };
+    }
+
     pub fn is_finished(&self) -> bool {
         return matches!(self, Self::Completed(_));
     }
@@ -182,31 +312,33 @@ where
     #[inline]
     pub fn poll(&mut self) -> bool {
         trace!("polling {:?}", self);
-        self.wait(Some(Duration::ZERO));
+        self.wait(Deadline::now());
         return self.is_none() || self.is_finished();
     }
 
     #[inline]
     pub fn block(&mut self) {
-        self.wait(None);
+        self.block_with_deadline(Deadline::after(None));
+    }
+
+    /// like `block`, but gives up once `deadline` passed, returns whether the operation finished
+    pub fn block_with_deadline(&mut self, deadline: Deadline) -> bool {
+        self.wait(deadline);
+        return !self.is_running();
     }
 
-    fn wait(&mut self, timeout: Option<Duration>) {
+    fn wait(&mut self, deadline: Deadline) {
         let tok = if let Self::Running { tok, .. } = self {
             *tok
         } else {
             return;
         };
 
-        let res = match demi::wait(tok, timeout) {
-            Ok(res) => Some(Ok(res)),
-            Err(err) => {
-                if err == PosixError::TIMEDOUT {
-                    None
-                } else {
-                    panic!("{}", err);
-                }
-            }
+        // a failed wait fails the operation instead of the process
+        let res = match demi::wait_retrying(tok, deadline) {
+            Ok(res) => Some(res.value),
+            Err(PosixError::TIMEDOUT) => None,
+            Err(err) => Some(Err(err)),
         };
 
         if let Some(res) = res {
@@ -214,3 +346,121 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[derive(Debug, Clone)]
+    enum Step {
+        Start(usize),
+        Complete(PosixResult<()>),
+        /// fails the running operation or, if not `own`, one of another token
+        Fail {
+            own: bool,
+            err: PosixError,
+        },
+        Get,
+        GetMut,
+        Cancel,
+        TimeOut,
+    }
+
+    fn err() -> impl Strategy<Value = PosixError> {
+        return prop::sample::select(vec![
+            PosixError::CONNRESET,
+            PosixError::PIPE,
+            PosixError::TIMEDOUT,
+            PosixError::NOBUFS,
+        ]);
+    }
+
+    fn step() -> impl Strategy<Value = Step> {
+        return prop_oneof![
+            (0..64usize).prop_map(Step::Start),
+            prop::option::of(err()).prop_map(|err| Step::Complete(err.map_or(Ok(()), Err))),
+            (any::<bool>(), err()).prop_map(|(own, err)| Step::Fail { own, err }),
+            Just(Step::Get),
+            Just(Step::GetMut),
+            Just(Step::Cancel),
+            Just(Step::TimeOut),
+        ];
+    }
+
+    /// a push payload of `len` bytes over memory kept in `bufs`, which has to outlive it
+    fn payload(bufs: &mut Vec<Vec<Vec<u8>>>, len: usize) -> demi::SgArray {
+        bufs.push(vec![vec![0xa5; len]]);
+        return demi::SgArray::from_segments(bufs.last_mut().unwrap());
+    }
+
+    proptest! {
+        /// a push taken through the steps legal in its state, in any order, behaves like a model
+        /// of its state: a completion or failure for another token does not touch it, only a
+        /// running operation leaves a tombstone and a completed one keeps its result until taken
+        #[test]
+        fn state_machine(steps in prop::collection::vec(step(), 0..64)) {
+            let mut bufs = Vec::new();
+            let mut tombstones = Vec::new();
+            let mut op: Operation<()> = Operation::default();
+            // the token of a running operation, or the result of a completed one
+            let mut model: (State, QToken, PosixResult<()>) = (State::None, 0, Ok(()));
+
+            for (tok, step) in (1..).zip(steps) {
+                match (step, model.0) {
+                    (Step::Start(len), State::None) => {
+                        op.start(tok, payload(&mut bufs, len));
+                        model = (State::Running, tok, Ok(()));
+                    }
+                    (Step::Complete(res), State::Running) => {
+                        op.complete(res);
+                        model = (State::Completed, 0, res);
+                    }
+                    (Step::Fail { own, err }, _) => {
+                        let target = if own { model.1 } else { tok + 1000 };
+                        let failed = op.fail(target, err);
+                        prop_assert_eq!(failed, model.0 == State::Running && own);
+                        if failed {
+                            model = (State::Completed, 0, Err(err));
+                        }
+                    }
+                    (Step::Get, State::Completed) => {
+                        prop_assert_eq!(op.get(), model.2);
+                        model = (State::None, 0, Ok(()));
+                    }
+                    (Step::GetMut, State::Completed) => {
+                        prop_assert_eq!(op.get_mut().map(|_| ()), model.2);
+                    }
+                    (Step::Cancel, state) => {
+                        let tombstone = op.cancel();
+                        let want = (state == State::Running).then_some(model.1);
+                        prop_assert_eq!(tombstone.as_ref().map(Tombstone::token), want);
+                        tombstones.extend(tombstone);
+                        model = (State::None, 0, Ok(()));
+                    }
+                    (Step::TimeOut, state) => {
+                        let tombstone = op.time_out();
+                        prop_assert_eq!(tombstone.is_some(), state == State::Running);
+                        tombstones.extend(tombstone);
+                        if state == State::Running {
+                            model = (State::Completed, 0, Err(PosixError::TIMEDOUT));
+                        }
+                    }
+                    // not legal in the state
+                    _ => {}
+                }
+
+                prop_assert_eq!(op.state(), model.0);
+                prop_assert_eq!(op.token(), (model.0 == State::Running).then_some(model.1));
+                prop_assert_eq!(op.is_running(), model.0 == State::Running);
+                prop_assert_eq!(op.is_finished(), model.0 == State::Completed);
+                prop_assert_eq!(op.is_none(), model.0 == State::None);
+            }
+
+            tombstones.extend(op.cancel());
+            for tombstone in tombstones {
+                tombstone.bury(Ok(QResultValue::Push));
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+
+use crate::wrappers::clock;
+
+/// a token bucket limiting the bytes a socket can write, one token is one byte
+#[derive(Debug)]
+pub struct Pacer {
+    /// bytes per second
+    rate: u64,
+    burst: u64,
+    /// the tokens left at `last`
+    tokens: u64,
+    last: Instant,
+}
+
+impl Pacer {
+    /// starts with a full bucket
+    pub fn new(rate: u64, burst: u64) -> Self {
+        assert!(rate > 0 && burst > 0);
+        return Self {
+            rate,
+            burst,
+            tokens: burst,
+            last: clock::now(),
+        };
+    }
+
+    /// the bytes that can be written at `now`
+    pub fn available(&self, now: Instant) -> u64 {
+        let elapsed = now.saturating_duration_since(self.last).as_nanos();
+        let refilled = elapsed.saturating_mul(self.rate as u128) / NANOS_PER_SEC;
+        let tokens = (self.tokens as u128).saturating_add(refilled);
+
+        return tokens.min(self.burst as u128) as u64;
+    }
+
+    pub fn consume(&mut self, now: Instant, bytes: u64) {
+        let available = self.available(now);
+        assert!(bytes <= available);
+
+        // only move `last` by the time that produced whole tokens, so fractions are not lost
+        if available < self.burst {
+            let refilled = available - self.tokens.min(available);
+            let nanos = (refilled as u128 * NANOS_PER_SEC).div_ceil(self.rate as u128);
+            self.last += Duration::from_nanos(nanos.try_into().unwrap_or(u64::MAX));
+            self.last = self.last.min(now);
+        } else {
+            self.last = now;
+        }
+        self.tokens = available - bytes;
+    }
+
+    /// the time until at least one byte can be written, `None` if it can be already
+    pub fn delay(&self, now: Instant) -> Option<Duration> {
+        if self.available(now) > 0 {
+            return None;
+        }
+
+        let since = now.saturating_duration_since(self.last).as_nanos();
+        let needed = NANOS_PER_SEC.div_ceil(self.rate as u128);
+        let nanos = needed.saturating_sub(since);
+        return Some(Duration::from_nanos(nanos.try_into().unwrap_or(u64::MAX)));
+    }
+}
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
@@ -0,0 +1,249 @@
+//! records the calls into the C ABI into a compact binary trace, to be fed back with the
+//! dpoll_replay binary
+//!
+//! recording is enabled with the record feature and the DPOLL_RECORD_FILE environment variable,
+//! the trace is a header followed by fixed size records, written unbuffered so it survives hangs
+//! and crashes
+//!
+//! only the sizes of buffers are recorded, not their contents. the bindings that are not recorded
+//! leave an `Unrecorded` record with their name, which the replay stops at
+
+use std::{
+    env,
+    fs::File,
+    io::{self, Read, Write},
+    sync::Mutex,
+};
+
+use libc::{epoll_event, iovec, sockaddr_in};
+
+use crate::wrappers::{
+    errno::{PosixError, PosixResult},
+    platform,
+};
+
+pub const MAGIC: [u8; 4] = *b"DPTR";
+pub const VERSION: u8 = 2;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Call {
+    Socket = 0,
+    Bind = 1,
+    Listen = 2,
+    Accept = 3,
+    Connect = 4,
+    Close = 5,
+    Write = 6,
+    Read = 7,
+    Writev = 8,
+    Readv = 9,
+    Create = 10,
+    Ctl = 11,
+    Pwait = 12,
+    Init = 13,
+    Unrecorded = 14,
+}
+
+impl Call {
+    const ALL: [Call; 15] = [
+        Call::Socket,
+        Call::Bind,
+        Call::Listen,
+        Call::Accept,
+        Call::Connect,
+        Call::Close,
+        Call::Write,
+        Call::Read,
+        Call::Writev,
+        Call::Readv,
+        Call::Create,
+        Call::Ctl,
+        Call::Pwait,
+        Call::Init,
+        Call::Unrecorded,
+    ];
+}
+
+/// one call, the meaning of the arguments depends on the call:
+/// - Socket: domain, type, protocol
+/// - Bind, Connect: fd, address packed with `pack_addr`
+/// - Listen: fd, backlog
+/// - Accept, Close: fd
+/// - Write, Read, Writev, Readv: fd, number of bytes
+/// - Create: flags
+/// - Ctl: dpoll fd, op | events << 32, fd, data
+/// - Pwait: dpoll fd, maxevents, timeout
+/// - Init: none
+/// - Unrecorded: the name of the binding packed with `pack_name`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub call: Call,
+    pub args: [i64; 4],
+    pub ret: i64,
+    /// the errno set by the call, 0 if it did not fail
+    pub errno: i32,
+}
+
+impl Record {
+    pub const SIZE: usize = 1 + 4 * 8 + 8 + 4;
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0; Self::SIZE];
+        buf[0] = self.call as u8;
+        for (i, arg) in self.args.iter().enumerate() {
+            buf[1 + i * 8..9 + i * 8].copy_from_slice(&arg.to_le_bytes());
+        }
+        buf[33..41].copy_from_slice(&self.ret.to_le_bytes());
+        buf[41..45].copy_from_slice(&self.errno.to_le_bytes());
+
+        return buf;
+    }
+
+    pub fn decode(buf: &[u8; Self::SIZE]) -> Option<Self> {
+        let call = *Call::ALL.get(buf[0] as usize)?;
+        let i64_at = |off: usize| i64::from_le_bytes(buf[off..off + 8].try_into().unwrap());
+
+        return Some(Self {
+            call,
+            args: [i64_at(1), i64_at(9), i64_at(17), i64_at(25)],
+            ret: i64_at(33),
+            errno: i32::from_le_bytes(buf[41..45].try_into().unwrap()),
+        });
+    }
+}
+
+static TRACE: Mutex<Option<File>> = Mutex::new(None);
+
+/// starts recording into DPOLL_RECORD_FILE, if it is set
+pub fn install() -> PosixResult<()> {
+    let Ok(path) = env::var("DPOLL_RECORD_FILE") else {
+        return Ok(());
+    };
+
+    let create = || -> io::Result<File> {
+        let mut file = File::create(&path)?;
+        file.write_all(&MAGIC)?;
+        file.write_all(&[VERSION])?;
+        return Ok(file);
+    };
+    let file = match create() {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("dpoll: cannot record into {path}: {e}");
+            let code = e.raw_os_error().unwrap_or(libc::EIO);
+            return PosixError::from_error_code(code).map(|_| unreachable!());
+        }
+    };
+    *TRACE.lock().unwrap() = Some(file);
+
+    return Ok(());
+}
+
+pub fn record(call: Call, args: &[i64], ret: i64) {
+    let mut trace = TRACE.lock().unwrap();
+    let Some(file) = trace.as_mut() else {
+        return;
+    };
+
+    let errno = if ret.is_negative() {
+        platform::errno()
+    } else {
+        0
+    };
+    let mut padded = [0; 4];
+    padded[..args.len()].copy_from_slice(args);
+    let rec = Record {
+        call,
+        args: padded,
+        ret,
+        errno,
+    };
+
+    if file.write_all(&rec.encode()).is_err() {
+        // a broken trace is worse than none
+        *trace = None;
+    }
+}
+
+/// marks a call of the binding `name`, whose arguments are not recorded
+pub fn unrecorded(name: &str) {
+    record(Call::Unrecorded, &pack_name(name), 0);
+}
+
+/// reads a whole trace, failing with InvalidData if it is not one
+pub fn read_trace<R: Read>(mut r: R) -> io::Result<Vec<Record>> {
+    let mut header = [0; 5];
+    r.read_exact(&mut header)?;
+    if header[..4] != MAGIC || header[4] != VERSION {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+
+    let mut records = Vec::new();
+    let mut buf = [0; Record::SIZE];
+    loop {
+        match r.read_exact(&mut buf) {
+            Ok(()) => {}
+            // a trace cut short by a crash ends with a partial record
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(records),
+            Err(e) => return Err(e),
+        }
+        records.push(Record::decode(&buf).ok_or(io::ErrorKind::InvalidData)?);
+    }
+}
+
+/// the first 32 bytes of `name`
+pub fn pack_name(name: &str) -> [i64; 4] {
+    let mut bytes = [0; 32];
+    let len = name.len().min(bytes.len());
+    bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+
+    return std::array::from_fn(|i| {
+        return i64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap());
+    });
+}
+
+pub fn unpack_name(packed: &[i64; 4]) -> String {
+    let bytes: Vec<u8> = packed.iter().flat_map(|arg| arg.to_le_bytes()).collect();
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    return String::from_utf8_lossy(&bytes[..len]).into_owned();
+}
+
+/// `addr` is null or a valid sockaddr_in
+pub unsafe fn pack_addr(addr: *const sockaddr_in) -> i64 {
+    let Some(addr) = (unsafe { addr.as_ref() }) else {
+        return 0;
+    };
+
+    return ((addr.sin_addr.s_addr as i64) << 16) | addr.sin_port as i64;
+}
+
+pub fn unpack_addr(packed: i64) -> sockaddr_in {
+    let mut addr: sockaddr_in = unsafe { std::mem::zeroed() };
+    addr.sin_family = libc::AF_INET as libc::sa_family_t;
+    addr.sin_port = packed as u16;
+    addr.sin_addr.s_addr = (packed >> 16) as u32;
+
+    return addr;
+}
+
+/// `op | events << 32` of a ctl, `event` is null or valid
+pub unsafe fn pack_ctl(op: i32, event: *const epoll_event) -> i64 {
+    let events = unsafe { event.as_ref() }.map_or(0, |ev| ev.events);
+    return op as u32 as i64 | (events as i64) << 32;
+}
+
+/// the user data of a ctl, `event` is null or valid
+pub unsafe fn ctl_data(event: *const epoll_event) -> i64 {
+    return unsafe { event.as_ref() }.map_or(0, |ev| ev.u64 as i64);
+}
+
+/// the total length of `count` iovecs, `vecs` is null or points to that many
+pub unsafe fn iovecs_total(vecs: *const iovec, count: i32) -> i64 {
+    if vecs.is_null() || count <= 0 {
+        return 0;
+    }
+
+    let vecs = unsafe { std::slice::from_raw_parts(vecs, count as usize) };
+    return vecs.iter().map(|v| v.iov_len as i64).sum();
+}
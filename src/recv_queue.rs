@@ -0,0 +1,184 @@
+use std::{collections::VecDeque, time::Instant};
+
+use log::trace;
+
+use crate::{
+    operation::{self, Operation, Tombstone},
+    wrappers::{
+        demi::{self, QToken},
+        errno::{PosixError, PosixResult},
+    },
+};
+
+/// the default high-water mark, the default SO_RCVBUF of linux
+pub const DEFAULT_RCVBUF: usize = 212992;
+
+/// the pop of a socket and the data delivered by the previous ones that was not read yet
+///
+/// a new pop can be started as soon as the last one completed, until the buffered bytes reach the
+/// high-water mark, popping then stops until reads drain them below half of it
+///
+/// a pop completing without data means the peer shut its side down, nothing is popped after it
+/// and reads return 0 once the data before it was read, like with the kernel
+#[derive(Debug)]
+pub struct RecvQueue {
+    pop: Operation<demi::SgArrayByteIter>,
+    received: VecDeque<demi::SgArrayByteIter>,
+    /// the unread bytes of `received`
+    buffered: usize,
+    high_water: usize,
+    /// set once `buffered` reached `high_water`
+    stopped: bool,
+    /// set by a pop that completed without data
+    eof: bool,
+}
+
+impl RecvQueue {
+    pub fn new(high_water: usize) -> Self {
+        assert!(high_water > 0);
+        return Self {
+            pop: Operation::default(),
+            received: VecDeque::new(),
+            buffered: 0,
+            high_water,
+            stopped: false,
+            eof: false,
+        };
+    }
+
+    /// whether there is data, an error or the end of the stream to be read
+    pub fn is_readable(&self) -> bool {
+        return !self.received.is_empty() || self.pop.is_finished() || self.eof;
+    }
+
+    /// whether a new pop may be started
+    pub fn can_pop(&self) -> bool {
+        return self.pop.is_none() && !self.stopped && !self.eof;
+    }
+
+    pub fn token(&self) -> Option<QToken> {
+        return self.pop.token();
+    }
+
+    pub fn buffered(&self) -> usize {
+        return self.buffered;
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        return self.stopped;
+    }
+
+    /// changes the high-water mark, popping stops or resumes right away if the buffered bytes
+    /// call for it
+    pub fn set_high_water(&mut self, high_water: usize) {
+        assert!(high_water > 0);
+        self.high_water = high_water;
+        self.stopped = if self.stopped {
+            self.buffered >= high_water / 2
+        } else {
+            self.buffered >= high_water
+        };
+    }
+
+    pub fn state(&self) -> operation::State {
+        if !self.received.is_empty() || self.eof {
+            return operation::State::Completed;
+        }
+        return self.pop.state();
+    }
+
+    pub fn start(&mut self, tok: QToken) {
+        self.pop.start(tok, ());
+    }
+
+    pub fn complete(&mut self, iter: demi::SgArrayByteIter) {
+        self.pop.complete(Ok(iter));
+        self.collect();
+    }
+
+    /// completes the pop with `err` if it is running `tok`
+    pub fn fail(&mut self, tok: QToken, err: PosixError) -> bool {
+        return self.pop.fail(tok, err);
+    }
+
+    /// fails the running pop with ETIMEDOUT, returning its tombstone, see `Operation::time_out`
+    pub fn time_out(&mut self) -> Option<Tombstone> {
+        return self.pop.time_out();
+    }
+
+    /// the token of the running pop and when it was started
+    pub fn running_since(&self) -> Option<(QToken, Instant)> {
+        return self.pop.running_since();
+    }
+
+    /// cancels the running pop, the data received so far stays readable
+    pub fn cancel(&mut self) -> Option<Tombstone> {
+        return self.pop.cancel();
+    }
+
+    /// polls the running pop, if any
+    pub fn poll(&mut self) {
+        self.pop.poll();
+        self.collect();
+    }
+
+    #[allow(dead_code)]
+    pub fn block(&mut self) {
+        self.pop.block();
+        self.collect();
+    }
+
+    /// passes the oldest unread data to `func`, or returns the error of the failed pop
+    ///
+    /// 0 once all the data before the end of the stream was read, WOULDBLOCK if there is nothing
+    /// to read yet or `func` read nothing
+    pub fn consume<F>(&mut self, func: F) -> PosixResult<usize>
+    where
+        F: FnOnce(&mut demi::SgArrayByteIter) -> Option<usize>,
+    {
+        let Some(iter) = self.received.front_mut() else {
+            if self.pop.is_finished() {
+                return self.pop.get().and(Err(PosixError::WOULDBLOCK));
+            }
+            if self.eof {
+                return Ok(0);
+            }
+            return Err(PosixError::WOULDBLOCK);
+        };
+
+        let before = iter.remaining();
+        let len = func(iter);
+        self.buffered -= before - iter.remaining();
+        if iter.is_empty() {
+            self.received.pop_front();
+        }
+
+        if self.stopped && self.buffered < self.high_water / 2 {
+            trace!("{} bytes buffered, resuming pops", self.buffered);
+            self.stopped = false;
+        }
+
+        return len.ok_or(PosixError::WOULDBLOCK);
+    }
+
+    /// moves the data of a successful pop to the unread data
+    fn collect(&mut self) {
+        if !matches!(self.pop, Operation::Completed(Ok(_))) {
+            return;
+        }
+
+        let iter = self.pop.get().unwrap();
+        if iter.is_empty() {
+            trace!("the peer shut down, not popping anymore");
+            self.eof = true;
+            return;
+        }
+        self.buffered += iter.remaining();
+        self.received.push_back(iter);
+
+        if self.buffered >= self.high_water {
+            trace!("{} bytes buffered, stopping pops", self.buffered);
+            self.stopped = true;
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! buffers the application registers up front and reads into by index, like the registered
+//! buffers of io_uring, see `dpoll_register_buffers`
+//!
+//! demikernel always pops into memory it allocated itself, none of the libOSes can pop into the
+//! memory of the application, so the reads copy into the registered buffers like `dpoll_read`
+//! does. the buffers are checked once when they are registered instead of on every read
+//!
+//! like the sockets, they belong to the thread that registered them
+
+use std::{cell::RefCell, os::raw::c_void};
+
+use libc::iovec;
+use log::trace;
+
+use crate::wrappers::errno::{PosixError, PosixResult};
+
+/// the most buffers that can be registered at once, the limit of io_uring
+pub const MAX_BUFFERS: usize = 1 << 14;
+
+thread_local! {
+    static REGISTERED: RefCell<Option<Vec<iovec>>> = const { RefCell::new(None) };
+}
+
+/// fails with EBUSY if buffers are registered already, they have to be unregistered first, and
+/// with EINVAL if there are none, too many or one of them is NULL or empty
+pub fn register(bufs: &[iovec]) -> PosixResult<()> {
+    if bufs.is_empty() || bufs.len() > MAX_BUFFERS {
+        return Err(PosixError::INVAL);
+    }
+    if bufs
+        .iter()
+        .any(|buf| buf.iov_base.is_null() || buf.iov_len == 0)
+    {
+        return Err(PosixError::INVAL);
+    }
+
+    return REGISTERED.with_borrow_mut(|registered| {
+        if registered.is_some() {
+            return Err(PosixError::BUSY);
+        }
+
+        trace!("registering {} buffers", bufs.len());
+        *registered = Some(bufs.to_vec());
+        return Ok(());
+    });
+}
+
+/// fails with ENXIO if no buffers are registered
+pub fn unregister() -> PosixResult<()> {
+    return REGISTERED.with_borrow_mut(|registered| {
+        return registered.take().map(|_| ()).ok_or(PosixError::NXIO);
+    });
+}
+
+/// `len` bytes of buffer `idx` starting `off` bytes in
+///
+/// fails with ENXIO if no buffers are registered and with EINVAL if the range is not within the
+/// buffer
+pub fn range(idx: usize, off: usize, len: usize) -> PosixResult<*mut c_void> {
+    return REGISTERED.with_borrow(|registered| {
+        let bufs = registered.as_ref().ok_or(PosixError::NXIO)?;
+        let buf = bufs.get(idx).ok_or(PosixError::INVAL)?;
+        if off.checked_add(len).is_none_or(|end| end > buf.iov_len) {
+            return Err(PosixError::INVAL);
+        }
+
+        return Ok(unsafe { buf.iov_base.add(off) });
+    });
+}
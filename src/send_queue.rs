@@ -0,0 +1,278 @@
+use std::{collections::VecDeque, time::Instant};
+
+use log::trace;
+
+use crate::{
+    operation::{Operation, Tombstone},
+    wrappers::{
+        deadline::Deadline,
+        demi::{self, QToken},
+        errno::{PosixError, PosixResult},
+    },
+};
+
+/// the number of pushes a socket can have in flight before writes would block
+pub const DEFAULT_SEND_QUEUE_DEPTH: usize = 8;
+
+/// the pushes of a socket that were submitted to demikernel and did not complete yet, writability
+/// is tied to it having a free slot and its bytes staying below SO_SNDBUF
+#[derive(Debug)]
+pub struct SendQueue {
+    pushes: VecDeque<Operation<()>>,
+    depth: usize,
+    /// the bytes the pushes can have in flight, only the depth limits them by default
+    max_bytes: usize,
+}
+
+impl SendQueue {
+    pub fn new(depth: usize) -> Self {
+        assert!(depth > 0);
+        return Self {
+            pushes: VecDeque::with_capacity(depth),
+            depth,
+            max_bytes: usize::MAX,
+        };
+    }
+
+    /// limits the bytes in flight to `max`, pushes beyond it stay queued until they complete
+    pub fn set_max_bytes(&mut self, max: usize) {
+        assert!(max > 0);
+        self.max_bytes = max;
+    }
+
+    #[inline]
+    pub fn has_capacity(&self) -> bool {
+        return self.pushes.len() < self.depth && self.room() > 0;
+    }
+
+    /// the bytes that can still be pushed before the queue is full
+    pub fn room(&self) -> usize {
+        if self.max_bytes == usize::MAX {
+            return usize::MAX;
+        }
+        return self.max_bytes.saturating_sub(self.queued_bytes());
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        return self.pushes.is_empty();
+    }
+
+    pub fn push(&mut self, tok: QToken, sga: demi::SgArray) {
+        assert!(self.has_capacity());
+        let mut op = Operation::default();
+        op.start(tok, sga);
+        self.pushes.push_back(op);
+    }
+
+    /// removes the push running `tok`
+    pub fn complete(&mut self, tok: QToken) -> bool {
+        let Some(pos) = self.position(tok) else {
+            return false;
+        };
+
+        trace!("push {tok} completed");
+        self.pushes.remove(pos);
+        return true;
+    }
+
+    /// removes the push running `tok`, the error is expected to be recorded by the caller
+    pub fn fail(&mut self, tok: QToken, err: PosixError) -> bool {
+        let Some(pos) = self.position(tok) else {
+            return false;
+        };
+
+        trace!("push {tok} failed with {err:?}");
+        self.pushes.remove(pos);
+        return true;
+    }
+
+    /// polls the pushes, removing the completed ones
+    ///
+    /// returns the error of the first failed push, if any
+    pub fn reap(&mut self) -> PosixResult<()> {
+        let mut res = Ok(());
+        self.pushes.retain_mut(|op| {
+            if !op.poll() {
+                return true;
+            }
+
+            if let Err(e) = op.get() {
+                res = res.and(Err(e));
+            }
+            return false;
+        });
+
+        return res;
+    }
+
+    /// removes the push running `tok`, returning its tombstone, see `Operation::time_out`
+    pub fn time_out(&mut self, tok: QToken) -> Option<Tombstone> {
+        let pos = self.position(tok)?;
+        trace!("push {tok} timed out");
+        return self.pushes.remove(pos)?.cancel();
+    }
+
+    /// removes every push, returning the tombstones of the running ones, for a socket closed while
+    /// its pushes still use their sgas
+    pub fn cancel_all(&mut self) -> Vec<Tombstone> {
+        return self
+            .pushes
+            .drain(..)
+            .filter_map(|mut op| op.cancel())
+            .collect();
+    }
+
+    /// the tokens of the running pushes and when they were started
+    pub fn running_since(&self) -> impl Iterator<Item = (QToken, Instant)> + '_ {
+        return self.pushes.iter().filter_map(Operation::running_since);
+    }
+
+    pub fn toks(&self) -> impl Iterator<Item = QToken> + '_ {
+        return self.pushes.iter().filter_map(|op| match op {
+            Operation::Running { tok, .. } => Some(*tok),
+            _ => None,
+        });
+    }
+
+    /// the bytes of the pushes that did not complete yet
+    pub fn queued_bytes(&self) -> usize {
+        return self
+            .pushes
+            .iter()
+            .map(|op| match op {
+                Operation::Running { _payload, .. } => _payload.len(),
+                _ => 0,
+            })
+            .sum();
+    }
+
+    /// blocks on the pushes in order until they all completed or `deadline` passed, removing the
+    /// completed ones
+    ///
+    /// returns the bytes left unflushed, or the error of the first failed push
+    pub fn flush_until(&mut self, deadline: Deadline) -> PosixResult<usize> {
+        let mut res = Ok(());
+        self.pushes.retain_mut(|op| {
+            if !op.block_with_deadline(deadline) {
+                return true;
+            }
+
+            if let Err(e) = op.get() {
+                res = res.and(Err(e));
+            }
+            return false;
+        });
+
+        return res.map(|()| self.queued_bytes());
+    }
+
+    #[allow(dead_code)]
+    pub fn flush(&mut self) {
+        for op in self.pushes.iter_mut() {
+            op.block();
+        }
+        self.pushes.clear();
+    }
+
+    fn position(&self, tok: QToken) -> Option<usize> {
+        return self
+            .pushes
+            .iter()
+            .position(|op| matches!(op, Operation::Running { tok: t, .. } if *t == tok));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wrappers::demi::QResultValue;
+    use proptest::prelude::*;
+
+    #[derive(Debug, Clone)]
+    enum Step {
+        Push(usize),
+        /// completes, fails or times out the running push picked by the index, one of a token
+        /// the queue does not run if none is
+        Complete(usize),
+        Fail(usize, PosixError),
+        TimeOut(usize),
+    }
+
+    fn step() -> impl Strategy<Value = Step> {
+        let err = prop::sample::select(vec![PosixError::CONNRESET, PosixError::PIPE]);
+        return prop_oneof![
+            2 => (0..64usize).prop_map(Step::Push),
+            1 => any::<usize>().prop_map(Step::Complete),
+            1 => (any::<usize>(), err).prop_map(|(pick, err)| Step::Fail(pick, err)),
+            1 => any::<usize>().prop_map(Step::TimeOut),
+        ];
+    }
+
+    fn payload(bufs: &mut Vec<Vec<Vec<u8>>>, len: usize) -> demi::SgArray {
+        bufs.push(vec![vec![0xa5; len]]);
+        return demi::SgArray::from_segments(bufs.last_mut().unwrap());
+    }
+
+    proptest! {
+        /// completions arriving in any order, like wait_any returns them, go to the push of their
+        /// token, the other pushes keep running in the order they were started and tokens the
+        /// queue does not run are rejected
+        #[test]
+        fn out_of_order_completions(
+            depth in 1..9usize,
+            steps in prop::collection::vec(step(), 0..64),
+        ) {
+            let mut bufs = Vec::new();
+            let mut tombstones = Vec::new();
+            let mut pushes = SendQueue::new(depth);
+            // the running pushes, in the order they were started, with their lengths
+            let mut running: Vec<(QToken, usize)> = Vec::new();
+
+            for (tok, step) in (1..).zip(steps) {
+                let pick = |i: usize| {
+                    return running.get(i % running.len().max(1)).map(|(t, _)| *t);
+                };
+                match step {
+                    Step::Push(len) => {
+                        if !pushes.has_capacity() {
+                            prop_assert_eq!(running.len(), depth);
+                            continue;
+                        }
+                        pushes.push(tok, payload(&mut bufs, len));
+                        running.push((tok, len));
+                    }
+                    Step::Complete(i) => {
+                        let pick = pick(i);
+                        prop_assert_eq!(pushes.complete(pick.unwrap_or(tok)), pick.is_some());
+                        running.retain(|(t, _)| Some(*t) != pick);
+                    }
+                    Step::Fail(i, err) => {
+                        let pick = pick(i);
+                        prop_assert_eq!(pushes.fail(pick.unwrap_or(tok), err), pick.is_some());
+                        running.retain(|(t, _)| Some(*t) != pick);
+                    }
+                    Step::TimeOut(i) => {
+                        let pick = pick(i);
+                        let tombstone = pushes.time_out(pick.unwrap_or(tok));
+                        prop_assert_eq!(tombstone.as_ref().map(Tombstone::token), pick);
+                        tombstones.extend(tombstone);
+                        running.retain(|(t, _)| Some(*t) != pick);
+                    }
+                }
+
+                let toks: Vec<QToken> = running.iter().map(|(t, _)| *t).collect();
+                prop_assert_eq!(pushes.toks().collect::<Vec<_>>(), toks);
+                let queued: usize = running.iter().map(|(_, len)| len).sum();
+                prop_assert_eq!(pushes.queued_bytes(), queued);
+                prop_assert_eq!(pushes.has_capacity(), running.len() < depth);
+                prop_assert_eq!(pushes.is_empty(), running.is_empty());
+            }
+
+            tombstones.extend(pushes.cancel_all());
+            for tombstone in tombstones {
+                tombstone.bury(Ok(QResultValue::Push));
+            }
+        }
+    }
+}
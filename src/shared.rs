@@ -1,19 +1,133 @@
-use std::{
-    cell::{Ref, RefCell, RefMut},
-    rc::Rc,
-};
+use std::cell::RefCell;
 
-use crate::buffer::Buffer;
+#[cfg(feature = "debug-borrows")]
+use std::panic::Location;
+
+#[cfg(feature = "debug-borrows")]
+use log::error;
+
+#[cfg(any(feature = "debug-borrows", feature = "thread-safe"))]
+use crate::wrappers::errno::PosixError;
+use crate::{buffer::Buffer, wrappers::errno::PosixResult};
+
+pub use strategy::{Ref, RefMut};
+
+/// single threaded, zero cost sharing
+#[cfg(not(feature = "thread-safe"))]
+mod strategy {
+    use std::{cell::RefCell, rc::Rc};
+
+    pub type Inner<T> = Rc<RefCell<T>>;
+    pub type Ref<'a, T> = std::cell::Ref<'a, T>;
+    pub type RefMut<'a, T> = std::cell::RefMut<'a, T>;
+
+    #[inline]
+    pub fn new<T>(it: T) -> Inner<T> {
+        return Rc::new(RefCell::new(it));
+    }
+
+    #[inline]
+    pub fn ptr_eq<T>(a: &Inner<T>, b: &Inner<T>) -> bool {
+        return Rc::ptr_eq(a, b);
+    }
+
+    #[inline]
+    pub fn is_unique<T>(it: &Inner<T>) -> bool {
+        return Rc::strong_count(it) == 1 && Rc::weak_count(it) == 0;
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn borrow<T>(it: &Inner<T>) -> Ref<'_, T> {
+        return it.borrow();
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn borrow_mut<T>(it: &Inner<T>) -> RefMut<'_, T> {
+        return it.borrow_mut();
+    }
+
+    #[inline]
+    pub fn try_borrow<T>(it: &Inner<T>) -> Option<Ref<'_, T>> {
+        return it.try_borrow().ok();
+    }
+
+    #[allow(dead_code)]
+    #[inline]
+    pub fn try_borrow_mut<T>(it: &Inner<T>) -> Option<RefMut<'_, T>> {
+        return it.try_borrow_mut().ok();
+    }
+}
+
+/// thread safe sharing, both kinds of borrows take the same lock
+///
+/// DPOLLS and SOCKETS stay thread local, so an item is only reached from the thread owning its fd
+/// or, once `dpoll_handoff` parked it unreferenced, from the thread adopting it. a lock that is
+/// already held is then always a reentrant borrow on this thread, which fails like the RefCell
+/// would instead of deadlocking
+#[cfg(feature = "thread-safe")]
+mod strategy {
+    use parking_lot::{Mutex, MutexGuard};
+    use std::sync::Arc;
+
+    pub type Inner<T> = Arc<Mutex<T>>;
+    pub type Ref<'a, T> = MutexGuard<'a, T>;
+    pub type RefMut<'a, T> = MutexGuard<'a, T>;
+
+    #[inline]
+    pub fn new<T>(it: T) -> Inner<T> {
+        return Arc::new(Mutex::new(it));
+    }
+
+    #[inline]
+    pub fn ptr_eq<T>(a: &Inner<T>, b: &Inner<T>) -> bool {
+        return Arc::ptr_eq(a, b);
+    }
+
+    #[inline]
+    pub fn is_unique<T>(it: &Inner<T>) -> bool {
+        return Arc::strong_count(it) == 1 && Arc::weak_count(it) == 0;
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn borrow<T>(it: &Inner<T>) -> Ref<'_, T> {
+        return it.try_lock().expect("already locked on this thread");
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn borrow_mut<T>(it: &Inner<T>) -> RefMut<'_, T> {
+        return it.try_lock().expect("already locked on this thread");
+    }
+
+    #[inline]
+    pub fn try_borrow<T>(it: &Inner<T>) -> Option<Ref<'_, T>> {
+        return it.try_lock();
+    }
+
+    #[allow(dead_code)]
+    #[inline]
+    pub fn try_borrow_mut<T>(it: &Inner<T>) -> Option<RefMut<'_, T>> {
+        return it.try_lock();
+    }
+}
 
 #[derive(Debug)]
 pub struct Shared<T> {
-    inner: Rc<RefCell<T>>,
+    inner: strategy::Inner<T>,
+    /// where the last mutable borrow was taken, to give context to borrow failures
+    #[cfg(feature = "debug-borrows")]
+    last_borrow_mut: strategy::Inner<Option<&'static Location<'static>>>,
 }
 
 impl<T> Clone for Shared<T> {
     fn clone(&self) -> Self {
         return Self {
             inner: self.inner.clone(),
+            #[cfg(feature = "debug-borrows")]
+            last_borrow_mut: self.last_borrow_mut.clone(),
         };
     }
 }
@@ -21,16 +135,95 @@ impl<T> Clone for Shared<T> {
 impl<T> Shared<T> {
     pub fn new(it: T) -> Self {
         return Self {
-            inner: Rc::new(RefCell::new(it)),
+            inner: strategy::new(it),
+            #[cfg(feature = "debug-borrows")]
+            last_borrow_mut: strategy::new(None),
         };
     }
 
+    /// whether both point to the same item
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        return strategy::ptr_eq(&self.inner, &other.inner);
+    }
+
+    /// whether this is the only reference to the item
+    pub fn is_unique(&self) -> bool {
+        return strategy::is_unique(&self.inner);
+    }
+
+    #[track_caller]
     pub fn borrow(&self) -> Ref<'_, T> {
-        return self.inner.borrow();
+        #[cfg(feature = "debug-borrows")]
+        if strategy::try_borrow(&self.inner).is_none() {
+            self.report_failure("borrow");
+        }
+
+        return strategy::borrow(&self.inner);
     }
 
+    #[track_caller]
     pub fn borrow_mut(&self) -> RefMut<'_, T> {
-        return self.inner.borrow_mut();
+        #[cfg(feature = "debug-borrows")]
+        {
+            if strategy::try_borrow_mut(&self.inner).is_none() {
+                self.report_failure("borrow_mut");
+            }
+            *strategy::borrow_mut(&self.last_borrow_mut) = Some(Location::caller());
+        }
+
+        return strategy::borrow_mut(&self.inner);
+    }
+
+    /// like `borrow`, but `None` instead of a panic while the item is borrowed mutably, for
+    /// diagnostics that might run while the item is in use
+    pub fn try_borrow_quiet(&self) -> Option<Ref<'_, T>> {
+        return strategy::try_borrow(&self.inner);
+    }
+
+    /// like `borrow_mut`, but `None` instead of a panic while the item is borrowed, for drops that
+    /// might run while unwinding
+    pub fn try_borrow_mut_quiet(&self) -> Option<RefMut<'_, T>> {
+        return strategy::try_borrow_mut(&self.inner);
+    }
+
+    /// like `borrow_mut`, but with the debug-borrows or thread-safe features a conflicting borrow
+    /// is turned into EDEADLK instead of a panic, logged with `context` by debug-borrows
+    #[track_caller]
+    pub fn try_borrow_mut(&self, context: &str) -> PosixResult<RefMut<'_, T>> {
+        #[cfg(feature = "debug-borrows")]
+        return match strategy::try_borrow_mut(&self.inner) {
+            Some(it) => {
+                *strategy::borrow_mut(&self.last_borrow_mut) = Some(Location::caller());
+                Ok(it)
+            }
+            None => {
+                self.report_failure(context);
+                Err(PosixError::DEADLOCK)
+            }
+        };
+
+        #[cfg(all(feature = "thread-safe", not(feature = "debug-borrows")))]
+        {
+            let _ = context;
+            return strategy::try_borrow_mut(&self.inner).ok_or(PosixError::DEADLOCK);
+        }
+
+        #[cfg(not(any(feature = "debug-borrows", feature = "thread-safe")))]
+        {
+            let _ = context;
+            return Ok(self.borrow_mut());
+        }
+    }
+
+    #[cfg(feature = "debug-borrows")]
+    #[track_caller]
+    fn report_failure(&self, context: &str) {
+        error!(
+            "{context} at {caller} conflicts with an outstanding borrow, last borrowed mutably at {last}",
+            caller = Location::caller(),
+            last = strategy::borrow(&self.last_borrow_mut)
+                .map_or("<unknown>".to_owned(), |loc| loc.to_string()),
+        );
     }
 }
 
@@ -39,3 +232,20 @@ pub type ThreadBuffer<const B: bool, T> = RefCell<Buffer<B, Shared<T>>>;
 pub const fn new_thread_buffer<const B: bool, T>() -> ThreadBuffer<B, T> {
     return RefCell::new(Buffer::new());
 }
+
+#[cfg(all(test, feature = "thread-safe"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reentrant_borrow_fails() {
+        let it = Shared::new(0);
+        let held = it.borrow_mut();
+        assert_eq!(
+            it.try_borrow_mut("reentrant").err(),
+            Some(PosixError::DEADLOCK)
+        );
+        drop(held);
+        assert!(it.try_borrow_mut("released").is_ok());
+    }
+}
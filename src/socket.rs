@@ -1,51 +1,259 @@
-use std::mem::MaybeUninit;
+use std::collections::VecDeque;
+use std::mem::{self, MaybeUninit};
+use std::time::{Duration, Instant};
 use std::usize;
 
-use log::trace;
+use log::{trace, warn};
 
-use crate::dpoll::Event;
-use crate::operation::Operation;
+use crate::bindings::{DPOLL_SO_AUTOPOP, SOL_DPOLL};
+use crate::buffer::Index;
+use crate::config::Config;
+use crate::dpoll::{DpollError, DpollResult, Event, Waker};
+use crate::keepalive::Keepalive;
+use crate::operation::{self, Operation, Tombstone};
+use crate::pacer::Pacer;
+use crate::recv_queue::RecvQueue;
+use crate::send_queue::SendQueue;
+use crate::watchdog::Watchdog;
 
+use crate::wrappers::backend::{Backend, Capabilities};
+use crate::wrappers::clock;
+use crate::wrappers::deadline::Deadline;
 use crate::wrappers::demi::QResultValue;
 use crate::wrappers::errno::PosixError;
+use crate::wrappers::sga_pool;
 use crate::wrappers::{demi, errno::PosixResult};
+use libc::{
+    IPPROTO_TCP, SHUT_RD, SHUT_RDWR, SHUT_WR, SO_KEEPALIVE, SO_PRIORITY, SO_RCVBUF, SO_SNDBUF,
+    SOL_SOCKET, SOMAXCONN, TCP_KEEPCNT, TCP_KEEPIDLE, TCP_KEEPINTVL, c_int,
+};
+
+/// the highest SO_PRIORITY a process can set without CAP_NET_ADMIN
+pub const MAX_PRIORITY: c_int = 6;
+/// the smallest SO_SNDBUF and SO_RCVBUF, the ones of linux on x86_64
+pub const MIN_SNDBUF: usize = 4608;
+pub const MIN_RCVBUF: usize = 2304;
+
+/// the state of a socket as far as the calls it accepts go, see `Phase::transition`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Unbound,
+    /// bound, but neither listening nor connected, e.g. for a connect from a given address
+    Bound,
+    Passive,
+    Connecting,
+    Active,
+    Closing,
+}
+
+/// what moves a socket from one `Phase` to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Bind,
+    Listen,
+    Connect,
+    /// the connect completed
+    Connected,
+    /// the error of a failed connect was taken
+    ConnectFailed,
+    Close,
+}
+
+impl Transition {
+    #[allow(dead_code)]
+    pub const ALL: [Self; 6] = [
+        Self::Bind,
+        Self::Listen,
+        Self::Connect,
+        Self::Connected,
+        Self::ConnectFailed,
+        Self::Close,
+    ];
+}
+
+impl Phase {
+    #[allow(dead_code)]
+    pub const ALL: [Self; 6] = [
+        Self::Unbound,
+        Self::Bound,
+        Self::Passive,
+        Self::Connecting,
+        Self::Active,
+        Self::Closing,
+    ];
+
+    /// the phase `t` moves the socket to, the errors are the ones of the kernel where it has
+    /// any, EINVAL otherwise
+    pub fn transition(self, t: Transition) -> PosixResult<Self> {
+        use Phase as P;
+        use Transition as T;
+
+        return match (self, t) {
+            (P::Closing, _) => Err(PosixError::BADF),
+            (_, T::Close) => Ok(P::Closing),
+
+            (P::Unbound, T::Bind) => Ok(P::Bound),
+            (P::Unbound | P::Bound | P::Passive, T::Listen) => Ok(P::Passive),
+            (P::Unbound | P::Bound, T::Connect) => Ok(P::Connecting),
+            (P::Connecting, T::Connect) => Err(PosixError::ALREADY),
+            (P::Active, T::Connect) => Err(PosixError::ISCONN),
+            (P::Connecting, T::Connected) => Ok(P::Active),
+            // like with the kernel, the address picked for the connect stays bound
+            (P::Connecting, T::ConnectFailed) => Ok(P::Bound),
+
+            (_, T::Bind | T::Listen | T::Connect | T::Connected | T::ConnectFailed) => {
+                Err(PosixError::INVAL)
+            }
+        };
+    }
+}
 
 #[derive(Debug)]
 enum SocketData {
+    /// neither listening nor connected, `bound` once an address was bound
+    Idle {
+        bound: bool,
+    },
+
     Passive {
         accept: Operation<demi::AcceptResult>,
+        /// accepted connections not taken by `Socket::accept` yet, a new accept is started as
+        /// soon as one completes
+        backlog: VecDeque<demi::AcceptResult>,
+        /// the backlog passed to listen, no accept is started while `backlog` is this long
+        max_backlog: usize,
+        /// shut down for reading, see `Socket::shutdown`
+        stopped: bool,
+    },
+
+    /// a successful connect turns the socket Active, a failed one stays here until the error is
+    /// taken with `Socket::take_error`
+    Connecting {
+        connect: Operation<demi::ConnectResult>,
+        /// connects to the other addresses of `Socket::connect_any`, the first to succeed wins
+        racers: Vec<Racer>,
     },
 
     Active {
-        write: Operation<()>,
-        read: Operation<demi::SgArrayByteIter>,
+        writes: SendQueue,
+        read: RecvQueue,
     },
+
+    /// closed, the demikernel queue is gone and every call fails with EBADF
+    Closing,
+}
+
+/// a connect raced against the one of the socket itself, on a demikernel socket of its own
+#[derive(Debug)]
+struct Racer {
+    soc: demi::SocketQd,
+    connect: Operation<demi::ConnectResult>,
+}
+
+impl Racer {
+    fn start(addr: &libc::sockaddr_in) -> PosixResult<Self> {
+        let mut soc = demi::SocketQd::new()?;
+        let tok = match soc.connect(addr) {
+            Ok(tok) => tok,
+            Err(e) => {
+                let _ = soc.close();
+                return Err(e);
+            }
+        };
+
+        let mut connect = Operation::default();
+        connect.start(tok, *addr);
+        return Ok(Self { soc, connect });
+    }
+
+    /// the pending connect is dropped with the socket
+    fn close(mut self) {
+        if let Err(e) = self.soc.close() {
+            trace!("closing racer {} failed with {e:?}", self.soc.qd);
+        }
+    }
 }
 
 impl SocketData {
-    pub const fn new_passive() -> Self {
+    pub const fn new_passive(max_backlog: usize) -> Self {
         return Self::Passive {
             accept: Operation::default(),
+            backlog: VecDeque::new(),
+            max_backlog,
+            stopped: false,
         };
     }
 
-    pub const fn new_active() -> Self {
+    /// closes the connections a listener completed that were not accepted
+    fn close_backlog(&mut self, qd: demi::DemiQd) {
+        let SocketData::Passive {
+            accept, backlog, ..
+        } = self
+        else {
+            return;
+        };
+
+        if accept.is_finished() {
+            backlog.extend(accept.get().ok());
+        }
+        for mut acc in backlog.drain(..) {
+            if let Err(e) = acc.qd.close() {
+                trace!("closing unaccepted {} of {qd} failed with {e:?}", acc.qd.qd);
+            }
+        }
+    }
+
+    pub fn new_active(sndbuf: usize, rcvbuf: usize) -> Self {
+        let mut writes = SendQueue::new(Config::current().send_queue_depth);
+        writes.set_max_bytes(sndbuf);
         return Self::Active {
-            write: Operation::default(),
-            read: Operation::default(),
+            writes,
+            read: RecvQueue::new(rcvbuf),
         };
     }
 
     #[allow(dead_code)]
     pub fn flush(&mut self) {
         match self {
-            SocketData::Passive { accept } => accept.block(),
-            SocketData::Active { write, read } => {
-                write.block();
+            SocketData::Passive { accept, .. } => accept.block(),
+            SocketData::Connecting { connect, .. } => connect.block(),
+            SocketData::Active { writes, read } => {
+                writes.flush();
                 read.block();
             }
+            SocketData::Idle { .. } | SocketData::Closing => {}
         }
     }
+
+    pub fn phase(&self) -> Phase {
+        return match self {
+            Self::Idle { bound: false } => Phase::Unbound,
+            Self::Idle { bound: true } => Phase::Bound,
+            Self::Passive { .. } => Phase::Passive,
+            Self::Connecting { .. } => Phase::Connecting,
+            Self::Active { .. } => Phase::Active,
+            Self::Closing => Phase::Closing,
+        };
+    }
+}
+
+/// the states of the operations a socket can be running, for diagnostics
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OperationStates {
+    pub accept: operation::State,
+    pub connect: operation::State,
+    pub read: operation::State,
+    pub write: operation::State,
+}
+
+/// where the connections accepted on a listening socket are registered, see
+/// `Socket::set_accept_autoreg`
+#[derive(Debug, Clone, Copy)]
+pub struct AcceptAutoreg {
+    pub dpoll: Index,
+    pub evs: Event,
+    /// the data of an accepted socket is `data_base + its fd`
+    pub data_base: u64,
 }
 
 #[derive(Debug)]
@@ -54,7 +262,35 @@ pub struct Socket {
     /// to be used with getsockname
     pub addr: Option<libc::sockaddr_in>,
 
-    pub open: bool,
+    /// the error of the last FAILED completion, reported as `Event::ERR` until it is taken either
+    /// by SO_ERROR or by the call consuming the failed operation
+    pub pending_error: Option<PosixError>,
+    /// the error of the failed push or pop that ended the connection, see `Socket::note_failure`
+    broken: Option<PosixError>,
+    /// the wake channels of the dpolls the socket is registered in
+    watchers: Vec<Waker>,
+    /// limits the write rate, see `Socket::set_rate`
+    pacer: Option<Pacer>,
+    keepalive: Keepalive,
+    /// see `DPOLL_SO_AUTOPOP`
+    auto_pop: bool,
+    /// handed over to the application by `Socket::detach`, no pops nor pushes are started
+    detached: bool,
+    /// how the writes of the socket use the sga pool of the thread
+    sga_pool: sga_pool::PoolConfig,
+    /// SO_PRIORITY, sockets with a higher one are scheduled and reported first by their dpolls
+    priority: u8,
+    /// SO_SNDBUF, the bytes the pushes of the socket can have in flight
+    sndbuf: usize,
+    /// SO_RCVBUF, the received bytes the socket buffers before it stops popping
+    rcvbuf: usize,
+    autoreg: Option<AcceptAutoreg>,
+    /// when an operation of the socket last completed, for keepalive
+    last_activity: Instant,
+    /// cancelled operations whose completions are still waited on, to be dropped
+    tombstones: Vec<Tombstone>,
+    /// running operations the watchdog already warned about
+    stuck: Vec<demi::QToken>,
     data: SocketData,
 }
 
@@ -67,178 +303,1080 @@ impl Socket {
         return Self {
             soc,
             addr: None,
-            open: true,
-            data: SocketData::Passive {
-                accept: Operation::None,
-            },
+            pending_error: None,
+            broken: None,
+            watchers: Vec::new(),
+            pacer: None,
+            keepalive: Config::current().keepalive,
+            auto_pop: Config::current().auto_pop,
+            detached: false,
+            sga_pool: Config::current().sga_pool,
+            priority: 0,
+            sndbuf: default_sndbuf(),
+            rcvbuf: Config::current().rcvbuf,
+            autoreg: None,
+            last_activity: clock::now(),
+            tombstones: Vec::new(),
+            stuck: Vec::new(),
+            data: SocketData::Idle { bound: false },
         };
     }
 
+    pub fn phase(&self) -> Phase {
+        return self.data.phase();
+    }
+
+    /// a socket listening with `backlog` without demikernel ever having been asked to, the fuzz
+    /// targets have no demikernel to listen with
+    #[cfg(feature = "fuzzing")]
+    pub fn listening(soc: demi::SocketQd, backlog: usize) -> Self {
+        let mut soc = Self::new(soc);
+        soc.enter(Transition::Listen, SocketData::new_passive(backlog));
+        return soc;
+    }
+
+    /// makes a listener wait on `tok` as if demikernel had started an accept for it
+    #[cfg(feature = "fuzzing")]
+    pub fn start_accept(&mut self, tok: demi::QToken) {
+        if let SocketData::Passive { accept, .. } = &mut self.data {
+            accept.start(tok, ());
+        }
+    }
+
+    /// makes a connected socket wait on `tok` as if demikernel had started a pop for it, returns
+    /// false if it could not pop
+    #[cfg(any(test, feature = "fuzzing"))]
+    pub fn start_pop(&mut self, tok: demi::QToken) -> bool {
+        let SocketData::Active { read, .. } = &mut self.data else {
+            return false;
+        };
+        if !read.can_pop() {
+            return false;
+        }
+        read.start(tok);
+        return true;
+    }
+
+    #[inline]
+    pub fn is_open(&self) -> bool {
+        return self.phase() != Phase::Closing;
+    }
+
+    /// replaces the state of the socket with `data`, which `t` has to lead to
+    fn enter(&mut self, t: Transition, data: SocketData) {
+        debug_assert_eq!(self.phase().transition(t), Ok(data.phase()), "{t:?}");
+        self.data = data;
+    }
+
+    /// fails with EINVAL unless the socket is unbound, a bound socket can still listen or connect
     #[inline]
     pub fn bind(&mut self, addr: &libc::sockaddr_in) -> PosixResult<()> {
+        self.phase().transition(Transition::Bind)?;
         self.soc.bind(addr)?;
-        self.data = SocketData::new_passive();
+        self.enter(Transition::Bind, SocketData::Idle { bound: true });
         self.addr = Some(*addr);
 
         return Ok(());
     }
 
+    /// like with the kernel, the backlog is capped to SOMAXCONN and listening again only changes
+    /// it, or resumes accepting after a shutdown, fails with EINVAL on connecting or connected
+    /// sockets
     #[inline]
     pub fn listen(&mut self, backlog: i32) -> PosixResult<()> {
-        return self.soc.listen(backlog);
+        self.phase().transition(Transition::Listen)?;
+        self.soc.listen(backlog)?;
+        let backlog = backlog.clamp(1, SOMAXCONN) as usize;
+        match &mut self.data {
+            SocketData::Passive {
+                max_backlog,
+                stopped,
+                ..
+            } => {
+                *max_backlog = backlog;
+                *stopped = false;
+            }
+            _ => self.enter(Transition::Listen, SocketData::new_passive(backlog)),
+        }
+
+        return Ok(());
     }
 
-    pub fn accept(
-        &mut self,
-        addr: Option<&mut MaybeUninit<libc::sockaddr_in>>,
-    ) -> PosixResult<Self> {
-        let data = match &mut self.data {
-            SocketData::Passive { accept } => accept,
-            _ => return Err(PosixError::INVAL),
+    /// starts connecting to `addr`, completion is reported as `Event::OUT`
+    #[inline]
+    pub fn connect(&mut self, addr: &libc::sockaddr_in) -> PosixResult<()> {
+        return self.connect_any(std::slice::from_ref(addr));
+    }
+
+    /// races connects to all of `addrs`, keeping the first to succeed and closing the rest
+    ///
+    /// completion is reported as `Event::OUT` once one succeeded or all of them failed, in which
+    /// case the pending error is the one of the last failure
+    pub fn connect_any(&mut self, addrs: &[libc::sockaddr_in]) -> PosixResult<()> {
+        if !Backend::current()
+            .capabilities()
+            .contains(Capabilities::CONNECT)
+        {
+            return Err(PosixError::OPNOTSUPP);
+        }
+
+        self.phase().transition(Transition::Connect)?;
+
+        let Some((first, rest)) = addrs.split_first() else {
+            return Err(PosixError::INVAL);
+        };
+
+        let tok = self.soc.connect(first)?;
+        let mut connect = Operation::default();
+        connect.start(tok, *first);
+
+        let racers = rest
+            .iter()
+            .filter_map(|addr| {
+                Racer::start(addr)
+                    .inspect_err(|e| trace!("not racing a connect to {addr:?}: {e:?}"))
+                    .ok()
+            })
+            .collect();
+        self.enter(
+            Transition::Connect,
+            SocketData::Connecting { connect, racers },
+        );
+
+        return Err(PosixError::INPROGRESS);
+    }
+
+    /// whether `qd` is the socket itself or one of its racing connects
+    pub fn owns_qd(&self, qd: demi::DemiQd) -> bool {
+        if self.soc.qd == qd {
+            return true;
+        }
+
+        return match &self.data {
+            SocketData::Connecting { racers, .. } => racers.iter().any(|r| r.soc.qd == qd),
+            _ => false,
         };
+    }
 
-        let soc: Socket = data
-            .get_or_schedule(|| (&mut self.soc, ()))
-            .unwrap_or(Err(PosixError::WOULDBLOCK))
-            .map(From::from)?;
-        if let Some(addr) = addr {
-            addr.write(soc.addr.unwrap());
+    /// returns and clears the pending error, to be used with SO_ERROR
+    pub fn take_error(&mut self) -> Option<PosixError> {
+        if let SocketData::Connecting { connect, .. } = &mut self.data
+            && connect.is_finished()
+        {
+            let _ = connect.get();
+            self.enter(Transition::ConnectFailed, SocketData::Idle { bound: true });
         }
+
+        return self.pending_error.take();
+    }
+
+    /// takes the oldest connection of the backlog, or polls the running accept if it is empty
+    ///
+    /// fails with EINVAL if the socket is not listening, also after it was shut down
+    pub fn accept(&mut self) -> PosixResult<Self> {
+        let (data, backlog) = match &mut self.data {
+            SocketData::Passive {
+                accept,
+                backlog,
+                stopped: false,
+                ..
+            } => (accept, backlog),
+            _ => return Err(PosixError::INVAL),
+        };
+
+        let mut soc: Socket = if let Some(acc) = backlog.pop_front() {
+            acc.into()
+        } else {
+            data.get_or_schedule(|| (&mut self.soc, ()))
+                .unwrap_or(Err(PosixError::WOULDBLOCK))
+                .inspect_err(|e| {
+                    if *e != PosixError::WOULDBLOCK {
+                        self.pending_error = None;
+                    }
+                })
+                .map(From::from)?
+        };
+        soc.auto_pop = self.auto_pop;
+        soc.priority = self.priority;
+        soc.set_buffers(self.sndbuf, self.rcvbuf);
         return Ok(soc);
     }
 
+    /// makes `accept` register the accepted sockets, `None` stops it
+    ///
+    /// fails with EINVAL if the socket is connecting or connected, it can be set up before listen
+    pub fn set_accept_autoreg(&mut self, autoreg: Option<AcceptAutoreg>) -> PosixResult<()> {
+        if !matches!(self.phase(), Phase::Unbound | Phase::Bound | Phase::Passive) {
+            return Err(PosixError::INVAL);
+        }
+
+        self.autoreg = autoreg;
+        return Ok(());
+    }
+
+    pub fn accept_autoreg(&self) -> Option<AcceptAutoreg> {
+        return self.autoreg;
+    }
+
+    /// the token of the running accept of a listener
+    pub fn accept_token(&self) -> Option<demi::QToken> {
+        return match &self.data {
+            SocketData::Passive { accept, .. } => accept.token(),
+            _ => None,
+        };
+    }
+
     pub fn write(&mut self, src: &[u8]) -> PosixResult<usize> {
         trace!("writing {} to {}", src.len(), self.soc.qd);
-        let res = self.write_impl(|| demi::SgArray::from_slice(src));
+        let pool = self.sga_pool;
+        let res = self.write_impl(src.len(), |off, len| {
+            demi::SgArray::from_slice(&src[off..off + len], pool)
+        });
         trace!("res: {res:?}, BRUH: {self:?}");
         return res;
     }
 
+    /// like `write`, any number of iovecs go into a single push as long as their bytes fit in one
     pub fn writev(&mut self, src: &[libc::iovec]) -> PosixResult<usize> {
-        return self.write_impl(|| demi::SgArray::from_slices(src));
+        let total = src.iter().map(|vec| vec.iov_len).sum();
+        let pool = self.sga_pool;
+        // the pushes take the bytes in order, the cursor never goes back
+        let mut src = demi::IovecCursor::new(src);
+        return self.write_impl(total, |off, len| {
+            src.seek(off);
+            demi::SgArray::from_iovecs(&mut src, len, pool)
+        });
     }
 
     pub fn read(&mut self, dst: &mut [MaybeUninit<u8>]) -> PosixResult<usize> {
         return self.read_impl(|it| it.copy_bytes(dst));
     }
 
+    /// unlike `read`, goes on with the data of the pops received after the first one while there
+    /// is room, so large iovec arrays are filled with everything received like the kernel does
     pub fn readv(&mut self, dst: &mut [libc::iovec]) -> PosixResult<usize> {
-        return self.read_impl(|it| it.copy_into_iovecs(dst));
+        let mut dst = demi::IovecCursor::new(dst);
+        let first = self.read_impl(|it| it.copy_into(&mut dst))?;
+        if first == 0 {
+            return Ok(0);
+        }
+
+        // a failed pop stays for the next read, only received data is taken
+        let mut total = first;
+        while !dst.is_end() && self.recv_buffered().0 > 0 {
+            total += self.consume_read(|it| it.copy_into(&mut dst))?;
+        }
+        if total > first
+            && self.auto_pop
+            && let Err(err) = self.schedule_read()
+        {
+            self.pending_error = Some(err.into());
+        }
+
+        return Ok(total);
+    }
+
+    /// like `read`, but only consumes data demikernel already delivered, it neither polls
+    /// demikernel nor starts a pop once the data is consumed
+    pub fn try_read(&mut self, dst: &mut [MaybeUninit<u8>]) -> PosixResult<usize> {
+        return self.consume_read(|it| it.copy_bytes(dst));
+    }
+
+    /// like `write`, but does not poll demikernel for completed pushes when the send queue is
+    /// full, so only completions already seen by a pwait make room
+    pub fn try_write(&mut self, src: &[u8]) -> PosixResult<usize> {
+        let pool = self.sga_pool;
+        return self.push_writes(src.len(), |off, len| {
+            demi::SgArray::from_slice(&src[off..off + len], pool)
+        });
     }
 
-    pub fn close(&mut self) {
-        assert!(self.open);
+    /// cancels the running accept, connects and pop, pushes are left to complete as their data was
+    /// already written
+    ///
+    /// the completions of the cancelled operations are dropped when they arrive, either through a
+    /// dpoll or `reap_tombstones`
+    pub fn cancel_operations(&mut self) {
+        let qd = self.soc.qd;
+        let cancelled = match &mut self.data {
+            SocketData::Passive { accept, .. } => vec![accept.cancel()],
+            SocketData::Connecting { connect, racers } => {
+                racers.drain(..).for_each(Racer::close);
+                vec![connect.cancel()]
+            }
+            SocketData::Active { read, .. } => vec![read.cancel()],
+            SocketData::Idle { .. } | SocketData::Closing => vec![],
+        };
+        for tombstone in cancelled.into_iter().flatten() {
+            trace!("soc {qd} cancelled {}", tombstone.token());
+            self.tombstones.push(tombstone);
+        }
+    }
+
+    /// like with the kernel, shutting a listener down for reading stops it accepting, which lets a
+    /// server drain the connections it accepted before closing: the running accept is cancelled,
+    /// the connections completed but not accepted yet are closed and the listener is not reported
+    /// as readable anymore, while the accepted connections are left as they are
+    ///
+    /// demikernel cannot stop listening without closing the queue, so connections arriving after
+    /// wait in its backlog until the socket listens again, which resumes accepting, or is closed
+    ///
+    /// SHUT_WR alone leaves a listener as it is, sockets that are not connected fail with ENOTCONN
+    /// and connected ones with EOPNOTSUPP, demikernel cannot half close a connection
+    pub fn shutdown(&mut self, how: c_int) -> PosixResult<()> {
+        if !matches!(how, SHUT_RD | SHUT_WR | SHUT_RDWR) {
+            return Err(PosixError::INVAL);
+        }
+
+        let qd = self.soc.qd;
+        match &mut self.data {
+            SocketData::Passive { .. } if how == SHUT_WR => return Ok(()),
+            SocketData::Passive { stopped, .. } => {
+                trace!("soc {qd} stops accepting");
+                *stopped = true;
+                self.data.close_backlog(qd);
+            }
+            SocketData::Active { .. } => return Err(PosixError::OPNOTSUPP),
+            SocketData::Idle { .. } | SocketData::Connecting { .. } => {
+                return Err(PosixError::NOTCONN);
+            }
+            SocketData::Closing => return Err(PosixError::BADF),
+        }
+
+        self.cancel_operations();
+        notify(&self.watchers);
+        return Ok(());
+    }
+
+    /// drops the completions of cancelled operations that arrived, returns whether any are left
+    pub fn reap_tombstones(&mut self) -> bool {
+        let mut idx = 0;
+        while idx < self.tombstones.len() {
+            match self.tombstones[idx].poll() {
+                Some(val) => self.tombstones.swap_remove(idx).bury(val),
+                None => idx += 1,
+            }
+        }
+        return !self.tombstones.is_empty();
+    }
+
+    /// the running operations become tombstones, see `cancel_operations`, and the connections a
+    /// listener completed that were not accepted are closed
+    pub fn close(&mut self) -> DpollResult<()> {
+        self.phase().transition(Transition::Close)?;
         //self.data.flush();
-        self.soc.close().unwrap();
-        self.open = false;
+        let qd = self.soc.qd;
+        match &mut self.data {
+            SocketData::Passive { .. } => self.data.close_backlog(qd),
+            SocketData::Active { writes, .. } => self.tombstones.extend(writes.cancel_all()),
+            _ => {}
+        }
+        self.cancel_operations();
+        self.enter(Transition::Close, SocketData::Closing);
+        return self.soc.close().map_err(|err| DpollError::Demi {
+            op: "close",
+            qd: self.soc.qd,
+            err,
+        });
+    }
+
+    /// blocks until the queued pushes completed or `deadline` passed, returns the bytes left
+    /// unflushed
+    ///
+    /// a socket that is not connected yet has nothing queued, a listening one fails with EINVAL
+    pub fn flush(&mut self, deadline: Deadline) -> PosixResult<usize> {
+        let writes = match &mut self.data {
+            SocketData::Active { writes, .. } => writes,
+            SocketData::Idle { .. } | SocketData::Connecting { .. } => return Ok(0),
+            SocketData::Passive { .. } => return Err(PosixError::INVAL),
+            SocketData::Closing => return Err(PosixError::BADF),
+        };
+
+        let had_capacity = writes.has_capacity();
+        let res = writes.flush_until(deadline);
+        if !had_capacity && writes.has_capacity() {
+            notify(&self.watchers);
+        }
+        if let Err(e) = res {
+            self.pending_error = None;
+            self.note_failure(e);
+        }
+        return res;
+    }
+
+    /// hands the qd of a connected socket over to the application, which pushes and pops on it
+    /// directly until `attach`, for TLS libraries doing the io of their handshake themselves
+    ///
+    /// from the first call on the socket starts no pops, writes fail with EBUSY and only the
+    /// received bytes left are reported, they come before the ones popped directly and are read as
+    /// usual. fails with EBUSY while pushes or a pop are still running, the call is repeated once
+    /// a pwait or read completed them
+    pub fn detach(&mut self) -> PosixResult<demi::DemiQd> {
+        let (writes, read) = match &self.data {
+            SocketData::Active { writes, read } => (writes, read),
+            SocketData::Idle { .. } | SocketData::Connecting { .. } => {
+                return Err(PosixError::NOTCONN);
+            }
+            SocketData::Passive { .. } => return Err(PosixError::INVAL),
+            SocketData::Closing => return Err(PosixError::BADF),
+        };
+
+        let running = !writes.is_empty() || read.token().is_some();
+        if !self.detached {
+            trace!("soc {} detaching", self.soc.qd);
+            self.detached = true;
+            notify(&self.watchers);
+        }
+        if running {
+            return Err(PosixError::BUSY);
+        }
+
+        return Ok(self.soc.qd);
+    }
+
+    /// takes the socket back from the application, which must not have operations running on the
+    /// qd anymore, EINVAL if it was not detached
+    pub fn attach(&mut self) -> PosixResult<()> {
+        if !self.detached {
+            return Err(PosixError::INVAL);
+        }
+
+        trace!("soc {} attached", self.soc.qd);
+        self.detached = false;
+        self.last_activity = clock::now();
+        notify(&self.watchers);
+        return Ok(());
+    }
+
+    #[allow(dead_code)]
+    pub fn is_detached(&self) -> bool {
+        return self.detached;
+    }
+
+    /// limits writes to `rate` bytes per second with bursts of up to `burst` bytes, a `rate` of
+    /// 0 removes the limit
+    pub fn set_rate(&mut self, rate: u64, burst: u64) -> PosixResult<()> {
+        if rate == 0 {
+            self.pacer = None;
+            return Ok(());
+        }
+        if burst == 0 {
+            return Err(PosixError::INVAL);
+        }
+
+        self.pacer = Some(Pacer::new(rate, burst));
+        return Ok(());
+    }
+
+    /// the value of an integer socket option, NOPROTOOPT for unsupported ones
+    pub fn get_option(&self, level: c_int, optname: c_int) -> PosixResult<c_int> {
+        let ka = &self.keepalive;
+        let val = match (level, optname) {
+            (SOL_SOCKET, SO_KEEPALIVE) => ka.enabled as u64,
+            (IPPROTO_TCP, TCP_KEEPIDLE) => ka.idle.as_secs(),
+            (IPPROTO_TCP, TCP_KEEPINTVL) => ka.interval.as_secs(),
+            (IPPROTO_TCP, TCP_KEEPCNT) => ka.count as u64,
+            (SOL_DPOLL, DPOLL_SO_AUTOPOP) => self.auto_pop as u64,
+            (SOL_SOCKET, SO_PRIORITY) => self.priority as u64,
+            (SOL_SOCKET, SO_SNDBUF) => self.sndbuf as u64,
+            (SOL_SOCKET, SO_RCVBUF) => self.rcvbuf as u64,
+            _ => return Err(PosixError::NOPROTOOPT),
+        };
+
+        return Ok(val as c_int);
+    }
+
+    /// sets an integer socket option, NOPROTOOPT for unsupported ones
+    pub fn set_option(&mut self, level: c_int, optname: c_int, val: c_int) -> PosixResult<()> {
+        let ka = &mut self.keepalive;
+        match (level, optname) {
+            (SOL_SOCKET, SO_KEEPALIVE) => ka.enabled = val != 0,
+            (IPPROTO_TCP, TCP_KEEPIDLE | TCP_KEEPINTVL | TCP_KEEPCNT) if val < 1 => {
+                return Err(PosixError::INVAL);
+            }
+            (IPPROTO_TCP, TCP_KEEPIDLE) => ka.idle = Duration::from_secs(val as u64),
+            (IPPROTO_TCP, TCP_KEEPINTVL) => ka.interval = Duration::from_secs(val as u64),
+            (IPPROTO_TCP, TCP_KEEPCNT) => ka.count = val as u32,
+            (SOL_DPOLL, DPOLL_SO_AUTOPOP) => self.auto_pop = val != 0,
+            // like for an unprivileged process
+            (SOL_SOCKET, SO_PRIORITY) if !(0..=MAX_PRIORITY).contains(&val) => {
+                return Err(PosixError::PERM);
+            }
+            (SOL_SOCKET, SO_PRIORITY) => self.priority = val as u8,
+            // like the kernel, which doubles the value for its bookkeeping and reports it doubled,
+            // negative values count as huge ones
+            (SOL_SOCKET, SO_SNDBUF) => {
+                self.set_buffers(doubled(val).max(MIN_SNDBUF), self.rcvbuf);
+            }
+            (SOL_SOCKET, SO_RCVBUF) => {
+                self.set_buffers(self.sndbuf, doubled(val).max(MIN_RCVBUF));
+            }
+            _ => return Err(PosixError::NOPROTOOPT),
+        }
+
+        // like with the kernel, the idle time starts over
+        self.last_activity = clock::now();
+        return Ok(());
+    }
+
+    /// applies SO_SNDBUF and SO_RCVBUF, right away to the queues of a connected socket, whose
+    /// writes or pops might resume
+    fn set_buffers(&mut self, sndbuf: usize, rcvbuf: usize) {
+        self.sndbuf = sndbuf;
+        self.rcvbuf = rcvbuf;
+        if let SocketData::Active { writes, read } = &mut self.data {
+            writes.set_max_bytes(sndbuf);
+            read.set_high_water(rcvbuf);
+            notify(&self.watchers);
+        }
+    }
+
+    /// SO_PRIORITY
+    #[inline]
+    pub fn priority(&self) -> u8 {
+        return self.priority;
+    }
+
+    /// records TIMEDOUT as the pending error if the keepalive of an active socket ran out
+    ///
+    /// returns the time left until it runs out otherwise
+    pub fn check_keepalive(&mut self, now: Instant) -> Option<Duration> {
+        // the application does the io of a detached socket, which keepalive does not see
+        if !matches!(self.data, SocketData::Active { .. })
+            || self.pending_error.is_some()
+            || self.detached
+        {
+            return None;
+        }
+
+        let remaining = self.keepalive.remaining(self.last_activity, now)?;
+        if !remaining.is_zero() {
+            return Some(remaining);
+        }
+
+        trace!(
+            "soc {} is idle for {:?}, timing out",
+            self.soc.qd,
+            self.keepalive.timeout()
+        );
+        self.pending_error = Some(PosixError::TIMEDOUT);
+        notify(&self.watchers);
+        return None;
+    }
+
+    /// warns about the operations running for longer than the threshold of `watchdog`, failing
+    /// them with TIMEDOUT if it says so
+    ///
+    /// returns the time until the next running operation gets stuck
+    pub fn check_watchdog(&mut self, now: Instant, watchdog: &Watchdog) -> Option<Duration> {
+        watchdog.threshold?;
+        let running: Vec<(&str, demi::QToken, Instant)> = match &self.data {
+            SocketData::Passive { accept, .. } => Vec::from_iter(
+                accept
+                    .running_since()
+                    .map(|(tok, since)| ("accept", tok, since)),
+            ),
+            SocketData::Connecting { connect, .. } => Vec::from_iter(
+                connect
+                    .running_since()
+                    .map(|(tok, since)| ("connect", tok, since)),
+            ),
+            SocketData::Active { writes, read } => writes
+                .running_since()
+                .map(|(tok, since)| ("push", tok, since))
+                .chain(read.running_since().map(|(tok, since)| ("pop", tok, since)))
+                .collect(),
+            SocketData::Idle { .. } | SocketData::Closing => Vec::new(),
+        };
+        self.stuck
+            .retain(|tok| running.iter().any(|(_, t, _)| t == tok));
+
+        let mut next: Option<Duration> = None;
+        for (op, tok, since) in running {
+            let remaining = watchdog.remaining(since, now)?;
+            if !remaining.is_zero() {
+                next = Some(next.map_or(remaining, |next| next.min(remaining)));
+                continue;
+            }
+
+            let qd = self.soc.qd;
+            if !self.stuck.contains(&tok) {
+                let running_for = now.saturating_duration_since(since);
+                warn!("stuck operation: qd={qd} op={op} qt={tok} running_for={running_for:?}");
+                self.stuck.push(tok);
+            }
+            if watchdog.fail {
+                self.time_out(tok);
+            }
+        }
+
+        return next;
+    }
+
+    /// fails the operation running `tok` with TIMEDOUT and records it as the pending error, its
+    /// completion is dropped when it arrives
+    fn time_out(&mut self, tok: demi::QToken) {
+        let tombstone = match &mut self.data {
+            SocketData::Passive { accept, .. } => accept.time_out(),
+            SocketData::Connecting { connect, .. } => connect.time_out(),
+            SocketData::Active { read, .. } if read.token() == Some(tok) => read.time_out(),
+            SocketData::Active { writes, .. } => writes.time_out(tok),
+            SocketData::Idle { .. } | SocketData::Closing => None,
+        };
+        let Some(tombstone) = tombstone else {
+            return;
+        };
+
+        trace!("soc {} timed out {tok}", self.soc.qd);
+        self.stuck.retain(|t| *t != tok);
+        self.tombstones.push(tombstone);
+        self.pending_error = Some(PosixError::TIMEDOUT);
+        notify(&self.watchers);
+    }
+
+    /// the time until a paced socket can write again, `None` if it is not waiting on its pacer
+    pub fn pacing_delay(&self) -> Option<Duration> {
+        return match (&self.data, &self.pacer) {
+            (SocketData::Active { writes, .. }, Some(pacer)) if writes.has_capacity() => {
+                pacer.delay(clock::now())
+            }
+            _ => None,
+        };
+    }
+
+    pub fn watch(&mut self, waker: Waker) {
+        self.watchers.push(waker);
+    }
+
+    pub fn unwatch(&mut self, waker: &Waker) {
+        self.watchers.retain(|w| !w.ptr_eq(waker));
+    }
+
+    /// whether the socket can move to another thread, fails with EBUSY while it is registered in a
+    /// dpoll or auto-registers accepted sockets, as both are specific to the current thread
+    ///
+    /// running operations move with the socket, except with the reactor feature, whose completions
+    /// go to the thread that waited on them
+    pub fn check_movable(&self) -> PosixResult<()> {
+        if !self.watchers.is_empty() || self.autoreg.is_some() {
+            return Err(PosixError::BUSY);
+        }
+        #[cfg(feature = "reactor")]
+        if self.running_operations() > 0 || !self.tombstones.is_empty() {
+            return Err(PosixError::BUSY);
+        }
+
+        return Ok(());
+    }
+
+    pub fn operation_states(&self) -> OperationStates {
+        let mut states = OperationStates::default();
+        match &self.data {
+            SocketData::Passive { accept, .. } => states.accept = accept.state(),
+            SocketData::Connecting { connect, .. } => states.connect = connect.state(),
+            SocketData::Active { writes, read } => {
+                states.read = read.state();
+                if !writes.is_empty() {
+                    states.write = operation::State::Running;
+                }
+            }
+            SocketData::Idle { .. } | SocketData::Closing => {}
+        }
+
+        return states;
+    }
+
+    /// the number of operations submitted to demikernel and not completed yet
+    pub fn running_operations(&self) -> usize {
+        let states = self.operation_states();
+        return [states.accept, states.connect, states.read, states.write]
+            .into_iter()
+            .filter(|s| *s == operation::State::Running)
+            .count();
     }
 
     pub fn available_events(&self, evs: Event) -> Event {
         let other = match &self.data {
-            SocketData::Passive { accept } => {
-                if accept.is_finished() {
+            // level triggered, IN stays asserted until the backlog is drained
+            SocketData::Passive {
+                accept,
+                backlog,
+                stopped,
+                ..
+            } => {
+                if !*stopped && (accept.is_finished() || !backlog.is_empty()) {
                     Event::IN
                 } else {
                     Event::empty()
                 }
             }
-            SocketData::Active { write, read } => {
-                let write = if !write.is_running() {
+            SocketData::Connecting { connect, .. } => {
+                if connect.is_finished() {
+                    Event::OUT
+                } else {
+                    Event::empty()
+                }
+            }
+            SocketData::Active { writes, read } => {
+                let paced = self
+                    .pacer
+                    .as_ref()
+                    .is_some_and(|p| p.available(clock::now()) == 0);
+                let write = if writes.has_capacity() && !paced && !self.detached {
                     Event::OUT
                 } else {
                     Event::empty()
                 };
-                let read = if read.is_finished() {
+                let read = if read.is_readable() {
                     Event::IN
                 } else {
                     Event::empty()
                 };
                 write.union(read)
             }
+            SocketData::Idle { .. } | SocketData::Closing => Event::empty(),
+        };
+        let err = if self.pending_error.is_some() {
+            Event::ERR
+        } else {
+            Event::empty()
         };
-        return evs.intersection(other);
+
+        // like with epoll, errors are reported regardless of the requested events
+        return evs.union(Event::ERR).intersection(other.union(err));
     }
 
-    pub fn schedule_events(&mut self, evs: Event, qtoks: &mut Vec<demi::QToken>) {
+    pub fn schedule_events(
+        &mut self,
+        evs: Event,
+        qtoks: &mut Vec<demi::QToken>,
+    ) -> DpollResult<()> {
+        let qd = self.soc.qd;
+        // cancelled operations are waited on regardless of the events, to drop their completions
+        qtoks.extend(self.tombstones.iter().map(Tombstone::token));
         match &mut self.data {
-            SocketData::Passive { accept } => {
-                if evs.intersects(Event::IN) {
-                    let tok = match accept {
-                        Operation::None => {
-                            let tok = self.soc.accept().unwrap();
-                            accept.start(tok, ());
-                            tok
-                        }
-                        Operation::Running { tok, .. } => *tok,
-                        Operation::Completed(_) => unreachable!(),
-                    };
-                    qtoks.push(tok);
+            SocketData::Passive {
+                accept,
+                backlog,
+                max_backlog,
+                stopped,
+            } => {
+                // IN is not asked for while the backlog is ready, keep accepting regardless
+                if !*stopped
+                    && (evs.intersects(Event::IN) || !backlog.is_empty())
+                    && backlog.len() < *max_backlog
+                {
+                    if accept.is_none() {
+                        let tok = self.soc.accept().map_err(|err| DpollError::Demi {
+                            op: "accept",
+                            qd,
+                            err,
+                        })?;
+                        accept.start(tok, ());
+                    }
+                    qtoks.extend(accept.token());
                 }
             }
-            SocketData::Active { write, read } => {
+            SocketData::Connecting { connect, racers } => {
+                // the connects are always pending, regardless of the events
+                qtoks.extend(connect.token());
+                qtoks.extend(racers.iter().filter_map(|r| r.connect.token()));
+            }
+            SocketData::Active { writes, read } => {
                 if evs.intersects(Event::IN) {
-                    let tok = match read {
-                        Operation::Running { tok, .. } => *tok,
-                        Operation::None => {
-                            let tok = self.soc.pop().unwrap();
-                            read.start(tok, ());
-                            tok
-                        }
-                        Operation::Completed(_) => unreachable!(),
-                    };
-                    qtoks.push(tok);
+                    // a pop started by a read is still waited on
+                    if read.can_pop() && self.auto_pop && !self.detached {
+                        let tok = self.soc.pop().map_err(|err| DpollError::Demi {
+                            op: "pop",
+                            qd,
+                            err,
+                        })?;
+                        read.start(tok);
+                    }
+                    qtoks.extend(read.token());
                 }
 
                 // always schedule pending writes
-                match write {
-                    Operation::Running { tok, .. } => qtoks.push(*tok),
-                    _ if evs.intersects(Event::OUT) => unreachable!(),
-                    _ => {}
-                }
+                qtoks.extend(writes.toks());
             }
+            SocketData::Idle { .. } | SocketData::Closing => {}
         };
+
+        return Ok(());
     }
 
-    pub fn process_event(&mut self, val: QResultValue) {
+    pub fn process_event(&mut self, tok: demi::QToken, val: PosixResult<QResultValue>) {
         trace!("soc {} new event: {val:?}", self.soc.qd);
+        self.last_activity = clock::now();
+        // the socket is left as it was, but the application learns about it through SO_ERROR
+        if let Err(err) = self.process_event_impl(tok, val) {
+            self.pending_error = Some(err.into());
+        }
+        notify(&self.watchers);
+    }
+
+    fn process_event_impl(
+        &mut self,
+        tok: demi::QToken,
+        val: PosixResult<QResultValue>,
+    ) -> DpollResult<()> {
+        if let Some(idx) = self.tombstones.iter().position(|t| t.token() == tok) {
+            self.tombstones.swap_remove(idx).bury(val);
+            return Ok(());
+        }
+
+        if let SocketData::Connecting { .. } = self.data {
+            return self.process_connect(tok, val);
+        }
+
+        let val = match val {
+            Ok(val) => val,
+            Err(e) => return self.fail(tok, e),
+        };
+
+        let qd = self.soc.qd;
+        let got = val.kind();
+        let unexpected = |state| DpollError::UnexpectedCompletion { qd, got, state };
         match &mut self.data {
-            SocketData::Passive { accept } => {
-                if let QResultValue::Accept(acc) = val {
-                    accept.complete(Ok(acc));
-                } else {
-                    panic!("cannot perform anything but accept on a passive socket");
-                }
+            SocketData::Passive {
+                accept, backlog, ..
+            } => {
+                let QResultValue::Accept(acc) = val else {
+                    return Err(unexpected("listening"));
+                };
+                accept.complete(Ok(acc));
+                backlog.extend(accept.get().ok());
             }
 
-            SocketData::Active { write, read } => match val {
-                QResultValue::Push => write.complete(Ok(())),
-                QResultValue::Pop(sga) => read.complete(Ok(sga.into_iter())),
-                _ => panic!(),
+            SocketData::Connecting { .. } => unreachable!(),
+
+            SocketData::Active { writes, read } => match val {
+                QResultValue::Push if writes.complete(tok) => {}
+                QResultValue::Push => return Err(DpollError::UnknownCompletion { qd, qt: tok }),
+                QResultValue::Pop(sga) if read.token() == Some(tok) => {
+                    read.complete(sga.into_iter())
+                }
+                QResultValue::Pop(_) => return Err(DpollError::UnknownCompletion { qd, qt: tok }),
+                _ => return Err(unexpected("connected")),
             },
+
+            SocketData::Idle { .. } => return Err(unexpected("idle")),
+            SocketData::Closing => return Err(unexpected("closing")),
+        }
+
+        return Ok(());
+    }
+
+    fn process_connect(
+        &mut self,
+        tok: demi::QToken,
+        val: PosixResult<QResultValue>,
+    ) -> DpollResult<()> {
+        let SocketData::Connecting { connect, racers } = &mut self.data else {
+            unreachable!();
+        };
+        if let Ok(val) = &val
+            && !matches!(val, QResultValue::Connect)
+        {
+            return Err(DpollError::UnexpectedCompletion {
+                qd: self.soc.qd,
+                got: val.kind(),
+                state: "connecting",
+            });
+        }
+        let racer = racers.iter().position(|r| r.connect.token() == Some(tok));
+
+        match (racer, val) {
+            (None, Ok(_)) => {
+                racers.drain(..).for_each(Racer::close);
+                self.enter(
+                    Transition::Connected,
+                    SocketData::new_active(self.sndbuf, self.rcvbuf),
+                );
+            }
+            (Some(idx), Ok(_)) => {
+                trace!(
+                    "soc {} lost the connect race to {}",
+                    self.soc.qd, racers[idx].soc.qd
+                );
+                let mut winner = racers.remove(idx);
+                racers.drain(..).for_each(Racer::close);
+                mem::swap(&mut self.soc, &mut winner.soc);
+                winner.close();
+                self.enter(
+                    Transition::Connected,
+                    SocketData::new_active(self.sndbuf, self.rcvbuf),
+                );
+            }
+            (Some(idx), Err(e)) => {
+                trace!("racer {} failed with {e:?}", racers[idx].soc.qd);
+                racers.remove(idx).close();
+            }
+            (None, Err(e)) if !racers.is_empty() => {
+                trace!(
+                    "soc {} failed with {e:?}, the next racer takes over",
+                    self.soc.qd
+                );
+                let mut next = racers.remove(0);
+                mem::swap(&mut self.soc, &mut next.soc);
+                mem::swap(connect, &mut next.connect);
+                next.close();
+            }
+            (None, Err(e)) => return self.fail(tok, e),
         }
+
+        return Ok(());
+    }
+
+    /// completes the operation running `tok` with `err` and records it as the pending error
+    fn fail(&mut self, tok: demi::QToken, err: PosixError) -> DpollResult<()> {
+        let failed = match &mut self.data {
+            SocketData::Passive { accept, .. } => accept.fail(tok, err),
+            SocketData::Connecting { connect, .. } => connect.fail(tok, err),
+            SocketData::Active { writes, read } => writes.fail(tok, err) || read.fail(tok, err),
+            SocketData::Idle { .. } | SocketData::Closing => false,
+        };
+
+        if !failed {
+            return Err(DpollError::UnknownCompletion {
+                qd: self.soc.qd,
+                qt: tok,
+            });
+        }
+        self.pending_error = Some(err);
+        self.note_failure(err);
+        return Ok(());
+    }
+
+    /// records `err` of a failed push or pop as the end of the connection if it is one of the
+    /// errors demikernel ends a connection with, the writes after it fail right away
+    fn note_failure(&mut self, err: PosixError) {
+        let ends = matches!(
+            err,
+            PosixError::CONNRESET
+                | PosixError::CONNABORTED
+                | PosixError::PIPE
+                | PosixError::NOTCONN
+                | PosixError::TIMEDOUT
+        );
+        if !ends || self.broken.is_some() || !matches!(self.data, SocketData::Active { .. }) {
+            return;
+        }
+
+        trace!("soc {} lost its connection: {err:?}", self.soc.qd);
+        self.broken = Some(err);
     }
 
-    fn write_impl<F>(&mut self, func: F) -> PosixResult<usize>
+    /// pushes `total` bytes in chunks of at most `SgArray::MAX_LEN`, `chunk` gets the offset and
+    /// length of each
+    ///
+    /// returns the number of accepted bytes, which is less than `total` if the send queue filled
+    /// up or an allocation failed after the first chunk
+    fn write_impl<F>(&mut self, total: usize, chunk: F) -> PosixResult<usize>
     where
-        F: FnOnce() -> demi::SgArray,
+        F: FnMut(usize, usize) -> PosixResult<demi::SgArray>,
     {
-        let write = match &mut self.data {
-            SocketData::Active { write, .. } => write,
+        self.check_broken()?;
+        self.reap_writes()?;
+        return self.push_writes(total, chunk);
+    }
+
+    /// polls demikernel for pushes completed outside of any pwait if the send queue is full
+    fn reap_writes(&mut self) -> PosixResult<()> {
+        let writes = match &mut self.data {
+            SocketData::Active { writes, .. } => writes,
             _ => return Err(PosixError::INVAL),
         };
 
-        if !write.is_none() {
-            if write.poll() {
-                write.get().unwrap();
-            } else {
-                return Err(PosixError::WOULDBLOCK);
+        if writes.has_capacity() {
+            return Ok(());
+        }
+
+        let reaped = writes.reap();
+        if writes.has_capacity() {
+            notify(&self.watchers);
+        }
+        if let Err(e) = reaped {
+            self.pending_error = None;
+            self.note_failure(e);
+            return Err(e);
+        }
+
+        return Ok(());
+    }
+
+    /// like with the kernel, once the connection broke the first write fails with its error if
+    /// nothing took it yet and every write after it with EPIPE
+    fn check_broken(&mut self) -> PosixResult<()> {
+        if self.broken.is_none() {
+            return Ok(());
+        }
+        return Err(self.pending_error.take().unwrap_or(PosixError::PIPE));
+    }
+
+    /// pushes up to `total` bytes, as many as the send queue and the pacer allow
+    fn push_writes<F>(&mut self, total: usize, mut chunk: F) -> PosixResult<usize>
+    where
+        F: FnMut(usize, usize) -> PosixResult<demi::SgArray>,
+    {
+        self.check_broken()?;
+        let writes = match &mut self.data {
+            SocketData::Active { writes, .. } => writes,
+            _ => return Err(PosixError::INVAL),
+        };
+
+        if self.detached {
+            return Err(PosixError::BUSY);
+        }
+        if !writes.has_capacity() {
+            return Err(PosixError::WOULDBLOCK);
+        }
+
+        let now = clock::now();
+        let total = match &self.pacer {
+            Some(pacer) => {
+                let available = pacer.available(now).try_into().unwrap_or(usize::MAX);
+                if available == 0 && total > 0 {
+                    return Err(PosixError::WOULDBLOCK);
+                }
+                total.min(available)
+            }
+            None => total,
+        };
+
+        let mut written = 0;
+        while written < total && writes.has_capacity() {
+            let len = (total - written)
+                .min(demi::SgArray::MAX_LEN)
+                .min(writes.room());
+            let pushed = chunk(written, len).and_then(|sga| Ok((self.soc.push(&sga)?, sga)));
+
+            // under memory pressure only part of the chunk might have been allocated
+            let pushed_len = match pushed {
+                Ok((tok, sga)) => {
+                    let pushed_len = sga.len();
+                    writes.push(tok, sga);
+                    pushed_len
+                }
+                Err(e) if written == 0 => return Err(e),
+                Err(e) => {
+                    trace!("stopping a segmented write after {written} bytes: {e:?}");
+                    break;
+                }
+            };
+            written += pushed_len;
+
+            if pushed_len < len {
+                trace!("only {pushed_len} of {len} bytes allocated, stopping at {written}");
+                break;
             }
         }
 
-        let sga = func();
-        let len = sga.len();
-        write.start(self.soc.push(&sga).unwrap(), sga);
-        return Ok(len);
+        if let Some(pacer) = &mut self.pacer {
+            pacer.consume(now, written as u64);
+        }
+
+        return Ok(written);
     }
 
     fn read_impl<F>(&mut self, func: F) -> PosixResult<usize>
@@ -250,31 +1388,237 @@ impl Socket {
             _ => return Err(PosixError::INVAL),
         };
 
-        if !read.poll() {
-            read.start(self.soc.pop().unwrap(), ());
-            return Err(PosixError::WOULDBLOCK);
+        // polls a running pop, buffering its data if it completed
+        read.poll();
+        let res = self.consume_read(func);
+        let schedule = match res {
+            Ok(_) => self.auto_pop,
+            Err(e) => e == PosixError::WOULDBLOCK,
+        };
+        // the read itself went through, a failed pop is reported like any failed operation
+        if schedule && let Err(err) = self.schedule_read() {
+            self.pending_error = Some(err.into());
         }
-        let iter = read.get_mut().unwrap();
 
-        let len = func(iter);
+        return res;
+    }
+
+    /// consumes the data of completed pops, WOULDBLOCK if there is none
+    fn consume_read<F>(&mut self, func: F) -> PosixResult<usize>
+    where
+        F: FnOnce(&mut demi::SgArrayByteIter) -> Option<usize>,
+    {
+        let read = match &mut self.data {
+            SocketData::Active { read, .. } => read,
+            _ => return Err(PosixError::INVAL),
+        };
 
-        if iter.is_empty() {
-            let _ = read.get();
-            read.start(self.soc.pop().unwrap(), ());
+        let res = read.consume(func);
+        match res {
+            Ok(len) => {
+                self.last_activity = clock::now();
+                trace!("read {len} bytes");
+            }
+            Err(PosixError::WOULDBLOCK) => (),
+            Err(e) => {
+                self.pending_error = None;
+                self.note_failure(e);
+            }
         }
 
-        trace!("read {:?} bytes", len);
-        return len.ok_or(PosixError::WOULDBLOCK);
+        return res;
+    }
+
+    /// starts a pop unless one is running, too much data is buffered already or the socket is
+    /// detached
+    fn schedule_read(&mut self) -> DpollResult<()> {
+        if let SocketData::Active { read, .. } = &mut self.data
+            && read.can_pop()
+            && !self.detached
+        {
+            let tok = self.soc.pop().map_err(|err| DpollError::Demi {
+                op: "pop",
+                qd: self.soc.qd,
+                err,
+            })?;
+            read.start(tok);
+        }
+
+        return Ok(());
+    }
+
+    /// the received bytes not read yet and whether popping is stopped until they are drained
+    pub fn recv_buffered(&self) -> (usize, bool) {
+        return match &self.data {
+            SocketData::Active { read, .. } => (read.buffered(), read.is_stopped()),
+            _ => (0, false),
+        };
     }
 }
 
+/// pings every dpoll a socket is registered in, its events might have changed
+fn notify(watchers: &[Waker]) {
+    for waker in watchers {
+        waker.ping();
+    }
+}
+
+/// the SO_SNDBUF of new sockets, the bytes a full send queue of the largest pushes holds
+fn default_sndbuf() -> usize {
+    let depth = Config::current().send_queue_depth;
+    return depth
+        .saturating_mul(demi::SgArray::MAX_LEN)
+        .min(c_int::MAX as usize);
+}
+
+/// a SO_SNDBUF or SO_RCVBUF as the kernel keeps it, capped so it can be reported as a c_int
+fn doubled(val: c_int) -> usize {
+    return (val as u32).min(c_int::MAX as u32 / 2) as usize * 2;
+}
+
 impl std::convert::From<demi::AcceptResult> for Socket {
     fn from(value: demi::AcceptResult) -> Self {
         return Self {
             soc: value.qd,
             addr: Some(value.addr),
-            open: true,
-            data: SocketData::new_active(),
+            pending_error: None,
+            broken: None,
+            watchers: Vec::new(),
+            pacer: None,
+            keepalive: Config::current().keepalive,
+            auto_pop: Config::current().auto_pop,
+            detached: false,
+            sga_pool: Config::current().sga_pool,
+            priority: 0,
+            sndbuf: default_sndbuf(),
+            rcvbuf: Config::current().rcvbuf,
+            autoreg: None,
+            last_activity: clock::now(),
+            tombstones: Vec::new(),
+            stuck: Vec::new(),
+            data: SocketData::new_active(default_sndbuf(), Config::current().rcvbuf),
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[derive(Debug, Clone)]
+    enum Step {
+        StartPop,
+        /// completes the running pop with `len` bytes, 0 is the peer shutting down
+        Pop(usize),
+        Fail(PosixError),
+        /// reads `len` bytes, polling the running pop first if `poll`
+        Read {
+            len: usize,
+            poll: bool,
+        },
+    }
+
+    fn step() -> impl Strategy<Value = Step> {
+        let err = prop::sample::select(vec![PosixError::CONNRESET, PosixError::TIMEDOUT]);
+        return prop_oneof![
+            2 => Just(Step::StartPop),
+            2 => (1..24usize).prop_map(Step::Pop),
+            1 => Just(Step::Pop(0)),
+            1 => err.prop_map(Step::Fail),
+            3 => (0..24usize, any::<bool>()).prop_map(|(len, poll)| Step::Read { len, poll }),
+        ];
+    }
+
+    proptest! {
+        /// like with the kernel, reads return the data of the pops in order, then the error of a
+        /// failed pop once, and 0 at the end of the stream, which stays readable and is never
+        /// popped from again, EWOULDBLOCK is only returned while nothing is there to read and the
+        /// connection is still open
+        #[test]
+        fn read_until_eof(steps in prop::collection::vec(step(), 0..64)) {
+            let mut soc = Socket::from(demi::AcceptResult {
+                qd: demi::SocketQd::from(0),
+                addr: unsafe { mem::zeroed() },
+            });
+            soc.set_option(SOL_DPOLL, DPOLL_SO_AUTOPOP, 0).unwrap();
+            let mut bufs: Vec<Vec<Vec<u8>>> = Vec::new();
+            let mut next = 0u8;
+
+            // the bytes not read yet, the running pop, the error of the failed one and the end of
+            // the stream
+            let mut pending: VecDeque<u8> = VecDeque::new();
+            let mut running: Option<demi::QToken> = None;
+            let mut failed: Option<PosixError> = None;
+            let mut eof = false;
+            for (tok, step) in (1..).zip(steps) {
+                match (step, running) {
+                    (Step::StartPop, _) => {
+                        let started = soc.start_pop(tok);
+                        prop_assert_eq!(started, running.is_none() && failed.is_none() && !eof);
+                        if started {
+                            running = Some(tok);
+                        }
+                    }
+                    (Step::Pop(len), Some(tok)) => {
+                        let seg: Vec<u8> = (0..len)
+                            .map(|_| {
+                                next = next.wrapping_add(1);
+                                return next;
+                            })
+                            .collect();
+                        pending.extend(&seg);
+                        bufs.push(vec![seg]);
+                        let sga = demi::SgArray::from_segments(bufs.last_mut().unwrap());
+                        soc.process_event(tok, Ok(QResultValue::Pop(sga)));
+                        eof = len == 0;
+                        running = None;
+                    }
+                    (Step::Fail(err), Some(tok)) => {
+                        soc.process_event(tok, Err(err));
+                        failed = Some(err);
+                        running = None;
+                    }
+                    (Step::Read { len, poll }, _) => {
+                        let mut dst = vec![MaybeUninit::new(0u8); len];
+                        let readable = !pending.is_empty() || failed.is_some() || eof;
+                        // a read polls a running pop and starts one when it would block, which
+                        // only demikernel can do
+                        let res = if readable && running.is_none() && poll {
+                            soc.read(&mut dst)
+                        } else {
+                            soc.try_read(&mut dst)
+                        };
+                        if !pending.is_empty() {
+                            // a read does not go past the data of a single pop
+                            let read = res.expect("a read of buffered data failed");
+                            let most = len.min(pending.len());
+                            prop_assert!(read <= most, "read {} of {}", read, len);
+                            prop_assert!(read > 0 || len == 0, "read nothing of {}", len);
+                        } else {
+                            let want = if let Some(err) = failed.take() {
+                                Err(err)
+                            } else if eof {
+                                Ok(0)
+                            } else {
+                                Err(PosixError::WOULDBLOCK)
+                            };
+                            prop_assert_eq!(res, want, "read of {}", len);
+                        }
+                        let read: Vec<u8> = pending.drain(..res.unwrap_or(0)).collect();
+                        let got: Vec<u8> = dst[..read.len()]
+                            .iter()
+                            .map(|b| unsafe { b.assume_init() })
+                            .collect();
+                        prop_assert_eq!(got, read, "the data came out of order");
+                    }
+                    // nothing is running to complete
+                    _ => {}
+                }
+
+                let readable = !pending.is_empty() || failed.is_some() || eof;
+                prop_assert_eq!(soc.available_events(Event::IN).contains(Event::IN), readable);
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! `DemiStream`, a dpoll socket behind `std::io::Read` and `std::io::Write` for Rust libraries
+//! doing their io through them, like the `Connection` of rustls with `read_tls` and `write_tls`
+//!
+//! streams are non-blocking like the sockets, calls that would block fail with
+//! `io::ErrorKind::WouldBlock` and are repeated once a dpoll reports the fd ready, see
+//! `examples/tls_echo.rs`
+
+use std::{
+    io::{self, IoSlice, IoSliceMut, Read, Write},
+    mem::{self, MaybeUninit},
+};
+
+use libc::{c_int, iovec};
+
+use crate::{
+    bindings::{self, dpoll_close},
+    wrappers::deadline::Deadline,
+};
+
+/// owns a dpoll socket, which is closed when the stream is dropped
+#[derive(Debug)]
+pub struct DemiStream {
+    fd: c_int,
+}
+
+impl DemiStream {
+    /// takes over the dpoll socket `fd`, e.g. one returned by `dpoll_accept`, EBADF for dpoll
+    /// instances and EOPNOTSUPP for kernel fds
+    pub fn from_fd(fd: c_int) -> io::Result<Self> {
+        bindings::with_socket_fd(fd, "stream", |_| Ok(()))?;
+        return Ok(Self { fd });
+    }
+
+    /// the fd of the socket, to register it in a dpoll or to pass it to the C ABI
+    pub fn fd(&self) -> c_int {
+        return self.fd;
+    }
+
+    /// gives the socket back without closing it
+    pub fn into_fd(self) -> c_int {
+        let fd = self.fd;
+        mem::forget(self);
+        return fd;
+    }
+}
+
+impl Read for DemiStream {
+    /// like `dpoll_read`, `Ok(0)` once the peer shut its side down and everything was read
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // only initialized bytes are written to the buffer
+        let buf = unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+        return Ok(bindings::with_socket_fd(self.fd, "stream_read", |soc| {
+            return soc.read(buf);
+        })?);
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        // `IoSliceMut` is an iovec on unix
+        let bufs = unsafe { &mut *(bufs as *mut [IoSliceMut<'_>] as *mut [iovec]) };
+        return Ok(bindings::with_socket_fd(self.fd, "stream_readv", |soc| {
+            return soc.readv(bufs);
+        })?);
+    }
+}
+
+impl Write for DemiStream {
+    /// like `dpoll_write`, which takes as many bytes as the send queue has room for
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        return Ok(bindings::with_socket_fd(self.fd, "stream_write", |soc| {
+            return soc.write(buf);
+        })?);
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        // `IoSlice` is an iovec on unix
+        let bufs = unsafe { &*(bufs as *const [IoSlice<'_>] as *const [iovec]) };
+        return Ok(bindings::with_socket_fd(self.fd, "stream_writev", |soc| {
+            return soc.writev(bufs);
+        })?);
+    }
+
+    /// polls the queued pushes without blocking, `WouldBlock` while some did not complete
+    fn flush(&mut self) -> io::Result<()> {
+        let left = bindings::with_socket_fd(self.fd, "stream_flush", |soc| {
+            return soc.flush(Deadline::now());
+        })?;
+        if left > 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        return Ok(());
+    }
+}
+
+impl Drop for DemiStream {
+    fn drop(&mut self) {
+        dpoll_close(self.fd);
+    }
+}
@@ -0,0 +1,30 @@
+use std::time::{Duration, Instant};
+
+/// watches for demikernel operations that never complete, which otherwise hang their socket
+/// without any trace of what it waits on
+///
+/// an operation running for longer than `threshold` is logged once as a warning with its qd,
+/// opcode and token, and with `fail` it is also failed with ETIMEDOUT, reported like any other
+/// failed operation
+#[derive(Debug, Clone, Copy)]
+pub struct Watchdog {
+    /// `None` disables the watchdog
+    pub threshold: Option<Duration>,
+    pub fail: bool,
+}
+
+impl Watchdog {
+    pub const fn new() -> Self {
+        return Self {
+            threshold: None,
+            fail: false,
+        };
+    }
+
+    /// the time left until an operation started at `since` is stuck, `None` if the watchdog is
+    /// disabled
+    pub fn remaining(&self, since: Instant, now: Instant) -> Option<Duration> {
+        let threshold = self.threshold?;
+        return Some((since + threshold).saturating_duration_since(now));
+    }
+}
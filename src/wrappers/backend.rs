@@ -0,0 +1,94 @@
+use std::{env, sync::OnceLock};
+
+use bitflags::bitflags;
+use log::{trace, warn};
+
+/// the demikernel libOS in use, the default is picked by the cargo features and can be
+/// overridden at runtime with `DPOLL_LIBOS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// kernel sockets, useful for local testing
+    Catnap,
+    /// DPDK
+    Catnip,
+    /// raw sockets
+    Catpowder,
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Capabilities: u32 {
+        const CONNECT = 1 << 0;
+        const KERNEL_BYPASS = 1 << 1;
+        const ZERO_COPY = 1 << 2;
+        /// listeners can share an address with demikernel spreading the connections over them,
+        /// like SO_REUSEPORT, no libOS supports it yet
+        const REUSEPORT = 1 << 3;
+    }
+}
+
+static BACKEND: OnceLock<Backend> = OnceLock::new();
+
+impl Backend {
+    /// the name demikernel expects in `LIBOS`
+    pub const fn name(self) -> &'static str {
+        return match self {
+            Self::Catnap => "catnap",
+            Self::Catnip => "catnip",
+            Self::Catpowder => "catpowder",
+        };
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        return match name {
+            "catnap" => Some(Self::Catnap),
+            "catnip" => Some(Self::Catnip),
+            "catpowder" => Some(Self::Catpowder),
+            _ => None,
+        };
+    }
+
+    pub const fn capabilities(self) -> Capabilities {
+        return match self {
+            Self::Catnap => Capabilities::CONNECT,
+            Self::Catnip => Capabilities::CONNECT
+                .union(Capabilities::KERNEL_BYPASS)
+                .union(Capabilities::ZERO_COPY),
+            Self::Catpowder => Capabilities::CONNECT.union(Capabilities::KERNEL_BYPASS),
+        };
+    }
+
+    /// the backend chosen at build time
+    pub const fn compiled() -> Self {
+        if cfg!(feature = "catnip") {
+            return Self::Catnip;
+        } else if cfg!(feature = "catpowder") {
+            return Self::Catpowder;
+        } else {
+            return Self::Catnap;
+        }
+    }
+
+    /// `DPOLL_LIBOS` if set to a valid backend, the compiled one otherwise
+    pub fn from_env() -> Self {
+        let Ok(name) = env::var("DPOLL_LIBOS") else {
+            return Self::compiled();
+        };
+
+        return Self::from_name(&name).unwrap_or_else(|| {
+            warn!("unknown DPOLL_LIBOS {name:?}, using {:?}", Self::compiled());
+            Self::compiled()
+        });
+    }
+
+    /// records the backend passed to demikernel, can only be done once
+    pub fn select(self) {
+        trace!("selecting the {self:?} backend");
+        assert_eq!(*BACKEND.get_or_init(|| self), self);
+    }
+
+    /// the selected backend, or the compiled one before demikernel is initialized
+    pub fn current() -> Self {
+        return BACKEND.get().copied().unwrap_or(Self::compiled());
+    }
+}
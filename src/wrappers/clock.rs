@@ -0,0 +1,77 @@
+//! the time source of deadlines, socket timers and the scheduling of pwait
+//!
+//! it is always the monotonic clock, except that tests can install a `Clock` for the current
+//! thread, so timeouts can be driven without sleeping
+
+use std::time::Instant;
+
+#[cfg(test)]
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// the clock used outside of the tests
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Monotonic;
+
+impl Clock for Monotonic {
+    fn now(&self) -> Instant {
+        return Instant::now();
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static INSTALLED: RefCell<Option<Box<dyn Clock>>> = const { RefCell::new(None) };
+}
+
+/// the current time of the installed clock
+#[cfg(not(test))]
+#[inline]
+pub fn now() -> Instant {
+    return Monotonic.now();
+}
+
+/// the current time of the installed clock
+#[cfg(test)]
+pub fn now() -> Instant {
+    return INSTALLED.with_borrow(|clock| match clock {
+        Some(clock) => clock.now(),
+        None => Monotonic.now(),
+    });
+}
+
+/// makes `now` use `clock` on the current thread, `None` goes back to the monotonic clock
+#[cfg(test)]
+pub fn install(clock: Option<Box<dyn Clock>>) {
+    INSTALLED.set(clock);
+}
+
+/// a clock that stands still until it is advanced, clones share the time
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Rc<RefCell<Instant>>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new() -> Self {
+        return Self {
+            now: Rc::new(RefCell::new(Instant::now())),
+        };
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.borrow_mut() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        return *self.now.borrow();
+    }
+}
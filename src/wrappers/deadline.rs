@@ -0,0 +1,141 @@
+use std::time::{Duration, Instant};
+
+use super::clock;
+
+/// the point in time a blocking call has to return by
+///
+/// taken once when the call is entered, so every phase of the call only waits for what is left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline {
+    /// `None` means the call may block forever
+    at: Option<Instant>,
+}
+
+impl Deadline {
+    /// `timeout` from now, `None` blocks forever
+    pub fn after(timeout: Option<Duration>) -> Self {
+        return Self {
+            at: timeout.map(|t| clock::now() + t),
+        };
+    }
+
+    /// a deadline that has already passed, i.e. only poll
+    pub fn now() -> Self {
+        return Self {
+            at: Some(clock::now()),
+        };
+    }
+
+    /// the earlier of the deadline and `timeout` from now
+    pub fn cap(self, timeout: Duration) -> Self {
+        let at = clock::now() + timeout;
+        return Self {
+            at: Some(self.at.map_or(at, |a| a.min(at))),
+        };
+    }
+
+    /// the time left until the deadline, `None` if there is no deadline
+    pub fn remaining(&self) -> Option<Duration> {
+        return self.at.map(|at| at.saturating_duration_since(clock::now()));
+    }
+
+    pub fn has_passed(&self) -> bool {
+        return self.remaining() == Some(Duration::ZERO);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keepalive::Keepalive, wrappers::clock::MockClock};
+    use proptest::prelude::*;
+
+    fn mock_clock() -> MockClock {
+        let clock = MockClock::new();
+        clock::install(Some(Box::new(clock.clone())));
+        return clock;
+    }
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn expires_on_time() {
+        let clock = mock_clock();
+        let deadline = Deadline::after(Some(10 * MS));
+        clock.advance(9 * MS);
+        assert_eq!(deadline.remaining(), Some(MS));
+        assert!(!deadline.has_passed());
+        clock.advance(MS);
+        assert!(deadline.has_passed());
+        clock.advance(MS);
+        assert_eq!(deadline.remaining(), Some(Duration::ZERO));
+
+        let forever = Deadline::after(None);
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(forever.remaining(), None);
+        assert!(!forever.has_passed());
+        assert!(Deadline::now().has_passed());
+    }
+
+    #[test]
+    fn cap_expires_first() {
+        let clock = mock_clock();
+        let call = Deadline::after(Some(10 * MS));
+        // a phase capped by a socket timer ends before the call, which goes on
+        let phase = call.cap(4 * MS);
+        clock.advance(4 * MS);
+        assert!(phase.has_passed() && !call.has_passed());
+
+        // a cap past the call leaves the deadline of the call
+        let phase = call.cap(20 * MS);
+        assert_eq!(phase, call);
+        clock.advance(6 * MS);
+        assert!(phase.has_passed() && call.has_passed());
+
+        // capping a call that blocks forever gives it a deadline
+        assert_eq!(Deadline::after(None).cap(MS).remaining(), Some(MS));
+    }
+
+    proptest! {
+        /// a deadline, the timers capping it and a keepalive, with time advancing in steps, expire
+        /// exactly once their time elapsed, and a capped deadline, like each phase of a pwait,
+        /// never outlives the deadline of the call
+        #[test]
+        fn timers(
+            timeout in prop::option::of(0..64u32),
+            steps in prop::collection::vec((any::<bool>(), 0..64u32), 0..64),
+        ) {
+            let clock = mock_clock();
+            // expires 14ms after the last activity
+            let keepalive = Keepalive {
+                enabled: true,
+                idle: 8 * MS,
+                interval: 2 * MS,
+                count: 3,
+            };
+            let start = clock::now();
+            let timeout = timeout.map(|ms| ms * MS);
+            let deadline = Deadline::after(timeout);
+            let mut capped = deadline;
+
+            for (cap, ms) in steps {
+                if cap {
+                    capped = capped.cap(ms * MS);
+                } else {
+                    clock.advance(ms * MS);
+                }
+
+                let now = clock::now();
+                match timeout {
+                    Some(timeout) => prop_assert_eq!(deadline.has_passed(), now - start >= timeout),
+                    None => prop_assert_eq!(deadline.remaining(), None),
+                }
+                if let Some(left) = deadline.remaining() {
+                    prop_assert!(capped.remaining().unwrap() <= left);
+                }
+                let left = keepalive.remaining(start, now).unwrap();
+                prop_assert_eq!(left.is_zero(), now - start >= keepalive.timeout());
+            }
+        }
+    }
+}
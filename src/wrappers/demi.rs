@@ -1,41 +1,144 @@
 use super::{
+    backend::Backend,
+    deadline::Deadline,
     errno::{PosixError, PosixResult},
     helpers::{self, WrapperConversion},
     raw::{self, demi_sgarray},
+    sga_pool,
 };
 use libc::{self, AF_INET, SOCK_STREAM, iovec, sockaddr_in};
-use log::trace;
+use log::{trace, warn};
 use std::{
+    collections::BTreeSet,
     mem::MaybeUninit,
     os::raw::{c_int, c_uint},
+    sync::{Mutex, PoisonError},
     time::Duration,
 };
 use thiserror::Error;
 
+#[cfg(feature = "faults")]
+use super::faults;
+#[cfg(not(feature = "reactor"))]
+use super::platform;
+
+/// serializes the calls into demikernel with the reactor thread
+#[cfg(feature = "reactor")]
+use super::reactor::{self, lock};
+
+/// demikernel is only called from the application threads, nothing to serialize
+#[cfg(not(feature = "reactor"))]
+#[inline]
+fn lock() {}
+
+/// the queues created through `SocketQd` and not closed yet, across all threads, see `close_all`
+static OPEN_QDS: Mutex<BTreeSet<DemiQd>> = Mutex::new(BTreeSet::new());
+
+fn open_qds() -> std::sync::MutexGuard<'static, BTreeSet<DemiQd>> {
+    return OPEN_QDS.lock().unwrap_or_else(PoisonError::into_inner);
+}
+
 pub type QToken = raw::demi_qtoken_t;
 pub type DemiQd = u32;
+/// a completion as demikernel reports it, for operations submitted by the application
+pub type RawQResult = raw::demi_qresult;
 
 #[derive(Debug)]
 pub struct SgArray {
     sga: raw::demi_sgarray,
+    /// set for the arrays of the sga pool, which go back to it when dropped
+    pooled: Option<sga_pool::Pooled>,
 }
 
 impl std::convert::From<demi_sgarray> for SgArray {
     fn from(sga: demi_sgarray) -> Self {
-        return Self { sga };
+        return Self { sga, pooled: None };
     }
 }
 
+/// gives `sga` back to demikernel
+pub(super) fn free_sga(mut sga: raw::demi_sgarray) {
+    let _demi = lock();
+    unsafe { raw::demi_sgafree(&mut sga) };
+}
+
 impl SgArray {
-    pub fn new(size: usize) -> Self {
+    /// the largest allocation requested from demikernel, larger writes are split into several
+    /// pushes
+    pub const MAX_LEN: usize = 64 * 1024;
+
+    /// fails with ENOBUFS if demikernel could not allocate the array
+    pub fn new(size: usize) -> PosixResult<Self> {
         trace!("allocating {size} bytes");
+        assert!(size <= Self::MAX_LEN);
+        let _demi = lock();
         let s = Self {
             sga: unsafe { raw::demi_sgaalloc(size) },
+            pooled: None,
+        };
+
+        if s.sga.sga_numsegs == 0 {
+            trace!("failed to allocate {size} bytes");
+            return Err(PosixError::NOBUFS);
+        }
+
+        return Ok(s);
+    }
+
+    /// like `new`, but takes the array from the sga pool of the thread, which it goes back to once
+    /// dropped, unless `pool` is off
+    pub fn new_pooled(size: usize, pool: sga_pool::PoolConfig) -> PosixResult<Self> {
+        if pool.is_off() {
+            return Self::new(size);
+        }
+
+        let (mut sga, class) = match sga_pool::take(sga_pool::class_of(size), pool) {
+            Some(cached) => cached,
+            None => {
+                let class = sga_pool::class_of(size);
+                (Self::new(sga_pool::class_len(class))?.into_raw(), class)
+            }
         };
+        let pooled = sga_pool::trim(&mut sga, size, class, pool);
+        return Ok(Self {
+            sga,
+            pooled: Some(pooled),
+        });
+    }
 
-        assert!(s.sga.sga_numsegs > 0);
+    /// the raw array of one that is not pooled, which then is not freed
+    pub(super) fn into_raw(self) -> raw::demi_sgarray {
+        assert!(self.pooled.is_none(), "pooled arrays go back to the pool");
+        return self.sga;
+    }
 
-        return s;
+    /// allocates up to `size` bytes, halving the size while demikernel is out of memory, from
+    /// the sga pool unless `pool` is off, see `new_pooled`
+    ///
+    /// fails with ENOBUFS only if not even a single byte could be allocated
+    pub fn new_at_most(size: usize, pool: sga_pool::PoolConfig) -> PosixResult<Self> {
+        let mut size = size;
+        loop {
+            match Self::new_pooled(size, pool) {
+                Err(PosixError::NOBUFS) if size > 1 => size /= 2,
+                res => return res,
+            }
+        }
+    }
+
+    /// an array over `segs`, which have to outlive it, at most `raw::DEMI_SGARRAY_MAXSIZE` of them
+    ///
+    /// the fuzz targets and tests have no demikernel to allocate from
+    #[cfg(any(test, feature = "fuzzing"))]
+    pub fn from_segments(segs: &mut [Vec<u8>]) -> Self {
+        assert!(segs.len() <= raw::DEMI_SGARRAY_MAXSIZE as usize);
+        let mut sga: raw::demi_sgarray = unsafe { std::mem::zeroed() };
+        sga.sga_numsegs = segs.len() as u32;
+        for (raw, seg) in sga.segments.iter_mut().zip(segs) {
+            raw.data_buf_ptr = seg.as_mut_ptr().cast();
+            raw.data_len_bytes = seg.len() as u32;
+        }
+        return Self { sga, pooled: None };
     }
 
     pub fn len(&self) -> usize {
@@ -45,24 +148,46 @@ impl SgArray {
             .sum();
     }
 
-    pub fn from_slice(src: &[u8]) -> Self {
-        let mut sga = Self::new(src.len());
+    /// copies a prefix of `src`, as long as could be allocated, see `new_at_most`
+    pub fn from_slice(src: &[u8], pool: sga_pool::PoolConfig) -> PosixResult<Self> {
+        let mut sga = Self::new_at_most(src.len(), pool)?;
         sga.fill(src);
-        return sga;
+        return Ok(sga);
     }
 
-    pub fn from_slices(src: &[libc::iovec]) -> Self {
-        let total_len = src.iter().map(|s| s.iov_len).sum();
-        let mut sga = Self::new(total_len);
-        sga.fill_from_slices(src);
-        return sga;
+    /// copies up to `len` bytes of `src` from its position on, as many as could be allocated, see
+    /// `new_at_most`, and moves the position past them
+    pub fn from_iovecs(
+        src: &mut IovecCursor,
+        len: usize,
+        pool: sga_pool::PoolConfig,
+    ) -> PosixResult<Self> {
+        let sga = Self::new_at_most(len, pool)?;
+        for seg in sga.segments() {
+            let dst = unsafe {
+                std::slice::from_raw_parts_mut(
+                    seg.data_buf_ptr as *mut u8,
+                    seg.data_len_bytes as usize,
+                )
+            };
+            let copied = src.read_into(dst);
+            assert_eq!(copied, dst.len(), "the iovecs are shorter than {len}");
+        }
+        return Ok(sga);
     }
 
+    /// the geometry depends on the libOS, any number of segments of any length has to be handled
     fn segments(&self) -> &[raw::demi_sgaseg] {
-        return &self.sga.segments[0..self.sga.sga_numsegs as usize];
+        let numsegs = self.sga.sga_numsegs as usize;
+        assert!(
+            numsegs <= raw::DEMI_SGARRAY_MAXSIZE as usize,
+            "invalid sga: {:?}",
+            self.sga
+        );
+        return &self.sga.segments[0..numsegs];
     }
 
-    /// will panic if `src.len() < self.len()`
+    /// fills the segments in order, will panic if `src.len() < self.len()`
     pub fn fill(&mut self, src: &[u8]) {
         assert!(src.len() >= self.len());
 
@@ -80,49 +205,19 @@ impl SgArray {
         }
     }
 
-    /// will panic if `src.iter().map(|s| s.len()).sum() < self.len()`
-    pub fn fill_from_slices(&mut self, mut src: &[libc::iovec]) {
-        assert!(src.iter().map(|s| s.iov_len).sum::<usize>() >= self.len());
-
-        let mut src_off = 0;
-        for seg in self.segments() {
-            let mut seg_off = 0;
-            let len = seg.data_len_bytes as usize;
-            let ptr = seg.data_buf_ptr as *mut u8;
-
-            while seg_off < len {
-                let bytes_left = len
-                    .saturating_sub(seg_off)
-                    .min(src[0].iov_len.saturating_sub(src_off));
-
-                unsafe {
-                    std::ptr::copy_nonoverlapping(
-                        (src[0].iov_base as *const u8).add(src_off),
-                        ptr.add(seg_off),
-                        bytes_left,
-                    );
-                }
-
-                seg_off += bytes_left;
-                src_off += bytes_left;
-                if src_off >= src[0].iov_len {
-                    src = &src[1..];
-                    src_off = 0;
-                }
-            }
-        }
-    }
-
     pub fn into_iter(self) -> SgArrayByteIter {
         return SgArrayByteIter::new(self);
     }
 }
 
-// impl Drop for SgArray {
-//     fn drop(&mut self) {
-//         assert!(unsafe { raw::demi_sgafree(&mut self.sga) } == 0);
-//     }
-// }
+// only the arrays of the sga pool are given back, see `new_pooled`
+impl Drop for SgArray {
+    fn drop(&mut self) {
+        if let Some(pooled) = self.pooled.take() {
+            sga_pool::give_back(self.sga, pooled);
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct SgArrayByteIter {
@@ -142,81 +237,242 @@ impl SgArrayByteIter {
         };
     }
 
+    /// the bytes the array held before any were consumed
+    pub fn len(&self) -> usize {
+        return self.sga.len();
+    }
+
+    /// whether every byte was consumed, an array without any, e.g. the pop signaling EOF, is
+    /// empty from the start
     pub fn is_empty(&self) -> bool {
+        return self.remaining() == 0;
+    }
+
+    /// the bytes left to be consumed
+    pub fn remaining(&self) -> usize {
+        return self.segments().map(<[u8]>::len).sum();
+    }
+
+    /// the unconsumed parts of the segments, in order and without empty ones, see `advance`
+    pub fn segments(&self) -> Segments<'_> {
+        return Segments {
+            segs: self.sga.segments().get(self.seg_off..).unwrap_or_default(),
+            byte_off: self.byte_off,
+        };
+    }
+
+    /// consumes up to `len` bytes without copying them, e.g. after reading them through
+    /// `segments`, returns how many were consumed
+    pub fn advance(&mut self, len: usize) -> usize {
         let segs = self.sga.segments();
-        return self.seg_off > segs.len() - 1;
+        let mut advanced = 0;
+        while advanced < len
+            && let Some(seg) = segs.get(self.seg_off)
+        {
+            let step = (seg.data_len_bytes as usize)
+                .saturating_sub(self.byte_off)
+                .min(len - advanced);
+            self.byte_off += step;
+            advanced += step;
+
+            if self.byte_off >= seg.data_len_bytes as usize {
+                self.seg_off += 1;
+                self.byte_off = 0;
+            }
+        }
+
+        return advanced;
     }
 
-    /// copies K bytes into dst
-    /// if the returned number of bytes is less than `dst.len()`, then `self.is_empty()` will be true
-    pub fn copy_bytes(&mut self, mut dst: &mut [MaybeUninit<u8>]) -> Option<usize> {
+    /// copies up to `dst.len()` bytes into `dst`, `None` if the array is empty
+    ///
+    /// if fewer bytes than `dst.len()` were copied, `self.is_empty()` is true
+    pub fn copy_bytes(&mut self, dst: &mut [MaybeUninit<u8>]) -> Option<usize> {
         if self.is_empty() {
             return None;
         }
 
-        let segs = self.sga.segments();
+        let mut copied = 0;
+        for seg in self.segments() {
+            let len = seg.len().min(dst.len() - copied);
+            unsafe {
+                let dst = dst.as_mut_ptr().add(copied) as *mut u8;
+                std::ptr::copy_nonoverlapping(seg.as_ptr(), dst, len);
+            }
+            copied += len;
+            if copied == dst.len() {
+                break;
+            }
+        }
+        self.advance(copied);
 
-        let max_copied = dst.len();
-        let mut total_copied = 0;
-        while total_copied < max_copied && !self.is_empty() {
-            let seg = &segs[self.seg_off];
+        return Some(copied);
+    }
 
-            let bytes_left = (seg.data_len_bytes as usize).saturating_sub(self.byte_off);
+    pub fn copy_into_iovecs(&mut self, iovecs: &mut [iovec]) -> Option<usize> {
+        return self.copy_into(&mut IovecCursor::new(iovecs));
+    }
 
-            // no more data to copy
-            if bytes_left == 0 {
-                self.byte_off = 0;
-                self.seg_off += 1;
-                continue;
+    /// copies as many bytes as fit into the iovecs of `dst` from its position on, moving it past
+    /// them, `None` if the array is empty
+    ///
+    /// if `dst` did not reach its end, `self.is_empty()` is true
+    pub fn copy_into(&mut self, dst: &mut IovecCursor) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let mut copied = 0;
+        for seg in self.segments() {
+            let len = dst.write_from(seg);
+            copied += len;
+            if len < seg.len() {
+                break;
             }
+        }
+        self.advance(copied);
 
-            let copy_len = bytes_left.min(dst.len());
+        return Some(copied);
+    }
+}
 
-            unsafe {
-                let src = seg.data_buf_ptr.add(self.byte_off) as *const u8;
-                let dst = dst.as_mut_ptr() as *mut u8;
+/// a position in an array of iovecs, so an operation can go through them in more than one step,
+/// e.g. a writev split into pushes, without walking them from the start every time
+#[derive(Debug)]
+pub struct IovecCursor<'a> {
+    vecs: &'a [iovec],
+    /// the iovec the position is in, never an empty one or one that was gone through, and the
+    /// offset into it
+    idx: usize,
+    off: usize,
+    /// the bytes before the position
+    pos: usize,
+}
 
-                std::ptr::copy_nonoverlapping(src, dst, copy_len);
-            }
+impl<'a> IovecCursor<'a> {
+    pub fn new(vecs: &'a [iovec]) -> Self {
+        let mut cursor = Self {
+            vecs,
+            idx: 0,
+            off: 0,
+            pos: 0,
+        };
+        cursor.skip_empty();
+        return cursor;
+    }
 
-            self.byte_off += copy_len;
-            total_copied += copy_len;
-            dst = &mut dst[copy_len..];
+    pub fn position(&self) -> usize {
+        return self.pos;
+    }
 
-            if self.byte_off >= seg.data_len_bytes as usize {
-                self.seg_off += 1;
-                self.byte_off = 0;
+    /// whether the position is past every byte of the iovecs
+    pub fn is_end(&self) -> bool {
+        return self.idx == self.vecs.len();
+    }
+
+    /// moves the position to `pos`, or to the end if the iovecs are shorter, going back to the
+    /// start first if it is behind
+    pub fn seek(&mut self, pos: usize) {
+        if pos < self.pos {
+            *self = Self::new(self.vecs);
+        }
+        self.advance(pos - self.pos);
+    }
+
+    /// moves the position up to `len` bytes forward, returns how far it moved
+    pub fn advance(&mut self, len: usize) -> usize {
+        let mut advanced = 0;
+        while advanced < len && !self.is_end() {
+            let step = (self.vecs[self.idx].iov_len - self.off).min(len - advanced);
+            self.step(step);
+            advanced += step;
+        }
+        return advanced;
+    }
+
+    /// copies the bytes from the position on into `dst`, as many as fit, and moves past them
+    pub fn read_into(&mut self, dst: &mut [u8]) -> usize {
+        let mut copied = 0;
+        while copied < dst.len() && !self.is_end() {
+            let vec = &self.vecs[self.idx];
+            let len = (vec.iov_len - self.off).min(dst.len() - copied);
+            unsafe {
+                let src = (vec.iov_base as *const u8).add(self.off);
+                std::ptr::copy_nonoverlapping(src, dst.as_mut_ptr().add(copied), len);
             }
+            self.step(len);
+            copied += len;
         }
+        return copied;
+    }
 
-        return Some(total_copied);
+    /// copies `src` into the iovecs from the position on, as much as fits, and moves past it
+    pub fn write_from(&mut self, src: &[u8]) -> usize {
+        let mut copied = 0;
+        while copied < src.len() && !self.is_end() {
+            let vec = &self.vecs[self.idx];
+            let len = (vec.iov_len - self.off).min(src.len() - copied);
+            unsafe {
+                let dst = (vec.iov_base as *mut u8).add(self.off);
+                std::ptr::copy_nonoverlapping(src.as_ptr().add(copied), dst, len);
+            }
+            self.step(len);
+            copied += len;
+        }
+        return copied;
     }
 
-    pub fn copy_into_iovecs(&mut self, iovecs: &mut [iovec]) -> Option<usize> {
-        if self.is_empty() {
-            return None;
+    /// moves `len` bytes forward within the current iovec
+    fn step(&mut self, len: usize) {
+        self.off += len;
+        self.pos += len;
+        if self.off == self.vecs[self.idx].iov_len {
+            self.idx += 1;
+            self.off = 0;
+            self.skip_empty();
+        }
+    }
+
+    fn skip_empty(&mut self) {
+        while self.vecs.get(self.idx).is_some_and(|vec| vec.iov_len == 0) {
+            self.idx += 1;
         }
+    }
+}
 
-        let mut total_copied = 0;
+/// the unconsumed bytes of an `SgArrayByteIter`, a segment at a time
+#[derive(Debug, Clone)]
+pub struct Segments<'a> {
+    segs: &'a [raw::demi_sgaseg],
+    /// offset into the first segment
+    byte_off: usize,
+}
 
-        for vec in iovecs.into_iter() {
-            if self.is_empty() {
-                break;
-            }
+impl<'a> Iterator for Segments<'a> {
+    type Item = &'a [u8];
 
-            let vec = unsafe {
-                std::ptr::slice_from_raw_parts_mut(
-                    vec.iov_base as *mut MaybeUninit<u8>,
-                    vec.iov_len,
-                )
-                .as_mut()
-                .unwrap()
-            };
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((seg, rest)) = self.segs.split_first() {
+            let off = std::mem::take(&mut self.byte_off);
+            self.segs = rest;
 
-            total_copied += self.copy_bytes(vec).unwrap();
+            let len = (seg.data_len_bytes as usize).saturating_sub(off);
+            if len > 0 {
+                let ptr = unsafe { (seg.data_buf_ptr as *const u8).add(off) };
+                return Some(unsafe { std::slice::from_raw_parts(ptr, len) });
+            }
         }
 
-        return Some(total_copied);
+        return None;
+    }
+}
+
+impl<'a> IntoIterator for &'a SgArrayByteIter {
+    type Item = &'a [u8];
+    type IntoIter = Segments<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        return self.segments();
     }
 }
 
@@ -269,6 +525,7 @@ pub struct AcceptResult {
 
 impl std::convert::From<raw::demi_accept_result> for AcceptResult {
     fn from(value: raw::demi_accept_result) -> Self {
+        open_qds().insert(value.qd as DemiQd);
         return Self {
             qd: value.qd.into(),
             addr: value.addr.cast(),
@@ -276,54 +533,82 @@ impl std::convert::From<raw::demi_accept_result> for AcceptResult {
     }
 }
 
+#[derive(Debug)]
+pub struct ConnectResult;
+
 #[derive(Debug)]
 pub enum QResultValue {
     Push,
     Pop(SgArray),
     Accept(AcceptResult),
+    Connect,
+    Close,
 }
 
+impl QResultValue {
+    /// the name of the operation, for errors
+    pub fn kind(&self) -> &'static str {
+        return match self {
+            Self::Push => "push",
+            Self::Pop(_) => "pop",
+            Self::Accept(_) => "accept",
+            Self::Connect => "connect",
+            Self::Close => "close",
+        };
+    }
+}
+
+/// a FAILED completion keeps its qd and qt, so the error can be attributed to the operation
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct QResult {
     pub qd: DemiQd,
     pub qt: QToken,
-    pub value: Option<QResultValue>,
-}
-
-impl std::convert::TryFrom<raw::demi_qresult> for QResult {
-    type Error = PosixError;
-
-    fn try_from(value: raw::demi_qresult) -> Result<Self, Self::Error> {
-        let opcode = value.qr_opcode.try_into().unwrap();
-        let val = match opcode {
-            Opcode::PUSH => Ok(Some(QResultValue::Push)),
-            Opcode::POP => Ok(Some(QResultValue::Pop(
-                unsafe { value.qr_value.sga }.into(),
-            ))),
-            Opcode::ACCEPT => Ok(Some(QResultValue::Accept(
-                unsafe { value.qr_value.ares }.into(),
-            ))),
-            Opcode::INVALID => panic!("invalid request to demikernel"),
-            Opcode::CONNECT => Ok(None),
-            Opcode::CLOSE => Ok(None),
-            Opcode::FAILED => Err(
-                PosixError::from_error_code(value.qr_ret.try_into().unwrap())
-                    .err()
-                    .unwrap(),
-            ),
-        }?;
+    pub value: PosixResult<QResultValue>,
+}
 
-        return Ok(Self {
+/// a completion demikernel should never return, an INVALID or unknown opcode or a FAILED one
+/// without an errno, fails its operation with EIO instead of taking the application down
+impl std::convert::From<raw::demi_qresult> for QResult {
+    fn from(value: raw::demi_qresult) -> Self {
+        let val = match value.qr_opcode.try_into() {
+            Ok(Opcode::PUSH) => Ok(QResultValue::Push),
+            Ok(Opcode::POP) => Ok(QResultValue::Pop(unsafe { value.qr_value.sga }.into())),
+            Ok(Opcode::ACCEPT) => Ok(QResultValue::Accept(unsafe { value.qr_value.ares }.into())),
+            Ok(Opcode::CONNECT) => Ok(QResultValue::Connect),
+            Ok(Opcode::CLOSE) => Ok(QResultValue::Close),
+            Ok(Opcode::FAILED) => Err(c_int::try_from(value.qr_ret)
+                .ok()
+                .and_then(PosixError::from_code)
+                .unwrap_or(PosixError::IO)),
+            Ok(Opcode::INVALID) | Err(_) => {
+                let (qt, opcode) = (value.qr_qt, value.qr_opcode);
+                warn!("qt {qt} completed with opcode {opcode}");
+                Err(PosixError::IO)
+            }
+        };
+
+        return Self {
             qd: value.qr_qd as u32,
             qt: value.qr_qt,
             value: val,
-        });
+        };
     }
 }
 
-#[inline]
-pub fn meta_init() -> PosixResult<()> {
+/// demikernel picks the libOS from `LIBOS`, which is set to `backend` unless the user already
+/// set it
+pub fn meta_init(backend: Backend) -> PosixResult<()> {
+    match std::env::var("LIBOS") {
+        Ok(libos) if Backend::from_name(&libos) == Some(backend) => {}
+        Ok(libos) => {
+            trace!("LIBOS={libos} overrides {backend:?}");
+            return Err(PosixError::INVAL);
+        }
+        Err(_) => unsafe { std::env::set_var("LIBOS", backend.name()) },
+    }
+    backend.select();
+
     let args = raw::demi_args {
         argc: 0,
         argv: std::ptr::null(),
@@ -331,9 +616,24 @@ pub fn meta_init() -> PosixResult<()> {
         logCallback: None,
     };
 
+    let _demi = lock();
     return PosixError::from_error_code(unsafe { raw::demi_init(&args) });
 }
 
+/// closes every queue still open, whichever thread owns it, returns how many there were
+///
+/// the `SocketQd`s of the queues are left dangling, they must not be used anymore
+pub fn close_all() -> usize {
+    let qds = std::mem::take(&mut *open_qds());
+    let _demi = lock();
+    for qd in &qds {
+        let res = PosixError::from_error_code(unsafe { raw::demi_close(*qd as c_int) });
+        trace!("closing {qd} for good: {res:?}");
+    }
+
+    return qds.len();
+}
+
 #[repr(transparent)]
 #[derive(Debug)]
 pub struct SocketQd {
@@ -350,18 +650,22 @@ impl SocketQd {
     #[inline]
     pub fn new() -> PosixResult<Self> {
         let mut qd: c_int = 0;
+        let _demi = lock();
         PosixError::from_error_code(unsafe { raw::demi_socket(&mut qd, AF_INET, SOCK_STREAM, 0) })?;
+        open_qds().insert(qd as DemiQd);
         return Ok(qd.into());
     }
 
     #[inline]
     pub fn listen(&mut self, backlog: i32) -> PosixResult<()> {
+        let _demi = lock();
         return PosixError::from_error_code(unsafe { raw::demi_listen(self.qd as c_int, backlog) });
     }
 
     #[inline]
     pub fn bind(&mut self, addr: *const libc::sockaddr_in) -> PosixResult<()> {
         let addr_ptr = addr as *const raw::sockaddr;
+        let _demi = lock();
         return PosixError::from_error_code(unsafe {
             raw::demi_bind(self.qd as c_int, addr_ptr, ADDR_SIZE)
         });
@@ -370,17 +674,17 @@ impl SocketQd {
     #[inline]
     pub fn accept(&mut self) -> PosixResult<QToken> {
         let mut tok: QToken = 0;
-
+        let _demi = lock();
         PosixError::from_error_code(unsafe { raw::demi_accept(&mut tok, self.qd as c_int) })?;
 
         return Ok(tok);
     }
 
-    #[allow(dead_code)]
     #[inline]
     pub fn connect(&mut self, addr: *const libc::sockaddr_in) -> PosixResult<QToken> {
         let addr_ptr = addr as *const raw::sockaddr;
         let mut tok: QToken = 0;
+        let _demi = lock();
         PosixError::from_error_code(unsafe {
             raw::demi_connect(&mut tok, self.qd as c_int, addr_ptr, ADDR_SIZE)
         })?;
@@ -390,12 +694,17 @@ impl SocketQd {
 
     #[inline]
     pub fn close(&mut self) -> PosixResult<()> {
+        open_qds().remove(&self.qd);
+        let _demi = lock();
         return PosixError::from_error_code(unsafe { raw::demi_close(self.qd as c_int) });
     }
 
     #[inline]
     pub fn push(&mut self, sga: &SgArray) -> PosixResult<QToken> {
+        #[cfg(feature = "faults")]
+        faults::push()?;
         let mut tok: QToken = 0;
+        let _demi = lock();
         PosixError::from_error_code(unsafe {
             raw::demi_push(&mut tok, self.qd as c_int, &sga.sga)
         })?;
@@ -406,6 +715,7 @@ impl SocketQd {
     #[inline]
     pub fn pop(&mut self) -> PosixResult<QToken> {
         let mut tok: QToken = 0;
+        let _demi = lock();
         PosixError::from_error_code(unsafe { raw::demi_pop(&mut tok, self.qd as c_int) })?;
 
         return Ok(tok);
@@ -413,6 +723,37 @@ impl SocketQd {
 }
 
 pub fn wait(tok: QToken, timeout: Option<Duration>) -> PosixResult<QResult> {
+    #[cfg(feature = "reactor")]
+    let res = reactor::wait_any(&[tok], timeout).map(|(_, res)| res);
+    #[cfg(not(feature = "reactor"))]
+    let res = {
+        let _demi = lock();
+        wait_direct(tok, timeout)
+    };
+
+    return res.map(From::from);
+}
+
+/// a kernel fd that is readable once completions of tokens the thread waited on arrived, only
+/// with the reactor feature, without it nothing harvests them outside of a wait
+pub fn completion_fd() -> PosixResult<Option<c_int>> {
+    #[cfg(feature = "reactor")]
+    return reactor::notify_fd().map(Some);
+    #[cfg(not(feature = "reactor"))]
+    return Ok(None);
+}
+
+/// the cpu the completions the calling thread waits on are harvested on, the one of the reactor
+/// thread with the reactor feature and the calling thread itself without
+pub fn harvest_cpu() -> Option<c_int> {
+    #[cfg(feature = "reactor")]
+    return reactor::harvest_cpu();
+    #[cfg(not(feature = "reactor"))]
+    return platform::current_cpu();
+}
+
+/// waits in demikernel itself, the caller has to hold `lock`
+pub(super) fn wait_direct(tok: QToken, timeout: Option<Duration>) -> PosixResult<RawQResult> {
     let mut res: MaybeUninit<raw::demi_qresult> = MaybeUninit::uninit();
     let ts: raw::timespec;
     let ts_ptr = if let Some(d) = timeout {
@@ -423,13 +764,37 @@ pub fn wait(tok: QToken, timeout: Option<Duration>) -> PosixResult<QResult> {
     };
 
     PosixError::from_error_code(unsafe { raw::demi_wait(res.as_mut_ptr(), tok, ts_ptr) })?;
-    return unsafe { res.assume_init() }.try_into();
+    #[allow(unused_mut)]
+    let mut res = unsafe { res.assume_init() };
+    #[cfg(feature = "faults")]
+    faults::completion(&mut res);
+    return Ok(res);
+}
+
+#[allow(dead_code)]
+pub fn wait_any(toks: &[QToken], timeout: Option<Duration>) -> PosixResult<(usize, QResult)> {
+    return wait_any_raw(toks, timeout).map(|(off, res)| (off, res.into()));
+}
+
+/// like `wait_any`, but leaves the result as demikernel reported it
+pub fn wait_any_raw(
+    toks: &[QToken],
+    timeout: Option<Duration>,
+) -> PosixResult<(usize, RawQResult)> {
+    #[cfg(feature = "reactor")]
+    return reactor::wait_any(toks, timeout);
+    #[cfg(not(feature = "reactor"))]
+    {
+        let _demi = lock();
+        return wait_any_raw_direct(toks, timeout);
+    }
 }
 
-pub fn wait_any(
+/// waits in demikernel itself, the caller has to hold `lock`
+pub(super) fn wait_any_raw_direct(
     toks: &[QToken],
     timeout: Option<Duration>,
-) -> PosixResult<(usize, PosixResult<QResult>)> {
+) -> PosixResult<(usize, RawQResult)> {
     let mut res: MaybeUninit<raw::demi_qresult> = MaybeUninit::uninit();
     let ts: raw::timespec;
     let ts_ptr = if let Some(d) = timeout {
@@ -451,8 +816,165 @@ pub fn wait_any(
         )
     })?;
 
-    return Ok((
-        unsafe { off.assume_init() }.try_into().unwrap(),
-        unsafe { res.assume_init() }.try_into(),
-    ));
+    #[allow(unused_mut)]
+    let mut res = unsafe { res.assume_init() };
+    #[cfg(feature = "faults")]
+    faults::completion(&mut res);
+    return Ok((unsafe { off.assume_init() }.try_into().unwrap(), res));
+}
+
+/// errors of a wait that do not say anything about the operations, so the wait can be retried
+#[inline]
+fn is_transient(err: PosixError) -> bool {
+    return matches!(err, PosixError::INTR | PosixError::WOULDBLOCK);
+}
+
+/// calls `func` with what is left until `deadline` until it returns anything but a transient error
+///
+/// once the deadline has passed, a transient error is turned into TIMEDOUT
+fn retry<T, F>(deadline: Deadline, mut func: F) -> PosixResult<T>
+where
+    F: FnMut(Option<Duration>) -> PosixResult<T>,
+{
+    loop {
+        let remaining = deadline.remaining();
+        match func(remaining) {
+            Err(e) if is_transient(e) => {
+                trace!("transient wait error {e:?}, {remaining:?} left");
+                if deadline.has_passed() {
+                    return Err(PosixError::TIMEDOUT);
+                }
+            }
+            res => return res,
+        }
+    }
+}
+
+/// like `wait`, but retries transient errors
+pub fn wait_retrying(tok: QToken, deadline: Deadline) -> PosixResult<QResult> {
+    return retry(deadline, |timeout| wait(tok, timeout));
+}
+
+/// like `wait_any`, but retries transient errors
+#[allow(dead_code)]
+pub fn wait_any_retrying(toks: &[QToken], deadline: Deadline) -> PosixResult<(usize, QResult)> {
+    return retry(deadline, |timeout| wait_any(toks, timeout));
+}
+
+/// like `wait_any_raw`, but retries transient errors
+pub fn wait_any_raw_retrying(
+    toks: &[QToken],
+    deadline: Deadline,
+) -> PosixResult<(usize, RawQResult)> {
+    return retry(deadline, |timeout| wait_any_raw(toks, timeout));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// the segments of an array as some libOS may return them, empty ones included
+    fn geometry() -> impl Strategy<Value = Vec<Vec<u8>>> {
+        let seg = prop::collection::vec(any::<u8>(), 0..16);
+        return prop::collection::vec(seg, 0..=raw::DEMI_SGARRAY_MAXSIZE as usize);
+    }
+
+    fn copied(dst: &[MaybeUninit<u8>]) -> Vec<u8> {
+        return dst.iter().map(|b| unsafe { b.assume_init() }).collect();
+    }
+
+    #[test]
+    fn fill_segments() {
+        let mut segs = vec![vec![0; 3], vec![], vec![0; 5], vec![0; 1]];
+        let mut sga = SgArray::from_segments(&mut segs);
+        assert_eq!(sga.len(), 9);
+        sga.fill(b"abcdefghijk");
+        drop(sga);
+        assert_eq!(segs, [&b"abc"[..], b"", b"defgh", b"i"]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn fill_short() {
+        let mut segs = vec![vec![0; 3], vec![0; 5]];
+        SgArray::from_segments(&mut segs).fill(b"abcdefg");
+    }
+
+    #[test]
+    fn iter_segments() {
+        let mut segs = vec![vec![], b"ab".to_vec(), vec![], b"cde".to_vec(), vec![]];
+        let mut iter = SgArray::from_segments(&mut segs).into_iter();
+        assert_eq!((iter.len(), iter.remaining()), (5, 5));
+        assert_eq!(iter.segments().collect::<Vec<_>>(), [&b"ab"[..], b"cde"]);
+
+        let mut dst = [MaybeUninit::new(0); 3];
+        assert_eq!(iter.copy_bytes(&mut dst), Some(3));
+        assert_eq!(copied(&dst), b"abc");
+        assert_eq!(iter.segments().collect::<Vec<_>>(), [&b"de"[..]]);
+        assert_eq!((iter.len(), iter.remaining()), (5, 2));
+
+        assert_eq!(iter.advance(10), 2);
+        assert!(iter.is_empty());
+        assert_eq!(iter.copy_bytes(&mut dst), None);
+        assert_eq!(iter.segments().count(), 0);
+    }
+
+    #[test]
+    fn iter_no_segments() {
+        let mut iter = SgArray::from_segments(&mut []).into_iter();
+        assert_eq!(iter.len(), 0);
+        assert!(iter.is_empty());
+        assert_eq!(iter.advance(1), 0);
+        assert_eq!(iter.copy_bytes(&mut [MaybeUninit::new(0); 1]), None);
+        assert_eq!(iter.copy_into_iovecs(&mut []), None);
+    }
+
+    proptest! {
+        /// filling an array of any geometry and consuming it in steps of any kind and size gives
+        /// the same bytes as a flat copy of them
+        #[test]
+        fn matches_flat_copy(
+            mut segs in geometry(),
+            src in prop::collection::vec(any::<u8>(), 320),
+            steps in prop::collection::vec((0u8..3, 0usize..24), 0..32),
+        ) {
+            let mut sga = SgArray::from_segments(&mut segs);
+            let model = &src[..segs.iter().map(Vec::len).sum::<usize>()];
+            prop_assert_eq!(sga.len(), model.len());
+            sga.fill(&src);
+
+            let mut iter = sga.into_iter();
+            let mut pos = 0;
+            for (kind, size) in steps {
+                let want = size.min(model.len() - pos);
+                match kind {
+                    0 => {
+                        let mut dst = vec![MaybeUninit::new(0); size];
+                        let res = iter.copy_bytes(&mut dst);
+                        prop_assert_eq!(res, (pos < model.len()).then_some(want));
+                        prop_assert_eq!(&copied(&dst)[..want], &model[pos..pos + want]);
+                    }
+                    1 => {
+                        let mut bufs = [vec![0u8; size / 3], vec![], vec![0u8; size - size / 3]];
+                        let mut vecs = bufs.each_mut().map(|buf| iovec {
+                            iov_base: buf.as_mut_ptr().cast(),
+                            iov_len: buf.len(),
+                        });
+                        let res = iter.copy_into_iovecs(&mut vecs);
+                        prop_assert_eq!(res, (pos < model.len()).then_some(want));
+                        prop_assert_eq!(&bufs.concat()[..want], &model[pos..pos + want]);
+                    }
+                    _ => prop_assert_eq!(iter.advance(size), want),
+                }
+                pos += want;
+
+                prop_assert_eq!(iter.len(), model.len());
+                prop_assert_eq!(iter.remaining(), model.len() - pos);
+                prop_assert_eq!(iter.is_empty(), pos == model.len());
+                prop_assert_eq!(iter.segments().collect::<Vec<_>>().concat(), &model[pos..]);
+                prop_assert!(iter.segments().all(|seg| !seg.is_empty()));
+            }
+        }
+    }
 }
@@ -1,6 +1,8 @@
 use std::os::raw::c_int;
 use thiserror::Error;
 
+use super::platform;
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, Error, PartialEq, Eq)]
 #[repr(i32)]
@@ -271,24 +273,34 @@ pub enum PosixError {
 
 impl PosixError {
     pub fn from_errno() -> PosixResult<()> {
-        let err = unsafe { libc::__errno_location().read() };
+        let err = platform::errno();
         return Self::from_error_code(err);
     }
 
     /// returns Ok(()) if errno == 0
     ///
     /// panics if errno does not map to anything
-    #[allow(unreachable_code)]
     pub fn from_error_code(code: c_int) -> PosixResult<()> {
         if code == 0 {
             return Ok(());
-        } else if code <= 133 {
-            let var: PosixError = unsafe { std::mem::transmute(code) };
-            return Err(var);
-        } else {
-            panic!("invalid errno: {}\n", code);
+        }
+
+        return match Self::from_code(code) {
+            Some(var) => Err(var),
+            None => panic!("invalid errno: {}\n", code),
         };
     }
+
+    /// `None` for codes linux does not define
+    pub fn from_code(code: c_int) -> Option<Self> {
+        // the codes linux skips below HWPOISON
+        const UNUSED: [c_int; 2] = [41, 58];
+        if !(1..=Self::HWPOISON as c_int).contains(&code) || UNUSED.contains(&code) {
+            return None;
+        }
+
+        return Some(unsafe { std::mem::transmute::<c_int, Self>(code) });
+    }
 }
 
 impl std::convert::Into<c_int> for PosixError {
@@ -297,4 +309,10 @@ impl std::convert::Into<c_int> for PosixError {
     }
 }
 
+impl From<PosixError> for std::io::Error {
+    fn from(err: PosixError) -> Self {
+        return Self::from_raw_os_error(err as c_int);
+    }
+}
+
 pub type PosixResult<T> = Result<T, PosixError>;
@@ -0,0 +1,145 @@
+//! failures injected into the calls into demikernel, enabled with the faults feature, to check
+//! that applications and the shim itself get errnos instead of panics when demikernel misbehaves
+//!
+//! `DPOLL_FAULTS` is read by dpoll_init, a comma separated list of `<fault>=<n>` with the faults
+//! - `push`: every nth push fails with ENOBUFS before it reaches demikernel
+//! - `wait`: every nth completion a wait returns is turned into a FAILED one with ECONNRESET
+//! - `accept`: every nth accept completion is turned into a FAILED one with ECONNABORTED
+//!
+//! an n of 0 disables the fault. the counters are shared by all the threads of the process, like
+//! demikernel itself
+//!
+//! the resources of a completion that is turned into a failure, the sga of a pop or the queue of
+//! an accepted connection, are released on the way
+
+use std::{
+    env,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use log::{trace, warn};
+
+use super::{
+    demi::{self, Opcode, RawQResult},
+    errno::{PosixError, PosixResult},
+    raw,
+};
+
+/// how often each fault is injected, 0 for never
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Faults {
+    pub push: u32,
+    pub wait: u32,
+    pub accept: u32,
+}
+
+impl Faults {
+    pub fn parse(spec: &str) -> PosixResult<Self> {
+        let mut faults = Self::default();
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (name, every) = part.split_once('=').ok_or(PosixError::INVAL)?;
+            let every = every.trim().parse().map_err(|_| PosixError::INVAL)?;
+            match name.trim() {
+                "push" => faults.push = every,
+                "wait" => faults.wait = every,
+                "accept" => faults.accept = every,
+                _ => return Err(PosixError::INVAL),
+            }
+        }
+        return Ok(faults);
+    }
+}
+
+/// a fault with how often it is injected and how many calls it saw
+struct Fault {
+    every: AtomicU32,
+    calls: AtomicU32,
+}
+
+impl Fault {
+    const fn new() -> Self {
+        return Self {
+            every: AtomicU32::new(0),
+            calls: AtomicU32::new(0),
+        };
+    }
+
+    fn set(&self, every: u32) {
+        self.every.store(every, Ordering::Relaxed);
+        self.calls.store(0, Ordering::Relaxed);
+    }
+
+    /// whether this call is one the fault is injected into
+    fn hit(&self) -> bool {
+        let every = self.every.load(Ordering::Relaxed);
+        if every == 0 {
+            return false;
+        }
+        let calls = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+        return calls.is_multiple_of(every);
+    }
+}
+
+static PUSH: Fault = Fault::new();
+static WAIT: Fault = Fault::new();
+static ACCEPT: Fault = Fault::new();
+
+/// replaces the faults injected, restarting their counts
+pub fn set(faults: Faults) {
+    trace!("injecting {faults:?}");
+    PUSH.set(faults.push);
+    WAIT.set(faults.wait);
+    ACCEPT.set(faults.accept);
+}
+
+/// sets the faults from `DPOLL_FAULTS`, failing with EINVAL if it cannot be parsed
+pub fn install() -> PosixResult<()> {
+    let Ok(spec) = env::var("DPOLL_FAULTS") else {
+        return Ok(());
+    };
+    let faults = Faults::parse(&spec).inspect_err(|_| warn!("invalid DPOLL_FAULTS {spec:?}"))?;
+    set(faults);
+    return Ok(());
+}
+
+/// called before a push reaches demikernel
+pub fn push() -> PosixResult<()> {
+    if PUSH.hit() {
+        trace!("injecting ENOBUFS into a push");
+        return Err(PosixError::NOBUFS);
+    }
+    return Ok(());
+}
+
+/// the error injected into the next completion with `opcode`, if any
+pub fn injected(opcode: u32) -> Option<PosixError> {
+    if opcode == Opcode::ACCEPT as u32 && ACCEPT.hit() {
+        return Some(PosixError::CONNABORTED);
+    }
+    if opcode != Opcode::FAILED as u32 && WAIT.hit() {
+        return Some(PosixError::CONNRESET);
+    }
+    return None;
+}
+
+/// called on every completion a wait returns
+pub fn completion(res: &mut RawQResult) {
+    let Some(err) = injected(res.qr_opcode) else {
+        return;
+    };
+
+    let qt = res.qr_qt;
+    trace!("injecting {err:?} into the completion of {qt}");
+    fail(res, err);
+}
+
+/// turns `res` into a FAILED completion with `err`, releasing what it carried
+pub fn fail(res: &mut RawQResult, err: PosixError) {
+    if res.qr_opcode == Opcode::POP as u32 {
+        demi::free_sga(unsafe { res.qr_value.sga });
+    } else if res.qr_opcode == Opcode::ACCEPT as u32 {
+        unsafe { raw::demi_close(res.qr_value.ares.qd) };
+    }
+    res.qr_opcode = Opcode::FAILED as u32;
+    res.qr_ret = err as i64;
+}
@@ -0,0 +1,54 @@
+//! compile time checks of the layouts the wrappers rely on
+//!
+//! the layout tests bindgen put into `raw` check the raw structs against the demikernel headers
+//! they were generated from, these check the casts between them and libc, and the unions and
+//! opcodes `QResult` is converted from, so a demikernel or libc whose ABI drifted fails to build
+//! instead of corrupting memory
+
+use std::mem::{align_of, offset_of, size_of};
+
+use super::{demi::Opcode, raw};
+
+/// `raw::sockaddr_in` is transmuted into `libc::sockaddr_in`, see `helpers`
+const _: () = {
+    assert!(size_of::<raw::sockaddr_in>() == size_of::<libc::sockaddr_in>());
+    assert!(align_of::<raw::sockaddr_in>() == align_of::<libc::sockaddr_in>());
+    assert!(offset_of!(raw::sockaddr_in, sin_family) == offset_of!(libc::sockaddr_in, sin_family));
+    assert!(offset_of!(raw::sockaddr_in, sin_port) == offset_of!(libc::sockaddr_in, sin_port));
+    assert!(offset_of!(raw::sockaddr_in, sin_addr) == offset_of!(libc::sockaddr_in, sin_addr));
+    assert!(size_of::<raw::in_addr>() == size_of::<libc::in_addr>());
+};
+
+/// bind and connect pass a `libc::sockaddr_in` as a `raw::sockaddr` of `ADDR_SIZE` bytes
+const _: () = {
+    assert!(size_of::<raw::sockaddr>() == size_of::<libc::sockaddr>());
+    assert!(offset_of!(raw::sockaddr, sa_family) == offset_of!(libc::sockaddr, sa_family));
+};
+
+/// the timeouts of the waits are built from libc types
+const _: () = {
+    assert!(size_of::<raw::timespec>() == size_of::<libc::timespec>());
+    assert!(offset_of!(raw::timespec, tv_sec) == offset_of!(libc::timespec, tv_sec));
+    assert!(offset_of!(raw::timespec, tv_nsec) == offset_of!(libc::timespec, tv_nsec));
+};
+
+/// a completion is read as the member of the union its opcode names
+const _: () = {
+    type Value = raw::demi_qresult__bindgen_ty_1;
+    assert!(size_of::<Value>() >= size_of::<raw::demi_sgarray>());
+    assert!(size_of::<Value>() >= size_of::<raw::demi_accept_result>());
+    assert!(offset_of!(Value, sga) == 0);
+    assert!(offset_of!(Value, ares) == 0);
+    assert!(size_of::<raw::demi_qtoken_t>() == size_of::<u64>());
+};
+
+/// `Opcode` is converted from `qr_opcode` by value
+const _: () = {
+    assert!(Opcode::INVALID as u32 == raw::demi_opcode_DEMI_OPC_INVALID);
+    assert!(Opcode::PUSH as u32 == raw::demi_opcode_DEMI_OPC_PUSH);
+    assert!(Opcode::POP as u32 == raw::demi_opcode_DEMI_OPC_POP);
+    assert!(Opcode::ACCEPT as u32 == raw::demi_opcode_DEMI_OPC_ACCEPT);
+    assert!(Opcode::CONNECT as u32 == raw::demi_opcode_DEMI_OPC_CONNECT);
+    assert!(Opcode::CLOSE as u32 == raw::demi_opcode_DEMI_OPC_CLOSE);
+    assert!(Opcode::FAILED as u32 == raw::demi_opcode_DEMI_OPC_FAILED);
+};
@@ -0,0 +1,443 @@
+//! an in-process loopback standing in for demikernel, enabled with the mock feature, which then
+//! does not link libdemikernel
+//!
+//! connections are only made between the queues of the process, a connect completes right away if
+//! a queue listens on the address and fails with ECONNREFUSED otherwise. a push hands its bytes to
+//! the peer at once, a pop completes once the peer pushed something or closed its queue, and a
+//! wait without a token ready blocks until another thread makes one ready or the timeout passes
+//!
+//! dpoll_replay runs on it, so a replay depends neither on the network nor on DPOLL_LIBOS
+
+use std::{
+    collections::{HashMap, VecDeque},
+    mem, ptr,
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use libc::{
+    AF_INET, EADDRINUSE, EBADF, ECANCELED, ECONNREFUSED, ECONNRESET, EINVAL, ENOTCONN, ENOTSUP,
+    ETIMEDOUT, SOCK_STREAM, c_int, c_void, sockaddr_in,
+};
+
+use super::raw::{self, demi_opcode, demi_qresult, demi_qtoken_t, demi_sgarray};
+
+/// a socket, addresses are kept as `(s_addr, sin_port)` in network order
+#[derive(Debug, Default)]
+struct Queue {
+    addr: Option<(u32, u16)>,
+    listening: bool,
+    /// the queues of connections made to a listening queue and not accepted yet
+    backlog: VecDeque<c_int>,
+    peer: Option<c_int>,
+    /// what the peer pushed and was not popped yet, a push at a time
+    recv: VecDeque<Vec<u8>>,
+    /// set once the peer closed its queue, the pops then complete without data
+    eof: bool,
+}
+
+#[derive(Debug)]
+enum Op {
+    /// an operation whose result was known when it was submitted, `err` is 0 for a success
+    Done {
+        qd: c_int,
+        opcode: demi_opcode,
+        err: c_int,
+    },
+    Pop(c_int),
+    Accept(c_int),
+}
+
+#[derive(Debug, Default)]
+struct State {
+    queues: HashMap<c_int, Queue>,
+    ops: HashMap<demi_qtoken_t, Op>,
+    /// the buffers of the arrays handed out, by the id in `sgaseg_md` of their segment
+    bufs: HashMap<usize, Box<[u8]>>,
+    last_qd: c_int,
+    last_qt: demi_qtoken_t,
+    last_buf: usize,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+/// notified whenever a token might have become ready
+static READY: Condvar = Condvar::new();
+
+fn state() -> MutexGuard<'static, State> {
+    return STATE.lock().unwrap_or_else(PoisonError::into_inner);
+}
+
+impl State {
+    fn queue(&mut self, qd: c_int) -> Result<&mut Queue, c_int> {
+        return self.queues.get_mut(&qd).ok_or(EBADF);
+    }
+
+    fn new_queue(&mut self, queue: Queue) -> c_int {
+        self.last_qd += 1;
+        self.queues.insert(self.last_qd, queue);
+        return self.last_qd;
+    }
+
+    fn submit(&mut self, op: Op) -> demi_qtoken_t {
+        self.last_qt += 1;
+        self.ops.insert(self.last_qt, op);
+        return self.last_qt;
+    }
+
+    /// an array of a single segment over `data`, which is freed by demi_sgafree
+    fn sga(&mut self, data: Box<[u8]>) -> demi_sgarray {
+        let mut sga: demi_sgarray = unsafe { mem::zeroed() };
+        self.last_buf += 1;
+        sga.sga_numsegs = 1;
+        sga.segments[0] = raw::demi_sgaseg {
+            sgaseg_md: self.last_buf as *mut c_void,
+            data_buf_ptr: data.as_ptr() as *mut c_void,
+            data_len_bytes: data.len() as u32,
+        };
+        self.bufs.insert(self.last_buf, data);
+        return sga;
+    }
+
+    fn connect(&mut self, qd: c_int, addr: (u32, u16)) -> c_int {
+        let listener = self.queues.iter().find_map(|(lqd, q)| {
+            let (s_addr, port) = q.addr?;
+            let matches = port == addr.1 && (s_addr == addr.0 || s_addr == 0);
+            return (q.listening && matches).then_some(*lqd);
+        });
+        let Some(listener) = listener else {
+            return ECONNREFUSED;
+        };
+
+        let accepted = self.new_queue(Queue {
+            peer: Some(qd),
+            ..Queue::default()
+        });
+        self.queues
+            .get_mut(&listener)
+            .unwrap()
+            .backlog
+            .push_back(accepted);
+        self.queues.get_mut(&qd).unwrap().peer = Some(accepted);
+        return 0;
+    }
+
+    /// closes `qd`, the peer then sees the end of the stream
+    fn close(&mut self, qd: c_int) -> c_int {
+        let Some(queue) = self.queues.remove(&qd) else {
+            return EBADF;
+        };
+        for peer in queue.peer.into_iter().chain(queue.backlog) {
+            if queue.listening {
+                self.close(peer);
+            } else if let Some(peer) = self.queues.get_mut(&peer) {
+                peer.eof = true;
+            }
+        }
+        return 0;
+    }
+
+    /// the completion of `qt` if it is ready, which consumes the token
+    fn complete(&mut self, qt: demi_qtoken_t) -> Option<demi_qresult> {
+        let mut res: demi_qresult = unsafe { mem::zeroed() };
+        res.qr_qt = qt;
+        match *self.ops.get(&qt)? {
+            Op::Done { qd, opcode, err } => {
+                res.qr_qd = qd;
+                res.qr_opcode = opcode;
+                if err != 0 {
+                    res.qr_opcode = raw::demi_opcode_DEMI_OPC_FAILED;
+                    res.qr_ret = err as i64;
+                }
+            }
+            Op::Pop(qd) => {
+                res.qr_qd = qd;
+                let data = match self.queues.get_mut(&qd) {
+                    Some(q) if q.recv.is_empty() && !q.eof => return None,
+                    Some(q) => q.recv.pop_front().unwrap_or_default(),
+                    None => {
+                        res.qr_opcode = raw::demi_opcode_DEMI_OPC_FAILED;
+                        res.qr_ret = ECANCELED as i64;
+                        self.ops.remove(&qt);
+                        return Some(res);
+                    }
+                };
+                res.qr_opcode = raw::demi_opcode_DEMI_OPC_POP;
+                res.qr_value.sga = self.sga(data.into_boxed_slice());
+            }
+            Op::Accept(qd) => {
+                res.qr_qd = qd;
+                match self.queues.get_mut(&qd).map(|q| q.backlog.pop_front()) {
+                    Some(None) => return None,
+                    Some(Some(accepted)) => {
+                        let mut addr: raw::sockaddr_in = unsafe { mem::zeroed() };
+                        addr.sin_family = AF_INET as raw::sa_family_t;
+                        res.qr_opcode = raw::demi_opcode_DEMI_OPC_ACCEPT;
+                        res.qr_value.ares = raw::demi_accept_result { qd: accepted, addr };
+                    }
+                    None => {
+                        res.qr_opcode = raw::demi_opcode_DEMI_OPC_FAILED;
+                        res.qr_ret = ECANCELED as i64;
+                    }
+                }
+            }
+        }
+
+        self.ops.remove(&qt);
+        return Some(res);
+    }
+}
+
+/// `(s_addr, sin_port)` of the address passed to a bind or connect
+fn addr_of(addr: *const raw::sockaddr, size: raw::socklen_t) -> Result<(u32, u16), c_int> {
+    if addr.is_null() || (size as usize) < mem::size_of::<sockaddr_in>() {
+        return Err(EINVAL);
+    }
+
+    let addr = unsafe { ptr::read_unaligned(addr as *const sockaddr_in) };
+    if addr.sin_family != AF_INET as libc::sa_family_t {
+        return Err(EINVAL);
+    }
+    return Ok((addr.sin_addr.s_addr, addr.sin_port));
+}
+
+/// stores the token of `op` in `qt_out`
+unsafe fn submitted(st: &mut State, qt_out: *mut demi_qtoken_t, op: Op) -> c_int {
+    let qt = st.submit(op);
+    unsafe { *qt_out = qt };
+    READY.notify_all();
+    return 0;
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn demi_init(_args: *const raw::demi_args) -> c_int {
+    return 0;
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn demi_socket(
+    sockqd_out: *mut c_int,
+    domain: c_int,
+    type_: c_int,
+    _protocol: c_int,
+) -> c_int {
+    if domain != AF_INET || type_ != SOCK_STREAM {
+        return ENOTSUP;
+    }
+
+    let qd = state().new_queue(Queue::default());
+    unsafe { *sockqd_out = qd };
+    return 0;
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn demi_bind(
+    sockqd: c_int,
+    addr: *const raw::sockaddr,
+    size: raw::socklen_t,
+) -> c_int {
+    let addr = match addr_of(addr, size) {
+        Ok(addr) => addr,
+        Err(e) => return e,
+    };
+
+    let mut st = state();
+    if st.queues.values().any(|q| q.addr == Some(addr)) {
+        return EADDRINUSE;
+    }
+    return match st.queue(sockqd) {
+        Ok(q) => {
+            q.addr = Some(addr);
+            0
+        }
+        Err(e) => e,
+    };
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn demi_listen(sockqd: c_int, _backlog: c_int) -> c_int {
+    return match state().queue(sockqd) {
+        Ok(q) if q.addr.is_none() || q.peer.is_some() => EINVAL,
+        Ok(q) => {
+            q.listening = true;
+            0
+        }
+        Err(e) => e,
+    };
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn demi_accept(qt_out: *mut demi_qtoken_t, sockqd: c_int) -> c_int {
+    let mut st = state();
+    return match st.queue(sockqd) {
+        Ok(q) if !q.listening => EINVAL,
+        Ok(_) => unsafe { submitted(&mut st, qt_out, Op::Accept(sockqd)) },
+        Err(e) => e,
+    };
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn demi_connect(
+    qt_out: *mut demi_qtoken_t,
+    sockqd: c_int,
+    addr: *const raw::sockaddr,
+    size: raw::socklen_t,
+) -> c_int {
+    let addr = match addr_of(addr, size) {
+        Ok(addr) => addr,
+        Err(e) => return e,
+    };
+
+    let mut st = state();
+    match st.queue(sockqd) {
+        Ok(q) if q.listening || q.peer.is_some() => return EINVAL,
+        Ok(_) => {}
+        Err(e) => return e,
+    }
+    let err = st.connect(sockqd, addr);
+    let op = Op::Done {
+        qd: sockqd,
+        opcode: raw::demi_opcode_DEMI_OPC_CONNECT,
+        err,
+    };
+    return unsafe { submitted(&mut st, qt_out, op) };
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn demi_close(qd: c_int) -> c_int {
+    let ret = state().close(qd);
+    READY.notify_all();
+    return ret;
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn demi_push(
+    qt_out: *mut demi_qtoken_t,
+    qd: c_int,
+    sga: *const demi_sgarray,
+) -> c_int {
+    let Some(sga) = (unsafe { sga.as_ref() }) else {
+        return EINVAL;
+    };
+    let segs = &sga.segments[..(sga.sga_numsegs as usize).min(sga.segments.len())];
+    let mut data = Vec::new();
+    for seg in segs {
+        let (ptr, len) = (seg.data_buf_ptr as *const u8, seg.data_len_bytes as usize);
+        data.extend_from_slice(unsafe { std::slice::from_raw_parts(ptr, len) });
+    }
+
+    let mut st = state();
+    let peer = match st.queue(qd) {
+        Ok(q) => q.peer,
+        Err(e) => return e,
+    };
+    let err = match peer.map(|peer| st.queues.get_mut(&peer)) {
+        None => ENOTCONN,
+        Some(None) => ECONNRESET,
+        Some(Some(peer)) => {
+            // an empty push would read as the end of the stream
+            if !data.is_empty() {
+                peer.recv.push_back(data);
+            }
+            0
+        }
+    };
+    let op = Op::Done {
+        qd,
+        opcode: raw::demi_opcode_DEMI_OPC_PUSH,
+        err,
+    };
+    return unsafe { submitted(&mut st, qt_out, op) };
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn demi_pop(qt_out: *mut demi_qtoken_t, qd: c_int) -> c_int {
+    let mut st = state();
+    return match st.queue(qd) {
+        Ok(q) if q.listening => EINVAL,
+        Ok(_) => unsafe { submitted(&mut st, qt_out, Op::Pop(qd)) },
+        Err(e) => e,
+    };
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn demi_wait(
+    qr_out: *mut demi_qresult,
+    qt: demi_qtoken_t,
+    timeout: *const raw::timespec,
+) -> c_int {
+    let mut off = 0;
+    return unsafe { demi_wait_any(qr_out, &mut off, &qt, 1, timeout) };
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn demi_wait_any(
+    qr_out: *mut demi_qresult,
+    ready_offset: *mut c_int,
+    qts: *const demi_qtoken_t,
+    num_qts: c_int,
+    timeout: *const raw::timespec,
+) -> c_int {
+    if qts.is_null() || num_qts <= 0 {
+        return EINVAL;
+    }
+    let qts = unsafe { std::slice::from_raw_parts(qts, num_qts as usize) };
+    let deadline = unsafe { timeout.as_ref() }.map(|ts| {
+        return Instant::now() + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32);
+    });
+
+    let mut st = state();
+    if qts.iter().any(|qt| !st.ops.contains_key(qt)) {
+        return EINVAL;
+    }
+    // the oldest token ready completes first, a pop waiting for data is not starved by the pushes
+    // submitted after it
+    let mut order: Vec<usize> = (0..qts.len()).collect();
+    order.sort_by_key(|off| qts[*off]);
+    loop {
+        for &off in &order {
+            if let Some(res) = st.complete(qts[off]) {
+                unsafe {
+                    *qr_out = res;
+                    *ready_offset = off as c_int;
+                }
+                return 0;
+            }
+        }
+
+        st = match deadline {
+            None => READY.wait(st).unwrap_or_else(PoisonError::into_inner),
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return ETIMEDOUT;
+                }
+                READY
+                    .wait_timeout(st, left)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0
+            }
+        };
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn demi_sgaalloc(size: usize) -> demi_sgarray {
+    return state().sga(vec![0; size].into_boxed_slice());
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn demi_sgafree(sga: *mut demi_sgarray) -> c_int {
+    let Some(sga) = (unsafe { sga.as_ref() }) else {
+        return EINVAL;
+    };
+
+    let mut st = state();
+    let segs = &sga.segments[..(sga.sga_numsegs as usize).min(sga.segments.len())];
+    for seg in segs {
+        st.bufs.remove(&(seg.sgaseg_md as usize));
+    }
+    return 0;
+}
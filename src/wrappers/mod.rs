@@ -6,9 +6,34 @@
     non_camel_case_types,
     unsafe_op_in_unsafe_fn
 )]
+#[cfg(not(dpoll_bindgen))]
 mod raw;
+/// generated from the installed demikernel headers with the bindgen feature, see build.rs
+#[allow(
+    dead_code,
+    unused,
+    non_upper_case_globals,
+    non_snake_case,
+    non_camel_case_types,
+    unsafe_op_in_unsafe_fn
+)]
+#[cfg(dpoll_bindgen)]
+mod raw {
+    include!(concat!(env!("OUT_DIR"), "/raw.rs"));
+}
 
+pub mod backend;
+pub mod clock;
+pub mod deadline;
 pub mod demi;
 pub mod errno;
+#[cfg(feature = "faults")]
+pub mod faults;
 mod helpers;
-pub mod sigmask;
+mod layout;
+#[cfg(feature = "mock")]
+mod mock;
+pub mod platform;
+#[cfg(feature = "reactor")]
+pub mod reactor;
+pub mod sga_pool;
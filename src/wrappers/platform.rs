@@ -0,0 +1,95 @@
+//! what depends on the C library the crate is linked with, errno, the signal mask and the cpu
+//! affinity of a thread
+//!
+//! only linux is supported, glibc and musl both export errno through `__errno_location` and take
+//! the same `sigset_t`, the kernel one is converted by the C library for epoll_pwait
+
+use std::{
+    mem::{self, MaybeUninit},
+    os::raw::c_int,
+};
+
+use libc::{CPU_SETSIZE, SIG_SETMASK, cpu_set_t, pthread_sigmask, sigset_t};
+
+use super::errno::{PosixError, PosixResult};
+
+/// the errno of the calling thread
+#[inline]
+pub fn errno() -> c_int {
+    return unsafe { libc::__errno_location().read() };
+}
+
+#[inline]
+pub fn set_errno(code: c_int) {
+    unsafe { libc::__errno_location().write(code) };
+}
+
+/// replaces the signal mask of the thread until dropped
+pub struct Sigset {
+    old: MaybeUninit<sigset_t>,
+}
+
+impl Sigset {
+    pub fn mask(new: &sigset_t) -> Self {
+        let mut old = MaybeUninit::uninit();
+        unsafe {
+            assert_eq!(pthread_sigmask(SIG_SETMASK, new, old.as_mut_ptr()), 0);
+        }
+
+        return Self { old };
+    }
+}
+
+impl Drop for Sigset {
+    fn drop(&mut self) {
+        unsafe {
+            assert_eq!(
+                pthread_sigmask(SIG_SETMASK, self.old.as_ptr(), std::ptr::null_mut()),
+                0
+            );
+        }
+    }
+}
+
+/// the id of the calling thread, as the affinity calls take it
+#[allow(dead_code)]
+pub fn thread_id() -> c_int {
+    return unsafe { libc::gettid() };
+}
+
+/// the cpu the calling thread runs on, `None` if the kernel cannot tell
+pub fn current_cpu() -> Option<c_int> {
+    let cpu = unsafe { libc::sched_getcpu() };
+    return (cpu >= 0).then_some(cpu);
+}
+
+/// a set of only `cpu`, fails with EINVAL if a set cannot hold it
+#[allow(dead_code)]
+pub fn cpu_set(cpu: usize) -> PosixResult<cpu_set_t> {
+    if cpu >= CPU_SETSIZE as usize {
+        return Err(PosixError::INVAL);
+    }
+    let mut set: cpu_set_t = unsafe { mem::zeroed() };
+    unsafe { libc::CPU_SET(cpu, &mut set) };
+    return Ok(set);
+}
+
+/// the cpus thread `tid` may run on, 0 for the calling one
+#[allow(dead_code)]
+pub fn affinity(tid: c_int) -> PosixResult<cpu_set_t> {
+    let mut set: cpu_set_t = unsafe { mem::zeroed() };
+    if unsafe { libc::sched_getaffinity(tid, mem::size_of::<cpu_set_t>(), &mut set) } != 0 {
+        return PosixError::from_errno().map(|_| unreachable!());
+    }
+    return Ok(set);
+}
+
+/// restricts thread `tid` to the cpus of `set`, 0 for the calling one, fails with EINVAL if the
+/// process may run on none of them
+#[allow(dead_code)]
+pub fn set_affinity(tid: c_int, set: &cpu_set_t) -> PosixResult<()> {
+    if unsafe { libc::sched_setaffinity(tid, mem::size_of::<cpu_set_t>(), set) } != 0 {
+        return PosixError::from_errno();
+    }
+    return Ok(());
+}
@@ -0,0 +1,441 @@
+//! the background reactor, enabled with the reactor feature
+//!
+//! a dedicated thread polls demikernel for the completions of every token anyone waits on and
+//! passes them to the thread that first waited on the token, so the waits of the application
+//! threads, including the ones of pwait, only look at memory and the kernel epoll, which decouples
+//! the latency of demikernel from the scheduling of the application threads
+//!
+//! tokens are submitted through a lock-free queue and completions come back through a bounded
+//! lock-free inbox per application thread, which is drained in batches. the reactor stops polling
+//! for the tokens of a thread whose inbox is full until it catches up, leaving the completions
+//! with demikernel meanwhile
+//!
+//! demikernel is not thread safe, so while the feature is on every call into it takes `lock`
+//!
+//! a thread can also ask for an eventfd that is readable while its inbox has completions, see
+//! `notify_fd`, so an event loop outside of dpoll learns about them without blocking in a pwait
+//!
+//! the reactor runs wherever the scheduler puts it unless it is pinned to a cpu with `pin`, which
+//! `DPOLL_REACTOR_CPU` does at `dpoll_init`. with a kernel-bypass libOS it should be a core near
+//! the NIC that no application thread polls on
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    env,
+    sync::{
+        Arc, Mutex, MutexGuard, OnceLock, PoisonError,
+        atomic::{AtomicBool, AtomicI32, Ordering},
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use crossbeam_queue::{ArrayQueue, SegQueue};
+use libc::{EFD_CLOEXEC, EFD_NONBLOCK, c_int, c_void, cpu_set_t};
+use log::{trace, warn};
+
+use super::{
+    demi::{self, QToken, RawQResult},
+    errno::{PosixError, PosixResult},
+    platform,
+};
+
+/// completions an inbox holds before the reactor stops polling for its thread
+const INBOX_LEN: usize = 4096;
+/// how long the reactor sleeps while all the tokens left belong to stalled threads
+const STALLED_SLEEP: Duration = Duration::from_micros(100);
+
+static DEMI: Mutex<()> = Mutex::new(());
+
+/// the cpu the reactor is pinned to, -1 for none
+static PINNED: AtomicI32 = AtomicI32::new(-1);
+/// the thread id of the reactor, 0 until it started
+static TID: AtomicI32 = AtomicI32::new(0);
+/// the cpus the reactor could run on when it started, unpinning restores them
+static UNPINNED: OnceLock<cpu_set_t> = OnceLock::new();
+/// the cpu the reactor last polled demikernel on, -1 until it polled
+static HARVEST_CPU: AtomicI32 = AtomicI32::new(-1);
+
+/// serializes the calls into demikernel
+pub fn lock() -> MutexGuard<'static, ()> {
+    return DEMI.lock().unwrap_or_else(PoisonError::into_inner);
+}
+
+/// a completion as the reactor thread got it, handed to the thread that waited for its token
+struct Completion(PosixResult<RawQResult>);
+
+// the sga and accept result in a completion are owned by whoever takes it, demikernel does not
+// touch them anymore
+unsafe impl Send for Completion {}
+
+/// where the completions of the tokens of an application thread go
+struct Inbox {
+    queue: ArrayQueue<(QToken, Completion)>,
+    /// set while the thread is parked waiting for completions
+    waiting: AtomicBool,
+    thread: Thread,
+    /// the eventfd of `notify_fd`, -1 until it is asked for
+    notify: AtomicI32,
+}
+
+impl Inbox {
+    fn wake(&self) {
+        if self.waiting.load(Ordering::SeqCst) {
+            self.thread.unpark();
+        }
+
+        let fd = self.notify.load(Ordering::SeqCst);
+        if fd >= 0 {
+            let val: u64 = 1;
+            unsafe { libc::write(fd, &val as *const u64 as *const c_void, 8) };
+        }
+    }
+
+    /// makes the eventfd unreadable again, before the inbox is drained so a completion pushed
+    /// meanwhile signals it anew
+    fn reset_notify(&self) {
+        let fd = self.notify.load(Ordering::SeqCst);
+        if fd >= 0 {
+            let mut val: u64 = 0;
+            unsafe { libc::read(fd, &mut val as *mut u64 as *mut c_void, 8) };
+        }
+    }
+}
+
+impl Drop for Inbox {
+    fn drop(&mut self) {
+        let fd = *self.notify.get_mut();
+        if fd >= 0 {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+struct Reactor {
+    submissions: &'static SegQueue<(QToken, Arc<Inbox>)>,
+    thread: Thread,
+}
+
+/// the state of the reactor thread
+#[derive(Default)]
+struct Harvester {
+    owners: HashMap<QToken, Arc<Inbox>>,
+    /// completions that did not fit into the inbox of their thread
+    stalled: Vec<(QToken, Arc<Inbox>, Completion)>,
+    /// the tokens polled for, those of stalled threads are left out
+    toks: Vec<QToken>,
+    changed: bool,
+}
+
+impl Harvester {
+    fn is_stalled(&self, inbox: &Arc<Inbox>) -> bool {
+        return self.stalled.iter().any(|(_, i, _)| Arc::ptr_eq(i, inbox));
+    }
+
+    fn deliver(&mut self, tok: QToken, res: PosixResult<RawQResult>) {
+        trace!("reactor got the completion of {tok}");
+        let Some(inbox) = self.owners.remove(&tok) else {
+            return;
+        };
+        self.changed = true;
+
+        match inbox.queue.push((tok, Completion(res))) {
+            Ok(()) => inbox.wake(),
+            Err((tok, completion)) => {
+                trace!("inbox of the owner of {tok} is full, not polling for it anymore");
+                self.stalled.push((tok, inbox, completion));
+            }
+        }
+    }
+
+    /// retries the completions of stalled threads, in order
+    fn unstall(&mut self) {
+        let mut left = Vec::new();
+        for (tok, inbox, completion) in self.stalled.drain(..) {
+            if left.iter().any(|(_, i, _)| Arc::ptr_eq(i, &inbox)) {
+                left.push((tok, inbox, completion));
+                continue;
+            }
+            match inbox.queue.push((tok, completion)) {
+                Ok(()) => {
+                    inbox.wake();
+                    self.changed = true;
+                }
+                Err((tok, completion)) => {
+                    inbox.wake();
+                    left.push((tok, inbox, completion));
+                }
+            }
+        }
+        self.stalled = left;
+    }
+
+    /// demikernel fails the whole wait for a single bad token, find the ones that fail on their own
+    fn deliver_failed(&mut self) {
+        for tok in self.toks.clone() {
+            let res = {
+                let _demi = lock();
+                demi::wait_direct(tok, Some(Duration::ZERO))
+            };
+            match res {
+                Ok(res) => self.deliver(tok, Ok(res)),
+                Err(PosixError::TIMEDOUT | PosixError::INTR | PosixError::WOULDBLOCK) => {}
+                Err(err) => self.deliver(tok, Err(err)),
+            }
+        }
+    }
+
+    fn run(&mut self, submissions: &SegQueue<(QToken, Arc<Inbox>)>) {
+        loop {
+            HARVEST_CPU.store(platform::current_cpu().unwrap_or(-1), Ordering::Relaxed);
+            while let Some((tok, inbox)) = submissions.pop() {
+                self.owners.insert(tok, inbox);
+                self.changed = true;
+            }
+            if !self.stalled.is_empty() {
+                self.unstall();
+            }
+            if self.changed {
+                let toks = self
+                    .owners
+                    .iter()
+                    .filter(|(_, inbox)| !self.is_stalled(inbox))
+                    .map(|(tok, _)| *tok)
+                    .collect();
+                self.toks = toks;
+                self.changed = false;
+            }
+
+            if self.toks.is_empty() {
+                if self.stalled.is_empty() {
+                    thread::park();
+                } else {
+                    thread::park_timeout(STALLED_SLEEP);
+                }
+                continue;
+            }
+
+            // only polls, so the application threads are never locked out of demikernel for long
+            let res = {
+                let _demi = lock();
+                demi::wait_any_raw_direct(&self.toks, Some(Duration::ZERO))
+            };
+            match res {
+                Ok((off, res)) => self.deliver(self.toks[off], Ok(res)),
+                Err(PosixError::TIMEDOUT | PosixError::INTR | PosixError::WOULDBLOCK) => {
+                    thread::yield_now()
+                }
+                Err(err) => {
+                    warn!(
+                        "reactor wait on {} tokens failed with {err:?}",
+                        self.toks.len()
+                    );
+                    self.deliver_failed();
+                }
+            }
+        }
+    }
+}
+
+fn reactor() -> &'static Reactor {
+    static REACTOR: OnceLock<Reactor> = OnceLock::new();
+    static SUBMISSIONS: OnceLock<SegQueue<(QToken, Arc<Inbox>)>> = OnceLock::new();
+
+    return REACTOR.get_or_init(|| {
+        let submissions = SUBMISSIONS.get_or_init(SegQueue::new);
+        let thread = thread::Builder::new()
+            .name("dpoll-reactor".to_owned())
+            .spawn(move || {
+                started();
+                Harvester::default().run(submissions)
+            })
+            .expect("cannot spawn the reactor thread");
+        return Reactor {
+            submissions,
+            thread: thread.thread().clone(),
+        };
+    });
+}
+
+/// applies the pinning asked for before the reactor thread started, on the thread itself
+fn started() {
+    if let Ok(set) = platform::affinity(0) {
+        let _ = UNPINNED.set(set);
+    }
+    let tid = platform::thread_id();
+    TID.store(tid, Ordering::SeqCst);
+
+    let cpu = PINNED.load(Ordering::SeqCst);
+    if let Err(e) = apply(tid, cpu) {
+        warn!("cannot pin the reactor to cpu {cpu}: {e:?}");
+    }
+}
+
+fn apply(tid: c_int, cpu: c_int) -> PosixResult<()> {
+    let set = match usize::try_from(cpu) {
+        Ok(cpu) => platform::cpu_set(cpu)?,
+        Err(_) => match UNPINNED.get() {
+            Some(set) => *set,
+            None => return Ok(()),
+        },
+    };
+    trace!("pinning the reactor to cpu {cpu}");
+    return platform::set_affinity(tid, &set);
+}
+
+/// pins the reactor thread to `cpu`, `None` lets it run on the cpus it could when it started,
+/// whether it started yet or not
+///
+/// fails with EINVAL for a cpu the process cannot run on, a reactor that did not start yet only
+/// warns about it once it does
+pub fn pin(cpu: Option<usize>) -> PosixResult<()> {
+    let cpu = match cpu {
+        Some(cpu) => {
+            platform::cpu_set(cpu)?;
+            cpu as c_int
+        }
+        None => -1,
+    };
+    PINNED.store(cpu, Ordering::SeqCst);
+
+    let tid = TID.load(Ordering::SeqCst);
+    if tid != 0 {
+        apply(tid, cpu)?;
+    }
+    return Ok(());
+}
+
+/// pins the reactor to `DPOLL_REACTOR_CPU` if it is set, failing with EINVAL if it is not a cpu
+pub fn install() -> PosixResult<()> {
+    let Ok(cpu) = env::var("DPOLL_REACTOR_CPU") else {
+        return Ok(());
+    };
+    let cpu = cpu
+        .trim()
+        .parse()
+        .inspect_err(|_| warn!("invalid DPOLL_REACTOR_CPU {cpu:?}"))
+        .map_err(|_| PosixError::INVAL)?;
+    return pin(Some(cpu));
+}
+
+/// the cpu the reactor last polled demikernel on, `None` until it polled
+pub fn harvest_cpu() -> Option<c_int> {
+    let cpu = HARVEST_CPU.load(Ordering::Relaxed);
+    return (cpu >= 0).then_some(cpu);
+}
+
+/// the side of the reactor owned by an application thread
+struct Consumer {
+    inbox: Arc<Inbox>,
+    /// tokens submitted to the reactor whose completions did not arrive yet
+    submitted: HashSet<QToken>,
+    /// completions taken from the inbox that were not waited for yet
+    done: HashMap<QToken, PosixResult<RawQResult>>,
+}
+
+thread_local! {
+    static CONSUMER: RefCell<Consumer> = RefCell::new(Consumer {
+        inbox: Arc::new(Inbox {
+            queue: ArrayQueue::new(INBOX_LEN),
+            waiting: AtomicBool::new(false),
+            thread: thread::current(),
+            notify: AtomicI32::new(-1),
+        }),
+        submitted: HashSet::new(),
+        done: HashMap::new(),
+    });
+}
+
+impl Consumer {
+    fn submit(&mut self, toks: &[QToken]) {
+        let mut any = false;
+        for tok in toks {
+            if !self.done.contains_key(tok) && self.submitted.insert(*tok) {
+                reactor().submissions.push((*tok, self.inbox.clone()));
+                any = true;
+            }
+        }
+        if any {
+            reactor().thread.unpark();
+        }
+    }
+
+    /// moves everything in the inbox to `done`
+    fn drain(&mut self) {
+        self.inbox.reset_notify();
+        while let Some((tok, completion)) = self.inbox.queue.pop() {
+            self.submitted.remove(&tok);
+            self.done.insert(tok, completion.0);
+        }
+    }
+
+    /// the first of `toks` that completed, with its offset
+    fn take(&mut self, toks: &[QToken]) -> Option<(usize, PosixResult<RawQResult>)> {
+        self.drain();
+        return toks
+            .iter()
+            .enumerate()
+            .find_map(|(off, tok)| self.done.remove(tok).map(|res| (off, res)));
+    }
+}
+
+/// an eventfd that is readable once the reactor delivered completions to the calling thread,
+/// until the thread next waits on any token, created on first use and owned by the thread
+///
+/// the completions are only harvested for the tokens the thread waited on before
+pub fn notify_fd() -> PosixResult<c_int> {
+    return CONSUMER.with_borrow(|consumer| {
+        let notify = &consumer.inbox.notify;
+        let fd = notify.load(Ordering::SeqCst);
+        if fd >= 0 {
+            return Ok(fd);
+        }
+
+        let fd = unsafe { libc::eventfd(0, EFD_NONBLOCK | EFD_CLOEXEC) };
+        if fd.is_negative() {
+            return PosixError::from_errno().map(|_| unreachable!());
+        }
+        notify.store(fd, Ordering::SeqCst);
+        // completions that arrived before are not waited on yet either
+        if !consumer.inbox.queue.is_empty() || !consumer.done.is_empty() {
+            consumer.inbox.wake();
+        }
+
+        return Ok(fd);
+    });
+}
+
+/// like `demi::wait_any_raw`, but waits for the reactor thread to harvest the completion
+///
+/// the tokens have to be waited on by the thread that first waited on them, their completions are
+/// delivered to it
+pub fn wait_any(toks: &[QToken], timeout: Option<Duration>) -> PosixResult<(usize, RawQResult)> {
+    let until = timeout.map(|t| Instant::now() + t);
+    return CONSUMER.with_borrow_mut(|consumer| {
+        consumer.submit(toks);
+        loop {
+            if let Some((off, res)) = consumer.take(toks) {
+                return res.map(|res| (off, res));
+            }
+
+            let inbox = &consumer.inbox;
+            inbox.waiting.store(true, Ordering::SeqCst);
+            // a completion pushed before `waiting` was set did not unpark us
+            if inbox.queue.is_empty() {
+                match until {
+                    None => thread::park(),
+                    Some(until) => {
+                        let left = until.saturating_duration_since(Instant::now());
+                        if left.is_zero() {
+                            inbox.waiting.store(false, Ordering::SeqCst);
+                            return Err(PosixError::TIMEDOUT);
+                        }
+                        thread::park_timeout(left);
+                    }
+                }
+            }
+            inbox.waiting.store(false, Ordering::SeqCst);
+        }
+    });
+}
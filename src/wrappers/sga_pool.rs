@@ -0,0 +1,199 @@
+//! a per thread cache of the sgas staging the copies of writes, enabled with the sga_pool config
+//! key
+//!
+//! demikernel allocates every sga anew, for small writes at a high rate that costs about as much
+//! as the copy itself, so pooled sgas are rounded up to a power of two and, once their push
+//! completed, kept for the next write of their size class instead of being freed
+//!
+//! the pool belongs to the thread like its sockets, sgas never move to another thread's pool
+//!
+//! for protocols with messages of a known size the pool can be prewarmed with sgas of their class,
+//! allocated by the first pooled write of the thread, after which a write is served by the
+//! smallest cached sga of its class or a larger one and steady traffic does not allocate anymore
+
+use std::cell::RefCell;
+
+use log::{trace, warn};
+
+use super::{demi, errno::PosixResult, raw};
+
+/// the smallest size class, 64 bytes
+const MIN_SHIFT: u32 = 6;
+/// the size classes from 64 bytes up to `SgArray::MAX_LEN`
+const CLASSES: usize = (demi::SgArray::MAX_LEN.trailing_zeros() - MIN_SHIFT + 1) as usize;
+
+/// how the sockets of a thread use its pool, set with the sga_pool config key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// the sgas the pool keeps per size class, 0 for not pooling
+    pub per_class: usize,
+    /// the size and number of sgas the pool is filled with before its first use
+    pub prewarm: Option<(usize, usize)>,
+}
+
+impl PoolConfig {
+    pub const fn off() -> Self {
+        return Self {
+            per_class: 0,
+            prewarm: None,
+        };
+    }
+
+    pub fn is_off(&self) -> bool {
+        return self.per_class == 0;
+    }
+}
+
+/// how the statistics of the pool of the calling thread stand, see `stats`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PoolStats {
+    /// allocations served by a cached sga
+    pub hits: u64,
+    /// allocations that went to demikernel
+    pub misses: u64,
+    /// sgas cached right now
+    pub cached: u64,
+    /// sgas freed because their class was full
+    pub freed: u64,
+    /// sgas allocated to prewarm the pool
+    pub prewarmed: u64,
+}
+
+/// what a pooled sga was trimmed from, to restore it once it goes back to the pool
+#[derive(Debug)]
+pub struct Pooled {
+    class: usize,
+    /// the sgas the class keeps at most
+    cap: usize,
+    numsegs: u32,
+    /// the segment the length was cut in and its length before
+    cut: usize,
+    cut_len: u32,
+}
+
+struct Pool {
+    classes: [Vec<raw::demi_sgarray>; CLASSES],
+    stats: PoolStats,
+    /// the last prewarm done, a new one tops the pool up
+    warmed: Option<(usize, usize)>,
+}
+
+impl Pool {
+    /// fills the class of `size` up to `count` sgas, stopping early if demikernel runs out of
+    /// memory
+    fn prewarm(&mut self, size: usize, count: usize) -> PosixResult<()> {
+        let class = class_of(size);
+        trace!(
+            "prewarming the sga pool with {count} sgas of {} bytes",
+            class_len(class)
+        );
+        while self.classes[class].len() < count {
+            let sga = demi::SgArray::new(class_len(class))?.into_raw();
+            self.classes[class].push(sga);
+            self.stats.cached += 1;
+            self.stats.prewarmed += 1;
+        }
+        return Ok(());
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        for sga in self.classes.iter_mut().flat_map(|class| class.drain(..)) {
+            demi::free_sga(sga);
+        }
+    }
+}
+
+thread_local! {
+    static POOL: RefCell<Pool> = RefCell::new(Pool {
+        classes: Default::default(),
+        stats: PoolStats::default(),
+        warmed: None,
+    });
+}
+
+/// the class of sgas of at least `len` bytes
+pub fn class_of(len: usize) -> usize {
+    let shift = len.next_power_of_two().trailing_zeros().max(MIN_SHIFT);
+    return (shift - MIN_SHIFT) as usize;
+}
+
+/// the bytes the sgas of `class` are allocated with
+pub fn class_len(class: usize) -> usize {
+    return 1 << (class as u32 + MIN_SHIFT);
+}
+
+/// a cached sga of `class` or else of the smallest larger class there is one of, with its class,
+/// `None` if there is none and one has to be allocated
+///
+/// prewarms the pool first if `config` asks for a prewarm it did not get yet
+pub fn take(class: usize, config: PoolConfig) -> Option<(raw::demi_sgarray, usize)> {
+    return POOL.with_borrow_mut(|pool| {
+        if let Some((size, count)) = config.prewarm
+            && pool.warmed != config.prewarm
+        {
+            pool.warmed = config.prewarm;
+            if let Err(e) = pool.prewarm(size, count) {
+                warn!("prewarming the sga pool stopped early: {e:?}");
+            }
+        }
+
+        let Some(class) = (class..CLASSES).find(|c| !pool.classes[*c].is_empty()) else {
+            pool.stats.misses += 1;
+            return None;
+        };
+        pool.stats.hits += 1;
+        pool.stats.cached -= 1;
+        return pool.classes[class].pop().map(|sga| (sga, class));
+    });
+}
+
+/// cuts `sga` of `class` down to `len` bytes, returning how to restore it
+pub fn trim(sga: &mut raw::demi_sgarray, len: usize, class: usize, config: PoolConfig) -> Pooled {
+    let numsegs = sga.sga_numsegs;
+    let mut left = len;
+    let mut cut = 0;
+    while cut + 1 < numsegs as usize && left > sga.segments[cut].data_len_bytes as usize {
+        left -= sga.segments[cut].data_len_bytes as usize;
+        cut += 1;
+    }
+
+    let cut_len = sga.segments[cut].data_len_bytes;
+    sga.segments[cut].data_len_bytes = left.min(cut_len as usize) as u32;
+    sga.sga_numsegs = cut as u32 + 1;
+    return Pooled {
+        class,
+        cap: config.per_class,
+        numsegs,
+        cut,
+        cut_len,
+    };
+}
+
+/// restores `sga` and caches it, freeing it instead if its class is full
+pub fn give_back(mut sga: raw::demi_sgarray, pooled: Pooled) {
+    sga.segments[pooled.cut].data_len_bytes = pooled.cut_len;
+    sga.sga_numsegs = pooled.numsegs;
+
+    // the pool is gone already if the sockets of the thread are dropped after it
+    let kept = POOL.try_with(|pool| {
+        let mut pool = pool.borrow_mut();
+        if pool.classes[pooled.class].len() >= pooled.cap {
+            pool.stats.freed += 1;
+            return false;
+        }
+        pool.classes[pooled.class].push(sga);
+        pool.stats.cached += 1;
+        return true;
+    });
+
+    if kept != Ok(true) {
+        demi::free_sga(sga);
+    }
+}
+
+/// the statistics of the pool of the calling thread
+pub fn stats() -> PoolStats {
+    return POOL.with_borrow(|pool| pool.stats);
+}
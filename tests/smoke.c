@@ -0,0 +1,35 @@
+// links against libdemi_epoll and checks the parts of the ABI that work without demikernel,
+// built and run by `make check`
+
+#include <errno.h>
+#include <stdio.h>
+#include <string.h>
+
+#include <dpoll.h>
+
+#define CHECK(cond)                                                            \
+    do {                                                                       \
+        if (!(cond)) {                                                         \
+            fprintf(stderr, "%s:%d: %s failed\n", __FILE__, __LINE__, #cond);  \
+            return 1;                                                          \
+        }                                                                      \
+    } while (0)
+
+int main(void) {
+    CHECK(dpoll_abi_version() == DPOLL_ABI_VERSION);
+    CHECK(dpoll_capabilities() & DPOLL_CAP_CONNECT);
+    CHECK(dpoll_version() != 0);
+
+    char buf[32];
+    CHECK(dpoll_configure("send_queue_depth", "8") == 0);
+    CHECK(dpoll_config_get("send_queue_depth", buf, sizeof(buf)) == 1);
+    CHECK(strcmp(buf, "8") == 0);
+
+    CHECK(dpoll_configure("no_such_key", "1") == -1);
+    CHECK(errno == ENOENT);
+    CHECK(dpoll_errno() == ENOENT);
+    CHECK(strcmp(dpoll_strerror(ENOENT), "No such file or directory") == 0);
+
+    puts("smoke test passed");
+    return 0;
+}